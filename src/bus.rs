@@ -0,0 +1,183 @@
+use std::cell::RefCell;
+
+/// Everything the CPU is wired to: the memory map and the components
+/// clocked alongside it. `Memory` is the console, `FlatBus` plain RAM.
+///
+/// Beyond reading, writing and ticking, the CPU reaches a few signals that
+/// do not go over the bus on hardware, which default to a bus without them.
+pub trait Bus {
+    fn read(&self, address: u16) -> u8;
+
+    /// Reads for inspection, e.g. tracing, without counting as an access
+    fn peek(&self, address: u16) -> u8 {
+        self.read(address)
+    }
+
+    fn write(&mut self, address: u16, data: u8);
+
+    /// Advances everything clocked alongside the CPU by `cycles` M-cycles
+    fn tick(&mut self, cycles: u16);
+
+    /// Interrupts both requested (IF) and enabled (IE)
+    fn pending_interrupts(&self) -> u8 {
+        self.read(0xFFFF) & self.read(0xFF0F) & 0x1F
+    }
+
+    /// Clears the IF bits of interrupts being serviced
+    fn acknowledge_interrupt(&mut self, interrupt: u8) {
+        let flags = self.read(0xFF0F);
+        self.write(0xFF0F, flags & !interrupt);
+    }
+
+    /// M-cycles the CPU must stay off the bus for, e.g. during a VRAM DMA
+    fn take_stall(&mut self) -> u16 {
+        0
+    }
+
+    /// STOP resets DIV
+    fn reset_divider(&mut self) {}
+
+    /// Whether KEY1 asks STOP to switch the CPU speed
+    fn speed_switch_armed(&self) -> bool {
+        false
+    }
+
+    fn switch_speed(&mut self) {}
+
+    fn double_speed(&self) -> bool {
+        false
+    }
+
+    /// ROM bank mapped at `address`, with no MBC bank 0 then bank 1
+    fn rom_bank(&self, address: u16) -> u16 {
+        if address < 0x4000 {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+/// Bus access, with the address and the data transferred
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read(u16, u8),
+    Write(u16, u8),
+}
+
+/// Flat 64KB of RAM with no hardware behind it, which records every access
+/// along with the M-cycle it happened in. CPU tests and fuzzing run against
+/// it.
+#[derive(Debug)]
+pub struct FlatBus {
+    ram: Vec<u8>,
+    /// M-cycles ticked so far
+    cycles: u64,
+    /// Reads happen through a shared reference, as they do on `Memory`
+    accesses: RefCell<Vec<(u64, Access)>>,
+}
+
+impl FlatBus {
+    pub fn init() -> FlatBus {
+        FlatBus {
+            ram: vec![0x00; 0x10000],
+            cycles: 0,
+            accesses: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// RAM holding `code` from `address` on, e.g. a program to step through
+    pub fn with_code(address: u16, code: &[u8]) -> FlatBus {
+        let mut bus = FlatBus::init();
+        for (offset, byte) in code.iter().enumerate() {
+            bus.poke(address.wrapping_add(offset as u16), *byte);
+        }
+        bus
+    }
+
+    /// M-cycles ticked since the bus was created
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Reads without recording an access
+    pub fn peek(&self, address: u16) -> u8 {
+        self.ram[address as usize]
+    }
+
+    /// Writes without recording an access
+    pub fn poke(&mut self, address: u16, data: u8) {
+        self.ram[address as usize] = data;
+    }
+
+    /// Returns the accesses recorded since the last call, each with the
+    /// M-cycle it happened in
+    pub fn take_accesses(&mut self) -> Vec<(u64, Access)> {
+        self.accesses.take()
+    }
+}
+
+impl Bus for FlatBus {
+    fn read(&self, address: u16) -> u8 {
+        let data = self.ram[address as usize];
+        let access = Access::Read(address, data);
+        self.accesses.borrow_mut().push((self.cycles, access));
+        data
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.ram[address as usize]
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        self.ram[address as usize] = data;
+        let access = Access::Write(address, data);
+        self.accesses.get_mut().push((self.cycles, access));
+    }
+
+    fn tick(&mut self, cycles: u16) {
+        self.cycles += cycles as u64;
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.peek(0xFFFF) & self.peek(0xFF0F) & 0x1F
+    }
+
+    fn acknowledge_interrupt(&mut self, interrupt: u8) {
+        self.poke(0xFF0F, self.peek(0xFF0F) & !interrupt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flat_bus_accesses() {
+        let mut bus = FlatBus::init();
+        bus.poke(0xC000, 0x12);
+        assert_eq!(bus.read(0xC000), 0x12);
+        bus.tick(2);
+        bus.write(0xFFFF, 0x34);
+        assert_eq!(bus.peek(0xFFFF), 0x34);
+        assert_eq!(
+            bus.take_accesses(),
+            [
+                (0, Access::Read(0xC000, 0x12)),
+                (2, Access::Write(0xFFFF, 0x34))
+            ]
+        );
+        assert!(bus.take_accesses().is_empty());
+    }
+
+    #[test]
+    fn test_interrupts_off_the_bus() {
+        let mut bus = FlatBus::init();
+        bus.poke(0xFFFF, 0x05);
+        bus.poke(0xFF0F, 0xE4);
+        assert_eq!(bus.pending_interrupts(), 0x04);
+        bus.acknowledge_interrupt(0x04);
+        assert_eq!(bus.peek(0xFF0F), 0xE0);
+        assert!(bus.take_accesses().is_empty());
+    }
+}
@@ -1,9 +1,24 @@
+use crate::bus::Bus;
 use crate::instructions::Instruction;
 use crate::memory::Memory;
+use crate::model::Model;
+use crate::ppu::FRAME_DOTS;
+use crate::state::{snapshot, Reader, Snapshot, Writer};
+use crate::trace::Tracer;
 use crate::*;
 
-const CLOCK: f32 = 8.388608;
+/// CGB double speed clock in Hz, the DMG and single speed run at half of it
+const CLOCK: usize = 8_388_608;
 
+/// M-cycles the CPU stays paused while switching speeds, STOP included
+const SPEED_SWITCH_CYCLES: u16 = 2050;
+
+const FLAG_Z: u8 = 0x80;
+const FLAG_N: u8 = 0x40;
+const FLAG_H: u8 = 0x20;
+const FLAG_C: u8 = 0x10;
+
+#[allow(dead_code)]
 enum Register {
     AF,
     BC,
@@ -13,29 +28,109 @@ enum Register {
     PC,
 }
 
-#[derive(Debug)]
-struct Registers {
-    af: u16, // Accumulator & Flags register
-    bc: u16,
-    de: u16,
-    hl: u16,
-    sp: u16, // Stack Pointer
-    pc: u16, // Program Counter
+/// 8 bits operands, `HL` being the byte HL points to and `u8` the byte
+/// following the opcode
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, PartialEq)]
+enum Operand {
+    A,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    HL,
+    u8,
+}
+
+#[derive(Clone, Copy)]
+enum Condition {
+    Always,
+    NZ,
+    Z,
+    NC,
+    C,
+}
+
+/// Rotate and shift operations of the CB-prefixed instructions
+#[derive(Clone, Copy)]
+enum Shift {
+    RLC,
+    RRC,
+    RL,
+    RR,
+    SLA,
+    SRA,
+    SWAP,
+    SRL,
+}
+
+impl Shift {
+    /// Returns the shifted value and the carry out
+    fn apply(self, value: u8, carry: bool) -> (u8, bool) {
+        match self {
+            Shift::RLC => (value.rotate_left(1), value & 0x80 != 0),
+            Shift::RRC => (value.rotate_right(1), value & 0x01 != 0),
+            Shift::RL => ((value << 1) | carry as u8, value & 0x80 != 0),
+            Shift::RR => ((value >> 1) | (carry as u8) << 7, value & 0x01 != 0),
+            Shift::SLA => (value << 1, value & 0x80 != 0),
+            Shift::SRA => ((value >> 1) | (value & 0x80), value & 0x01 != 0),
+            Shift::SWAP => (value.rotate_left(4), false),
+            Shift::SRL => (value >> 1, value & 0x01 != 0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Registers {
+    pub af: u16, // Accumulator & Flags register
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16, // Stack Pointer
+    pub pc: u16, // Program Counter
 }
 
 impl Registers {
-    pub fn init() -> Registers {
+    pub fn init(model: Model, cgb_mode: bool, header_checksum: u8) -> Registers {
         // Initial values for registers obtained from Pandocs
+        let checksum_flags = if header_checksum != 0 { 0x30 } else { 0x00 };
+        let (af, bc, de, hl) = match model {
+            Model::DMG0 => (0x0100, 0xFF13, 0x00C1, 0x8403),
+            Model::DMG | Model::MGB => {
+                let a = if model == Model::MGB { 0xFF } else { 0x01 };
+                (as_u16(0x80 | checksum_flags, a), 0x0013, 0x00D8, 0x014D)
+            }
+            Model::SGB => (0x0100, 0x0014, 0x0000, 0xC060),
+            Model::SGB2 => (0xFF00, 0x0014, 0x0000, 0xC060),
+            Model::CGB if cgb_mode => (0x1180, 0x0000, 0xFF56, 0x000D),
+            Model::CGB => (0x1180, 0x0000, 0x0008, 0x007C),
+            Model::AGB if cgb_mode => (0x1100, 0x0100, 0xFF56, 0x000D),
+            Model::AGB => (0x1100, 0x0100, 0x0008, 0x007C),
+        };
         Registers {
-            af: 0x1180,
-            bc: 0x0000,
-            de: 0xFF56,
-            hl: 0x000D,
+            af,
+            bc,
+            de,
+            hl,
             pc: 0x0100, // ROM data starts at 0x0100, ignoring the bootloader checks
             sp: 0xFFFE,
         }
     }
 
+    /// Power-on state, the boot ROM takes it from here
+    pub fn power_on() -> Registers {
+        Registers {
+            af: 0x0000,
+            bc: 0x0000,
+            de: 0x0000,
+            hl: 0x0000,
+            pc: 0x0000,
+            sp: 0x0000,
+        }
+    }
+
     pub fn set_flags(
         &mut self,
         z: Option<bool>,
@@ -43,11 +138,33 @@ impl Registers {
         h: Option<bool>,
         c: Option<bool>,
     ) {
-        let current_state = self.af.split().1;
-        let new_state: u8 = 0x00;
+        let (flags, accumulator) = self.af.split();
+        let update = |flags: u8, flag: Option<bool>, mask: u8| match flag {
+            Some(true) => flags | mask,
+            Some(false) => flags & !mask,
+            None => flags,
+        };
+        let flags = update(flags, z, FLAG_Z);
+        let flags = update(flags, n, FLAG_N);
+        let flags = update(flags, h, FLAG_H);
+        let flags = update(flags, c, FLAG_C);
+        self.af = as_u16(flags, accumulator);
+    }
+
+    pub fn flag(&self, mask: u8) -> bool {
+        self.af.split().0 & mask != 0
+    }
+
+    pub fn a(&self) -> u8 {
+        self.af.split().1
+    }
+
+    pub fn set_a(&mut self, data: u8) {
+        self.af = as_u16(self.af.split().0, data);
     }
 
-    pub fn from_enum(&mut self, reg: Register) -> &mut u16 {
+    #[allow(clippy::wrong_self_convention)]
+    fn from_enum(&mut self, reg: Register) -> &mut u16 {
         match reg {
             Register::AF => &mut self.af,
             Register::BC => &mut self.bc,
@@ -60,7 +177,7 @@ impl Registers {
 }
 
 struct Clock {
-    cycles: u8,
+    cycles: u64,
     clock_speed: usize,
 }
 
@@ -68,193 +185,1291 @@ impl Clock {
     pub fn init() -> Clock {
         Clock {
             cycles: 0,
-            clock_speed: CLOCK as usize,
+            clock_speed: CLOCK / 2,
         }
     }
 }
 
-struct ALU {}
-
-pub struct CPU {
-    memory: Memory,
+/// The SM83 core, running against a `Bus`: the console's `Memory` unless
+/// testing
+pub struct CPU<B: Bus = Memory> {
+    bus: B,
     registers: Registers,
     clock: Clock,
+    /// Set by a STOP that is not a speed switch, until a selected joypad
+    /// line goes low
+    stopped: bool,
+    /// Interrupt Master Enable
+    ime: bool,
+    /// EI enables interrupts only after the following instruction
+    ime_scheduled: bool,
+    halted: bool,
+    /// HALT with IME off and an interrupt pending fails to increment PC
+    halt_bug: bool,
+    /// Receives a line per instruction executed
+    trace: Option<Tracer>,
 }
 
 impl CPU {
     pub fn new(memory: Memory) -> CPU {
+        let registers = if memory.boot_rom_mapped() {
+            Registers::power_on()
+        } else {
+            Registers::init(memory.model(), memory.cgb_mode(), memory.header_checksum())
+        };
+        let mut cpu = CPU::with_bus(memory);
+        cpu.registers = registers;
+        cpu
+    }
+
+    /// Flushes the audio and trace outputs, once done emulating
+    pub fn finish(&mut self) {
+        self.bus.finish_audio();
+        if let Some(trace) = self.trace.as_mut() {
+            trace.finish();
+        }
+    }
+
+    /// Runs until the PPU completes a frame, or for as long as a frame lasts
+    /// while the LCD is off
+    pub fn run_frame(&mut self) {
+        let frames = self.bus.frames();
+        let end = self.bus.elapsed() + FRAME_DOTS as u64;
+        while self.bus.frames() == frames && self.bus.elapsed() < end {
+            self.step();
+        }
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.bus
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.bus
+    }
+}
+
+impl<B: Bus> CPU<B> {
+    /// Starts from the power-on state, as a boot ROM would
+    pub fn with_bus(bus: B) -> CPU<B> {
         CPU {
-            memory,
-            registers: Registers::init(),
+            bus,
+            registers: Registers::power_on(),
             clock: Clock::init(),
+            stopped: false,
+            ime: false,
+            ime_scheduled: false,
+            halted: false,
+            halt_bug: false,
+            trace: None,
         }
     }
 
-    pub fn init(&mut self) {
-        // TODO: Should this be constrained according to CPU clock?
-        loop {
-            let data = CPU::fetch(&mut self.registers.pc, &self.memory);
-            let ins = Instruction::decode(data);
-            if ins == Instruction::STOP {
-                break;
+    /// Moves the CPU, as it is, onto another bus, e.g. one wrapping its own
+    pub fn map_bus<C: Bus>(self, map: impl FnOnce(B) -> C) -> CPU<C> {
+        CPU {
+            bus: map(self.bus),
+            registers: self.registers,
+            clock: self.clock,
+            stopped: self.stopped,
+            ime: self.ime,
+            ime_scheduled: self.ime_scheduled,
+            halted: self.halted,
+            halt_bug: self.halt_bug,
+            trace: self.trace,
+        }
+    }
+
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    /// Logs the registers before each instruction executed
+    pub fn set_trace(&mut self, trace: Tracer) {
+        self.trace = Some(trace);
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    /// Interrupt Master Enable, including one scheduled by EI
+    pub fn ime(&self) -> bool {
+        self.ime || self.ime_scheduled
+    }
+
+    pub fn set_ime(&mut self, enabled: bool) {
+        self.ime = enabled;
+        self.ime_scheduled = false;
+    }
+
+    /// M-cycles run since power on
+    pub fn cycles(&self) -> u64 {
+        self.clock.cycles
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.registers.pc
+    }
+
+    /// Whether the CPU is in STOP mode, waiting for a button press
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Runs a single instruction, or dispatches a pending interrupt, in
+    /// which case it returns true
+    pub fn step(&mut self) -> bool {
+        if self.stopped {
+            // Only a selected P10 ~ P13 line going low ends STOP mode. The
+            // clocks keep running meanwhile, so that frames keep coming.
+            if self.bus.peek(0xFF00) & 0x0F == 0x0F {
+                self.tick(1);
+                return false;
+            }
+            self.stopped = false;
+        }
+        if self.service_interrupt() {
+            return true;
+        }
+        if self.halted {
+            self.tick(1);
+            return false;
+        }
+        if let Some(trace) = self.trace.as_mut() {
+            trace.trace(&self.registers, &self.bus);
+        }
+        let enable_interrupts = self.ime_scheduled;
+        let opcode = self.fetch_opcode();
+        let instruction = if opcode == 0xCB {
+            Instruction::decode_cb(self.fetch())
+        } else {
+            Instruction::decode(opcode)
+        };
+        self.execute(instruction);
+        if enable_interrupts && self.ime_scheduled {
+            self.ime = true;
+            self.ime_scheduled = false;
+        }
+        false
+    }
+
+    /// Current CPU clock in Hz
+    pub fn clock_speed(&self) -> usize {
+        self.clock.clock_speed
+    }
+
+    fn service_interrupt(&mut self) -> bool {
+        let pending = self.bus.pending_interrupts();
+        if pending == 0 {
+            return false;
+        }
+        self.halted = false;
+        if !self.ime {
+            return false;
+        }
+        self.ime = false;
+        let interrupt = pending.trailing_zeros() as u16;
+        self.bus.acknowledge_interrupt(1 << interrupt);
+        // Two internal cycles, the push, then one more to jump
+        self.tick(1);
+        self.push(self.registers.pc);
+        self.registers.pc = 0x0040 + interrupt * 8;
+        self.tick(1);
+        true
+    }
+
+    fn fetch_opcode(&mut self) -> u8 {
+        if self.halt_bug {
+            self.halt_bug = false;
+            return self.read(self.registers.pc);
+        }
+        self.fetch()
+    }
+
+    fn stop(&mut self) {
+        // STOP is followed by a padding byte, read within its only M-cycle
+        self.bus.read(self.registers.pc);
+        self.registers.pc = self.registers.pc.wrapping_add(1);
+        self.bus.reset_divider();
+        if self.bus.speed_switch_armed() {
+            self.bus.switch_speed();
+            self.clock.clock_speed = if self.bus.double_speed() {
+                CLOCK
             } else {
-                self.execute(ins);
+                CLOCK / 2
+            };
+            // The cycle of STOP itself was its fetch
+            self.tick(SPEED_SWITCH_CYCLES - 1);
+        } else {
+            self.stopped = true;
+        }
+    }
+
+    fn halt(&mut self) {
+        if !self.ime && self.bus.pending_interrupts() != 0 {
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
+        }
+    }
+
+    fn read_operand(&mut self, operand: Operand) -> u8 {
+        let registers = &self.registers;
+        match operand {
+            Operand::A => registers.af.split().1,
+            Operand::B => registers.bc.split().1,
+            Operand::C => registers.bc.split().0,
+            Operand::D => registers.de.split().1,
+            Operand::E => registers.de.split().0,
+            Operand::H => registers.hl.split().1,
+            Operand::L => registers.hl.split().0,
+            Operand::HL => self.read(registers.hl),
+            Operand::u8 => self.fetch(),
+        }
+    }
+
+    fn write_operand(&mut self, operand: Operand, data: u8) {
+        let registers = &mut self.registers;
+        let (register, msb) = match operand {
+            Operand::A => (&mut registers.af, true),
+            Operand::B => (&mut registers.bc, true),
+            Operand::C => (&mut registers.bc, false),
+            Operand::D => (&mut registers.de, true),
+            Operand::E => (&mut registers.de, false),
+            Operand::H => (&mut registers.hl, true),
+            Operand::L => (&mut registers.hl, false),
+            Operand::HL => {
+                let address = registers.hl;
+                return self.write(address, data);
             }
+            Operand::u8 => unreachable!("Immediate operands are read-only"),
+        };
+        let (lsb, high) = register.split();
+        *register = if msb {
+            as_u16(lsb, data)
+        } else {
+            as_u16(data, high)
+        };
+    }
+
+    fn condition(&self, condition: Condition) -> bool {
+        match condition {
+            Condition::Always => true,
+            Condition::NZ => !self.registers.flag(FLAG_Z),
+            Condition::Z => self.registers.flag(FLAG_Z),
+            Condition::NC => !self.registers.flag(FLAG_C),
+            Condition::C => self.registers.flag(FLAG_C),
         }
     }
 
     fn inc_r8_lsb(&mut self, reg: Register) {
         let register = self.registers.from_enum(reg);
         let (lsb, msb) = register.split();
-        if lsb == 0xFF {
-            *register = as_u16(0x00, msb);
-            self.registers
-                .set_flags(Some(true), Some(false), Some(true), None);
-        } else {
-            *register = as_u16(lsb + 1, msb);
-            self.registers.set_flags(None, Some(false), None, None);
-        };
-        self.clock.cycles += 1;
+        let data = lsb.wrapping_add(1);
+        *register = as_u16(data, msb);
+        self.registers
+            .set_flags(Some(data == 0), Some(false), Some(lsb & 0x0F == 0x0F), None);
     }
 
     fn inc_r8_msb(&mut self, reg: Register) {
         let register = self.registers.from_enum(reg);
         let (lsb, msb) = register.split();
-        if msb == 0xFF {
-            *register = as_u16(lsb, 0x00);
-            self.registers
-                .set_flags(Some(true), Some(false), Some(true), None);
-        } else {
-            *register = as_u16(lsb, msb + 1);
-            self.registers.set_flags(None, Some(false), None, None);
-        };
-        self.clock.cycles += 1;
+        let data = msb.wrapping_add(1);
+        *register = as_u16(lsb, data);
+        self.registers
+            .set_flags(Some(data == 0), Some(false), Some(msb & 0x0F == 0x0F), None);
     }
 
     fn dec_r8_lsb(&mut self, reg: Register) {
         let register = self.registers.from_enum(reg);
         let (lsb, msb) = register.split();
-        if lsb == 0x00 {
-            *register = as_u16(0xFF, msb);
-            self.registers
-                .set_flags(Some(true), Some(false), Some(true), None);
-        } else {
-            *register = as_u16(lsb - 1, msb);
-            self.registers.set_flags(None, Some(false), None, None);
-        };
-        self.clock.cycles += 1;
+        let data = lsb.wrapping_sub(1);
+        *register = as_u16(data, msb);
+        self.registers
+            .set_flags(Some(data == 0), Some(true), Some(lsb & 0x0F == 0x00), None);
     }
 
     fn dec_r8_msb(&mut self, reg: Register) {
         let register = self.registers.from_enum(reg);
         let (lsb, msb) = register.split();
-        if msb == 0x00 {
-            *register = as_u16(lsb, 0xFF);
-            self.registers
-                .set_flags(Some(true), Some(false), Some(true), None);
-        } else {
-            *register = as_u16(lsb, msb - 1);
-            self.registers.set_flags(None, Some(false), None, None);
-        };
-        self.clock.cycles += 1;
+        let data = msb.wrapping_sub(1);
+        *register = as_u16(lsb, data);
+        self.registers
+            .set_flags(Some(data == 0), Some(true), Some(msb & 0x0F == 0x00), None);
+    }
+
+    fn inc_indirect(&mut self) {
+        let data = self.read_operand(Operand::HL);
+        let result = data.wrapping_add(1);
+        self.write_operand(Operand::HL, result);
+        self.registers.set_flags(
+            Some(result == 0),
+            Some(false),
+            Some(data & 0x0F == 0x0F),
+            None,
+        );
+    }
+
+    fn dec_indirect(&mut self) {
+        let data = self.read_operand(Operand::HL);
+        let result = data.wrapping_sub(1);
+        self.write_operand(Operand::HL, result);
+        self.registers.set_flags(
+            Some(result == 0),
+            Some(true),
+            Some(data & 0x0F == 0x00),
+            None,
+        );
     }
 
     fn load_r8_lsb(&mut self, reg: Register) {
-        let data = CPU::fetch(&mut self.registers.pc, &self.memory);
+        let data = self.fetch();
         let register = self.registers.from_enum(reg);
-        *register = *register | (data as u16);
-        self.clock.cycles += 2;
+        *register = as_u16(data, register.split().1);
     }
 
     fn load_r8_msb(&mut self, reg: Register) {
-        let data = CPU::fetch(&mut self.registers.pc, &self.memory);
+        let data = self.fetch();
         let register = self.registers.from_enum(reg);
-        *register = *register | ((data as u16) << 8);
-        self.clock.cycles += 2;
+        *register = as_u16(register.split().0, data);
     }
 
     fn load_r16(&mut self, reg: Register) {
-        let lsb = CPU::fetch(&mut self.registers.pc, &self.memory);
-        let msb = CPU::fetch(&mut self.registers.pc, &self.memory);
+        let lsb = self.fetch();
+        let msb = self.fetch();
         let register = self.registers.from_enum(reg);
         *register = as_u16(lsb, msb);
-        self.clock.cycles += 3;
     }
 
-    fn fetch(pc: &mut u16, memory: &Memory) -> u8 {
-        let data = memory.read(*pc);
-        *pc += 1;
+    /// LD between two 8 bits operands
+    fn load(&mut self, destination: Operand, source: Operand) {
+        let data = self.read_operand(source);
+        self.write_operand(destination, data);
+    }
+
+    /// LD A,(rr) and LD (rr),A, with an optional post increment/decrement of rr
+    fn load_indirect(&mut self, reg: Register, to_memory: bool, step: i8) {
+        let register = self.registers.from_enum(reg);
+        let address = *register;
+        *register = address.wrapping_add(step as u16);
+        if to_memory {
+            self.write(address, self.registers.a());
+        } else {
+            let data = self.read(address);
+            self.registers.set_a(data);
+        }
+    }
+
+    /// LDH between A and 0xFF00 + offset
+    fn load_high(&mut self, offset: Operand, to_memory: bool) {
+        let address = 0xFF00 | self.read_operand(offset) as u16;
+        if to_memory {
+            self.write(address, self.registers.a());
+        } else {
+            let data = self.read(address);
+            self.registers.set_a(data);
+        }
+    }
+
+    /// LD (u16),A and LD A,(u16)
+    fn load_absolute(&mut self, to_memory: bool) {
+        let address = self.fetch_u16();
+        if to_memory {
+            self.write(address, self.registers.a());
+        } else {
+            let data = self.read(address);
+            self.registers.set_a(data);
+        }
+    }
+
+    fn store_sp(&mut self) {
+        let address = self.fetch_u16();
+        let (lsb, msb) = self.registers.sp.split();
+        self.write(address, lsb);
+        self.write(address.wrapping_add(1), msb);
+    }
+
+    fn add(&mut self, operand: Operand, with_carry: bool) {
+        let data = self.read_operand(operand);
+        let a = self.registers.a();
+        let carry = (with_carry && self.registers.flag(FLAG_C)) as u8;
+        let result = a.wrapping_add(data).wrapping_add(carry);
+        let half = (a & 0x0F) + (data & 0x0F) + carry > 0x0F;
+        let full = a as u16 + data as u16 + carry as u16 > 0xFF;
+        self.registers.set_a(result);
+        self.registers
+            .set_flags(Some(result == 0), Some(false), Some(half), Some(full));
+    }
+
+    /// SUB, SBC and CP, which only sets the flags
+    fn sub(&mut self, operand: Operand, with_carry: bool, store: bool) {
+        let data = self.read_operand(operand);
+        let a = self.registers.a();
+        let carry = (with_carry && self.registers.flag(FLAG_C)) as u8;
+        let result = a.wrapping_sub(data).wrapping_sub(carry);
+        let half = (a & 0x0F) < (data & 0x0F) + carry;
+        let full = (a as u16) < data as u16 + carry as u16;
+        if store {
+            self.registers.set_a(result);
+        }
+        self.registers
+            .set_flags(Some(result == 0), Some(true), Some(half), Some(full));
+    }
+
+    fn and(&mut self, operand: Operand) {
+        let result = self.registers.a() & self.read_operand(operand);
+        self.registers.set_a(result);
+        self.registers
+            .set_flags(Some(result == 0), Some(false), Some(true), Some(false));
+    }
+
+    fn xor(&mut self, operand: Operand) {
+        let result = self.registers.a() ^ self.read_operand(operand);
+        self.registers.set_a(result);
+        self.registers
+            .set_flags(Some(result == 0), Some(false), Some(false), Some(false));
+    }
+
+    fn or(&mut self, operand: Operand) {
+        let result = self.registers.a() | self.read_operand(operand);
+        self.registers.set_a(result);
+        self.registers
+            .set_flags(Some(result == 0), Some(false), Some(false), Some(false));
+    }
+
+    fn daa(&mut self) {
+        let mut a = self.registers.a();
+        let mut carry = self.registers.flag(FLAG_C);
+        if self.registers.flag(FLAG_N) {
+            if carry {
+                a = a.wrapping_sub(0x60);
+            }
+            if self.registers.flag(FLAG_H) {
+                a = a.wrapping_sub(0x06);
+            }
+        } else {
+            if carry || a > 0x99 {
+                a = a.wrapping_add(0x60);
+                carry = true;
+            }
+            if self.registers.flag(FLAG_H) || a & 0x0F > 0x09 {
+                a = a.wrapping_add(0x06);
+            }
+        }
+        self.registers.set_a(a);
+        self.registers
+            .set_flags(Some(a == 0), None, Some(false), Some(carry));
+    }
+
+    fn cpl(&mut self) {
+        let a = self.registers.a();
+        self.registers.set_a(!a);
+        self.registers.set_flags(None, Some(true), Some(true), None);
+    }
+
+    fn set_carry(&mut self, complement: bool) {
+        let carry = !complement || !self.registers.flag(FLAG_C);
+        self.registers
+            .set_flags(None, Some(false), Some(false), Some(carry));
+    }
+
+    fn inc_r16(&mut self, reg: Register) {
+        let register = self.registers.from_enum(reg);
+        *register = register.wrapping_add(1);
+        self.tick(1);
+    }
+
+    fn dec_r16(&mut self, reg: Register) {
+        let register = self.registers.from_enum(reg);
+        *register = register.wrapping_sub(1);
+        self.tick(1);
+    }
+
+    fn add_hl(&mut self, reg: Register) {
+        let data = *self.registers.from_enum(reg);
+        let hl = self.registers.hl;
+        let half = (hl & 0x0FFF) + (data & 0x0FFF) > 0x0FFF;
+        let (result, full) = hl.overflowing_add(data);
+        self.registers.hl = result;
+        self.registers
+            .set_flags(None, Some(false), Some(half), Some(full));
+        self.tick(1);
+    }
+
+    /// SP plus a signed immediate, flags come from the unsigned lower byte
+    fn sp_offset(&mut self) -> u16 {
+        let offset = self.fetch();
+        let sp = self.registers.sp;
+        let half = (sp & 0x000F) + (offset as u16 & 0x000F) > 0x000F;
+        let full = (sp & 0x00FF) + offset as u16 > 0x00FF;
+        self.registers
+            .set_flags(Some(false), Some(false), Some(half), Some(full));
+        sp.wrapping_add(offset as i8 as u16)
+    }
+
+    fn add_sp(&mut self) {
+        self.registers.sp = self.sp_offset();
+        self.tick(2);
+    }
+
+    fn load_hl_sp(&mut self) {
+        self.registers.hl = self.sp_offset();
+        self.tick(1);
+    }
+
+    fn load_sp_hl(&mut self) {
+        self.registers.sp = self.registers.hl;
+        self.tick(1);
+    }
+
+    /// RLCA, RRCA, RLA and RRA always clear Z
+    fn shift_a(&mut self, shift: Shift) {
+        let (result, carry) = shift.apply(self.registers.a(), self.registers.flag(FLAG_C));
+        self.registers.set_a(result);
+        self.registers
+            .set_flags(Some(false), Some(false), Some(false), Some(carry));
+    }
+
+    fn shift(&mut self, shift: Shift, operand: Operand) {
+        let data = self.read_operand(operand);
+        let (result, carry) = shift.apply(data, self.registers.flag(FLAG_C));
+        self.write_operand(operand, result);
+        self.registers
+            .set_flags(Some(result == 0), Some(false), Some(false), Some(carry));
+    }
+
+    fn bit(&mut self, bit: u8, operand: Operand) {
+        let data = self.read_operand(operand);
+        self.registers
+            .set_flags(Some(data & (1 << bit) == 0), Some(false), Some(true), None);
+    }
+
+    fn set_bit(&mut self, bit: u8, operand: Operand, set: bool) {
+        let data = self.read_operand(operand);
+        let result = if set {
+            data | (1 << bit)
+        } else {
+            data & !(1 << bit)
+        };
+        self.write_operand(operand, result);
+    }
+
+    /// Pushes after an internal cycle decrementing SP
+    fn push(&mut self, data: u16) {
+        let (lsb, msb) = data.split();
+        self.tick(1);
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write(self.registers.sp, msb);
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write(self.registers.sp, lsb);
+    }
+
+    fn pop(&mut self) -> u16 {
+        let lsb = self.read(self.registers.sp);
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+        let msb = self.read(self.registers.sp);
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+        as_u16(lsb, msb)
+    }
+
+    fn push_r16(&mut self, reg: Register) {
+        let data = *self.registers.from_enum(reg);
+        self.push(data);
+    }
+
+    fn pop_r16(&mut self, reg: Register) {
+        let data = self.pop();
+        let register = self.registers.from_enum(reg);
+        *register = data;
+        // The lower nibble of F is hardwired to 0
+        self.registers.af &= 0xFFF0;
+    }
+
+    /// Branches taken spend an internal cycle loading PC
+    fn jr(&mut self, condition: Condition) {
+        let offset = self.fetch() as i8;
+        if self.condition(condition) {
+            self.registers.pc = self.registers.pc.wrapping_add(offset as u16);
+            self.tick(1);
+        }
+    }
+
+    fn jp(&mut self, condition: Condition) {
+        let address = self.fetch_u16();
+        if self.condition(condition) {
+            self.registers.pc = address;
+            self.tick(1);
+        }
+    }
+
+    fn jp_hl(&mut self) {
+        self.registers.pc = self.registers.hl;
+    }
+
+    fn call(&mut self, condition: Condition) {
+        let address = self.fetch_u16();
+        if self.condition(condition) {
+            self.push(self.registers.pc);
+            self.registers.pc = address;
+        }
+    }
+
+    /// Conditional returns spend an internal cycle checking the condition
+    fn ret(&mut self, condition: Condition) {
+        if !matches!(condition, Condition::Always) {
+            self.tick(1);
+        }
+        if self.condition(condition) {
+            self.registers.pc = self.pop();
+            self.tick(1);
+        }
+    }
+
+    fn reti(&mut self) {
+        self.ime = true;
+        self.ret(Condition::Always);
+    }
+
+    fn rst(&mut self, address: u16) {
+        self.push(self.registers.pc);
+        self.registers.pc = address;
+    }
+
+    fn set_interrupts(&mut self, enabled: bool) {
+        if enabled {
+            self.ime_scheduled = !self.ime;
+        } else {
+            self.ime = false;
+            self.ime_scheduled = false;
+        }
+    }
+
+    /// Advances the clock, and everything that runs in lockstep with the CPU,
+    /// by `cycles` M-cycles
+    fn tick(&mut self, cycles: u16) {
+        self.clock.cycles += cycles as u64;
+        self.bus.tick(cycles);
+        // Keep the CPU halted while a VRAM DMA owns the bus
+        loop {
+            let stall = self.bus.take_stall();
+            if stall == 0 {
+                break;
+            }
+            self.clock.cycles += stall as u64;
+            self.bus.tick(stall);
+        }
+    }
+
+    /// Reads over the bus, taking an M-cycle
+    fn read(&mut self, address: u16) -> u8 {
+        let data = self.bus.read(address);
+        self.tick(1);
+        data
+    }
+
+    /// Writes over the bus, taking an M-cycle
+    fn write(&mut self, address: u16, data: u8) {
+        self.bus.write(address, data);
+        self.tick(1);
+    }
+
+    fn fetch(&mut self) -> u8 {
+        let data = self.read(self.registers.pc);
+        self.registers.pc = self.registers.pc.wrapping_add(1);
         data
     }
 
+    fn fetch_u16(&mut self) -> u16 {
+        let lsb = self.fetch();
+        let msb = self.fetch();
+        as_u16(lsb, msb)
+    }
+
+    /// Runs an instruction after its opcode was fetched, ticking an M-cycle
+    /// per bus access and per internal cycle as the hardware does
     fn execute(&mut self, ins: Instruction) {
         match ins {
-            // Control
-            Instruction::NOP => self.clock.cycles += 1,
-            Instruction::CB => {
-                let next_byte = CPU::fetch(&mut self.registers.pc, &self.memory);
-                self.execute(Instruction::decode_cb(next_byte));
-            }
+            Instruction::NOP => {}
+            Instruction::STOP => self.stop(),
+            Instruction::HALT => self.halt(),
+            Instruction::DI => self.set_interrupts(false),
+            Instruction::EI => self.set_interrupts(true),
+            Instruction::CB => unreachable!("CB-prefixed instructions are decoded by step"),
+
             // Load 8 bits
-            Instruction::LD_A_u8 => self.load_r8_lsb(Register::AF),
-            Instruction::LD_B_u8 => self.load_r8_lsb(Register::BC),
-            Instruction::LD_C_u8 => self.load_r8_msb(Register::BC),
-            Instruction::LD_D_u8 => self.load_r8_lsb(Register::DE),
-            Instruction::LD_E_u8 => self.load_r8_msb(Register::DE),
-            Instruction::LD_H_u8 => self.load_r8_lsb(Register::HL),
-            Instruction::LD_L_u8 => self.load_r8_msb(Register::HL),
+            Instruction::LD_A_u8 => self.load_r8_msb(Register::AF),
+            Instruction::LD_B_u8 => self.load_r8_msb(Register::BC),
+            Instruction::LD_C_u8 => self.load_r8_lsb(Register::BC),
+            Instruction::LD_D_u8 => self.load_r8_msb(Register::DE),
+            Instruction::LD_E_u8 => self.load_r8_lsb(Register::DE),
+            Instruction::LD_H_u8 => self.load_r8_msb(Register::HL),
+            Instruction::LD_L_u8 => self.load_r8_lsb(Register::HL),
+            Instruction::LD_HL_u8 => self.load(Operand::HL, Operand::u8),
+            Instruction::LD_A_A => self.load(Operand::A, Operand::A),
+            Instruction::LD_A_B => self.load(Operand::A, Operand::B),
+            Instruction::LD_A_C => self.load(Operand::A, Operand::C),
+            Instruction::LD_A_D => self.load(Operand::A, Operand::D),
+            Instruction::LD_A_E => self.load(Operand::A, Operand::E),
+            Instruction::LD_A_H => self.load(Operand::A, Operand::H),
+            Instruction::LD_A_L => self.load(Operand::A, Operand::L),
+            Instruction::LD_A_HL => self.load(Operand::A, Operand::HL),
+            Instruction::LD_B_A => self.load(Operand::B, Operand::A),
+            Instruction::LD_B_B => self.load(Operand::B, Operand::B),
+            Instruction::LD_B_C => self.load(Operand::B, Operand::C),
+            Instruction::LD_B_D => self.load(Operand::B, Operand::D),
+            Instruction::LD_B_E => self.load(Operand::B, Operand::E),
+            Instruction::LD_B_H => self.load(Operand::B, Operand::H),
+            Instruction::LD_B_L => self.load(Operand::B, Operand::L),
+            Instruction::LD_B_HL => self.load(Operand::B, Operand::HL),
+            Instruction::LD_C_A => self.load(Operand::C, Operand::A),
+            Instruction::LD_C_B => self.load(Operand::C, Operand::B),
+            Instruction::LD_C_C => self.load(Operand::C, Operand::C),
+            Instruction::LD_C_D => self.load(Operand::C, Operand::D),
+            Instruction::LD_C_E => self.load(Operand::C, Operand::E),
+            Instruction::LD_C_H => self.load(Operand::C, Operand::H),
+            Instruction::LD_C_L => self.load(Operand::C, Operand::L),
+            Instruction::LD_C_HL => self.load(Operand::C, Operand::HL),
+            Instruction::LD_D_A => self.load(Operand::D, Operand::A),
+            Instruction::LD_D_B => self.load(Operand::D, Operand::B),
+            Instruction::LD_D_C => self.load(Operand::D, Operand::C),
+            Instruction::LD_D_D => self.load(Operand::D, Operand::D),
+            Instruction::LD_D_E => self.load(Operand::D, Operand::E),
+            Instruction::LD_D_H => self.load(Operand::D, Operand::H),
+            Instruction::LD_D_L => self.load(Operand::D, Operand::L),
+            Instruction::LD_D_HL => self.load(Operand::D, Operand::HL),
+            Instruction::LD_E_A => self.load(Operand::E, Operand::A),
+            Instruction::LD_E_B => self.load(Operand::E, Operand::B),
+            Instruction::LD_E_C => self.load(Operand::E, Operand::C),
+            Instruction::LD_E_D => self.load(Operand::E, Operand::D),
+            Instruction::LD_E_E => self.load(Operand::E, Operand::E),
+            Instruction::LD_E_H => self.load(Operand::E, Operand::H),
+            Instruction::LD_E_L => self.load(Operand::E, Operand::L),
+            Instruction::LD_E_HL => self.load(Operand::E, Operand::HL),
+            Instruction::LD_H_A => self.load(Operand::H, Operand::A),
+            Instruction::LD_H_B => self.load(Operand::H, Operand::B),
+            Instruction::LD_H_C => self.load(Operand::H, Operand::C),
+            Instruction::LD_H_D => self.load(Operand::H, Operand::D),
+            Instruction::LD_H_E => self.load(Operand::H, Operand::E),
+            Instruction::LD_H_H => self.load(Operand::H, Operand::H),
+            Instruction::LD_H_L => self.load(Operand::H, Operand::L),
+            Instruction::LD_H_HL => self.load(Operand::H, Operand::HL),
+            Instruction::LD_L_A => self.load(Operand::L, Operand::A),
+            Instruction::LD_L_B => self.load(Operand::L, Operand::B),
+            Instruction::LD_L_C => self.load(Operand::L, Operand::C),
+            Instruction::LD_L_D => self.load(Operand::L, Operand::D),
+            Instruction::LD_L_E => self.load(Operand::L, Operand::E),
+            Instruction::LD_L_H => self.load(Operand::L, Operand::H),
+            Instruction::LD_L_L => self.load(Operand::L, Operand::L),
+            Instruction::LD_L_HL => self.load(Operand::L, Operand::HL),
+            Instruction::LD_HL_A => self.load(Operand::HL, Operand::A),
+            Instruction::LD_HL_B => self.load(Operand::HL, Operand::B),
+            Instruction::LD_HL_C => self.load(Operand::HL, Operand::C),
+            Instruction::LD_HL_D => self.load(Operand::HL, Operand::D),
+            Instruction::LD_HL_E => self.load(Operand::HL, Operand::E),
+            Instruction::LD_HL_H => self.load(Operand::HL, Operand::H),
+            Instruction::LD_HL_L => self.load(Operand::HL, Operand::L),
+            Instruction::LD_BC_A => self.load_indirect(Register::BC, true, 0),
+            Instruction::LD_DE_A => self.load_indirect(Register::DE, true, 0),
+            Instruction::LD_A_BC => self.load_indirect(Register::BC, false, 0),
+            Instruction::LD_A_DE => self.load_indirect(Register::DE, false, 0),
+            Instruction::LD_HL_A_Plus => self.load_indirect(Register::HL, true, 1),
+            Instruction::LD_HL_A_Minus => self.load_indirect(Register::HL, true, -1),
+            Instruction::LD_A_HL_Plus => self.load_indirect(Register::HL, false, 1),
+            Instruction::LD_A_HL_Minus => self.load_indirect(Register::HL, false, -1),
+            Instruction::LD_FF00_u8_A => self.load_high(Operand::u8, true),
+            Instruction::LD_A_FF00_u8 => self.load_high(Operand::u8, false),
+            Instruction::LD_FF00_C_A => self.load_high(Operand::C, true),
+            Instruction::LD_A_FF00_C => self.load_high(Operand::C, false),
+            Instruction::LD_u16_A => self.load_absolute(true),
+            Instruction::LD_A_u16 => self.load_absolute(false),
 
             // Load 16 bits
             Instruction::LD_BC_u16 => self.load_r16(Register::BC),
             Instruction::LD_DE_u16 => self.load_r16(Register::DE),
             Instruction::LD_HL_u16 => self.load_r16(Register::HL),
+            Instruction::LD_SP_u16 => self.load_r16(Register::SP),
+            Instruction::LD_u16_SP => self.store_sp(),
+            Instruction::LD_SP_HL => self.load_sp_hl(),
+            Instruction::LD_HL_SP_i8 => self.load_hl_sp(),
+            Instruction::PUSH_BC => self.push_r16(Register::BC),
+            Instruction::PUSH_DE => self.push_r16(Register::DE),
+            Instruction::PUSH_HL => self.push_r16(Register::HL),
+            Instruction::PUSH_AF => self.push_r16(Register::AF),
+            Instruction::POP_BC => self.pop_r16(Register::BC),
+            Instruction::POP_DE => self.pop_r16(Register::DE),
+            Instruction::POP_HL => self.pop_r16(Register::HL),
+            Instruction::POP_AF => self.pop_r16(Register::AF),
 
             // ALU 8 bits
-            Instruction::INC_A => self.inc_r8_lsb(Register::AF),
-            Instruction::INC_B => self.inc_r8_lsb(Register::BC),
-            Instruction::INC_C => self.inc_r8_msb(Register::BC),
-            Instruction::INC_D => self.inc_r8_lsb(Register::DE),
-            Instruction::INC_E => self.inc_r8_msb(Register::DE),
-            Instruction::INC_H => self.inc_r8_lsb(Register::HL),
-            Instruction::INC_L => self.inc_r8_msb(Register::HL),
-            Instruction::DEC_A => self.dec_r8_lsb(Register::AF),
-            Instruction::DEC_B => self.dec_r8_lsb(Register::BC),
-            Instruction::DEC_C => self.dec_r8_msb(Register::BC),
-            Instruction::DEC_D => self.dec_r8_lsb(Register::DE),
-            Instruction::DEC_E => self.dec_r8_msb(Register::DE),
-            Instruction::DEC_H => self.dec_r8_lsb(Register::HL),
-            Instruction::DEC_L => self.dec_r8_msb(Register::HL),
-
-            Instruction::LD_BC_A => {
-                self.memory
-                    .write(self.registers.bc, self.registers.af.split().0);
-                self.clock.cycles += 2;
-            }
+            Instruction::INC_A => self.inc_r8_msb(Register::AF),
+            Instruction::INC_B => self.inc_r8_msb(Register::BC),
+            Instruction::INC_C => self.inc_r8_lsb(Register::BC),
+            Instruction::INC_D => self.inc_r8_msb(Register::DE),
+            Instruction::INC_E => self.inc_r8_lsb(Register::DE),
+            Instruction::INC_H => self.inc_r8_msb(Register::HL),
+            Instruction::INC_L => self.inc_r8_lsb(Register::HL),
+            Instruction::INC_HL_Indirect => self.inc_indirect(),
+            Instruction::DEC_A => self.dec_r8_msb(Register::AF),
+            Instruction::DEC_B => self.dec_r8_msb(Register::BC),
+            Instruction::DEC_C => self.dec_r8_lsb(Register::BC),
+            Instruction::DEC_D => self.dec_r8_msb(Register::DE),
+            Instruction::DEC_E => self.dec_r8_lsb(Register::DE),
+            Instruction::DEC_H => self.dec_r8_msb(Register::HL),
+            Instruction::DEC_L => self.dec_r8_lsb(Register::HL),
+            Instruction::DEC_HL_Indirect => self.dec_indirect(),
+            Instruction::ADD_A_A => self.add(Operand::A, false),
+            Instruction::ADD_A_B => self.add(Operand::B, false),
+            Instruction::ADD_A_C => self.add(Operand::C, false),
+            Instruction::ADD_A_D => self.add(Operand::D, false),
+            Instruction::ADD_A_E => self.add(Operand::E, false),
+            Instruction::ADD_A_H => self.add(Operand::H, false),
+            Instruction::ADD_A_L => self.add(Operand::L, false),
+            Instruction::ADD_A_HL => self.add(Operand::HL, false),
+            Instruction::ADD_A_u8 => self.add(Operand::u8, false),
+            Instruction::ADC_A_A => self.add(Operand::A, true),
+            Instruction::ADC_A_B => self.add(Operand::B, true),
+            Instruction::ADC_A_C => self.add(Operand::C, true),
+            Instruction::ADC_A_D => self.add(Operand::D, true),
+            Instruction::ADC_A_E => self.add(Operand::E, true),
+            Instruction::ADC_A_H => self.add(Operand::H, true),
+            Instruction::ADC_A_L => self.add(Operand::L, true),
+            Instruction::ADC_A_HL => self.add(Operand::HL, true),
+            Instruction::ADC_A_u8 => self.add(Operand::u8, true),
+            Instruction::SUB_A_A => self.sub(Operand::A, false, true),
+            Instruction::SUB_A_B => self.sub(Operand::B, false, true),
+            Instruction::SUB_A_C => self.sub(Operand::C, false, true),
+            Instruction::SUB_A_D => self.sub(Operand::D, false, true),
+            Instruction::SUB_A_E => self.sub(Operand::E, false, true),
+            Instruction::SUB_A_H => self.sub(Operand::H, false, true),
+            Instruction::SUB_A_L => self.sub(Operand::L, false, true),
+            Instruction::SUB_A_HL => self.sub(Operand::HL, false, true),
+            Instruction::SUB_A_u8 => self.sub(Operand::u8, false, true),
+            Instruction::SBC_A_A => self.sub(Operand::A, true, true),
+            Instruction::SBC_A_B => self.sub(Operand::B, true, true),
+            Instruction::SBC_A_C => self.sub(Operand::C, true, true),
+            Instruction::SBC_A_D => self.sub(Operand::D, true, true),
+            Instruction::SBC_A_E => self.sub(Operand::E, true, true),
+            Instruction::SBC_A_H => self.sub(Operand::H, true, true),
+            Instruction::SBC_A_L => self.sub(Operand::L, true, true),
+            Instruction::SBC_A_HL => self.sub(Operand::HL, true, true),
+            Instruction::SBC_A_u8 => self.sub(Operand::u8, true, true),
+            Instruction::AND_A_A => self.and(Operand::A),
+            Instruction::AND_A_B => self.and(Operand::B),
+            Instruction::AND_A_C => self.and(Operand::C),
+            Instruction::AND_A_D => self.and(Operand::D),
+            Instruction::AND_A_E => self.and(Operand::E),
+            Instruction::AND_A_H => self.and(Operand::H),
+            Instruction::AND_A_L => self.and(Operand::L),
+            Instruction::AND_A_HL => self.and(Operand::HL),
+            Instruction::AND_A_u8 => self.and(Operand::u8),
+            Instruction::XOR_A_A => self.xor(Operand::A),
+            Instruction::XOR_A_B => self.xor(Operand::B),
+            Instruction::XOR_A_C => self.xor(Operand::C),
+            Instruction::XOR_A_D => self.xor(Operand::D),
+            Instruction::XOR_A_E => self.xor(Operand::E),
+            Instruction::XOR_A_H => self.xor(Operand::H),
+            Instruction::XOR_A_L => self.xor(Operand::L),
+            Instruction::XOR_A_HL => self.xor(Operand::HL),
+            Instruction::XOR_A_u8 => self.xor(Operand::u8),
+            Instruction::OR_A_A => self.or(Operand::A),
+            Instruction::OR_A_B => self.or(Operand::B),
+            Instruction::OR_A_C => self.or(Operand::C),
+            Instruction::OR_A_D => self.or(Operand::D),
+            Instruction::OR_A_E => self.or(Operand::E),
+            Instruction::OR_A_H => self.or(Operand::H),
+            Instruction::OR_A_L => self.or(Operand::L),
+            Instruction::OR_A_HL => self.or(Operand::HL),
+            Instruction::OR_A_u8 => self.or(Operand::u8),
+            Instruction::CP_A_A => self.sub(Operand::A, false, false),
+            Instruction::CP_A_B => self.sub(Operand::B, false, false),
+            Instruction::CP_A_C => self.sub(Operand::C, false, false),
+            Instruction::CP_A_D => self.sub(Operand::D, false, false),
+            Instruction::CP_A_E => self.sub(Operand::E, false, false),
+            Instruction::CP_A_H => self.sub(Operand::H, false, false),
+            Instruction::CP_A_L => self.sub(Operand::L, false, false),
+            Instruction::CP_A_HL => self.sub(Operand::HL, false, false),
+            Instruction::CP_A_u8 => self.sub(Operand::u8, false, false),
+            Instruction::DAA => self.daa(),
+            Instruction::CPL => self.cpl(),
+            Instruction::SCF => self.set_carry(false),
+            Instruction::CCF => self.set_carry(true),
 
-            Instruction::LD_DE_A => {
-                self.memory
-                    .write(self.registers.de, self.registers.af.split().0);
-                self.clock.cycles += 2;
-            }
+            // ALU 16 bits
+            Instruction::INC_BC => self.inc_r16(Register::BC),
+            Instruction::INC_DE => self.inc_r16(Register::DE),
+            Instruction::INC_HL => self.inc_r16(Register::HL),
+            Instruction::INC_SP => self.inc_r16(Register::SP),
+            Instruction::DEC_BC => self.dec_r16(Register::BC),
+            Instruction::DEC_DE => self.dec_r16(Register::DE),
+            Instruction::DEC_HL => self.dec_r16(Register::HL),
+            Instruction::DEC_SP => self.dec_r16(Register::SP),
+            Instruction::ADD_HL_BC => self.add_hl(Register::BC),
+            Instruction::ADD_HL_DE => self.add_hl(Register::DE),
+            Instruction::ADD_HL_HL => self.add_hl(Register::HL),
+            Instruction::ADD_HL_SP => self.add_hl(Register::SP),
+            Instruction::ADD_SP_i8 => self.add_sp(),
 
-            Instruction::Invalid => {}
+            // Rotates, shifts and bit operations
+            Instruction::RLCA => self.shift_a(Shift::RLC),
+            Instruction::RRCA => self.shift_a(Shift::RRC),
+            Instruction::RLA => self.shift_a(Shift::RL),
+            Instruction::RRA => self.shift_a(Shift::RR),
+            Instruction::RLC_A => self.shift(Shift::RLC, Operand::A),
+            Instruction::RLC_B => self.shift(Shift::RLC, Operand::B),
+            Instruction::RLC_C => self.shift(Shift::RLC, Operand::C),
+            Instruction::RLC_D => self.shift(Shift::RLC, Operand::D),
+            Instruction::RLC_E => self.shift(Shift::RLC, Operand::E),
+            Instruction::RLC_H => self.shift(Shift::RLC, Operand::H),
+            Instruction::RLC_L => self.shift(Shift::RLC, Operand::L),
+            Instruction::RLC_HL => self.shift(Shift::RLC, Operand::HL),
+            Instruction::RRC_A => self.shift(Shift::RRC, Operand::A),
+            Instruction::RRC_B => self.shift(Shift::RRC, Operand::B),
+            Instruction::RRC_C => self.shift(Shift::RRC, Operand::C),
+            Instruction::RRC_D => self.shift(Shift::RRC, Operand::D),
+            Instruction::RRC_E => self.shift(Shift::RRC, Operand::E),
+            Instruction::RRC_H => self.shift(Shift::RRC, Operand::H),
+            Instruction::RRC_L => self.shift(Shift::RRC, Operand::L),
+            Instruction::RRC_HL => self.shift(Shift::RRC, Operand::HL),
+            Instruction::RL_A => self.shift(Shift::RL, Operand::A),
+            Instruction::RL_B => self.shift(Shift::RL, Operand::B),
+            Instruction::RL_C => self.shift(Shift::RL, Operand::C),
+            Instruction::RL_D => self.shift(Shift::RL, Operand::D),
+            Instruction::RL_E => self.shift(Shift::RL, Operand::E),
+            Instruction::RL_H => self.shift(Shift::RL, Operand::H),
+            Instruction::RL_L => self.shift(Shift::RL, Operand::L),
+            Instruction::RL_HL => self.shift(Shift::RL, Operand::HL),
+            Instruction::RR_A => self.shift(Shift::RR, Operand::A),
+            Instruction::RR_B => self.shift(Shift::RR, Operand::B),
+            Instruction::RR_C => self.shift(Shift::RR, Operand::C),
+            Instruction::RR_D => self.shift(Shift::RR, Operand::D),
+            Instruction::RR_E => self.shift(Shift::RR, Operand::E),
+            Instruction::RR_H => self.shift(Shift::RR, Operand::H),
+            Instruction::RR_L => self.shift(Shift::RR, Operand::L),
+            Instruction::RR_HL => self.shift(Shift::RR, Operand::HL),
+            Instruction::SLA_A => self.shift(Shift::SLA, Operand::A),
+            Instruction::SLA_B => self.shift(Shift::SLA, Operand::B),
+            Instruction::SLA_C => self.shift(Shift::SLA, Operand::C),
+            Instruction::SLA_D => self.shift(Shift::SLA, Operand::D),
+            Instruction::SLA_E => self.shift(Shift::SLA, Operand::E),
+            Instruction::SLA_H => self.shift(Shift::SLA, Operand::H),
+            Instruction::SLA_L => self.shift(Shift::SLA, Operand::L),
+            Instruction::SLA_HL => self.shift(Shift::SLA, Operand::HL),
+            Instruction::SRA_A => self.shift(Shift::SRA, Operand::A),
+            Instruction::SRA_B => self.shift(Shift::SRA, Operand::B),
+            Instruction::SRA_C => self.shift(Shift::SRA, Operand::C),
+            Instruction::SRA_D => self.shift(Shift::SRA, Operand::D),
+            Instruction::SRA_E => self.shift(Shift::SRA, Operand::E),
+            Instruction::SRA_H => self.shift(Shift::SRA, Operand::H),
+            Instruction::SRA_L => self.shift(Shift::SRA, Operand::L),
+            Instruction::SRA_HL => self.shift(Shift::SRA, Operand::HL),
+            Instruction::SWAP_A => self.shift(Shift::SWAP, Operand::A),
+            Instruction::SWAP_B => self.shift(Shift::SWAP, Operand::B),
+            Instruction::SWAP_C => self.shift(Shift::SWAP, Operand::C),
+            Instruction::SWAP_D => self.shift(Shift::SWAP, Operand::D),
+            Instruction::SWAP_E => self.shift(Shift::SWAP, Operand::E),
+            Instruction::SWAP_H => self.shift(Shift::SWAP, Operand::H),
+            Instruction::SWAP_L => self.shift(Shift::SWAP, Operand::L),
+            Instruction::SWAP_HL => self.shift(Shift::SWAP, Operand::HL),
+            Instruction::SRL_A => self.shift(Shift::SRL, Operand::A),
+            Instruction::SRL_B => self.shift(Shift::SRL, Operand::B),
+            Instruction::SRL_C => self.shift(Shift::SRL, Operand::C),
+            Instruction::SRL_D => self.shift(Shift::SRL, Operand::D),
+            Instruction::SRL_E => self.shift(Shift::SRL, Operand::E),
+            Instruction::SRL_H => self.shift(Shift::SRL, Operand::H),
+            Instruction::SRL_L => self.shift(Shift::SRL, Operand::L),
+            Instruction::SRL_HL => self.shift(Shift::SRL, Operand::HL),
+            Instruction::BIT_0_A => self.bit(0, Operand::A),
+            Instruction::BIT_0_B => self.bit(0, Operand::B),
+            Instruction::BIT_0_C => self.bit(0, Operand::C),
+            Instruction::BIT_0_D => self.bit(0, Operand::D),
+            Instruction::BIT_0_E => self.bit(0, Operand::E),
+            Instruction::BIT_0_H => self.bit(0, Operand::H),
+            Instruction::BIT_0_L => self.bit(0, Operand::L),
+            Instruction::BIT_0_HL => self.bit(0, Operand::HL),
+            Instruction::BIT_1_A => self.bit(1, Operand::A),
+            Instruction::BIT_1_B => self.bit(1, Operand::B),
+            Instruction::BIT_1_C => self.bit(1, Operand::C),
+            Instruction::BIT_1_D => self.bit(1, Operand::D),
+            Instruction::BIT_1_E => self.bit(1, Operand::E),
+            Instruction::BIT_1_H => self.bit(1, Operand::H),
+            Instruction::BIT_1_L => self.bit(1, Operand::L),
+            Instruction::BIT_1_HL => self.bit(1, Operand::HL),
+            Instruction::BIT_2_A => self.bit(2, Operand::A),
+            Instruction::BIT_2_B => self.bit(2, Operand::B),
+            Instruction::BIT_2_C => self.bit(2, Operand::C),
+            Instruction::BIT_2_D => self.bit(2, Operand::D),
+            Instruction::BIT_2_E => self.bit(2, Operand::E),
+            Instruction::BIT_2_H => self.bit(2, Operand::H),
+            Instruction::BIT_2_L => self.bit(2, Operand::L),
+            Instruction::BIT_2_HL => self.bit(2, Operand::HL),
+            Instruction::BIT_3_A => self.bit(3, Operand::A),
+            Instruction::BIT_3_B => self.bit(3, Operand::B),
+            Instruction::BIT_3_C => self.bit(3, Operand::C),
+            Instruction::BIT_3_D => self.bit(3, Operand::D),
+            Instruction::BIT_3_E => self.bit(3, Operand::E),
+            Instruction::BIT_3_H => self.bit(3, Operand::H),
+            Instruction::BIT_3_L => self.bit(3, Operand::L),
+            Instruction::BIT_3_HL => self.bit(3, Operand::HL),
+            Instruction::BIT_4_A => self.bit(4, Operand::A),
+            Instruction::BIT_4_B => self.bit(4, Operand::B),
+            Instruction::BIT_4_C => self.bit(4, Operand::C),
+            Instruction::BIT_4_D => self.bit(4, Operand::D),
+            Instruction::BIT_4_E => self.bit(4, Operand::E),
+            Instruction::BIT_4_H => self.bit(4, Operand::H),
+            Instruction::BIT_4_L => self.bit(4, Operand::L),
+            Instruction::BIT_4_HL => self.bit(4, Operand::HL),
+            Instruction::BIT_5_A => self.bit(5, Operand::A),
+            Instruction::BIT_5_B => self.bit(5, Operand::B),
+            Instruction::BIT_5_C => self.bit(5, Operand::C),
+            Instruction::BIT_5_D => self.bit(5, Operand::D),
+            Instruction::BIT_5_E => self.bit(5, Operand::E),
+            Instruction::BIT_5_H => self.bit(5, Operand::H),
+            Instruction::BIT_5_L => self.bit(5, Operand::L),
+            Instruction::BIT_5_HL => self.bit(5, Operand::HL),
+            Instruction::BIT_6_A => self.bit(6, Operand::A),
+            Instruction::BIT_6_B => self.bit(6, Operand::B),
+            Instruction::BIT_6_C => self.bit(6, Operand::C),
+            Instruction::BIT_6_D => self.bit(6, Operand::D),
+            Instruction::BIT_6_E => self.bit(6, Operand::E),
+            Instruction::BIT_6_H => self.bit(6, Operand::H),
+            Instruction::BIT_6_L => self.bit(6, Operand::L),
+            Instruction::BIT_6_HL => self.bit(6, Operand::HL),
+            Instruction::BIT_7_A => self.bit(7, Operand::A),
+            Instruction::BIT_7_B => self.bit(7, Operand::B),
+            Instruction::BIT_7_C => self.bit(7, Operand::C),
+            Instruction::BIT_7_D => self.bit(7, Operand::D),
+            Instruction::BIT_7_E => self.bit(7, Operand::E),
+            Instruction::BIT_7_H => self.bit(7, Operand::H),
+            Instruction::BIT_7_L => self.bit(7, Operand::L),
+            Instruction::BIT_7_HL => self.bit(7, Operand::HL),
+            Instruction::RES_0_A => self.set_bit(0, Operand::A, false),
+            Instruction::RES_0_B => self.set_bit(0, Operand::B, false),
+            Instruction::RES_0_C => self.set_bit(0, Operand::C, false),
+            Instruction::RES_0_D => self.set_bit(0, Operand::D, false),
+            Instruction::RES_0_E => self.set_bit(0, Operand::E, false),
+            Instruction::RES_0_H => self.set_bit(0, Operand::H, false),
+            Instruction::RES_0_L => self.set_bit(0, Operand::L, false),
+            Instruction::RES_0_HL => self.set_bit(0, Operand::HL, false),
+            Instruction::RES_1_A => self.set_bit(1, Operand::A, false),
+            Instruction::RES_1_B => self.set_bit(1, Operand::B, false),
+            Instruction::RES_1_C => self.set_bit(1, Operand::C, false),
+            Instruction::RES_1_D => self.set_bit(1, Operand::D, false),
+            Instruction::RES_1_E => self.set_bit(1, Operand::E, false),
+            Instruction::RES_1_H => self.set_bit(1, Operand::H, false),
+            Instruction::RES_1_L => self.set_bit(1, Operand::L, false),
+            Instruction::RES_1_HL => self.set_bit(1, Operand::HL, false),
+            Instruction::RES_2_A => self.set_bit(2, Operand::A, false),
+            Instruction::RES_2_B => self.set_bit(2, Operand::B, false),
+            Instruction::RES_2_C => self.set_bit(2, Operand::C, false),
+            Instruction::RES_2_D => self.set_bit(2, Operand::D, false),
+            Instruction::RES_2_E => self.set_bit(2, Operand::E, false),
+            Instruction::RES_2_H => self.set_bit(2, Operand::H, false),
+            Instruction::RES_2_L => self.set_bit(2, Operand::L, false),
+            Instruction::RES_2_HL => self.set_bit(2, Operand::HL, false),
+            Instruction::RES_3_A => self.set_bit(3, Operand::A, false),
+            Instruction::RES_3_B => self.set_bit(3, Operand::B, false),
+            Instruction::RES_3_C => self.set_bit(3, Operand::C, false),
+            Instruction::RES_3_D => self.set_bit(3, Operand::D, false),
+            Instruction::RES_3_E => self.set_bit(3, Operand::E, false),
+            Instruction::RES_3_H => self.set_bit(3, Operand::H, false),
+            Instruction::RES_3_L => self.set_bit(3, Operand::L, false),
+            Instruction::RES_3_HL => self.set_bit(3, Operand::HL, false),
+            Instruction::RES_4_A => self.set_bit(4, Operand::A, false),
+            Instruction::RES_4_B => self.set_bit(4, Operand::B, false),
+            Instruction::RES_4_C => self.set_bit(4, Operand::C, false),
+            Instruction::RES_4_D => self.set_bit(4, Operand::D, false),
+            Instruction::RES_4_E => self.set_bit(4, Operand::E, false),
+            Instruction::RES_4_H => self.set_bit(4, Operand::H, false),
+            Instruction::RES_4_L => self.set_bit(4, Operand::L, false),
+            Instruction::RES_4_HL => self.set_bit(4, Operand::HL, false),
+            Instruction::RES_5_A => self.set_bit(5, Operand::A, false),
+            Instruction::RES_5_B => self.set_bit(5, Operand::B, false),
+            Instruction::RES_5_C => self.set_bit(5, Operand::C, false),
+            Instruction::RES_5_D => self.set_bit(5, Operand::D, false),
+            Instruction::RES_5_E => self.set_bit(5, Operand::E, false),
+            Instruction::RES_5_H => self.set_bit(5, Operand::H, false),
+            Instruction::RES_5_L => self.set_bit(5, Operand::L, false),
+            Instruction::RES_5_HL => self.set_bit(5, Operand::HL, false),
+            Instruction::RES_6_A => self.set_bit(6, Operand::A, false),
+            Instruction::RES_6_B => self.set_bit(6, Operand::B, false),
+            Instruction::RES_6_C => self.set_bit(6, Operand::C, false),
+            Instruction::RES_6_D => self.set_bit(6, Operand::D, false),
+            Instruction::RES_6_E => self.set_bit(6, Operand::E, false),
+            Instruction::RES_6_H => self.set_bit(6, Operand::H, false),
+            Instruction::RES_6_L => self.set_bit(6, Operand::L, false),
+            Instruction::RES_6_HL => self.set_bit(6, Operand::HL, false),
+            Instruction::RES_7_A => self.set_bit(7, Operand::A, false),
+            Instruction::RES_7_B => self.set_bit(7, Operand::B, false),
+            Instruction::RES_7_C => self.set_bit(7, Operand::C, false),
+            Instruction::RES_7_D => self.set_bit(7, Operand::D, false),
+            Instruction::RES_7_E => self.set_bit(7, Operand::E, false),
+            Instruction::RES_7_H => self.set_bit(7, Operand::H, false),
+            Instruction::RES_7_L => self.set_bit(7, Operand::L, false),
+            Instruction::RES_7_HL => self.set_bit(7, Operand::HL, false),
+            Instruction::SET_0_A => self.set_bit(0, Operand::A, true),
+            Instruction::SET_0_B => self.set_bit(0, Operand::B, true),
+            Instruction::SET_0_C => self.set_bit(0, Operand::C, true),
+            Instruction::SET_0_D => self.set_bit(0, Operand::D, true),
+            Instruction::SET_0_E => self.set_bit(0, Operand::E, true),
+            Instruction::SET_0_H => self.set_bit(0, Operand::H, true),
+            Instruction::SET_0_L => self.set_bit(0, Operand::L, true),
+            Instruction::SET_0_HL => self.set_bit(0, Operand::HL, true),
+            Instruction::SET_1_A => self.set_bit(1, Operand::A, true),
+            Instruction::SET_1_B => self.set_bit(1, Operand::B, true),
+            Instruction::SET_1_C => self.set_bit(1, Operand::C, true),
+            Instruction::SET_1_D => self.set_bit(1, Operand::D, true),
+            Instruction::SET_1_E => self.set_bit(1, Operand::E, true),
+            Instruction::SET_1_H => self.set_bit(1, Operand::H, true),
+            Instruction::SET_1_L => self.set_bit(1, Operand::L, true),
+            Instruction::SET_1_HL => self.set_bit(1, Operand::HL, true),
+            Instruction::SET_2_A => self.set_bit(2, Operand::A, true),
+            Instruction::SET_2_B => self.set_bit(2, Operand::B, true),
+            Instruction::SET_2_C => self.set_bit(2, Operand::C, true),
+            Instruction::SET_2_D => self.set_bit(2, Operand::D, true),
+            Instruction::SET_2_E => self.set_bit(2, Operand::E, true),
+            Instruction::SET_2_H => self.set_bit(2, Operand::H, true),
+            Instruction::SET_2_L => self.set_bit(2, Operand::L, true),
+            Instruction::SET_2_HL => self.set_bit(2, Operand::HL, true),
+            Instruction::SET_3_A => self.set_bit(3, Operand::A, true),
+            Instruction::SET_3_B => self.set_bit(3, Operand::B, true),
+            Instruction::SET_3_C => self.set_bit(3, Operand::C, true),
+            Instruction::SET_3_D => self.set_bit(3, Operand::D, true),
+            Instruction::SET_3_E => self.set_bit(3, Operand::E, true),
+            Instruction::SET_3_H => self.set_bit(3, Operand::H, true),
+            Instruction::SET_3_L => self.set_bit(3, Operand::L, true),
+            Instruction::SET_3_HL => self.set_bit(3, Operand::HL, true),
+            Instruction::SET_4_A => self.set_bit(4, Operand::A, true),
+            Instruction::SET_4_B => self.set_bit(4, Operand::B, true),
+            Instruction::SET_4_C => self.set_bit(4, Operand::C, true),
+            Instruction::SET_4_D => self.set_bit(4, Operand::D, true),
+            Instruction::SET_4_E => self.set_bit(4, Operand::E, true),
+            Instruction::SET_4_H => self.set_bit(4, Operand::H, true),
+            Instruction::SET_4_L => self.set_bit(4, Operand::L, true),
+            Instruction::SET_4_HL => self.set_bit(4, Operand::HL, true),
+            Instruction::SET_5_A => self.set_bit(5, Operand::A, true),
+            Instruction::SET_5_B => self.set_bit(5, Operand::B, true),
+            Instruction::SET_5_C => self.set_bit(5, Operand::C, true),
+            Instruction::SET_5_D => self.set_bit(5, Operand::D, true),
+            Instruction::SET_5_E => self.set_bit(5, Operand::E, true),
+            Instruction::SET_5_H => self.set_bit(5, Operand::H, true),
+            Instruction::SET_5_L => self.set_bit(5, Operand::L, true),
+            Instruction::SET_5_HL => self.set_bit(5, Operand::HL, true),
+            Instruction::SET_6_A => self.set_bit(6, Operand::A, true),
+            Instruction::SET_6_B => self.set_bit(6, Operand::B, true),
+            Instruction::SET_6_C => self.set_bit(6, Operand::C, true),
+            Instruction::SET_6_D => self.set_bit(6, Operand::D, true),
+            Instruction::SET_6_E => self.set_bit(6, Operand::E, true),
+            Instruction::SET_6_H => self.set_bit(6, Operand::H, true),
+            Instruction::SET_6_L => self.set_bit(6, Operand::L, true),
+            Instruction::SET_6_HL => self.set_bit(6, Operand::HL, true),
+            Instruction::SET_7_A => self.set_bit(7, Operand::A, true),
+            Instruction::SET_7_B => self.set_bit(7, Operand::B, true),
+            Instruction::SET_7_C => self.set_bit(7, Operand::C, true),
+            Instruction::SET_7_D => self.set_bit(7, Operand::D, true),
+            Instruction::SET_7_E => self.set_bit(7, Operand::E, true),
+            Instruction::SET_7_H => self.set_bit(7, Operand::H, true),
+            Instruction::SET_7_L => self.set_bit(7, Operand::L, true),
+            Instruction::SET_7_HL => self.set_bit(7, Operand::HL, true),
+
+            // Control flow
+            Instruction::JR_i8 => self.jr(Condition::Always),
+            Instruction::JR_NZ_i8 => self.jr(Condition::NZ),
+            Instruction::JR_Z_i8 => self.jr(Condition::Z),
+            Instruction::JR_NC_i8 => self.jr(Condition::NC),
+            Instruction::JR_C_i8 => self.jr(Condition::C),
+            Instruction::JP_u16 => self.jp(Condition::Always),
+            Instruction::JP_NZ_u16 => self.jp(Condition::NZ),
+            Instruction::JP_Z_u16 => self.jp(Condition::Z),
+            Instruction::JP_NC_u16 => self.jp(Condition::NC),
+            Instruction::JP_C_u16 => self.jp(Condition::C),
+            Instruction::JP_HL => self.jp_hl(),
+            Instruction::CALL_u16 => self.call(Condition::Always),
+            Instruction::CALL_NZ_u16 => self.call(Condition::NZ),
+            Instruction::CALL_Z_u16 => self.call(Condition::Z),
+            Instruction::CALL_NC_u16 => self.call(Condition::NC),
+            Instruction::CALL_C_u16 => self.call(Condition::C),
+            Instruction::RET => self.ret(Condition::Always),
+            Instruction::RET_NZ => self.ret(Condition::NZ),
+            Instruction::RET_Z => self.ret(Condition::Z),
+            Instruction::RET_NC => self.ret(Condition::NC),
+            Instruction::RET_C => self.ret(Condition::C),
+            Instruction::RETI => self.reti(),
+            Instruction::RST_00h => self.rst(0x0000),
+            Instruction::RST_08h => self.rst(0x0008),
+            Instruction::RST_10h => self.rst(0x0010),
+            Instruction::RST_18h => self.rst(0x0018),
+            Instruction::RST_20h => self.rst(0x0020),
+            Instruction::RST_28h => self.rst(0x0028),
+            Instruction::RST_30h => self.rst(0x0030),
+            Instruction::RST_38h => self.rst(0x0038),
 
-            _ => {}
+            Instruction::Invalid => {}
         }
     }
 }
 
+snapshot!(Registers {
+    af,
+    bc,
+    de,
+    hl,
+    sp,
+    pc
+});
+snapshot!(Clock {
+    cycles,
+    clock_speed
+});
+
+/// The CPU on its own, the bus is saved separately
+impl<B: Bus> Snapshot for CPU<B> {
+    fn save(&self, writer: &mut Writer) {
+        self.registers.save(writer);
+        self.clock.save(writer);
+        self.stopped.save(writer);
+        self.ime.save(writer);
+        self.ime_scheduled.save(writer);
+        self.halted.save(writer);
+        self.halt_bug.save(writer);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> Result<(), String> {
+        self.registers.load(reader)?;
+        self.clock.load(reader)?;
+        self.stopped.load(reader)?;
+        self.ime.load(reader)?;
+        self.ime_scheduled.load(reader)?;
+        self.halted.load(reader)?;
+        self.halt_bug.load(reader)
+    }
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
 
     fn new_test_cpu() -> CPU {
         CPU {
-            memory: Memory::empty(),
+            bus: Memory::empty(),
             clock: Clock {
                 cycles: 0,
                 clock_speed: 0,
             },
+            stopped: false,
+            ime: false,
+            ime_scheduled: false,
+            halted: false,
+            halt_bug: false,
+            trace: None,
             registers: Registers {
                 af: 0x0000,
                 bc: 0x0000,
@@ -315,4 +1530,275 @@ mod tests {
         assert_eq!(*cpu.registers.from_enum(Register::SP), 0x0005);
         assert_eq!(*cpu.registers.from_enum(Register::PC), 0x0006);
     }
+
+    #[test]
+    fn test_speed_switch() {
+        let mut program = [0; _32KB];
+        program[0x0000] = 0x10;
+        program[0x0002] = 0x10;
+        program[0x0143] = 0x80;
+        let mut cpu = new_test_cpu();
+        cpu.bus = Memory::new(program);
+
+        cpu.bus.write(0xFF4D, 0x01);
+        cpu.step();
+        assert!(!cpu.stopped);
+        assert_eq!(cpu.registers.pc, 0x0002);
+        assert_eq!(cpu.bus.read(0xFF4D), 0xFE);
+        assert_eq!(cpu.clock_speed(), CLOCK);
+        assert_eq!(cpu.clock.cycles, SPEED_SWITCH_CYCLES as u64);
+
+        // Without KEY1 armed STOP waits for a selected button to be pressed
+        cpu.step();
+        assert!(cpu.stopped);
+        assert_eq!(cpu.bus.read(0xFF4D), 0xFE);
+        cpu.bus.write(0xFF00, 0x20);
+        cpu.bus.set_button(crate::memory::joypad::Button::A, true);
+        cpu.step();
+        assert!(cpu.stopped);
+        assert_eq!(cpu.registers.pc, 0x0004);
+        cpu.bus
+            .set_button(crate::memory::joypad::Button::Down, true);
+        cpu.step();
+        assert!(!cpu.stopped);
+        assert_eq!(cpu.registers.pc, 0x0005);
+    }
+
+    /// CPU about to run `code` at 0x0100, shared with the tests of other
+    /// modules
+    pub(crate) fn cpu_with_program(code: &[u8]) -> CPU {
+        let mut program = [0; _32KB];
+        program[0x0100..0x0100 + code.len()].copy_from_slice(code);
+        let mut cpu = CPU::new(Memory::new(program));
+        cpu.registers.sp = 0xFFFE;
+        cpu
+    }
+
+    #[test]
+    fn test_post_boot_registers() {
+        let cpu = CPU::new(Memory::empty());
+        assert_eq!(cpu.registers.af, 0x0180);
+        assert_eq!(cpu.registers.pc, 0x0100);
+
+        let mut program = [0; _32KB];
+        program[0x014D] = 0xE7;
+        let cpu = CPU::new(Memory::with_model(program, Model::MGB));
+        assert_eq!(cpu.registers.af, 0xFFB0);
+        let cpu = CPU::new(Memory::with_model(program, Model::SGB2));
+        assert_eq!(cpu.registers.hl, 0xC060);
+        let cpu = CPU::new(Memory::with_model(program, Model::CGB));
+        assert_eq!(cpu.registers.de, 0x0008);
+
+        program[0x0143] = 0x80;
+        let cpu = CPU::new(Memory::new(program));
+        assert_eq!(cpu.registers.af, 0x1180);
+        assert_eq!(cpu.registers.de, 0xFF56);
+        let cpu = CPU::new(Memory::with_model(program, Model::AGB));
+        assert_eq!(cpu.registers.bc, 0x0100);
+        let cpu = CPU::new(Memory::with_model(program, Model::DMG0));
+        assert_eq!(cpu.registers.bc, 0xFF13);
+    }
+
+    #[test]
+    fn test_boot_rom() {
+        let mut program = [0; _32KB];
+        program[0x0000] = 0xAA;
+        let mut boot_rom = vec![0; 0x100];
+        // LD A,0x01; LDH (0x50),A
+        boot_rom[0xFC..].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+        let mut memory = Memory::new(program);
+        memory.load_boot_rom(boot_rom).unwrap();
+        assert!(memory.load_boot_rom(vec![0; 0x200]).is_err());
+        let mut cpu = CPU::new(memory);
+        assert_eq!(cpu.registers.pc, 0x0000);
+        assert_eq!(cpu.bus.read(0x0000), 0x00);
+
+        while cpu.registers.pc < 0x0100 {
+            cpu.step();
+        }
+        assert_eq!(cpu.bus.read(0x0000), 0xAA);
+        assert_eq!(cpu.bus.read(0xFF50), 0xFF);
+    }
+
+    #[test]
+    fn test_byte_registers() {
+        // LD B,0x12; LD C,0x34; LD A,B; LD H,C; LD L,A
+        let mut cpu = cpu_with_program(&[0x06, 0x12, 0x0E, 0x34, 0x78, 0x61, 0x6F]);
+        for _ in 0..5 {
+            cpu.step();
+        }
+        assert_eq!(cpu.registers.bc, 0x1234);
+        assert_eq!(cpu.registers.af >> 8, 0x12);
+        assert_eq!(cpu.registers.hl, 0x3412);
+        assert_eq!(cpu.clock.cycles, 7);
+    }
+
+    #[test]
+    fn test_alu_flags() {
+        // LD A,0x3A; ADD A,0xC6; SUB A,0x01; CP A,0xFF
+        let mut cpu = cpu_with_program(&[0x3E, 0x3A, 0xC6, 0xC6, 0xD6, 0x01, 0xFE, 0xFF]);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.registers.af, 0x00B0);
+        cpu.step();
+        assert_eq!(cpu.registers.af, 0xFF70);
+        cpu.step();
+        assert_eq!(cpu.registers.af, 0xFFC0);
+    }
+
+    #[test]
+    fn test_stack_and_calls() {
+        // CALL 0x0110; LD B,0x01; ... 0x0110: PUSH BC; POP DE; RET
+        let mut code = vec![0xCD, 0x10, 0x01, 0x06, 0x01];
+        code.resize(0x10, 0x00);
+        code.extend_from_slice(&[0xC5, 0xD1, 0xC9]);
+        let mut cpu = cpu_with_program(&code);
+        cpu.registers.bc = 0xBEEF;
+        cpu.step();
+        assert_eq!(cpu.registers.pc, 0x0110);
+        assert_eq!(cpu.registers.sp, 0xFFFC);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.registers.de, 0xBEEF);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.registers.pc, 0x0105);
+        assert_eq!(cpu.registers.bc, 0x01EF);
+        assert_eq!(cpu.clock.cycles, 6 + 4 + 3 + 4 + 2);
+    }
+
+    #[test]
+    fn test_cb_instructions() {
+        // SWAP A; BIT 7,A; SET 0,(HL); SRL (HL)
+        let mut cpu = cpu_with_program(&[0xCB, 0x37, 0xCB, 0x7F, 0xCB, 0xC6, 0xCB, 0x3E]);
+        cpu.registers.af = 0x1E00;
+        cpu.registers.hl = 0xC000;
+        cpu.step();
+        assert_eq!(cpu.registers.af, 0xE100);
+        cpu.step();
+        assert_eq!(cpu.registers.af, 0xE120);
+        cpu.step();
+        assert_eq!(cpu.bus.read(0xC000), 0x01);
+        cpu.step();
+        assert_eq!(cpu.bus.read(0xC000), 0x00);
+        assert_eq!(cpu.registers.af, 0xE190);
+        assert_eq!(cpu.clock.cycles, 2 + 2 + 4 + 4);
+    }
+
+    #[test]
+    fn test_interrupts() {
+        // EI; NOP; HALT
+        let mut cpu = cpu_with_program(&[0xFB, 0x00, 0x76, 0x00]);
+        cpu.bus.write(0xFFFF, 0x04);
+        cpu.bus.write(0xFF0F, 0x04);
+        cpu.step();
+        // IME is only set after the instruction following EI
+        cpu.step();
+        assert_eq!(cpu.registers.pc, 0x0102);
+        cpu.step();
+        assert_eq!(cpu.registers.pc, 0x0050);
+        assert!(!cpu.ime);
+        assert_eq!(cpu.bus.read(0xFF0F), 0xE0);
+        assert_eq!(cpu.bus.read(0xFFFC), 0x02);
+    }
+
+    /// Runs every opcode with the flags all clear then all set, checking its
+    /// metadata against the PC, M-cycles, bus accesses and flags that result
+    /// M-cycles an instruction spends off the bus, as documented rather than
+    /// as the CPU ticks them. Every other M-cycle is a read or a write.
+    fn internal_cycles(template: &str, taken: bool) -> usize {
+        let register_pair = |operand: &str| ["bc", "de", "hl", "sp"].contains(&operand);
+        match template {
+            "ld sp, hl" | "ld hl, sp{s8}" | "ret" | "reti" => 1,
+            "add sp, {i8}" => 2,
+            "jp hl" => 0,
+            _ if template.starts_with("push")
+                || template.starts_with("rst")
+                || template.starts_with("add hl") =>
+            {
+                1
+            }
+            _ if template.starts_with("inc ") || template.starts_with("dec ") => {
+                register_pair(&template[4..]) as usize
+            }
+            _ if template.starts_with("ret ") => 1 + taken as usize,
+            _ if ["jr", "jp", "call"]
+                .iter()
+                .any(|jump| template.starts_with(jump)) =>
+            {
+                taken as usize
+            }
+            _ => 0,
+        }
+    }
+
+    #[test]
+    fn test_metadata_matches_execution() {
+        use crate::bus::FlatBus;
+        use crate::instructions::{FlagEffect, CB_OPCODES, OPCODES};
+
+        for &prefixed in &[false, true] {
+            for opcode in 0..=0xFF {
+                let (instruction, metadata) = if prefixed {
+                    (Instruction::decode_cb(opcode), &CB_OPCODES[opcode as usize])
+                } else {
+                    (Instruction::decode(opcode), &OPCODES[opcode as usize])
+                };
+                // STOP reads its padding byte within a single M-cycle, the
+                // console halting right after
+                if matches!(
+                    instruction,
+                    Instruction::Invalid | Instruction::CB | Instruction::STOP
+                ) {
+                    continue;
+                }
+                for &flags in &[0x00u8, 0xF0] {
+                    let code: &[u8] = if prefixed {
+                        &[0xCB, opcode]
+                    } else {
+                        &[opcode, 0x10, 0x20]
+                    };
+                    let mut cpu = CPU::with_bus(FlatBus::with_code(0xC000, code));
+                    cpu.registers = Registers {
+                        af: 0x1200 | flags as u16,
+                        bc: 0x3456,
+                        de: 0x789A,
+                        hl: 0xD000,
+                        sp: 0xE000,
+                        pc: 0xC000,
+                    };
+                    cpu.step();
+
+                    let name = format!("{:?} with F={:02X}", instruction, flags);
+                    let advanced = cpu.registers.pc == 0xC000 + metadata.length as u16;
+                    let branches = metadata.cycles_taken.is_some()
+                        || ["jp", "jr", "call", "ret", "rst"]
+                            .iter()
+                            .any(|jump| instruction.template().starts_with(jump));
+                    assert!(advanced || branches, "{}: length", name);
+                    let cycles = if advanced {
+                        metadata.cycles
+                    } else {
+                        metadata.cycles_taken.unwrap_or(metadata.cycles)
+                    };
+                    assert_eq!(cpu.bus.cycles(), cycles as u64, "{}: cycles", name);
+                    let accesses = cpu.bus.take_accesses().len();
+                    let internal = internal_cycles(instruction.template(), !advanced);
+                    assert_eq!(accesses + internal, cycles as usize, "{}: accesses", name);
+                    for (i, effect) in metadata.flags.iter().enumerate() {
+                        let mask = FLAG_Z >> i;
+                        let before = flags & mask != 0;
+                        let after = cpu.registers.flag(mask);
+                        let expected = match effect {
+                            FlagEffect::Unaffected => before,
+                            FlagEffect::Reset => false,
+                            FlagEffect::Set => true,
+                            FlagEffect::Changed => after,
+                        };
+                        assert_eq!(after, expected, "{}: flag {:02X}", name, mask);
+                    }
+                }
+            }
+        }
+    }
 }
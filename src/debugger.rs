@@ -0,0 +1,1003 @@
+use crate::bus::{Access, Bus};
+use crate::cpu::{Registers, CPU};
+use crate::disassembler::{self, Disassembly};
+use crate::instructions::{Instruction, OPCODES};
+use crate::symbols::Symbols;
+use std::cell::RefCell;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "Commands:
+  s, step [N]             Run N instructions, 1 by default
+  n, next                 Run an instruction, stepping over calls
+  c, continue             Run until a breakpoint or watchpoint is hit
+  finish                  Run until the current function returns
+  b, break ADDR [if COND] Stop before running ADDR, when COND holds if given
+  watch ADDR              Stop after ADDR is written
+  rwatch ADDR             Stop after ADDR is read
+  awatch ADDR             Stop after ADDR is read or written
+  d, delete N             Delete breakpoint or watchpoint N
+  i, info                 List breakpoints and watchpoints
+  r, regs                 Show the registers
+  x ADDR [N]              Show N bytes of memory from ADDR, 16 by default
+  set REG=VALUE           Edit a register, e.g. set a=0x10
+  set [ADDR]=VALUE        Edit a byte of memory, e.g. set [0xC000]=0
+  l, list [ADDR] [N]      Disassemble N instructions from ADDR, around PC by default
+  bt, backtrace           Show the call stack
+  q, quit                 Stop debugging
+
+Numbers are decimal, or hex with a 0x or $ prefix. With symbols loaded,
+addresses can also be labels, e.g. Main.loop or wCounter+2. Conditions compare
+registers, numbers and bytes of memory, e.g. A==0x10, [HL]!=0 or
+BC>=0x100 && [0xC000]==1. An empty line repeats the last command.";
+
+/// Instructions shown before PC when disassembling around it
+const LIST_BEFORE: usize = 3;
+/// Instructions listed by default, PC included
+const LIST_LENGTH: usize = 10;
+/// Bytes dumped by default
+const DUMP_LENGTH: u16 = 16;
+
+/// Registers as named in commands
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Register {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+}
+
+impl Register {
+    fn parse(name: &str) -> Option<Register> {
+        let register = match name.to_ascii_uppercase().as_str() {
+            "A" => Register::A,
+            "F" => Register::F,
+            "B" => Register::B,
+            "C" => Register::C,
+            "D" => Register::D,
+            "E" => Register::E,
+            "H" => Register::H,
+            "L" => Register::L,
+            "AF" => Register::AF,
+            "BC" => Register::BC,
+            "DE" => Register::DE,
+            "HL" => Register::HL,
+            "SP" => Register::SP,
+            "PC" => Register::PC,
+            _ => return None,
+        };
+        Some(register)
+    }
+
+    /// The 16 bits register holding it, and whether it is the high byte for
+    /// 8 bits registers
+    fn location(self, registers: &mut Registers) -> (&mut u16, Option<bool>) {
+        match self {
+            Register::A => (&mut registers.af, Some(true)),
+            Register::F => (&mut registers.af, Some(false)),
+            Register::B => (&mut registers.bc, Some(true)),
+            Register::C => (&mut registers.bc, Some(false)),
+            Register::D => (&mut registers.de, Some(true)),
+            Register::E => (&mut registers.de, Some(false)),
+            Register::H => (&mut registers.hl, Some(true)),
+            Register::L => (&mut registers.hl, Some(false)),
+            Register::AF => (&mut registers.af, None),
+            Register::BC => (&mut registers.bc, None),
+            Register::DE => (&mut registers.de, None),
+            Register::HL => (&mut registers.hl, None),
+            Register::SP => (&mut registers.sp, None),
+            Register::PC => (&mut registers.pc, None),
+        }
+    }
+
+    fn get(self, registers: &Registers) -> u16 {
+        let mut registers = *registers;
+        match self.location(&mut registers) {
+            (register, Some(true)) => *register >> 8,
+            (register, Some(false)) => *register & 0x00FF,
+            (register, None) => *register,
+        }
+    }
+
+    fn set(self, registers: &mut Registers, value: u16) -> Result<(), String> {
+        match self.location(registers) {
+            (_, Some(_)) if value > 0xFF => {
+                return Err(format!("{:?} is 8 bits wide", self));
+            }
+            (register, Some(true)) => *register = (*register & 0x00FF) | value << 8,
+            (register, Some(false)) => *register = (*register & 0xFF00) | value,
+            (register, None) => *register = value,
+        }
+        // The lower nibble of F is hardwired to 0
+        registers.af &= 0xFFF0;
+        Ok(())
+    }
+}
+
+fn parse_number(text: &str) -> Result<u16, String> {
+    let text = text.trim();
+    let parsed = if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix('$')) {
+        u16::from_str_radix(hex, 16)
+    } else {
+        text.parse()
+    };
+    parsed.map_err(|_| format!("Invalid number {}", text))
+}
+
+/// Operand of a condition
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Number(u16),
+    Register(Register),
+    /// Byte at an address
+    Memory(Box<Value>),
+}
+
+/// Parses a number, or else a label
+fn parse_address(text: &str, symbols: &Symbols) -> Result<u16, String> {
+    parse_number(text)
+        .ok()
+        .or_else(|| symbols.address(text))
+        .ok_or_else(|| format!("Invalid address {}", text.trim()))
+}
+
+impl Value {
+    fn parse(text: &str, symbols: &Symbols) -> Result<Value, String> {
+        let text = text.trim();
+        if let Some(address) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+            return Ok(Value::Memory(Box::new(Value::parse(address, symbols)?)));
+        }
+        match Register::parse(text) {
+            Some(register) => Ok(Value::Register(register)),
+            None => parse_address(text, symbols).map(Value::Number),
+        }
+    }
+
+    fn eval<B: Bus>(&self, cpu: &CPU<Watched<B>>) -> u16 {
+        match self {
+            Value::Number(number) => *number,
+            Value::Register(register) => register.get(cpu.registers()),
+            Value::Memory(address) => cpu.bus().bus.read(address.eval(cpu)) as u16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    LessOrEqual,
+    GreaterOrEqual,
+    Less,
+    Greater,
+}
+
+const COMPARISONS: [(&str, Comparison); 6] = [
+    ("==", Comparison::Equal),
+    ("!=", Comparison::NotEqual),
+    ("<=", Comparison::LessOrEqual),
+    (">=", Comparison::GreaterOrEqual),
+    ("<", Comparison::Less),
+    (">", Comparison::Greater),
+];
+
+/// Comparisons that must all hold, e.g. `A==0x10 && [HL]!=0`
+#[derive(Debug, Clone, PartialEq)]
+struct Condition {
+    text: String,
+    comparisons: Vec<(Value, Comparison, Value)>,
+}
+
+impl Condition {
+    fn parse(text: &str, symbols: &Symbols) -> Result<Condition, String> {
+        let comparisons = text
+            .split("&&")
+            .map(|comparison| {
+                let (operator, comparison_kind) = COMPARISONS
+                    .iter()
+                    .find(|(operator, _)| comparison.contains(operator))
+                    .ok_or_else(|| format!("Missing comparison in {}", comparison.trim()))?;
+                let (left, right) = comparison.split_once(operator).unwrap();
+                Ok((
+                    Value::parse(left, symbols)?,
+                    *comparison_kind,
+                    Value::parse(right, symbols)?,
+                ))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Condition {
+            text: text.trim().to_string(),
+            comparisons,
+        })
+    }
+
+    fn holds<B: Bus>(&self, cpu: &CPU<Watched<B>>) -> bool {
+        self.comparisons.iter().all(|(left, comparison, right)| {
+            let (left, right) = (left.eval(cpu), right.eval(cpu));
+            match comparison {
+                Comparison::Equal => left == right,
+                Comparison::NotEqual => left != right,
+                Comparison::LessOrEqual => left <= right,
+                Comparison::GreaterOrEqual => left >= right,
+                Comparison::Less => left < right,
+                Comparison::Greater => left > right,
+            }
+        })
+    }
+}
+
+struct Breakpoint {
+    id: usize,
+    address: u16,
+    condition: Option<Condition>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Watch {
+    Read,
+    Write,
+    Access,
+}
+
+struct Watchpoint {
+    id: usize,
+    address: u16,
+    watch: Watch,
+}
+
+/// Bus the CPU runs on while debugging, noting accesses to watched addresses
+struct Watched<B: Bus> {
+    bus: B,
+    watchpoints: Vec<Watchpoint>,
+    /// Watchpoints hit, reads happen through a shared reference
+    hits: RefCell<Vec<(usize, Access)>>,
+}
+
+impl<B: Bus> Watched<B> {
+    fn check(&self, access: Access) {
+        let (address, write) = match access {
+            Access::Read(address, _) => (address, false),
+            Access::Write(address, _) => (address, true),
+        };
+        for watchpoint in &self.watchpoints {
+            let watched = match watchpoint.watch {
+                Watch::Read => !write,
+                Watch::Write => write,
+                Watch::Access => true,
+            };
+            if watched && watchpoint.address == address {
+                self.hits.borrow_mut().push((watchpoint.id, access));
+            }
+        }
+    }
+}
+
+impl<B: Bus> Bus for Watched<B> {
+    fn read(&self, address: u16) -> u8 {
+        let data = self.bus.read(address);
+        self.check(Access::Read(address, data));
+        data
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.bus.peek(address)
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        self.bus.write(address, data);
+        self.check(Access::Write(address, data));
+    }
+
+    fn tick(&mut self, cycles: u16) {
+        self.bus.tick(cycles);
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.bus.pending_interrupts()
+    }
+
+    fn acknowledge_interrupt(&mut self, interrupt: u8) {
+        self.bus.acknowledge_interrupt(interrupt);
+    }
+
+    fn take_stall(&mut self) -> u16 {
+        self.bus.take_stall()
+    }
+
+    fn reset_divider(&mut self) {
+        self.bus.reset_divider();
+    }
+
+    fn speed_switch_armed(&self) -> bool {
+        self.bus.speed_switch_armed()
+    }
+
+    fn switch_speed(&mut self) {
+        self.bus.switch_speed();
+    }
+
+    fn double_speed(&self) -> bool {
+        self.bus.double_speed()
+    }
+
+    fn rom_bank(&self, address: u16) -> u16 {
+        self.bus.rom_bank(address)
+    }
+}
+
+/// A function being run, entered through CALL, RST or an interrupt
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Frame {
+    /// Address of the call, or of the instruction interrupted
+    caller: u16,
+    entry: u16,
+    /// SP once the return address is pushed
+    sp: u16,
+    interrupt: bool,
+}
+
+/// Why running stopped
+enum Stop {
+    Steps,
+    Breakpoint(usize),
+    Watchpoint(usize, Access),
+    Stopped,
+}
+
+/// Command line debugger, stepping a CPU and inspecting its state. The call
+/// stack is tracked from CALL, RST, interrupts and RET as they run.
+pub struct Debugger<B: Bus> {
+    cpu: CPU<Watched<B>>,
+    breakpoints: Vec<Breakpoint>,
+    next_id: usize,
+    calls: Vec<Frame>,
+    last_command: String,
+    symbols: Symbols,
+}
+
+impl<B: Bus> Debugger<B> {
+    pub fn new(cpu: CPU<B>) -> Debugger<B> {
+        Debugger {
+            cpu: cpu.map_bus(|bus| Watched {
+                bus,
+                watchpoints: Vec::new(),
+                hits: RefCell::new(Vec::new()),
+            }),
+            breakpoints: Vec::new(),
+            next_id: 1,
+            calls: Vec::new(),
+            last_command: String::new(),
+            symbols: Symbols::default(),
+        }
+    }
+
+    /// Labels accepted as addresses and shown next to them
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    /// Gives the CPU back, once done debugging
+    pub fn into_cpu(self) -> CPU<B> {
+        self.cpu.map_bus(|watched| watched.bus)
+    }
+
+    /// Reads and runs commands until `quit` or the end of the input
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, output: &mut W) -> io::Result<()> {
+        writeln!(output, "{}", self.location(self.cpu.pc()))?;
+        let mut lines = input.lines();
+        loop {
+            write!(output, "(gbc) ")?;
+            output.flush()?;
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(()),
+            };
+            let line = if line.trim().is_empty() {
+                self.last_command.clone()
+            } else {
+                line.trim().to_string()
+            };
+            if line == "q" || line == "quit" {
+                return Ok(());
+            }
+            match self.execute(&line) {
+                Ok(response) if response.is_empty() => {}
+                Ok(response) => writeln!(output, "{}", response)?,
+                Err(error) => writeln!(output, "error: {}", error)?,
+            }
+            self.last_command = line;
+        }
+    }
+
+    /// Runs a command, returning what it prints
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        let (command, arguments) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        let arguments = arguments.trim();
+        let count = |default: usize| match arguments {
+            "" => Ok(default),
+            count => count
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid count {}", count)),
+        };
+        match command {
+            "s" | "step" => {
+                let steps = count(1)?;
+                self.resume(|debugger, step| step < steps && !debugger.at_breakpoint(step))
+            }
+            "n" | "next" => {
+                let depth = self.calls.len();
+                self.resume(|debugger, step| {
+                    step == 0 || (debugger.calls.len() > depth && !debugger.at_breakpoint(step))
+                })
+            }
+            "c" | "continue" => self.resume(|debugger, step| !debugger.at_breakpoint(step)),
+            "finish" => {
+                let depth = self.calls.len();
+                if depth == 0 {
+                    return Err(String::from("Not in a function called"));
+                }
+                self.resume(|debugger, step| {
+                    debugger.calls.len() >= depth && !debugger.at_breakpoint(step)
+                })
+            }
+            "b" | "break" => self.add_breakpoint(arguments),
+            "watch" => self.add_watchpoint(arguments, Watch::Write),
+            "rwatch" => self.add_watchpoint(arguments, Watch::Read),
+            "awatch" => self.add_watchpoint(arguments, Watch::Access),
+            "d" | "delete" => self.delete(arguments),
+            "i" | "info" => Ok(self.info()),
+            "r" | "regs" => Ok(self.registers()),
+            "x" => {
+                let mut arguments = arguments.split_whitespace();
+                let address = self.address(arguments.next().ok_or("Missing address")?)?;
+                let length = arguments.next().map_or(Ok(DUMP_LENGTH), parse_number)?;
+                Ok(self.dump(address, length))
+            }
+            "set" => self.set(arguments),
+            "l" | "list" => {
+                let mut arguments = arguments.split_whitespace();
+                let address = arguments.next().map(|a| self.address(a)).transpose()?;
+                let length = match arguments.next() {
+                    Some(length) => length
+                        .parse()
+                        .map_err(|_| format!("Invalid count {}", length))?,
+                    None => LIST_LENGTH,
+                };
+                Ok(self.list(address, length))
+            }
+            "bt" | "backtrace" => Ok(self.backtrace()),
+            "h" | "help" => Ok(String::from(HELP)),
+            _ => Err(format!("Unknown command {}, see help", command)),
+        }
+    }
+
+    /// Whether a breakpoint stops the CPU at PC. The instruction running
+    /// first is never stopped at, so that resuming from a breakpoint works.
+    fn at_breakpoint(&self, step: usize) -> bool {
+        step > 0 && self.breakpoint_hit().is_some()
+    }
+
+    fn breakpoint_hit(&self) -> Option<usize> {
+        let pc = self.cpu.pc();
+        self.breakpoints
+            .iter()
+            .find(|breakpoint| {
+                breakpoint.address == pc
+                    && breakpoint
+                        .condition
+                        .as_ref()
+                        .is_none_or(|condition| condition.holds(&self.cpu))
+            })
+            .map(|breakpoint| breakpoint.id)
+    }
+
+    /// Steps for as long as `proceed` says, given the steps run so far, or
+    /// until a watchpoint is hit or the CPU stops
+    fn resume<F: Fn(&Self, usize) -> bool>(&mut self, proceed: F) -> Result<String, String> {
+        if self.cpu.is_stopped() {
+            return Err(String::from(
+                "The CPU is in STOP mode, waiting for a button press",
+            ));
+        }
+        let mut steps = 0;
+        let stop = loop {
+            if !proceed(self, steps) {
+                break match self.breakpoint_hit() {
+                    Some(id) if steps > 0 => Stop::Breakpoint(id),
+                    _ => Stop::Steps,
+                };
+            }
+            self.step();
+            steps += 1;
+            if let Some((id, access)) = self.cpu.bus().hits.borrow_mut().drain(..).next() {
+                break Stop::Watchpoint(id, access);
+            }
+            if self.cpu.is_stopped() {
+                break Stop::Stopped;
+            }
+        };
+        let reason = match stop {
+            Stop::Steps => String::new(),
+            Stop::Breakpoint(id) => format!("Breakpoint {}\n", id),
+            Stop::Watchpoint(id, Access::Read(address, data)) => format!(
+                "Watchpoint {}: read ${:04X}{} = ${:02X}\n",
+                id,
+                address,
+                self.symbol(address),
+                data
+            ),
+            Stop::Watchpoint(id, Access::Write(address, data)) => format!(
+                "Watchpoint {}: write ${:04X}{} = ${:02X}\n",
+                id,
+                address,
+                self.symbol(address),
+                data
+            ),
+            Stop::Stopped => String::from("The CPU entered STOP mode\n"),
+        };
+        Ok(reason + &self.location(self.cpu.pc()))
+    }
+
+    /// Runs an instruction, or an interrupt dispatch, tracking calls
+    fn step(&mut self) {
+        let pc = self.cpu.pc();
+        let opcode = self.cpu.bus().bus.read(pc);
+        let instruction = Instruction::decode(opcode);
+        let interrupt = self.cpu.step();
+
+        let registers = self.cpu.registers();
+        // The opcode at PC was not run when an interrupt was dispatched
+        let call = !interrupt
+            && matches!(
+                instruction,
+                Instruction::CALL_u16
+                    | Instruction::CALL_NZ_u16
+                    | Instruction::CALL_Z_u16
+                    | Instruction::CALL_NC_u16
+                    | Instruction::CALL_C_u16
+                    | Instruction::RST_00h
+                    | Instruction::RST_08h
+                    | Instruction::RST_10h
+                    | Instruction::RST_18h
+                    | Instruction::RST_20h
+                    | Instruction::RST_28h
+                    | Instruction::RST_30h
+                    | Instruction::RST_38h
+            );
+        let jumped = registers.pc != pc.wrapping_add(OPCODES[opcode as usize].length as u16);
+        if (call && jumped) || interrupt {
+            self.calls.push(Frame {
+                caller: pc,
+                entry: registers.pc,
+                sp: registers.sp,
+                interrupt,
+            });
+        } else {
+            // Returns, or anything else releasing return addresses
+            let sp = registers.sp;
+            self.calls.retain(|frame| frame.sp >= sp);
+        }
+    }
+
+    fn add_breakpoint(&mut self, arguments: &str) -> Result<String, String> {
+        let (address, condition) = match arguments.split_once(" if ") {
+            Some((address, condition)) => {
+                (address, Some(Condition::parse(condition, &self.symbols)?))
+            }
+            None => (arguments, None),
+        };
+        if address.is_empty() {
+            return Err(String::from("Missing address"));
+        }
+        let address = self.address(address)?;
+        let id = self.next_id;
+        self.next_id += 1;
+        self.breakpoints.push(Breakpoint {
+            id,
+            address,
+            condition,
+        });
+        Ok(format!(
+            "Breakpoint {} at ${:04X}{}",
+            id,
+            address,
+            self.symbol(address)
+        ))
+    }
+
+    fn add_watchpoint(&mut self, arguments: &str, watch: Watch) -> Result<String, String> {
+        let address = self.address(arguments)?;
+        let id = self.next_id;
+        self.next_id += 1;
+        self.cpu
+            .bus_mut()
+            .watchpoints
+            .push(Watchpoint { id, address, watch });
+        Ok(format!(
+            "Watchpoint {} at ${:04X}{}",
+            id,
+            address,
+            self.symbol(address)
+        ))
+    }
+
+    fn delete(&mut self, arguments: &str) -> Result<String, String> {
+        let id: usize = arguments
+            .parse()
+            .map_err(|_| format!("Invalid breakpoint {}", arguments))?;
+        let breakpoints = self.breakpoints.len();
+        self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+        let watchpoints = &mut self.cpu.bus_mut().watchpoints;
+        let count = watchpoints.len();
+        watchpoints.retain(|watchpoint| watchpoint.id != id);
+        if breakpoints == self.breakpoints.len() && count == watchpoints.len() {
+            return Err(format!("No breakpoint {}", id));
+        }
+        Ok(String::new())
+    }
+
+    fn info(&self) -> String {
+        let mut lines = Vec::new();
+        for breakpoint in &self.breakpoints {
+            let mut line = format!(
+                "{:<3} break  ${:04X}{}",
+                breakpoint.id,
+                breakpoint.address,
+                self.symbol(breakpoint.address)
+            );
+            if let Some(condition) = &breakpoint.condition {
+                line += &format!(" if {}", condition.text);
+            }
+            lines.push((breakpoint.id, line));
+        }
+        for watchpoint in &self.cpu.bus().watchpoints {
+            let kind = match watchpoint.watch {
+                Watch::Read => "rwatch",
+                Watch::Write => "watch",
+                Watch::Access => "awatch",
+            };
+            let line = format!(
+                "{:<3} {:<6} ${:04X}{}",
+                watchpoint.id,
+                kind,
+                watchpoint.address,
+                self.symbol(watchpoint.address)
+            );
+            lines.push((watchpoint.id, line));
+        }
+        if lines.is_empty() {
+            return String::from("No breakpoints or watchpoints");
+        }
+        lines.sort();
+        let lines: Vec<String> = lines.into_iter().map(|(_, line)| line).collect();
+        lines.join("\n")
+    }
+
+    fn registers(&self) -> String {
+        let registers = self.cpu.registers();
+        let flags: String = ['Z', 'N', 'H', 'C']
+            .iter()
+            .enumerate()
+            .map(|(i, flag)| {
+                if registers.flag(0x80 >> i) {
+                    *flag
+                } else {
+                    '-'
+                }
+            })
+            .collect();
+        format!(
+            "AF:{:04X} BC:{:04X} DE:{:04X} HL:{:04X} SP:{:04X} PC:{:04X}\nFlags:{} IME:{} Cycles:{}",
+            registers.af,
+            registers.bc,
+            registers.de,
+            registers.hl,
+            registers.sp,
+            registers.pc,
+            flags,
+            self.cpu.ime() as u8,
+            self.cpu.cycles()
+        )
+    }
+
+    fn dump(&self, address: u16, length: u16) -> String {
+        let bus = &self.cpu.bus().bus;
+        let mut lines = Vec::new();
+        let mut row = 0;
+        while row < length {
+            let start = address.wrapping_add(row);
+            let bytes: Vec<String> = (row..length.min(row.saturating_add(16)))
+                .map(|offset| format!("{:02X}", bus.read(address.wrapping_add(offset))))
+                .collect();
+            lines.push(format!("${:04X}: {}", start, bytes.join(" ")));
+            row = row.saturating_add(16);
+        }
+        lines.join("\n")
+    }
+
+    fn set(&mut self, arguments: &str) -> Result<String, String> {
+        let (target, value) = arguments
+            .split_once('=')
+            .ok_or("Expected REG=VALUE or [ADDR]=VALUE")?;
+        let value = parse_number(value)?;
+        match Value::parse(target, &self.symbols)? {
+            Value::Register(register) => register.set(self.cpu.registers_mut(), value)?,
+            Value::Memory(address) => {
+                let address = address.eval(&self.cpu);
+                if address < 0x8000 {
+                    return Err(format!("ROM at ${:04X} cannot be written", address));
+                }
+                if value > 0xFF {
+                    return Err(String::from("Memory is written a byte at a time"));
+                }
+                self.cpu.bus_mut().bus.write(address, value as u8);
+            }
+            Value::Number(_) => return Err(format!("Cannot set {}", target.trim())),
+        }
+        Ok(String::new())
+    }
+
+    /// Parses a number, or else a label
+    fn address(&self, text: &str) -> Result<u16, String> {
+        parse_address(text, &self.symbols)
+    }
+
+    /// ` <bank:label+offset>` for an address, if there is a label before it
+    fn symbol(&self, address: u16) -> String {
+        let bank = self.cpu.bus().bus.rom_bank(address);
+        match self.symbols.label(bank, address) {
+            Some(label) => format!(" <{}>", label),
+            None => String::new(),
+        }
+    }
+
+    fn disassemble(&self, address: u16) -> Disassembly {
+        disassembler::disassemble_bus(&self.cpu.bus().bus, address)
+    }
+
+    /// The instruction at an address, as shown when stopping there
+    fn location(&self, address: u16) -> String {
+        let instruction = self.disassemble(address);
+        let bytes: Vec<String> = instruction
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        let target = instruction
+            .target
+            .map_or_else(String::new, |target| self.symbol(target));
+        format!(
+            "${:04X}{}: {:<9} {}{}",
+            address,
+            self.symbol(address),
+            bytes.join(" "),
+            instruction,
+            target
+        )
+    }
+
+    /// Instructions from `address`, or around PC, marking PC and breakpoints
+    fn list(&self, address: Option<u16>, length: usize) -> String {
+        let pc = self.cpu.pc();
+        let start = address.unwrap_or_else(|| self.start_before(pc));
+        let mut address = start;
+        let mut lines = Vec::new();
+        for _ in 0..length {
+            let marker = if address == pc {
+                "=>"
+            } else if self.breakpoints.iter().any(|b| b.address == address) {
+                " *"
+            } else {
+                "  "
+            };
+            lines.push(format!("{} {}", marker, self.location(address)));
+            address = address.wrapping_add(self.disassemble(address).length());
+        }
+        lines.join("\n")
+    }
+
+    /// Address a few instructions before PC, found by decoding from further
+    /// back until the instructions line up with PC
+    fn start_before(&self, pc: u16) -> u16 {
+        for back in (1..=LIST_BEFORE as u16 * 3).rev() {
+            let mut address = match pc.checked_sub(back) {
+                Some(address) => address,
+                None => continue,
+            };
+            let mut starts = Vec::new();
+            while address < pc {
+                starts.push(address);
+                address += self.disassemble(address).length();
+            }
+            if address == pc {
+                return starts[starts.len().saturating_sub(LIST_BEFORE)];
+            }
+        }
+        pc
+    }
+
+    fn backtrace(&self) -> String {
+        let mut lines = Vec::new();
+        let mut pc = self.cpu.pc();
+        for (depth, frame) in self.calls.iter().rev().enumerate() {
+            let kind = if frame.interrupt { "interrupt" } else { "call" };
+            lines.push(format!(
+                "#{:<2} ${:04X}{} in ${:04X}{} ({})",
+                depth,
+                pc,
+                self.symbol(pc),
+                frame.entry,
+                self.symbol(frame.entry),
+                kind
+            ));
+            pc = frame.caller;
+        }
+        lines.push(format!(
+            "#{:<2} ${:04X}{}",
+            self.calls.len(),
+            pc,
+            self.symbol(pc)
+        ));
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::FlatBus;
+
+    /// Debugger on a flat bus running `code` from 0x0100
+    fn debugger(code: &[u8]) -> Debugger<FlatBus> {
+        let mut cpu = CPU::with_bus(FlatBus::with_code(0x0100, code));
+        cpu.registers_mut().pc = 0x0100;
+        cpu.registers_mut().sp = 0xFFFE;
+        Debugger::new(cpu)
+    }
+
+    // INC A; JR -3
+    const LOOP: [u8; 3] = [0x3C, 0x18, 0xFD];
+
+    #[test]
+    fn test_breakpoints() {
+        let mut debugger = debugger(&LOOP);
+        debugger.execute("break 0x101 if A==0x10").unwrap();
+        let stop = debugger.execute("continue").unwrap();
+        assert!(stop.starts_with("Breakpoint 1\n$0101"));
+        assert_eq!(debugger.cpu.registers().a(), 0x10);
+        debugger.execute("set a=$20").unwrap();
+        debugger
+            .execute("b $100 if A>=0x22 && [0x0101]==0x18")
+            .unwrap();
+        debugger.execute("c").unwrap();
+        assert_eq!(debugger.cpu.registers().a(), 0x22);
+        assert_eq!(debugger.cpu.pc(), 0x0100);
+        debugger.execute("delete 2").unwrap();
+        assert!(debugger.execute("delete 2").is_err());
+        assert_eq!(debugger.info(), "1   break  $0101 if A==0x10");
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        // LD HL,0xC000; LD A,(HL); INC A; LD (HL),A
+        let mut debugger = debugger(&[0x21, 0x00, 0xC0, 0x7E, 0x3C, 0x77]);
+        debugger.execute("set [0xC000]=7").unwrap();
+        debugger.execute("watch 0xC000").unwrap();
+        debugger.execute("rwatch 0xC000").unwrap();
+        let stop = debugger.execute("c").unwrap();
+        assert!(stop.starts_with("Watchpoint 2: read $C000 = $07\n$0104"));
+        let stop = debugger.execute("c").unwrap();
+        assert!(stop.starts_with("Watchpoint 1: write $C000 = $08\n$0106"));
+        assert_eq!(debugger.dump(0xC000, 2), "$C000: 08 00");
+        let dump = debugger.dump(0x0000, 0xFFFF);
+        assert_eq!(dump.lines().count(), 0x1000);
+        assert!(dump.ends_with("$FFF0: 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00"));
+    }
+
+    #[test]
+    fn test_calls() {
+        // CALL 0x0110; NOP ... 0x0110: CALL 0x0120 ... 0x0120: INC A; RET
+        let mut code = vec![0x00; 0x22];
+        code[0x00..0x04].copy_from_slice(&[0xCD, 0x10, 0x01, 0x00]);
+        code[0x10..0x14].copy_from_slice(&[0xCD, 0x20, 0x01, 0xC9]);
+        code[0x20..0x22].copy_from_slice(&[0x3C, 0xC9]);
+        let mut debugger = debugger(&code);
+        debugger.execute("step 2").unwrap();
+        assert_eq!(debugger.cpu.pc(), 0x0120);
+        assert_eq!(
+            debugger.backtrace(),
+            "#0  $0120 in $0120 (call)\n#1  $0110 in $0110 (call)\n#2  $0100"
+        );
+        debugger.execute("finish").unwrap();
+        assert_eq!(debugger.cpu.pc(), 0x0113);
+        assert_eq!(debugger.calls.len(), 1);
+        debugger.execute("finish").unwrap();
+        assert_eq!(debugger.cpu.pc(), 0x0103);
+        assert!(debugger.execute("finish").is_err());
+
+        let mut debugger = self::debugger(&code);
+        debugger.execute("next").unwrap();
+        assert_eq!(debugger.cpu.pc(), 0x0103);
+        assert_eq!(debugger.cpu.registers().a(), 0x01);
+    }
+
+    #[test]
+    fn test_interrupt_on_call() {
+        // An interrupt dispatched with PC on CALL 0x0110 runs no CALL
+        let mut debugger = debugger(&[0xCD, 0x10, 0x01]);
+        debugger.cpu.set_ime(true);
+        debugger.execute("set [0xFFFF]=1").unwrap();
+        debugger.execute("set [0xFF0F]=1").unwrap();
+        debugger.execute("step").unwrap();
+        assert_eq!(debugger.cpu.pc(), 0x0040);
+        assert_eq!(
+            debugger.backtrace(),
+            "#0  $0040 in $0040 (interrupt)\n#1  $0100"
+        );
+    }
+
+    #[test]
+    fn test_list() {
+        let mut debugger = debugger(&[0x00, 0x3E, 0x10, 0x00, 0xC3, 0x00, 0x01]);
+        debugger.execute("s 3").unwrap();
+        debugger.execute("b 0x101").unwrap();
+        assert_eq!(
+            debugger.list(None, 4),
+            "   $0100: 00        nop\n \
+             * $0101: 3E 10     ld a, $10\n   \
+             $0103: 00        nop\n\
+             => $0104: C3 00 01  jp $0100"
+        );
+    }
+
+    #[test]
+    fn test_symbols() {
+        let mut debugger = debugger(&LOOP);
+        let symbols = "00:0100 Main\n00:0101 Main.loop\n00:C000 wCounter";
+        debugger.set_symbols(Symbols::parse_sym(symbols));
+        assert_eq!(
+            debugger.execute("break Main.loop if [wCounter]==0"),
+            Ok(String::from("Breakpoint 1 at $0101 <00:Main.loop>"))
+        );
+        assert_eq!(
+            debugger.execute("c"),
+            Ok(String::from(
+                "Breakpoint 1\n$0101 <00:Main.loop>: 18 FD     jr $0100 <00:Main>"
+            ))
+        );
+        assert_eq!(
+            debugger.execute("watch wCounter+1"),
+            Ok(String::from("Watchpoint 2 at $C001 <00:wCounter+1>"))
+        );
+        assert_eq!(
+            debugger.execute("b Main.loop.nope"),
+            Err(String::from("Invalid address Main.loop.nope"))
+        );
+        assert_eq!(debugger.backtrace(), "#0  $0101 <00:Main.loop>");
+    }
+
+    #[test]
+    fn test_repl() {
+        let mut debugger = debugger(&LOOP);
+        let mut output = Vec::new();
+        let input = "s\n\nregs\nset pc=0x8000\nset b=0x100\nfoo\nq\nregs\n";
+        debugger.run(input.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("(gbc) $0101: 18 FD     jr $0100"));
+        assert!(output.contains("(gbc) $0100: 3C        inc a"));
+        assert!(output.contains("PC:0100\nFlags:"));
+        assert!(output.contains("error: B is 8 bits wide"));
+        assert!(output.contains("error: Unknown command foo"));
+        assert_eq!(output.matches("AF:").count(), 1);
+        assert_eq!(debugger.cpu.pc(), 0x8000);
+    }
+}
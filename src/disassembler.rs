@@ -0,0 +1,272 @@
+use crate::bus::Bus;
+use crate::instructions::{Instruction, OperandKind, CB_OPCODES, OPCODES};
+use crate::symbols::Symbols;
+use std::fmt;
+use std::io::{self, Write};
+
+/// A decoded instruction
+#[derive(Debug, Clone, PartialEq)]
+pub struct Disassembly {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: String,
+    /// Operands with immediates and jump targets resolved, empty if none
+    pub operands: String,
+    /// M-cycles, when not branching for conditional instructions
+    pub cycles: u8,
+    /// M-cycles of conditional instructions when branching
+    pub cycles_taken: Option<u8>,
+    /// Address jumped or called to, unless through HL
+    pub target: Option<u16>,
+}
+
+impl Disassembly {
+    pub fn length(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+
+    /// Data that is not an instruction, or an instruction cut short
+    fn data(address: u16, bytes: &[u8]) -> Disassembly {
+        let operands: Vec<String> = bytes.iter().map(|byte| format!("${:02X}", byte)).collect();
+        Disassembly {
+            address,
+            bytes: bytes.to_vec(),
+            mnemonic: String::from("db"),
+            operands: operands.join(", "),
+            cycles: 1,
+            cycles_taken: None,
+            target: None,
+        }
+    }
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.operands.is_empty() {
+            write!(f, "{}", self.mnemonic)
+        } else {
+            write!(f, "{} {}", self.mnemonic, self.operands)
+        }
+    }
+}
+
+/// Decodes the instruction at the start of `bytes`, located at `address`
+pub fn disassemble(bytes: &[u8], address: u16) -> Disassembly {
+    let opcode = match bytes.first() {
+        Some(opcode) => *opcode,
+        None => return Disassembly::data(address, &[]),
+    };
+    let (instruction, prefix, metadata) = if opcode == 0xCB {
+        match bytes.get(1) {
+            Some(opcode) => (
+                Instruction::decode_cb(*opcode),
+                2,
+                &CB_OPCODES[*opcode as usize],
+            ),
+            None => return Disassembly::data(address, bytes),
+        }
+    } else {
+        (Instruction::decode(opcode), 1, &OPCODES[opcode as usize])
+    };
+    if instruction == Instruction::Invalid {
+        return Disassembly::data(address, &bytes[..1]);
+    }
+
+    // STOP is followed by a padding byte, which rgbds emits as 0x00
+    let length = metadata.length as usize;
+    if bytes.len() < length || (instruction == Instruction::STOP && bytes[1] != 0x00) {
+        return Disassembly::data(address, &bytes[..length.min(bytes.len())]);
+    }
+    let bytes = &bytes[..length];
+    let u8 = bytes.get(prefix).copied().unwrap_or(0);
+    let u16 = u8 as u16 | (bytes.get(prefix + 1).copied().unwrap_or(0) as u16) << 8;
+    let target = address
+        .wrapping_add(length as u16)
+        .wrapping_add(u8 as i8 as u16);
+    let (placeholder, operand) = match metadata.operand {
+        OperandKind::Implied => ("", String::new()),
+        OperandKind::U8 => ("{u8}", format!("${:02X}", u8)),
+        OperandKind::U16 => ("{u16}", format!("${:04X}", u16)),
+        OperandKind::A8 => ("{a8}", format!("$FF{:02X}", u8)),
+        OperandKind::I8 => ("{i8}", format!("{}", u8 as i8)),
+        OperandKind::S8 => ("{s8}", format!("{:+}", u8 as i8)),
+        OperandKind::R8 => ("{r8}", format!("${:04X}", target)),
+    };
+    let template = instruction.template();
+    let text = if placeholder.is_empty() {
+        template.to_string()
+    } else {
+        template.replace(placeholder, &operand)
+    };
+    let (mnemonic, operands) = match text.split_once(' ') {
+        Some((mnemonic, operands)) => (mnemonic.to_string(), operands.to_string()),
+        None => (text, String::new()),
+    };
+    let target = match (mnemonic.as_str(), metadata.operand) {
+        (_, OperandKind::R8) => Some(target),
+        ("jp", OperandKind::U16) | ("call", OperandKind::U16) => Some(u16),
+        ("rst", _) => Some((opcode & 0x38) as u16),
+        _ => None,
+    };
+    Disassembly {
+        address,
+        bytes: bytes.to_vec(),
+        mnemonic,
+        operands,
+        cycles: metadata.cycles,
+        cycles_taken: metadata.cycles_taken,
+        target,
+    }
+}
+
+/// Decodes the instruction at `address` on a bus
+pub fn disassemble_bus<B: Bus>(bus: &B, address: u16) -> Disassembly {
+    let bytes: Vec<u8> = (0..3)
+        .map(|offset| bus.read(address.wrapping_add(offset)))
+        .collect();
+    disassemble(&bytes, address)
+}
+
+/// Writes a ROM as rgbds source, one section per 16KB bank, which assembles
+/// back to the same bytes. Everything is decoded as code, data included,
+/// with the labels of `symbols` defined where they start.
+pub fn write_source<W: Write>(rom: &[u8], symbols: &Symbols, output: &mut W) -> io::Result<()> {
+    for (bank, data) in rom.chunks(0x4000).enumerate() {
+        if bank == 0 {
+            writeln!(output, "SECTION \"ROM Bank $000\", ROM0[$0000]")?;
+        } else {
+            writeln!(output)?;
+            writeln!(
+                output,
+                "SECTION \"ROM Bank ${:03X}\", ROMX[$4000], BANK[{}]",
+                bank, bank
+            )?;
+        }
+        let bank = bank as u16;
+        let base = if bank == 0 { 0x0000 } else { 0x4000 };
+        let mut offset = 0;
+        while offset < data.len() {
+            let address = base + offset as u16;
+            if let Some(label) = symbols.label_at(bank, address) {
+                writeln!(output, "{}:", label)?;
+            }
+            // Instructions overlapping a label are cut short into data
+            let end = (offset + 1..data.len().min(offset + 3))
+                .find(|end| symbols.label_at(bank, base + *end as u16).is_some())
+                .unwrap_or(data.len());
+            let instruction = disassemble(&data[offset..end], address);
+            let bytes: Vec<String> = instruction
+                .bytes
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect();
+            writeln!(
+                output,
+                "    {:<24}; ${:04X}: {}",
+                instruction.to_string(),
+                address,
+                bytes.join(" ")
+            )?;
+            offset += instruction.bytes.len();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(bytes: &[u8], address: u16) -> String {
+        disassemble(bytes, address).to_string()
+    }
+
+    #[test]
+    fn test_operands() {
+        assert_eq!(text(&[0x00], 0x0100), "nop");
+        assert_eq!(text(&[0x3E, 0x10], 0x0100), "ld a, $10");
+        assert_eq!(text(&[0x21, 0x34, 0x12], 0x0100), "ld hl, $1234");
+        assert_eq!(text(&[0x22], 0x0100), "ld [hl+], a");
+        assert_eq!(text(&[0xE0, 0x40], 0x0100), "ldh [$FF40], a");
+        assert_eq!(text(&[0xF2], 0x0100), "ldh a, [c]");
+        assert_eq!(text(&[0xE8, 0xFE], 0x0100), "add sp, -2");
+        assert_eq!(text(&[0xF8, 0x05], 0x0100), "ld hl, sp+5");
+        assert_eq!(text(&[0xCB, 0x7E], 0x0100), "bit 7, [hl]");
+        assert_eq!(text(&[0xFF], 0x0100), "rst $38");
+        assert_eq!(text(&[0x10, 0x00], 0x0100), "stop");
+    }
+
+    #[test]
+    fn test_relative_jumps() {
+        assert_eq!(text(&[0x18, 0xFE], 0x0150), "jr $0150");
+        assert_eq!(text(&[0x20, 0x05], 0x0150), "jr nz, $0157");
+        let jr = disassemble(&[0x38, 0x80], 0x0000);
+        assert_eq!(jr.operands, "c, $FF82");
+        assert_eq!((jr.cycles, jr.cycles_taken), (2, Some(3)));
+        assert_eq!(jr.target, Some(0xFF82));
+        assert_eq!(disassemble(&[0xC3, 0x50, 0x01], 0).target, Some(0x0150));
+        assert_eq!(disassemble(&[0xDF], 0).target, Some(0x0018));
+        assert_eq!(disassemble(&[0xE9], 0).target, None);
+        assert_eq!(disassemble(&[0x21, 0x50, 0x01], 0).target, None);
+    }
+
+    #[test]
+    fn test_lengths_and_cycles() {
+        let call = disassemble(&[0xCD, 0x00, 0x40], 0x0100);
+        assert_eq!((call.mnemonic.as_str(), call.length()), ("call", 3));
+        assert_eq!((call.cycles, call.cycles_taken), (6, None));
+        let ret = disassemble(&[0xC0], 0x0100);
+        assert_eq!((ret.cycles, ret.cycles_taken), (2, Some(5)));
+        let set = disassemble(&[0xCB, 0xC6], 0x0100);
+        assert_eq!((set.length(), set.cycles), (2, 4));
+    }
+
+    #[test]
+    fn test_data() {
+        assert_eq!(text(&[0xD3], 0x0100), "db $D3");
+        assert_eq!(text(&[0xC3, 0x50], 0x7FFE), "db $C3, $50");
+        assert_eq!(text(&[0x10, 0x01], 0x0100), "db $10, $01");
+    }
+
+    #[test]
+    fn test_write_source() {
+        let mut rom = vec![0x00; 0x4000];
+        rom[0x3FFF] = 0x3E;
+        rom.extend_from_slice(&[0xC3, 0x00, 0x40]);
+        let mut source = Vec::new();
+        write_source(&rom, &Symbols::default(), &mut source).unwrap();
+        let source = String::from_utf8(source).unwrap();
+        let lines: Vec<&str> = source.lines().collect();
+        assert_eq!(lines[0], "SECTION \"ROM Bank $000\", ROM0[$0000]");
+        assert_eq!(lines[1], "    nop                     ; $0000: 00");
+        assert_eq!(lines[0x4000], "    db $3E                  ; $3FFF: 3E");
+        assert_eq!(
+            lines[0x4002],
+            "SECTION \"ROM Bank $001\", ROMX[$4000], BANK[1]"
+        );
+        assert_eq!(
+            lines[0x4003],
+            "    jp $4000                ; $4000: C3 00 40"
+        );
+    }
+
+    #[test]
+    fn test_write_source_labels() {
+        let rom = [0xC3, 0x02, 0x00, 0x18, 0xFE];
+        let symbols = Symbols::parse_sym("00:0000 Start\n00:0002 Main.loop\n01:4000 Data");
+        let mut source = Vec::new();
+        write_source(&rom, &symbols, &mut source).unwrap();
+        let source = String::from_utf8(source).unwrap();
+        let lines: Vec<&str> = source.lines().skip(1).collect();
+        assert_eq!(
+            lines,
+            [
+                "Start:",
+                "    db $C3, $02             ; $0000: C3 02",
+                "Main.loop:",
+                "    nop                     ; $0002: 00",
+                "    jr $0003                ; $0003: 18 FE",
+            ]
+        );
+    }
+}
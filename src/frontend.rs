@@ -0,0 +1,208 @@
+use crate::cpu::CPU;
+use crate::memory::joypad::Button;
+use crate::movie::Recorder;
+use crate::ppu::{FRAME_DOTS, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::rewind::Rewind;
+use crate::state;
+use pixels::{Pixels, PixelsBuilder, SurfaceTexture};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use winit::dpi::LogicalSize;
+use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::window::{Window, WindowBuilder};
+
+/// Frames emulated per frame presented while fast-forwarding
+const FAST_FORWARD_FRAMES: u32 = 4;
+
+/// Joypad layout: arrows, X for A, Z for B, Enter for Start and Backspace
+/// for Select
+fn button(key: VirtualKeyCode) -> Option<Button> {
+    match key {
+        VirtualKeyCode::Right => Some(Button::Right),
+        VirtualKeyCode::Left => Some(Button::Left),
+        VirtualKeyCode::Up => Some(Button::Up),
+        VirtualKeyCode::Down => Some(Button::Down),
+        VirtualKeyCode::X => Some(Button::A),
+        VirtualKeyCode::Z => Some(Button::B),
+        VirtualKeyCode::Back => Some(Button::Select),
+        VirtualKeyCode::Return => Some(Button::Start),
+        _ => None,
+    }
+}
+
+fn create_pixels(window: &Window) -> Pixels {
+    let size = window.inner_size();
+    let surface = SurfaceTexture::new(size.width, size.height, window);
+    let builder = PixelsBuilder::new(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32, surface);
+    #[cfg(feature = "software-rasterizer")]
+    let builder = {
+        if std::env::var_os("WGPU_ADAPTER_NAME").is_none() {
+            std::env::set_var("WGPU_ADAPTER_NAME", "llvmpipe");
+        }
+        builder.wgpu_backend(wgpu::Backends::GL)
+    };
+    builder.build().expect("No graphics adapter available")
+}
+
+/// Writes a save state, reporting failures without stopping
+fn save_state(cpu: &CPU, path: &Path) {
+    match fs::write(path, state::save(cpu)) {
+        Ok(()) => eprintln!("State saved to {}", path.display()),
+        Err(error) => eprintln!("{} could not be written: {}", path.display(), error),
+    }
+}
+
+/// Loads a save state, keeping the game running as it was on failure.
+/// Returns whether it was loaded.
+fn load_state(cpu: &mut CPU, path: &Path) -> bool {
+    let loaded = fs::read(path)
+        .map_err(|error| error.to_string())
+        .and_then(|data| state::load(cpu, &data));
+    match loaded {
+        Ok(()) => {
+            eprintln!("State loaded from {}", path.display());
+            true
+        }
+        Err(error) => {
+            eprintln!("{} could not be loaded: {}", path.display(), error);
+            false
+        }
+    }
+}
+
+/// Stops recording a movie that could not be written
+fn check_movie(movie: &mut Option<Recorder>, written: io::Result<()>) {
+    if let Err(error) = written {
+        eprintln!("Movie could not be written: {}", error);
+        *movie = None;
+    }
+}
+
+/// Opens a window presenting the PPU framebuffer, scaled by the largest
+/// integer factor that fits and letterboxed, initially `scale` times the LCD
+/// size. `boot` creates the CPU, again on every reset, and `on_frame` is
+/// called after every frame emulated, returning whether to keep going.
+///
+/// Hotkeys: P pauses, R resets, holding Tab fast-forwards, holding ` steps
+/// back through the states captured by `rewind` a frame at a time, F5 saves
+/// the state to `state_path`, F8 loads it back and Escape quits.
+///
+/// The input is recorded into `movie` if given, which starts over from the
+/// current state after a reset, a state load or a rewind.
+pub fn run<F, G>(
+    boot: F,
+    scale: u32,
+    state_path: PathBuf,
+    mut rewind: Rewind,
+    mut movie: Option<Recorder>,
+    mut on_frame: G,
+) where
+    F: Fn() -> CPU + 'static,
+    G: FnMut(&mut CPU) -> bool + 'static,
+{
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .with_title("gbc")
+        .with_inner_size(LogicalSize::new(
+            SCREEN_WIDTH as u32 * scale,
+            SCREEN_HEIGHT as u32 * scale,
+        ))
+        .with_min_inner_size(LogicalSize::new(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32))
+        .build(&event_loop)
+        .expect("Window could not be created");
+    let mut pixels = create_pixels(&window);
+
+    let mut cpu = boot();
+    let clock = cpu.memory().model().clock() as u64;
+    let frame_duration = Duration::from_nanos(1_000_000_000 * FRAME_DOTS as u64 / clock);
+    let mut paused = false;
+    let mut fast_forward = false;
+    let mut rewinding = false;
+    let mut restart_movie = true;
+    let mut deadline = Instant::now();
+    event_loop.run(move |event, _, control_flow| match event {
+        Event::WindowEvent { event, .. } => match event {
+            WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+            WindowEvent::Resized(size) => pixels.resize_surface(size.width, size.height),
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => {
+                let pressed = state == ElementState::Pressed;
+                match key {
+                    VirtualKeyCode::Escape => *control_flow = ControlFlow::Exit,
+                    VirtualKeyCode::P if pressed => paused = !paused,
+                    VirtualKeyCode::R if pressed => {
+                        cpu = boot();
+                        rewind.clear();
+                        restart_movie = true;
+                    }
+                    VirtualKeyCode::Tab => fast_forward = pressed,
+                    VirtualKeyCode::Grave => rewinding = pressed,
+                    VirtualKeyCode::F5 if pressed => save_state(&cpu, &state_path),
+                    VirtualKeyCode::F8 if pressed => {
+                        if load_state(&mut cpu, &state_path) {
+                            rewind.clear();
+                            restart_movie = true;
+                        }
+                        window.request_redraw();
+                    }
+                    key => {
+                        if let Some(button) = button(key) {
+                            cpu.memory_mut().set_button(button, pressed);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        },
+        Event::MainEventsCleared => {
+            let now = Instant::now();
+            if now >= deadline {
+                if rewinding {
+                    restart_movie |= rewind.step_back(&mut cpu);
+                } else if !paused {
+                    let frames = if fast_forward { FAST_FORWARD_FRAMES } else { 1 };
+                    for _ in 0..frames {
+                        if let Some(recorder) = movie.as_mut().filter(|_| restart_movie) {
+                            let started = recorder.start(&cpu);
+                            check_movie(&mut movie, started);
+                        }
+                        restart_movie = false;
+                        cpu.run_frame();
+                        rewind.on_frame(&cpu);
+                        if let Some(recorder) = movie.as_mut() {
+                            let recorded = recorder.on_frame(&cpu);
+                            check_movie(&mut movie, recorded);
+                        }
+                        if !on_frame(&mut cpu) {
+                            *control_flow = ControlFlow::Exit;
+                            return;
+                        }
+                    }
+                }
+                window.request_redraw();
+                // Falling behind skips the frames missed instead of catching up
+                deadline = (deadline + frame_duration).max(now);
+            }
+            *control_flow = ControlFlow::WaitUntil(deadline);
+        }
+        Event::RedrawRequested(_) => {
+            pixels.get_frame().copy_from_slice(cpu.memory().frame());
+            if let Err(error) = pixels.render() {
+                eprintln!("Rendering failed: {}", error);
+                *control_flow = ControlFlow::Exit;
+            }
+        }
+        Event::LoopDestroyed => cpu.finish(),
+        _ => {}
+    });
+}
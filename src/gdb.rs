@@ -0,0 +1,464 @@
+use crate::bus::Bus;
+use crate::cpu::CPU;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+/// Describes the registers to clients, as the SM83 is unknown to gdb
+const TARGET_XML: &str = "<?xml version=\"1.0\"?>
+<!DOCTYPE target SYSTEM \"gdb-target.dtd\">
+<target version=\"1.0\">
+  <feature name=\"org.gnu.gdb.sm83.cpu\">
+    <reg name=\"af\" bitsize=\"16\" type=\"uint16\" regnum=\"0\"/>
+    <reg name=\"bc\" bitsize=\"16\" type=\"uint16\"/>
+    <reg name=\"de\" bitsize=\"16\" type=\"uint16\"/>
+    <reg name=\"hl\" bitsize=\"16\" type=\"uint16\"/>
+    <reg name=\"sp\" bitsize=\"16\" type=\"data_ptr\"/>
+    <reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>
+  </feature>
+</target>
+";
+
+/// Registers in the order of `TARGET_XML`
+const REGISTERS: usize = 6;
+/// Instructions run between checks for an interrupt from the client
+const POLL_INTERVAL: u32 = 4096;
+/// Signals reported when stopping
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+/// Client connection, framing packets as `$data#checksum`
+struct Connection {
+    stream: TcpStream,
+    /// Bytes received but not handled yet
+    received: Vec<u8>,
+    acknowledge: bool,
+    last_sent: Vec<u8>,
+}
+
+impl Connection {
+    /// Fills the buffer, returning false once the client disconnected
+    fn receive(&mut self) -> io::Result<bool> {
+        let mut buffer = [0; 1024];
+        match self.stream.read(&mut buffer) {
+            Ok(0) => Ok(false),
+            Ok(read) => {
+                self.received.extend_from_slice(&buffer[..read]);
+                Ok(true)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Waits for the next packet, `None` once the client disconnected
+    fn packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // Acknowledgements and interrupts outside of packets
+            while let Some(&byte) = self.received.first() {
+                match byte {
+                    b'$' => break,
+                    b'-' => {
+                        let last_sent = self.last_sent.clone();
+                        self.stream.write_all(&last_sent)?;
+                    }
+                    _ => {}
+                }
+                self.received.remove(0);
+            }
+            let end = self.received.iter().position(|&byte| byte == b'#');
+            if let Some(end) = end.filter(|end| self.received.len() >= end + 3) {
+                let packet: Vec<u8> = self.received.drain(..end + 3).collect();
+                let data = &packet[1..end];
+                let checksum = std::str::from_utf8(&packet[end + 1..])
+                    .ok()
+                    .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+                if self.acknowledge {
+                    let valid = checksum == Some(Self::checksum(data));
+                    self.stream.write_all(if valid { b"+" } else { b"-" })?;
+                    if !valid {
+                        continue;
+                    }
+                }
+                return Ok(Some(String::from_utf8_lossy(data).into_owned()));
+            }
+            if !self.receive()? {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, Self::checksum(data.as_bytes()));
+        self.last_sent = packet.into_bytes();
+        self.stream.write_all(&self.last_sent)
+    }
+
+    /// Whether the client sent an interrupt, without waiting
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let received = self.receive();
+        self.stream.set_nonblocking(false)?;
+        match received {
+            Ok(_) => {}
+            Err(error) if error.kind() == ErrorKind::WouldBlock => {}
+            Err(error) => return Err(error),
+        }
+        match self.received.iter().position(|&byte| byte == 0x03) {
+            Some(interrupt) => {
+                self.received.remove(interrupt);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn checksum(data: &[u8]) -> u8 {
+        data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+    }
+}
+
+fn hex(text: &str) -> Option<u16> {
+    u16::from_str_radix(text, 16).ok()
+}
+
+/// Bytes as sent over the wire, two hex digits each
+fn decode_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// GDB Remote Serial Protocol stub controlling a CPU, with breakpoints and
+/// single stepping. Registers are AF, BC, DE, HL, SP and PC, 16 bits each.
+pub struct GdbStub<'a, B: Bus> {
+    cpu: &'a mut CPU<B>,
+    connection: Connection,
+    /// Software and hardware breakpoints alike, as the CPU is emulated
+    breakpoints: Vec<u16>,
+}
+
+/// Waits for a client on `address`, e.g. `127.0.0.1:2345`, and serves it
+/// until it detaches or disconnects
+pub fn listen<B: Bus>(cpu: &mut CPU<B>, address: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    eprintln!("Waiting for gdb on {}", listener.local_addr()?);
+    let (stream, client) = listener.accept()?;
+    eprintln!("gdb attached from {}", client);
+    GdbStub::new(cpu, stream).serve()
+}
+
+impl<'a, B: Bus> GdbStub<'a, B> {
+    pub fn new(cpu: &'a mut CPU<B>, stream: TcpStream) -> GdbStub<'a, B> {
+        GdbStub {
+            cpu,
+            connection: Connection {
+                stream,
+                received: Vec::new(),
+                acknowledge: true,
+                last_sent: Vec::new(),
+            },
+            breakpoints: Vec::new(),
+        }
+    }
+
+    /// Answers packets until the client detaches, kills or disconnects
+    pub fn serve(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.connection.packet()? {
+            let reply = match packet.as_bytes().first() {
+                Some(b'D') => return self.connection.send("OK"),
+                Some(b'k') => return Ok(()),
+                Some(b'c') => self.resume(&packet[1..], false)?,
+                Some(b's') => self.resume(&packet[1..], true)?,
+                _ => self.reply(&packet),
+            };
+            self.connection.send(&reply)?;
+            if packet == "QStartNoAckMode" {
+                self.connection.acknowledge = false;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reply to packets that do not run the CPU, empty when unsupported
+    fn reply(&mut self, packet: &str) -> String {
+        let error = String::from("E01");
+        if packet.is_empty() || !packet.is_char_boundary(1) {
+            return String::new();
+        }
+        let (command, arguments) = packet.split_at(1);
+        match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => (0..REGISTERS).map(|n| self.read_register(n)).collect(),
+            "G" => match decode_bytes(arguments).filter(|bytes| bytes.len() == REGISTERS * 2) {
+                Some(bytes) => {
+                    for (n, value) in bytes.chunks(2).enumerate() {
+                        self.write_register(n, value[0] as u16 | (value[1] as u16) << 8);
+                    }
+                    String::from("OK")
+                }
+                None => error,
+            },
+            "p" => match hex(arguments).map(|n| n as usize) {
+                Some(n) if n < REGISTERS => self.read_register(n),
+                _ => error,
+            },
+            "P" => {
+                let register = arguments.split_once('=').and_then(|(n, value)| {
+                    let value = decode_bytes(value).filter(|value| value.len() == 2)?;
+                    Some((hex(n)? as usize, value[0] as u16 | (value[1] as u16) << 8))
+                });
+                match register {
+                    Some((n, value)) if n < REGISTERS => {
+                        self.write_register(n, value);
+                        String::from("OK")
+                    }
+                    _ => error,
+                }
+            }
+            "m" => {
+                let range = arguments
+                    .split_once(',')
+                    .and_then(|(address, length)| Some((hex(address)?, hex(length)?)));
+                match range {
+                    Some((address, length)) => (0..length)
+                        .map(|offset| {
+                            let data = self.cpu.bus().read(address.wrapping_add(offset));
+                            format!("{:02x}", data)
+                        })
+                        .collect(),
+                    None => error,
+                }
+            }
+            "M" => {
+                let write = arguments.split_once(':').and_then(|(range, data)| {
+                    let (address, length) = range.split_once(',')?;
+                    let data = decode_bytes(data)?;
+                    Some((hex(address)?, hex(length)?, data))
+                });
+                match write {
+                    // The cartridge ROM is read-only
+                    Some((address, length, data))
+                        if data.len() == length as usize && address >= 0x8000 =>
+                    {
+                        for (offset, byte) in data.iter().enumerate() {
+                            let address = address.wrapping_add(offset as u16);
+                            self.cpu.bus_mut().write(address, *byte);
+                        }
+                        String::from("OK")
+                    }
+                    _ => error,
+                }
+            }
+            "Z" | "z" => {
+                let breakpoint = arguments.split(',').collect::<Vec<_>>();
+                match breakpoint.as_slice() {
+                    [kind, address, ..] if *kind == "0" || *kind == "1" => match hex(address) {
+                        Some(address) => {
+                            self.breakpoints.retain(|breakpoint| *breakpoint != address);
+                            if command == "Z" {
+                                self.breakpoints.push(address);
+                            }
+                            String::from("OK")
+                        }
+                        None => error,
+                    },
+                    // Watchpoints are not supported
+                    _ => String::new(),
+                }
+            }
+            "H" | "T" => String::from("OK"),
+            "q" | "Q" => self.query(packet),
+            _ => String::new(),
+        }
+    }
+
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return String::from("PacketSize=1000;qXfer:features:read+;QStartNoAckMode+");
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let range = range
+                .split_once(',')
+                .and_then(|(offset, length)| Some((hex(offset)?, hex(length)?)));
+            return match range {
+                Some((offset, length)) => {
+                    let offset = (offset as usize).min(TARGET_XML.len());
+                    let end = (offset + length as usize).min(TARGET_XML.len());
+                    let more = if end < TARGET_XML.len() { "m" } else { "l" };
+                    format!("{}{}", more, &TARGET_XML[offset..end])
+                }
+                None => String::from("E01"),
+            };
+        }
+        match packet {
+            "QStartNoAckMode" => String::from("OK"),
+            "qAttached" => String::from("1"),
+            "qfThreadInfo" => String::from("m1"),
+            "qsThreadInfo" => String::from("l"),
+            "qC" => String::from("QC1"),
+            _ => String::new(),
+        }
+    }
+
+    /// Register as sent over the wire, little endian
+    fn read_register(&self, n: usize) -> String {
+        let registers = self.cpu.registers();
+        let value = [
+            registers.af,
+            registers.bc,
+            registers.de,
+            registers.hl,
+            registers.sp,
+            registers.pc,
+        ][n];
+        format!("{:02x}{:02x}", value & 0xFF, value >> 8)
+    }
+
+    fn write_register(&mut self, n: usize, value: u16) {
+        let registers = self.cpu.registers_mut();
+        match n {
+            // The lower nibble of F is hardwired to 0
+            0 => registers.af = value & 0xFFF0,
+            1 => registers.bc = value,
+            2 => registers.de = value,
+            3 => registers.hl = value,
+            4 => registers.sp = value,
+            _ => registers.pc = value,
+        }
+    }
+
+    /// Continues or single steps, optionally from another address, replying
+    /// once stopped
+    fn resume(&mut self, address: &str, single_step: bool) -> io::Result<String> {
+        if let Some(address) = hex(address) {
+            self.cpu.registers_mut().pc = address;
+        }
+        let mut steps: u32 = 0;
+        loop {
+            if steps > 0 && (single_step || self.breakpoints.contains(&self.cpu.pc())) {
+                return Ok(format!("S{:02x}", SIGTRAP));
+            }
+            if steps % POLL_INTERVAL == POLL_INTERVAL - 1 && self.connection.interrupted()? {
+                return Ok(format!("S{:02x}", SIGINT));
+            }
+            self.cpu.step();
+            steps = steps.wrapping_add(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::FlatBus;
+    use std::thread;
+
+    fn packet(data: &str) -> String {
+        format!("${}#{:02x}", data, Connection::checksum(data.as_bytes()))
+    }
+
+    /// Sends each packet in turn from a client, returning what it received
+    fn exchange(cpu: &mut CPU<FlatBus>, packets: &[&str]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let packets: Vec<String> = packets.iter().map(|data| data.to_string()).collect();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            let mut received = Vec::new();
+            for data in packets {
+                // A continue interrupted right away, e.g. "c\x03"
+                let request = match data.strip_suffix('\x03') {
+                    Some(data) => packet(data) + "\x03",
+                    None => packet(&data),
+                };
+                stream.write_all(request.as_bytes()).unwrap();
+                // Killing gets no reply
+                if data == "k" {
+                    break;
+                }
+                // Waits for the reply packet, which ends with a checksum
+                loop {
+                    let mut byte = [0];
+                    stream.read_exact(&mut byte).unwrap();
+                    received.push(byte[0]);
+                    let length = received.len();
+                    if length > 3 && received[length - 3] == b'#' {
+                        break;
+                    }
+                }
+                received.push(b'\n');
+            }
+            String::from_utf8(received).unwrap()
+        });
+        let (stream, _) = listener.accept().unwrap();
+        GdbStub::new(cpu, stream).serve().unwrap();
+        client.join().unwrap()
+    }
+
+    fn cpu(code: &[u8]) -> CPU<FlatBus> {
+        let mut cpu = CPU::with_bus(FlatBus::with_code(0x0100, code));
+        cpu.registers_mut().pc = 0x0100;
+        cpu
+    }
+
+    #[test]
+    fn test_registers_and_memory() {
+        let mut cpu = cpu(&[0x3E, 0x42]);
+        cpu.registers_mut().bc = 0x1234;
+        let replies = exchange(
+            &mut cpu,
+            &[
+                "qSupported:multiprocess+",
+                "QStartNoAckMode",
+                "g",
+                "p1",
+                "P2=cdab",
+                "m100,2",
+                "Mc000,2:beef",
+                "M0000,1:00",
+                "D",
+            ],
+        );
+        let replies: Vec<&str> = replies.lines().collect();
+        let features = packet("PacketSize=1000;qXfer:features:read+;QStartNoAckMode+");
+        assert_eq!(replies[0], format!("+{}", features));
+        assert_eq!(replies[1], format!("+{}", packet("OK")));
+        assert_eq!(replies[2], packet("000034120000000000000001"));
+        assert_eq!(replies[3], packet("3412"));
+        assert_eq!(replies[4], packet("OK"));
+        assert_eq!(replies[5], packet("3e42"));
+        assert_eq!(replies[6], packet("OK"));
+        assert_eq!(replies[7], packet("E01"));
+        assert_eq!(cpu.registers().de, 0xABCD);
+        assert_eq!(cpu.bus().peek(0xC001), 0xEF);
+    }
+
+    #[test]
+    fn test_breakpoints_and_stepping() {
+        // INC A; INC A; JR -4
+        let mut cpu = cpu(&[0x3C, 0x3C, 0x18, 0xFC]);
+        let replies = exchange(
+            &mut cpu,
+            &[
+                "QStartNoAckMode",
+                "Z0,102,1",
+                "c",
+                "s",
+                "z0,102,1",
+                "c\x03",
+                "?",
+                "k",
+            ],
+        );
+        let replies: Vec<&str> = replies.lines().collect();
+        assert_eq!(replies[1], packet("OK"));
+        assert_eq!(replies[2], packet("S05"));
+        assert_eq!(replies[3], packet("S05"));
+        assert_eq!(replies[5], packet("S02"));
+        assert_eq!(replies[6], packet("S05"));
+        assert!((0x0100..0x0104).contains(&cpu.pc()));
+    }
+}
@@ -1,6 +1,8 @@
 #![allow(non_camel_case_types)]
 
-#[derive(Debug, PartialEq)]
+use self::OperandKind::*;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Instruction {
     // x8/lsm
     LD_A_u8,
@@ -23,6 +25,15 @@ pub enum Instruction {
 
     LD_HL_u8,
 
+    LD_A_A,
+    LD_A_B,
+    LD_A_C,
+    LD_A_D,
+    LD_A_E,
+    LD_A_H,
+    LD_A_L,
+    LD_A_HL,
+
     LD_B_A,
     LD_B_B,
     LD_B_C,
@@ -77,12 +88,38 @@ pub enum Instruction {
     LD_L_L,
     LD_L_HL,
 
+    LD_HL_A,
+    LD_HL_B,
+    LD_HL_C,
+    LD_HL_D,
+    LD_HL_E,
+    LD_HL_H,
+    LD_HL_L,
+
+    LD_FF00_u8_A,
+    LD_A_FF00_u8,
+    LD_FF00_C_A,
+    LD_A_FF00_C,
+    LD_u16_A,
+    LD_A_u16,
+
     // x16/lsm
     LD_BC_u16,
     LD_DE_u16,
     LD_HL_u16,
     LD_SP_u16,
     LD_u16_SP,
+    LD_SP_HL,
+    LD_HL_SP_i8,
+
+    PUSH_BC,
+    PUSH_DE,
+    PUSH_HL,
+    PUSH_AF,
+    POP_BC,
+    POP_DE,
+    POP_HL,
+    POP_AF,
 
     // x8/alu
     INC_A,
@@ -92,6 +129,7 @@ pub enum Instruction {
     INC_E,
     INC_H,
     INC_L,
+    INC_HL_Indirect,
     DEC_A,
     DEC_B,
     DEC_C,
@@ -99,6 +137,87 @@ pub enum Instruction {
     DEC_E,
     DEC_H,
     DEC_L,
+    DEC_HL_Indirect,
+
+    ADD_A_A,
+    ADD_A_B,
+    ADD_A_C,
+    ADD_A_D,
+    ADD_A_E,
+    ADD_A_H,
+    ADD_A_L,
+    ADD_A_HL,
+    ADD_A_u8,
+
+    ADC_A_A,
+    ADC_A_B,
+    ADC_A_C,
+    ADC_A_D,
+    ADC_A_E,
+    ADC_A_H,
+    ADC_A_L,
+    ADC_A_HL,
+    ADC_A_u8,
+
+    SUB_A_A,
+    SUB_A_B,
+    SUB_A_C,
+    SUB_A_D,
+    SUB_A_E,
+    SUB_A_H,
+    SUB_A_L,
+    SUB_A_HL,
+    SUB_A_u8,
+
+    SBC_A_A,
+    SBC_A_B,
+    SBC_A_C,
+    SBC_A_D,
+    SBC_A_E,
+    SBC_A_H,
+    SBC_A_L,
+    SBC_A_HL,
+    SBC_A_u8,
+
+    AND_A_A,
+    AND_A_B,
+    AND_A_C,
+    AND_A_D,
+    AND_A_E,
+    AND_A_H,
+    AND_A_L,
+    AND_A_HL,
+    AND_A_u8,
+
+    XOR_A_A,
+    XOR_A_B,
+    XOR_A_C,
+    XOR_A_D,
+    XOR_A_E,
+    XOR_A_H,
+    XOR_A_L,
+    XOR_A_HL,
+    XOR_A_u8,
+
+    OR_A_A,
+    OR_A_B,
+    OR_A_C,
+    OR_A_D,
+    OR_A_E,
+    OR_A_H,
+    OR_A_L,
+    OR_A_HL,
+    OR_A_u8,
+
+    CP_A_A,
+    CP_A_B,
+    CP_A_C,
+    CP_A_D,
+    CP_A_E,
+    CP_A_H,
+    CP_A_L,
+    CP_A_HL,
+    CP_A_u8,
 
     DAA,
     SCF,
@@ -118,6 +237,7 @@ pub enum Instruction {
     ADD_HL_DE,
     ADD_HL_HL,
     ADD_HL_SP,
+    ADD_SP_i8,
 
     // x8/rsb
     RLCA,
@@ -125,6 +245,294 @@ pub enum Instruction {
     RLA,
     RRA,
 
+    RLC_A,
+    RLC_B,
+    RLC_C,
+    RLC_D,
+    RLC_E,
+    RLC_H,
+    RLC_L,
+    RLC_HL,
+
+    RRC_A,
+    RRC_B,
+    RRC_C,
+    RRC_D,
+    RRC_E,
+    RRC_H,
+    RRC_L,
+    RRC_HL,
+
+    RL_A,
+    RL_B,
+    RL_C,
+    RL_D,
+    RL_E,
+    RL_H,
+    RL_L,
+    RL_HL,
+
+    RR_A,
+    RR_B,
+    RR_C,
+    RR_D,
+    RR_E,
+    RR_H,
+    RR_L,
+    RR_HL,
+
+    SLA_A,
+    SLA_B,
+    SLA_C,
+    SLA_D,
+    SLA_E,
+    SLA_H,
+    SLA_L,
+    SLA_HL,
+
+    SRA_A,
+    SRA_B,
+    SRA_C,
+    SRA_D,
+    SRA_E,
+    SRA_H,
+    SRA_L,
+    SRA_HL,
+
+    SWAP_A,
+    SWAP_B,
+    SWAP_C,
+    SWAP_D,
+    SWAP_E,
+    SWAP_H,
+    SWAP_L,
+    SWAP_HL,
+
+    SRL_A,
+    SRL_B,
+    SRL_C,
+    SRL_D,
+    SRL_E,
+    SRL_H,
+    SRL_L,
+    SRL_HL,
+
+    BIT_0_A,
+    BIT_0_B,
+    BIT_0_C,
+    BIT_0_D,
+    BIT_0_E,
+    BIT_0_H,
+    BIT_0_L,
+    BIT_0_HL,
+
+    BIT_1_A,
+    BIT_1_B,
+    BIT_1_C,
+    BIT_1_D,
+    BIT_1_E,
+    BIT_1_H,
+    BIT_1_L,
+    BIT_1_HL,
+
+    BIT_2_A,
+    BIT_2_B,
+    BIT_2_C,
+    BIT_2_D,
+    BIT_2_E,
+    BIT_2_H,
+    BIT_2_L,
+    BIT_2_HL,
+
+    BIT_3_A,
+    BIT_3_B,
+    BIT_3_C,
+    BIT_3_D,
+    BIT_3_E,
+    BIT_3_H,
+    BIT_3_L,
+    BIT_3_HL,
+
+    BIT_4_A,
+    BIT_4_B,
+    BIT_4_C,
+    BIT_4_D,
+    BIT_4_E,
+    BIT_4_H,
+    BIT_4_L,
+    BIT_4_HL,
+
+    BIT_5_A,
+    BIT_5_B,
+    BIT_5_C,
+    BIT_5_D,
+    BIT_5_E,
+    BIT_5_H,
+    BIT_5_L,
+    BIT_5_HL,
+
+    BIT_6_A,
+    BIT_6_B,
+    BIT_6_C,
+    BIT_6_D,
+    BIT_6_E,
+    BIT_6_H,
+    BIT_6_L,
+    BIT_6_HL,
+
+    BIT_7_A,
+    BIT_7_B,
+    BIT_7_C,
+    BIT_7_D,
+    BIT_7_E,
+    BIT_7_H,
+    BIT_7_L,
+    BIT_7_HL,
+
+    RES_0_A,
+    RES_0_B,
+    RES_0_C,
+    RES_0_D,
+    RES_0_E,
+    RES_0_H,
+    RES_0_L,
+    RES_0_HL,
+
+    RES_1_A,
+    RES_1_B,
+    RES_1_C,
+    RES_1_D,
+    RES_1_E,
+    RES_1_H,
+    RES_1_L,
+    RES_1_HL,
+
+    RES_2_A,
+    RES_2_B,
+    RES_2_C,
+    RES_2_D,
+    RES_2_E,
+    RES_2_H,
+    RES_2_L,
+    RES_2_HL,
+
+    RES_3_A,
+    RES_3_B,
+    RES_3_C,
+    RES_3_D,
+    RES_3_E,
+    RES_3_H,
+    RES_3_L,
+    RES_3_HL,
+
+    RES_4_A,
+    RES_4_B,
+    RES_4_C,
+    RES_4_D,
+    RES_4_E,
+    RES_4_H,
+    RES_4_L,
+    RES_4_HL,
+
+    RES_5_A,
+    RES_5_B,
+    RES_5_C,
+    RES_5_D,
+    RES_5_E,
+    RES_5_H,
+    RES_5_L,
+    RES_5_HL,
+
+    RES_6_A,
+    RES_6_B,
+    RES_6_C,
+    RES_6_D,
+    RES_6_E,
+    RES_6_H,
+    RES_6_L,
+    RES_6_HL,
+
+    RES_7_A,
+    RES_7_B,
+    RES_7_C,
+    RES_7_D,
+    RES_7_E,
+    RES_7_H,
+    RES_7_L,
+    RES_7_HL,
+
+    SET_0_A,
+    SET_0_B,
+    SET_0_C,
+    SET_0_D,
+    SET_0_E,
+    SET_0_H,
+    SET_0_L,
+    SET_0_HL,
+
+    SET_1_A,
+    SET_1_B,
+    SET_1_C,
+    SET_1_D,
+    SET_1_E,
+    SET_1_H,
+    SET_1_L,
+    SET_1_HL,
+
+    SET_2_A,
+    SET_2_B,
+    SET_2_C,
+    SET_2_D,
+    SET_2_E,
+    SET_2_H,
+    SET_2_L,
+    SET_2_HL,
+
+    SET_3_A,
+    SET_3_B,
+    SET_3_C,
+    SET_3_D,
+    SET_3_E,
+    SET_3_H,
+    SET_3_L,
+    SET_3_HL,
+
+    SET_4_A,
+    SET_4_B,
+    SET_4_C,
+    SET_4_D,
+    SET_4_E,
+    SET_4_H,
+    SET_4_L,
+    SET_4_HL,
+
+    SET_5_A,
+    SET_5_B,
+    SET_5_C,
+    SET_5_D,
+    SET_5_E,
+    SET_5_H,
+    SET_5_L,
+    SET_5_HL,
+
+    SET_6_A,
+    SET_6_B,
+    SET_6_C,
+    SET_6_D,
+    SET_6_E,
+    SET_6_H,
+    SET_6_L,
+    SET_6_HL,
+
+    SET_7_A,
+    SET_7_B,
+    SET_7_C,
+    SET_7_D,
+    SET_7_E,
+    SET_7_H,
+    SET_7_L,
+    SET_7_HL,
+
     // control/branch
     JR_i8,
     JR_NZ_i8,
@@ -132,11 +540,39 @@ pub enum Instruction {
     JR_C_i8,
     JR_Z_i8,
     JP_u16,
+    JP_NZ_u16,
+    JP_NC_u16,
+    JP_C_u16,
+    JP_Z_u16,
+    JP_HL,
+
+    CALL_u16,
+    CALL_NZ_u16,
+    CALL_NC_u16,
+    CALL_C_u16,
+    CALL_Z_u16,
+    RET,
+    RET_NZ,
+    RET_NC,
+    RET_C,
+    RET_Z,
+    RETI,
+
+    RST_00h,
+    RST_08h,
+    RST_10h,
+    RST_18h,
+    RST_20h,
+    RST_28h,
+    RST_30h,
+    RST_38h,
 
     // control/misc
     STOP,
     NOP,
     HALT,
+    DI,
+    EI,
     Invalid,
     CB, // Used to switch to CB-prefixed instructions
 }
@@ -199,8 +635,8 @@ impl Instruction {
             0x31 => Instruction::LD_SP_u16,
             0x32 => Instruction::LD_HL_A_Minus,
             0x33 => Instruction::INC_SP,
-            0x34 => Instruction::INC_HL,
-            0x35 => Instruction::DEC_HL,
+            0x34 => Instruction::INC_HL_Indirect,
+            0x35 => Instruction::DEC_HL_Indirect,
             0x36 => Instruction::LD_HL_u8,
             0x37 => Instruction::SCF,
             0x38 => Instruction::JR_C_i8,
@@ -246,451 +682,1321 @@ impl Instruction {
             0x5E => Instruction::LD_E_HL,
             0x5F => Instruction::LD_E_A,
 
-            0x60 => todo!(),
-            0x61 => todo!(),
-            0x62 => todo!(),
-            0x63 => todo!(),
-            0x64 => todo!(),
-            0x65 => todo!(),
-            0x66 => todo!(),
-            0x67 => todo!(),
-            0x68 => todo!(),
-            0x69 => todo!(),
-            0x6A => todo!(),
-            0x6B => todo!(),
-            0x6C => todo!(),
-            0x6D => todo!(),
-            0x6E => todo!(),
-            0x6F => todo!(),
-
-            0x70 => todo!(),
-            0x71 => todo!(),
-            0x72 => todo!(),
-            0x73 => todo!(),
-            0x74 => todo!(),
-            0x75 => todo!(),
-            0x76 => todo!(),
-            0x77 => todo!(),
-            0x78 => todo!(),
-            0x79 => todo!(),
-            0x7A => todo!(),
-            0x7B => todo!(),
-            0x7C => todo!(),
-            0x7D => todo!(),
-            0x7E => todo!(),
-            0x7F => todo!(),
-
-            0x80 => todo!(),
-            0x81 => todo!(),
-            0x82 => todo!(),
-            0x83 => todo!(),
-            0x84 => todo!(),
-            0x85 => todo!(),
-            0x86 => todo!(),
-            0x87 => todo!(),
-            0x88 => todo!(),
-            0x89 => todo!(),
-            0x8A => todo!(),
-            0x8B => todo!(),
-            0x8C => todo!(),
-            0x8D => todo!(),
-            0x8E => todo!(),
-            0x8F => todo!(),
-
-            0x90 => todo!(),
-            0x91 => todo!(),
-            0x92 => todo!(),
-            0x93 => todo!(),
-            0x94 => todo!(),
-            0x95 => todo!(),
-            0x96 => todo!(),
-            0x97 => todo!(),
-            0x98 => todo!(),
-            0x99 => todo!(),
-            0x9A => todo!(),
-            0x9B => todo!(),
-            0x9C => todo!(),
-            0x9D => todo!(),
-            0x9E => todo!(),
-            0x9F => todo!(),
-
-            0xA0 => todo!(),
-            0xA1 => todo!(),
-            0xA2 => todo!(),
-            0xA3 => todo!(),
-            0xA4 => todo!(),
-            0xA5 => todo!(),
-            0xA6 => todo!(),
-            0xA7 => todo!(),
-            0xA8 => todo!(),
-            0xA9 => todo!(),
-            0xAA => todo!(),
-            0xAB => todo!(),
-            0xAC => todo!(),
-            0xAD => todo!(),
-            0xAE => todo!(),
-            0xAF => todo!(),
-
-            0xB0 => todo!(),
-            0xB1 => todo!(),
-            0xB2 => todo!(),
-            0xB3 => todo!(),
-            0xB4 => todo!(),
-            0xB5 => todo!(),
-            0xB6 => todo!(),
-            0xB7 => todo!(),
-            0xB8 => todo!(),
-            0xB9 => todo!(),
-            0xBA => todo!(),
-            0xBB => todo!(),
-            0xBC => todo!(),
-            0xBD => todo!(),
-            0xBE => todo!(),
-            0xBF => todo!(),
-
-            0xC0 => todo!(),
-            0xC1 => todo!(),
-            0xC2 => todo!(),
-            0xC3 => todo!(),
-            0xC4 => todo!(),
-            0xC5 => todo!(),
-            0xC6 => todo!(),
-            0xC7 => todo!(),
-            0xC8 => todo!(),
-            0xC9 => todo!(),
-            0xCA => todo!(),
+            0x60 => Instruction::LD_H_B,
+            0x61 => Instruction::LD_H_C,
+            0x62 => Instruction::LD_H_D,
+            0x63 => Instruction::LD_H_E,
+            0x64 => Instruction::LD_H_H,
+            0x65 => Instruction::LD_H_L,
+            0x66 => Instruction::LD_H_HL,
+            0x67 => Instruction::LD_H_A,
+            0x68 => Instruction::LD_L_B,
+            0x69 => Instruction::LD_L_C,
+            0x6A => Instruction::LD_L_D,
+            0x6B => Instruction::LD_L_E,
+            0x6C => Instruction::LD_L_H,
+            0x6D => Instruction::LD_L_L,
+            0x6E => Instruction::LD_L_HL,
+            0x6F => Instruction::LD_L_A,
+
+            0x70 => Instruction::LD_HL_B,
+            0x71 => Instruction::LD_HL_C,
+            0x72 => Instruction::LD_HL_D,
+            0x73 => Instruction::LD_HL_E,
+            0x74 => Instruction::LD_HL_H,
+            0x75 => Instruction::LD_HL_L,
+            0x76 => Instruction::HALT,
+            0x77 => Instruction::LD_HL_A,
+            0x78 => Instruction::LD_A_B,
+            0x79 => Instruction::LD_A_C,
+            0x7A => Instruction::LD_A_D,
+            0x7B => Instruction::LD_A_E,
+            0x7C => Instruction::LD_A_H,
+            0x7D => Instruction::LD_A_L,
+            0x7E => Instruction::LD_A_HL,
+            0x7F => Instruction::LD_A_A,
+
+            0x80 => Instruction::ADD_A_B,
+            0x81 => Instruction::ADD_A_C,
+            0x82 => Instruction::ADD_A_D,
+            0x83 => Instruction::ADD_A_E,
+            0x84 => Instruction::ADD_A_H,
+            0x85 => Instruction::ADD_A_L,
+            0x86 => Instruction::ADD_A_HL,
+            0x87 => Instruction::ADD_A_A,
+            0x88 => Instruction::ADC_A_B,
+            0x89 => Instruction::ADC_A_C,
+            0x8A => Instruction::ADC_A_D,
+            0x8B => Instruction::ADC_A_E,
+            0x8C => Instruction::ADC_A_H,
+            0x8D => Instruction::ADC_A_L,
+            0x8E => Instruction::ADC_A_HL,
+            0x8F => Instruction::ADC_A_A,
+
+            0x90 => Instruction::SUB_A_B,
+            0x91 => Instruction::SUB_A_C,
+            0x92 => Instruction::SUB_A_D,
+            0x93 => Instruction::SUB_A_E,
+            0x94 => Instruction::SUB_A_H,
+            0x95 => Instruction::SUB_A_L,
+            0x96 => Instruction::SUB_A_HL,
+            0x97 => Instruction::SUB_A_A,
+            0x98 => Instruction::SBC_A_B,
+            0x99 => Instruction::SBC_A_C,
+            0x9A => Instruction::SBC_A_D,
+            0x9B => Instruction::SBC_A_E,
+            0x9C => Instruction::SBC_A_H,
+            0x9D => Instruction::SBC_A_L,
+            0x9E => Instruction::SBC_A_HL,
+            0x9F => Instruction::SBC_A_A,
+
+            0xA0 => Instruction::AND_A_B,
+            0xA1 => Instruction::AND_A_C,
+            0xA2 => Instruction::AND_A_D,
+            0xA3 => Instruction::AND_A_E,
+            0xA4 => Instruction::AND_A_H,
+            0xA5 => Instruction::AND_A_L,
+            0xA6 => Instruction::AND_A_HL,
+            0xA7 => Instruction::AND_A_A,
+            0xA8 => Instruction::XOR_A_B,
+            0xA9 => Instruction::XOR_A_C,
+            0xAA => Instruction::XOR_A_D,
+            0xAB => Instruction::XOR_A_E,
+            0xAC => Instruction::XOR_A_H,
+            0xAD => Instruction::XOR_A_L,
+            0xAE => Instruction::XOR_A_HL,
+            0xAF => Instruction::XOR_A_A,
+
+            0xB0 => Instruction::OR_A_B,
+            0xB1 => Instruction::OR_A_C,
+            0xB2 => Instruction::OR_A_D,
+            0xB3 => Instruction::OR_A_E,
+            0xB4 => Instruction::OR_A_H,
+            0xB5 => Instruction::OR_A_L,
+            0xB6 => Instruction::OR_A_HL,
+            0xB7 => Instruction::OR_A_A,
+            0xB8 => Instruction::CP_A_B,
+            0xB9 => Instruction::CP_A_C,
+            0xBA => Instruction::CP_A_D,
+            0xBB => Instruction::CP_A_E,
+            0xBC => Instruction::CP_A_H,
+            0xBD => Instruction::CP_A_L,
+            0xBE => Instruction::CP_A_HL,
+            0xBF => Instruction::CP_A_A,
+
+            0xC0 => Instruction::RET_NZ,
+            0xC1 => Instruction::POP_BC,
+            0xC2 => Instruction::JP_NZ_u16,
+            0xC3 => Instruction::JP_u16,
+            0xC4 => Instruction::CALL_NZ_u16,
+            0xC5 => Instruction::PUSH_BC,
+            0xC6 => Instruction::ADD_A_u8,
+            0xC7 => Instruction::RST_00h,
+            0xC8 => Instruction::RET_Z,
+            0xC9 => Instruction::RET,
+            0xCA => Instruction::JP_Z_u16,
             0xCB => Instruction::CB,
-            0xCC => todo!(),
-            0xCD => todo!(),
-            0xCE => todo!(),
-            0xCF => todo!(),
-
-            0xD0 => todo!(),
-            0xD1 => todo!(),
-            0xD2 => todo!(),
+            0xCC => Instruction::CALL_Z_u16,
+            0xCD => Instruction::CALL_u16,
+            0xCE => Instruction::ADC_A_u8,
+            0xCF => Instruction::RST_08h,
+
+            0xD0 => Instruction::RET_NC,
+            0xD1 => Instruction::POP_DE,
+            0xD2 => Instruction::JP_NC_u16,
             0xD3 => Instruction::Invalid,
-            0xD4 => todo!(),
-            0xD5 => todo!(),
-            0xD6 => todo!(),
-            0xD7 => todo!(),
-            0xD8 => todo!(),
-            0xD9 => todo!(),
-            0xDA => todo!(),
+            0xD4 => Instruction::CALL_NC_u16,
+            0xD5 => Instruction::PUSH_DE,
+            0xD6 => Instruction::SUB_A_u8,
+            0xD7 => Instruction::RST_10h,
+            0xD8 => Instruction::RET_C,
+            0xD9 => Instruction::RETI,
+            0xDA => Instruction::JP_C_u16,
             0xDB => Instruction::Invalid,
-            0xDC => todo!(),
+            0xDC => Instruction::CALL_C_u16,
             0xDD => Instruction::Invalid,
-            0xDE => todo!(),
-            0xDF => todo!(),
+            0xDE => Instruction::SBC_A_u8,
+            0xDF => Instruction::RST_18h,
 
-            0xE0 => todo!(),
-            0xE1 => todo!(),
-            0xE2 => todo!(),
+            0xE0 => Instruction::LD_FF00_u8_A,
+            0xE1 => Instruction::POP_HL,
+            0xE2 => Instruction::LD_FF00_C_A,
             0xE3 => Instruction::Invalid,
             0xE4 => Instruction::Invalid,
-            0xE5 => todo!(),
-            0xE6 => todo!(),
-            0xE7 => todo!(),
-            0xE8 => todo!(),
-            0xE9 => todo!(),
-            0xEA => todo!(),
+            0xE5 => Instruction::PUSH_HL,
+            0xE6 => Instruction::AND_A_u8,
+            0xE7 => Instruction::RST_20h,
+            0xE8 => Instruction::ADD_SP_i8,
+            0xE9 => Instruction::JP_HL,
+            0xEA => Instruction::LD_u16_A,
             0xEB => Instruction::Invalid,
             0xEC => Instruction::Invalid,
             0xED => Instruction::Invalid,
-            0xEE => todo!(),
-            0xEF => todo!(),
+            0xEE => Instruction::XOR_A_u8,
+            0xEF => Instruction::RST_28h,
 
-            0xF0 => todo!(),
-            0xF1 => todo!(),
-            0xF2 => todo!(),
-            0xF3 => todo!(),
+            0xF0 => Instruction::LD_A_FF00_u8,
+            0xF1 => Instruction::POP_AF,
+            0xF2 => Instruction::LD_A_FF00_C,
+            0xF3 => Instruction::DI,
             0xF4 => Instruction::Invalid,
-            0xF5 => todo!(),
-            0xF6 => todo!(),
-            0xF7 => todo!(),
-            0xF8 => todo!(),
-            0xF9 => todo!(),
-            0xFA => todo!(),
-            0xFB => todo!(),
+            0xF5 => Instruction::PUSH_AF,
+            0xF6 => Instruction::OR_A_u8,
+            0xF7 => Instruction::RST_30h,
+            0xF8 => Instruction::LD_HL_SP_i8,
+            0xF9 => Instruction::LD_SP_HL,
+            0xFA => Instruction::LD_A_u16,
+            0xFB => Instruction::EI,
             0xFC => Instruction::Invalid,
             0xFD => Instruction::Invalid,
-            0xFE => todo!(),
-            0xFF => todo!(),
+            0xFE => Instruction::CP_A_u8,
+            0xFF => Instruction::RST_38h,
         }
     }
 
     pub fn decode_cb(byte: u8) -> Instruction {
         match byte {
-            0x00 => todo!(),
-            0x01 => todo!(),
-            0x02 => todo!(),
-            0x03 => todo!(),
-            0x04 => todo!(),
-            0x05 => todo!(),
-            0x06 => todo!(),
-            0x07 => todo!(),
-            0x08 => todo!(),
-            0x09 => todo!(),
-            0x0A => todo!(),
-            0x0B => todo!(),
-            0x0C => todo!(),
-            0x0D => todo!(),
-            0x0E => todo!(),
-            0x0F => todo!(),
-
-            0x10 => todo!(),
-            0x11 => todo!(),
-            0x12 => todo!(),
-            0x13 => todo!(),
-            0x14 => todo!(),
-            0x15 => todo!(),
-            0x16 => todo!(),
-            0x17 => todo!(),
-            0x18 => todo!(),
-            0x19 => todo!(),
-            0x1A => todo!(),
-            0x1B => todo!(),
-            0x1C => todo!(),
-            0x1D => todo!(),
-            0x1E => todo!(),
-            0x1F => todo!(),
-
-            0x20 => todo!(),
-            0x21 => todo!(),
-            0x22 => todo!(),
-            0x23 => todo!(),
-            0x24 => todo!(),
-            0x25 => todo!(),
-            0x26 => todo!(),
-            0x27 => todo!(),
-            0x28 => todo!(),
-            0x29 => todo!(),
-            0x2A => todo!(),
-            0x2B => todo!(),
-            0x2C => todo!(),
-            0x2D => todo!(),
-            0x2E => todo!(),
-            0x2F => todo!(),
-
-            0x30 => todo!(),
-            0x31 => todo!(),
-            0x32 => todo!(),
-            0x33 => todo!(),
-            0x34 => todo!(),
-            0x35 => todo!(),
-            0x36 => todo!(),
-            0x37 => todo!(),
-            0x38 => todo!(),
-            0x39 => todo!(),
-            0x3A => todo!(),
-            0x3B => todo!(),
-            0x3C => todo!(),
-            0x3D => todo!(),
-            0x3E => todo!(),
-            0x3F => todo!(),
-
-            0x40 => todo!(),
-            0x41 => todo!(),
-            0x42 => todo!(),
-            0x43 => todo!(),
-            0x44 => todo!(),
-            0x45 => todo!(),
-            0x46 => todo!(),
-            0x47 => todo!(),
-            0x48 => todo!(),
-            0x49 => todo!(),
-            0x4A => todo!(),
-            0x4B => todo!(),
-            0x4C => todo!(),
-            0x4D => todo!(),
-            0x4E => todo!(),
-            0x4F => todo!(),
-
-            0x50 => todo!(),
-            0x51 => todo!(),
-            0x52 => todo!(),
-            0x53 => todo!(),
-            0x54 => todo!(),
-            0x55 => todo!(),
-            0x56 => todo!(),
-            0x57 => todo!(),
-            0x58 => todo!(),
-            0x59 => todo!(),
-            0x5A => todo!(),
-            0x5B => todo!(),
-            0x5C => todo!(),
-            0x5D => todo!(),
-            0x5E => todo!(),
-            0x5F => todo!(),
-
-            0x60 => todo!(),
-            0x61 => todo!(),
-            0x62 => todo!(),
-            0x63 => todo!(),
-            0x64 => todo!(),
-            0x65 => todo!(),
-            0x66 => todo!(),
-            0x67 => todo!(),
-            0x68 => todo!(),
-            0x69 => todo!(),
-            0x6A => todo!(),
-            0x6B => todo!(),
-            0x6C => todo!(),
-            0x6D => todo!(),
-            0x6E => todo!(),
-            0x6F => todo!(),
-
-            0x70 => todo!(),
-            0x71 => todo!(),
-            0x72 => todo!(),
-            0x73 => todo!(),
-            0x74 => todo!(),
-            0x75 => todo!(),
-            0x76 => todo!(),
-            0x77 => todo!(),
-            0x78 => todo!(),
-            0x79 => todo!(),
-            0x7A => todo!(),
-            0x7B => todo!(),
-            0x7C => todo!(),
-            0x7D => todo!(),
-            0x7E => todo!(),
-            0x7F => todo!(),
-
-            0x80 => todo!(),
-            0x81 => todo!(),
-            0x82 => todo!(),
-            0x83 => todo!(),
-            0x84 => todo!(),
-            0x85 => todo!(),
-            0x86 => todo!(),
-            0x87 => todo!(),
-            0x88 => todo!(),
-            0x89 => todo!(),
-            0x8A => todo!(),
-            0x8B => todo!(),
-            0x8C => todo!(),
-            0x8D => todo!(),
-            0x8E => todo!(),
-            0x8F => todo!(),
-
-            0x90 => todo!(),
-            0x91 => todo!(),
-            0x92 => todo!(),
-            0x93 => todo!(),
-            0x94 => todo!(),
-            0x95 => todo!(),
-            0x96 => todo!(),
-            0x97 => todo!(),
-            0x98 => todo!(),
-            0x99 => todo!(),
-            0x9A => todo!(),
-            0x9B => todo!(),
-            0x9C => todo!(),
-            0x9D => todo!(),
-            0x9E => todo!(),
-            0x9F => todo!(),
-
-            0xA0 => todo!(),
-            0xA1 => todo!(),
-            0xA2 => todo!(),
-            0xA3 => todo!(),
-            0xA4 => todo!(),
-            0xA5 => todo!(),
-            0xA6 => todo!(),
-            0xA7 => todo!(),
-            0xA8 => todo!(),
-            0xA9 => todo!(),
-            0xAA => todo!(),
-            0xAB => todo!(),
-            0xAC => todo!(),
-            0xAD => todo!(),
-            0xAE => todo!(),
-            0xAF => todo!(),
-
-            0xB0 => todo!(),
-            0xB1 => todo!(),
-            0xB2 => todo!(),
-            0xB3 => todo!(),
-            0xB4 => todo!(),
-            0xB5 => todo!(),
-            0xB6 => todo!(),
-            0xB7 => todo!(),
-            0xB8 => todo!(),
-            0xB9 => todo!(),
-            0xBA => todo!(),
-            0xBB => todo!(),
-            0xBC => todo!(),
-            0xBD => todo!(),
-            0xBE => todo!(),
-            0xBF => todo!(),
-
-            0xC0 => todo!(),
-            0xC1 => todo!(),
-            0xC2 => todo!(),
-            0xC3 => todo!(),
-            0xC4 => todo!(),
-            0xC5 => todo!(),
-            0xC6 => todo!(),
-            0xC7 => todo!(),
-            0xC8 => todo!(),
-            0xC9 => todo!(),
-            0xCA => todo!(),
-            0xCB => todo!(),
-            0xCC => todo!(),
-            0xCD => todo!(),
-            0xCE => todo!(),
-            0xCF => todo!(),
-
-            0xD0 => todo!(),
-            0xD1 => todo!(),
-            0xD2 => todo!(),
-            0xD3 => todo!(),
-            0xD4 => todo!(),
-            0xD5 => todo!(),
-            0xD6 => todo!(),
-            0xD7 => todo!(),
-            0xD8 => todo!(),
-            0xD9 => todo!(),
-            0xDA => todo!(),
-            0xDB => todo!(),
-            0xDC => todo!(),
-            0xDD => todo!(),
-            0xDE => todo!(),
-            0xDF => todo!(),
-
-            0xE0 => todo!(),
-            0xE1 => todo!(),
-            0xE2 => todo!(),
-            0xE3 => todo!(),
-            0xE4 => todo!(),
-            0xE5 => todo!(),
-            0xE6 => todo!(),
-            0xE7 => todo!(),
-            0xE8 => todo!(),
-            0xE9 => todo!(),
-            0xEA => todo!(),
-            0xEB => todo!(),
-            0xEC => todo!(),
-            0xED => todo!(),
-            0xEE => todo!(),
-            0xEF => todo!(),
-
-            0xF0 => todo!(),
-            0xF1 => todo!(),
-            0xF2 => todo!(),
-            0xF3 => todo!(),
-            0xF4 => todo!(),
-            0xF5 => todo!(),
-            0xF6 => todo!(),
-            0xF7 => todo!(),
-            0xF8 => todo!(),
-            0xF9 => todo!(),
-            0xFA => todo!(),
-            0xFB => todo!(),
-            0xFC => todo!(),
-            0xFD => todo!(),
-            0xFE => todo!(),
-            0xFF => todo!(),
+            0x00 => Instruction::RLC_B,
+            0x01 => Instruction::RLC_C,
+            0x02 => Instruction::RLC_D,
+            0x03 => Instruction::RLC_E,
+            0x04 => Instruction::RLC_H,
+            0x05 => Instruction::RLC_L,
+            0x06 => Instruction::RLC_HL,
+            0x07 => Instruction::RLC_A,
+            0x08 => Instruction::RRC_B,
+            0x09 => Instruction::RRC_C,
+            0x0A => Instruction::RRC_D,
+            0x0B => Instruction::RRC_E,
+            0x0C => Instruction::RRC_H,
+            0x0D => Instruction::RRC_L,
+            0x0E => Instruction::RRC_HL,
+            0x0F => Instruction::RRC_A,
+
+            0x10 => Instruction::RL_B,
+            0x11 => Instruction::RL_C,
+            0x12 => Instruction::RL_D,
+            0x13 => Instruction::RL_E,
+            0x14 => Instruction::RL_H,
+            0x15 => Instruction::RL_L,
+            0x16 => Instruction::RL_HL,
+            0x17 => Instruction::RL_A,
+            0x18 => Instruction::RR_B,
+            0x19 => Instruction::RR_C,
+            0x1A => Instruction::RR_D,
+            0x1B => Instruction::RR_E,
+            0x1C => Instruction::RR_H,
+            0x1D => Instruction::RR_L,
+            0x1E => Instruction::RR_HL,
+            0x1F => Instruction::RR_A,
+
+            0x20 => Instruction::SLA_B,
+            0x21 => Instruction::SLA_C,
+            0x22 => Instruction::SLA_D,
+            0x23 => Instruction::SLA_E,
+            0x24 => Instruction::SLA_H,
+            0x25 => Instruction::SLA_L,
+            0x26 => Instruction::SLA_HL,
+            0x27 => Instruction::SLA_A,
+            0x28 => Instruction::SRA_B,
+            0x29 => Instruction::SRA_C,
+            0x2A => Instruction::SRA_D,
+            0x2B => Instruction::SRA_E,
+            0x2C => Instruction::SRA_H,
+            0x2D => Instruction::SRA_L,
+            0x2E => Instruction::SRA_HL,
+            0x2F => Instruction::SRA_A,
+
+            0x30 => Instruction::SWAP_B,
+            0x31 => Instruction::SWAP_C,
+            0x32 => Instruction::SWAP_D,
+            0x33 => Instruction::SWAP_E,
+            0x34 => Instruction::SWAP_H,
+            0x35 => Instruction::SWAP_L,
+            0x36 => Instruction::SWAP_HL,
+            0x37 => Instruction::SWAP_A,
+            0x38 => Instruction::SRL_B,
+            0x39 => Instruction::SRL_C,
+            0x3A => Instruction::SRL_D,
+            0x3B => Instruction::SRL_E,
+            0x3C => Instruction::SRL_H,
+            0x3D => Instruction::SRL_L,
+            0x3E => Instruction::SRL_HL,
+            0x3F => Instruction::SRL_A,
+
+            0x40 => Instruction::BIT_0_B,
+            0x41 => Instruction::BIT_0_C,
+            0x42 => Instruction::BIT_0_D,
+            0x43 => Instruction::BIT_0_E,
+            0x44 => Instruction::BIT_0_H,
+            0x45 => Instruction::BIT_0_L,
+            0x46 => Instruction::BIT_0_HL,
+            0x47 => Instruction::BIT_0_A,
+            0x48 => Instruction::BIT_1_B,
+            0x49 => Instruction::BIT_1_C,
+            0x4A => Instruction::BIT_1_D,
+            0x4B => Instruction::BIT_1_E,
+            0x4C => Instruction::BIT_1_H,
+            0x4D => Instruction::BIT_1_L,
+            0x4E => Instruction::BIT_1_HL,
+            0x4F => Instruction::BIT_1_A,
+
+            0x50 => Instruction::BIT_2_B,
+            0x51 => Instruction::BIT_2_C,
+            0x52 => Instruction::BIT_2_D,
+            0x53 => Instruction::BIT_2_E,
+            0x54 => Instruction::BIT_2_H,
+            0x55 => Instruction::BIT_2_L,
+            0x56 => Instruction::BIT_2_HL,
+            0x57 => Instruction::BIT_2_A,
+            0x58 => Instruction::BIT_3_B,
+            0x59 => Instruction::BIT_3_C,
+            0x5A => Instruction::BIT_3_D,
+            0x5B => Instruction::BIT_3_E,
+            0x5C => Instruction::BIT_3_H,
+            0x5D => Instruction::BIT_3_L,
+            0x5E => Instruction::BIT_3_HL,
+            0x5F => Instruction::BIT_3_A,
+
+            0x60 => Instruction::BIT_4_B,
+            0x61 => Instruction::BIT_4_C,
+            0x62 => Instruction::BIT_4_D,
+            0x63 => Instruction::BIT_4_E,
+            0x64 => Instruction::BIT_4_H,
+            0x65 => Instruction::BIT_4_L,
+            0x66 => Instruction::BIT_4_HL,
+            0x67 => Instruction::BIT_4_A,
+            0x68 => Instruction::BIT_5_B,
+            0x69 => Instruction::BIT_5_C,
+            0x6A => Instruction::BIT_5_D,
+            0x6B => Instruction::BIT_5_E,
+            0x6C => Instruction::BIT_5_H,
+            0x6D => Instruction::BIT_5_L,
+            0x6E => Instruction::BIT_5_HL,
+            0x6F => Instruction::BIT_5_A,
+
+            0x70 => Instruction::BIT_6_B,
+            0x71 => Instruction::BIT_6_C,
+            0x72 => Instruction::BIT_6_D,
+            0x73 => Instruction::BIT_6_E,
+            0x74 => Instruction::BIT_6_H,
+            0x75 => Instruction::BIT_6_L,
+            0x76 => Instruction::BIT_6_HL,
+            0x77 => Instruction::BIT_6_A,
+            0x78 => Instruction::BIT_7_B,
+            0x79 => Instruction::BIT_7_C,
+            0x7A => Instruction::BIT_7_D,
+            0x7B => Instruction::BIT_7_E,
+            0x7C => Instruction::BIT_7_H,
+            0x7D => Instruction::BIT_7_L,
+            0x7E => Instruction::BIT_7_HL,
+            0x7F => Instruction::BIT_7_A,
+
+            0x80 => Instruction::RES_0_B,
+            0x81 => Instruction::RES_0_C,
+            0x82 => Instruction::RES_0_D,
+            0x83 => Instruction::RES_0_E,
+            0x84 => Instruction::RES_0_H,
+            0x85 => Instruction::RES_0_L,
+            0x86 => Instruction::RES_0_HL,
+            0x87 => Instruction::RES_0_A,
+            0x88 => Instruction::RES_1_B,
+            0x89 => Instruction::RES_1_C,
+            0x8A => Instruction::RES_1_D,
+            0x8B => Instruction::RES_1_E,
+            0x8C => Instruction::RES_1_H,
+            0x8D => Instruction::RES_1_L,
+            0x8E => Instruction::RES_1_HL,
+            0x8F => Instruction::RES_1_A,
+
+            0x90 => Instruction::RES_2_B,
+            0x91 => Instruction::RES_2_C,
+            0x92 => Instruction::RES_2_D,
+            0x93 => Instruction::RES_2_E,
+            0x94 => Instruction::RES_2_H,
+            0x95 => Instruction::RES_2_L,
+            0x96 => Instruction::RES_2_HL,
+            0x97 => Instruction::RES_2_A,
+            0x98 => Instruction::RES_3_B,
+            0x99 => Instruction::RES_3_C,
+            0x9A => Instruction::RES_3_D,
+            0x9B => Instruction::RES_3_E,
+            0x9C => Instruction::RES_3_H,
+            0x9D => Instruction::RES_3_L,
+            0x9E => Instruction::RES_3_HL,
+            0x9F => Instruction::RES_3_A,
+
+            0xA0 => Instruction::RES_4_B,
+            0xA1 => Instruction::RES_4_C,
+            0xA2 => Instruction::RES_4_D,
+            0xA3 => Instruction::RES_4_E,
+            0xA4 => Instruction::RES_4_H,
+            0xA5 => Instruction::RES_4_L,
+            0xA6 => Instruction::RES_4_HL,
+            0xA7 => Instruction::RES_4_A,
+            0xA8 => Instruction::RES_5_B,
+            0xA9 => Instruction::RES_5_C,
+            0xAA => Instruction::RES_5_D,
+            0xAB => Instruction::RES_5_E,
+            0xAC => Instruction::RES_5_H,
+            0xAD => Instruction::RES_5_L,
+            0xAE => Instruction::RES_5_HL,
+            0xAF => Instruction::RES_5_A,
+
+            0xB0 => Instruction::RES_6_B,
+            0xB1 => Instruction::RES_6_C,
+            0xB2 => Instruction::RES_6_D,
+            0xB3 => Instruction::RES_6_E,
+            0xB4 => Instruction::RES_6_H,
+            0xB5 => Instruction::RES_6_L,
+            0xB6 => Instruction::RES_6_HL,
+            0xB7 => Instruction::RES_6_A,
+            0xB8 => Instruction::RES_7_B,
+            0xB9 => Instruction::RES_7_C,
+            0xBA => Instruction::RES_7_D,
+            0xBB => Instruction::RES_7_E,
+            0xBC => Instruction::RES_7_H,
+            0xBD => Instruction::RES_7_L,
+            0xBE => Instruction::RES_7_HL,
+            0xBF => Instruction::RES_7_A,
+
+            0xC0 => Instruction::SET_0_B,
+            0xC1 => Instruction::SET_0_C,
+            0xC2 => Instruction::SET_0_D,
+            0xC3 => Instruction::SET_0_E,
+            0xC4 => Instruction::SET_0_H,
+            0xC5 => Instruction::SET_0_L,
+            0xC6 => Instruction::SET_0_HL,
+            0xC7 => Instruction::SET_0_A,
+            0xC8 => Instruction::SET_1_B,
+            0xC9 => Instruction::SET_1_C,
+            0xCA => Instruction::SET_1_D,
+            0xCB => Instruction::SET_1_E,
+            0xCC => Instruction::SET_1_H,
+            0xCD => Instruction::SET_1_L,
+            0xCE => Instruction::SET_1_HL,
+            0xCF => Instruction::SET_1_A,
+
+            0xD0 => Instruction::SET_2_B,
+            0xD1 => Instruction::SET_2_C,
+            0xD2 => Instruction::SET_2_D,
+            0xD3 => Instruction::SET_2_E,
+            0xD4 => Instruction::SET_2_H,
+            0xD5 => Instruction::SET_2_L,
+            0xD6 => Instruction::SET_2_HL,
+            0xD7 => Instruction::SET_2_A,
+            0xD8 => Instruction::SET_3_B,
+            0xD9 => Instruction::SET_3_C,
+            0xDA => Instruction::SET_3_D,
+            0xDB => Instruction::SET_3_E,
+            0xDC => Instruction::SET_3_H,
+            0xDD => Instruction::SET_3_L,
+            0xDE => Instruction::SET_3_HL,
+            0xDF => Instruction::SET_3_A,
+
+            0xE0 => Instruction::SET_4_B,
+            0xE1 => Instruction::SET_4_C,
+            0xE2 => Instruction::SET_4_D,
+            0xE3 => Instruction::SET_4_E,
+            0xE4 => Instruction::SET_4_H,
+            0xE5 => Instruction::SET_4_L,
+            0xE6 => Instruction::SET_4_HL,
+            0xE7 => Instruction::SET_4_A,
+            0xE8 => Instruction::SET_5_B,
+            0xE9 => Instruction::SET_5_C,
+            0xEA => Instruction::SET_5_D,
+            0xEB => Instruction::SET_5_E,
+            0xEC => Instruction::SET_5_H,
+            0xED => Instruction::SET_5_L,
+            0xEE => Instruction::SET_5_HL,
+            0xEF => Instruction::SET_5_A,
+
+            0xF0 => Instruction::SET_6_B,
+            0xF1 => Instruction::SET_6_C,
+            0xF2 => Instruction::SET_6_D,
+            0xF3 => Instruction::SET_6_E,
+            0xF4 => Instruction::SET_6_H,
+            0xF5 => Instruction::SET_6_L,
+            0xF6 => Instruction::SET_6_HL,
+            0xF7 => Instruction::SET_6_A,
+            0xF8 => Instruction::SET_7_B,
+            0xF9 => Instruction::SET_7_C,
+            0xFA => Instruction::SET_7_D,
+            0xFB => Instruction::SET_7_E,
+            0xFC => Instruction::SET_7_H,
+            0xFD => Instruction::SET_7_L,
+            0xFE => Instruction::SET_7_HL,
+            0xFF => Instruction::SET_7_A,
+        }
+    }
+
+    /// Assembly in rgbds syntax, with placeholders for the operands that
+    /// follow the opcode: `{u8}` and `{u16}` immediates, `{a8}` the low byte
+    /// of a 0xFF00 page address, `{i8}` and `{s8}` signed offsets, the latter
+    /// with its sign always shown, and `{r8}` a relative jump target
+    pub fn template(self) -> &'static str {
+        match self {
+            Instruction::LD_A_u8 => "ld a, {u8}",
+            Instruction::LD_B_u8 => "ld b, {u8}",
+            Instruction::LD_C_u8 => "ld c, {u8}",
+            Instruction::LD_D_u8 => "ld d, {u8}",
+            Instruction::LD_E_u8 => "ld e, {u8}",
+            Instruction::LD_H_u8 => "ld h, {u8}",
+            Instruction::LD_L_u8 => "ld l, {u8}",
+            Instruction::LD_A_BC => "ld a, [bc]",
+            Instruction::LD_BC_A => "ld [bc], a",
+            Instruction::LD_A_DE => "ld a, [de]",
+            Instruction::LD_DE_A => "ld [de], a",
+            Instruction::LD_HL_A_Plus => "ld [hl+], a",
+            Instruction::LD_HL_A_Minus => "ld [hl-], a",
+            Instruction::LD_A_HL_Plus => "ld a, [hl+]",
+            Instruction::LD_A_HL_Minus => "ld a, [hl-]",
+            Instruction::LD_HL_u8 => "ld [hl], {u8}",
+            Instruction::LD_A_A => "ld a, a",
+            Instruction::LD_A_B => "ld a, b",
+            Instruction::LD_A_C => "ld a, c",
+            Instruction::LD_A_D => "ld a, d",
+            Instruction::LD_A_E => "ld a, e",
+            Instruction::LD_A_H => "ld a, h",
+            Instruction::LD_A_L => "ld a, l",
+            Instruction::LD_A_HL => "ld a, [hl]",
+            Instruction::LD_B_A => "ld b, a",
+            Instruction::LD_B_B => "ld b, b",
+            Instruction::LD_B_C => "ld b, c",
+            Instruction::LD_B_D => "ld b, d",
+            Instruction::LD_B_E => "ld b, e",
+            Instruction::LD_B_H => "ld b, h",
+            Instruction::LD_B_L => "ld b, l",
+            Instruction::LD_B_HL => "ld b, [hl]",
+            Instruction::LD_C_A => "ld c, a",
+            Instruction::LD_C_B => "ld c, b",
+            Instruction::LD_C_C => "ld c, c",
+            Instruction::LD_C_D => "ld c, d",
+            Instruction::LD_C_E => "ld c, e",
+            Instruction::LD_C_H => "ld c, h",
+            Instruction::LD_C_L => "ld c, l",
+            Instruction::LD_C_HL => "ld c, [hl]",
+            Instruction::LD_D_A => "ld d, a",
+            Instruction::LD_D_B => "ld d, b",
+            Instruction::LD_D_C => "ld d, c",
+            Instruction::LD_D_D => "ld d, d",
+            Instruction::LD_D_E => "ld d, e",
+            Instruction::LD_D_H => "ld d, h",
+            Instruction::LD_D_L => "ld d, l",
+            Instruction::LD_D_HL => "ld d, [hl]",
+            Instruction::LD_E_A => "ld e, a",
+            Instruction::LD_E_B => "ld e, b",
+            Instruction::LD_E_C => "ld e, c",
+            Instruction::LD_E_D => "ld e, d",
+            Instruction::LD_E_E => "ld e, e",
+            Instruction::LD_E_H => "ld e, h",
+            Instruction::LD_E_L => "ld e, l",
+            Instruction::LD_E_HL => "ld e, [hl]",
+            Instruction::LD_H_A => "ld h, a",
+            Instruction::LD_H_B => "ld h, b",
+            Instruction::LD_H_C => "ld h, c",
+            Instruction::LD_H_D => "ld h, d",
+            Instruction::LD_H_E => "ld h, e",
+            Instruction::LD_H_H => "ld h, h",
+            Instruction::LD_H_L => "ld h, l",
+            Instruction::LD_H_HL => "ld h, [hl]",
+            Instruction::LD_L_A => "ld l, a",
+            Instruction::LD_L_B => "ld l, b",
+            Instruction::LD_L_C => "ld l, c",
+            Instruction::LD_L_D => "ld l, d",
+            Instruction::LD_L_E => "ld l, e",
+            Instruction::LD_L_H => "ld l, h",
+            Instruction::LD_L_L => "ld l, l",
+            Instruction::LD_L_HL => "ld l, [hl]",
+            Instruction::LD_HL_A => "ld [hl], a",
+            Instruction::LD_HL_B => "ld [hl], b",
+            Instruction::LD_HL_C => "ld [hl], c",
+            Instruction::LD_HL_D => "ld [hl], d",
+            Instruction::LD_HL_E => "ld [hl], e",
+            Instruction::LD_HL_H => "ld [hl], h",
+            Instruction::LD_HL_L => "ld [hl], l",
+            Instruction::LD_FF00_u8_A => "ldh [{a8}], a",
+            Instruction::LD_A_FF00_u8 => "ldh a, [{a8}]",
+            Instruction::LD_FF00_C_A => "ldh [c], a",
+            Instruction::LD_A_FF00_C => "ldh a, [c]",
+            Instruction::LD_u16_A => "ld [{u16}], a",
+            Instruction::LD_A_u16 => "ld a, [{u16}]",
+            Instruction::LD_BC_u16 => "ld bc, {u16}",
+            Instruction::LD_DE_u16 => "ld de, {u16}",
+            Instruction::LD_HL_u16 => "ld hl, {u16}",
+            Instruction::LD_SP_u16 => "ld sp, {u16}",
+            Instruction::LD_u16_SP => "ld [{u16}], sp",
+            Instruction::LD_SP_HL => "ld sp, hl",
+            Instruction::LD_HL_SP_i8 => "ld hl, sp{s8}",
+            Instruction::PUSH_BC => "push bc",
+            Instruction::PUSH_DE => "push de",
+            Instruction::PUSH_HL => "push hl",
+            Instruction::PUSH_AF => "push af",
+            Instruction::POP_BC => "pop bc",
+            Instruction::POP_DE => "pop de",
+            Instruction::POP_HL => "pop hl",
+            Instruction::POP_AF => "pop af",
+            Instruction::INC_A => "inc a",
+            Instruction::INC_B => "inc b",
+            Instruction::INC_C => "inc c",
+            Instruction::INC_D => "inc d",
+            Instruction::INC_E => "inc e",
+            Instruction::INC_H => "inc h",
+            Instruction::INC_L => "inc l",
+            Instruction::INC_HL_Indirect => "inc [hl]",
+            Instruction::DEC_A => "dec a",
+            Instruction::DEC_B => "dec b",
+            Instruction::DEC_C => "dec c",
+            Instruction::DEC_D => "dec d",
+            Instruction::DEC_E => "dec e",
+            Instruction::DEC_H => "dec h",
+            Instruction::DEC_L => "dec l",
+            Instruction::DEC_HL_Indirect => "dec [hl]",
+            Instruction::ADD_A_A => "add a, a",
+            Instruction::ADD_A_B => "add a, b",
+            Instruction::ADD_A_C => "add a, c",
+            Instruction::ADD_A_D => "add a, d",
+            Instruction::ADD_A_E => "add a, e",
+            Instruction::ADD_A_H => "add a, h",
+            Instruction::ADD_A_L => "add a, l",
+            Instruction::ADD_A_HL => "add a, [hl]",
+            Instruction::ADD_A_u8 => "add a, {u8}",
+            Instruction::ADC_A_A => "adc a, a",
+            Instruction::ADC_A_B => "adc a, b",
+            Instruction::ADC_A_C => "adc a, c",
+            Instruction::ADC_A_D => "adc a, d",
+            Instruction::ADC_A_E => "adc a, e",
+            Instruction::ADC_A_H => "adc a, h",
+            Instruction::ADC_A_L => "adc a, l",
+            Instruction::ADC_A_HL => "adc a, [hl]",
+            Instruction::ADC_A_u8 => "adc a, {u8}",
+            Instruction::SUB_A_A => "sub a, a",
+            Instruction::SUB_A_B => "sub a, b",
+            Instruction::SUB_A_C => "sub a, c",
+            Instruction::SUB_A_D => "sub a, d",
+            Instruction::SUB_A_E => "sub a, e",
+            Instruction::SUB_A_H => "sub a, h",
+            Instruction::SUB_A_L => "sub a, l",
+            Instruction::SUB_A_HL => "sub a, [hl]",
+            Instruction::SUB_A_u8 => "sub a, {u8}",
+            Instruction::SBC_A_A => "sbc a, a",
+            Instruction::SBC_A_B => "sbc a, b",
+            Instruction::SBC_A_C => "sbc a, c",
+            Instruction::SBC_A_D => "sbc a, d",
+            Instruction::SBC_A_E => "sbc a, e",
+            Instruction::SBC_A_H => "sbc a, h",
+            Instruction::SBC_A_L => "sbc a, l",
+            Instruction::SBC_A_HL => "sbc a, [hl]",
+            Instruction::SBC_A_u8 => "sbc a, {u8}",
+            Instruction::AND_A_A => "and a, a",
+            Instruction::AND_A_B => "and a, b",
+            Instruction::AND_A_C => "and a, c",
+            Instruction::AND_A_D => "and a, d",
+            Instruction::AND_A_E => "and a, e",
+            Instruction::AND_A_H => "and a, h",
+            Instruction::AND_A_L => "and a, l",
+            Instruction::AND_A_HL => "and a, [hl]",
+            Instruction::AND_A_u8 => "and a, {u8}",
+            Instruction::XOR_A_A => "xor a, a",
+            Instruction::XOR_A_B => "xor a, b",
+            Instruction::XOR_A_C => "xor a, c",
+            Instruction::XOR_A_D => "xor a, d",
+            Instruction::XOR_A_E => "xor a, e",
+            Instruction::XOR_A_H => "xor a, h",
+            Instruction::XOR_A_L => "xor a, l",
+            Instruction::XOR_A_HL => "xor a, [hl]",
+            Instruction::XOR_A_u8 => "xor a, {u8}",
+            Instruction::OR_A_A => "or a, a",
+            Instruction::OR_A_B => "or a, b",
+            Instruction::OR_A_C => "or a, c",
+            Instruction::OR_A_D => "or a, d",
+            Instruction::OR_A_E => "or a, e",
+            Instruction::OR_A_H => "or a, h",
+            Instruction::OR_A_L => "or a, l",
+            Instruction::OR_A_HL => "or a, [hl]",
+            Instruction::OR_A_u8 => "or a, {u8}",
+            Instruction::CP_A_A => "cp a, a",
+            Instruction::CP_A_B => "cp a, b",
+            Instruction::CP_A_C => "cp a, c",
+            Instruction::CP_A_D => "cp a, d",
+            Instruction::CP_A_E => "cp a, e",
+            Instruction::CP_A_H => "cp a, h",
+            Instruction::CP_A_L => "cp a, l",
+            Instruction::CP_A_HL => "cp a, [hl]",
+            Instruction::CP_A_u8 => "cp a, {u8}",
+            Instruction::DAA => "daa",
+            Instruction::SCF => "scf",
+            Instruction::CPL => "cpl",
+            Instruction::CCF => "ccf",
+            Instruction::INC_BC => "inc bc",
+            Instruction::INC_DE => "inc de",
+            Instruction::INC_HL => "inc hl",
+            Instruction::INC_SP => "inc sp",
+            Instruction::DEC_BC => "dec bc",
+            Instruction::DEC_DE => "dec de",
+            Instruction::DEC_HL => "dec hl",
+            Instruction::DEC_SP => "dec sp",
+            Instruction::ADD_HL_BC => "add hl, bc",
+            Instruction::ADD_HL_DE => "add hl, de",
+            Instruction::ADD_HL_HL => "add hl, hl",
+            Instruction::ADD_HL_SP => "add hl, sp",
+            Instruction::ADD_SP_i8 => "add sp, {i8}",
+            Instruction::RLCA => "rlca",
+            Instruction::RRCA => "rrca",
+            Instruction::RLA => "rla",
+            Instruction::RRA => "rra",
+            Instruction::RLC_A => "rlc a",
+            Instruction::RLC_B => "rlc b",
+            Instruction::RLC_C => "rlc c",
+            Instruction::RLC_D => "rlc d",
+            Instruction::RLC_E => "rlc e",
+            Instruction::RLC_H => "rlc h",
+            Instruction::RLC_L => "rlc l",
+            Instruction::RLC_HL => "rlc [hl]",
+            Instruction::RRC_A => "rrc a",
+            Instruction::RRC_B => "rrc b",
+            Instruction::RRC_C => "rrc c",
+            Instruction::RRC_D => "rrc d",
+            Instruction::RRC_E => "rrc e",
+            Instruction::RRC_H => "rrc h",
+            Instruction::RRC_L => "rrc l",
+            Instruction::RRC_HL => "rrc [hl]",
+            Instruction::RL_A => "rl a",
+            Instruction::RL_B => "rl b",
+            Instruction::RL_C => "rl c",
+            Instruction::RL_D => "rl d",
+            Instruction::RL_E => "rl e",
+            Instruction::RL_H => "rl h",
+            Instruction::RL_L => "rl l",
+            Instruction::RL_HL => "rl [hl]",
+            Instruction::RR_A => "rr a",
+            Instruction::RR_B => "rr b",
+            Instruction::RR_C => "rr c",
+            Instruction::RR_D => "rr d",
+            Instruction::RR_E => "rr e",
+            Instruction::RR_H => "rr h",
+            Instruction::RR_L => "rr l",
+            Instruction::RR_HL => "rr [hl]",
+            Instruction::SLA_A => "sla a",
+            Instruction::SLA_B => "sla b",
+            Instruction::SLA_C => "sla c",
+            Instruction::SLA_D => "sla d",
+            Instruction::SLA_E => "sla e",
+            Instruction::SLA_H => "sla h",
+            Instruction::SLA_L => "sla l",
+            Instruction::SLA_HL => "sla [hl]",
+            Instruction::SRA_A => "sra a",
+            Instruction::SRA_B => "sra b",
+            Instruction::SRA_C => "sra c",
+            Instruction::SRA_D => "sra d",
+            Instruction::SRA_E => "sra e",
+            Instruction::SRA_H => "sra h",
+            Instruction::SRA_L => "sra l",
+            Instruction::SRA_HL => "sra [hl]",
+            Instruction::SWAP_A => "swap a",
+            Instruction::SWAP_B => "swap b",
+            Instruction::SWAP_C => "swap c",
+            Instruction::SWAP_D => "swap d",
+            Instruction::SWAP_E => "swap e",
+            Instruction::SWAP_H => "swap h",
+            Instruction::SWAP_L => "swap l",
+            Instruction::SWAP_HL => "swap [hl]",
+            Instruction::SRL_A => "srl a",
+            Instruction::SRL_B => "srl b",
+            Instruction::SRL_C => "srl c",
+            Instruction::SRL_D => "srl d",
+            Instruction::SRL_E => "srl e",
+            Instruction::SRL_H => "srl h",
+            Instruction::SRL_L => "srl l",
+            Instruction::SRL_HL => "srl [hl]",
+            Instruction::BIT_0_A => "bit 0, a",
+            Instruction::BIT_0_B => "bit 0, b",
+            Instruction::BIT_0_C => "bit 0, c",
+            Instruction::BIT_0_D => "bit 0, d",
+            Instruction::BIT_0_E => "bit 0, e",
+            Instruction::BIT_0_H => "bit 0, h",
+            Instruction::BIT_0_L => "bit 0, l",
+            Instruction::BIT_0_HL => "bit 0, [hl]",
+            Instruction::BIT_1_A => "bit 1, a",
+            Instruction::BIT_1_B => "bit 1, b",
+            Instruction::BIT_1_C => "bit 1, c",
+            Instruction::BIT_1_D => "bit 1, d",
+            Instruction::BIT_1_E => "bit 1, e",
+            Instruction::BIT_1_H => "bit 1, h",
+            Instruction::BIT_1_L => "bit 1, l",
+            Instruction::BIT_1_HL => "bit 1, [hl]",
+            Instruction::BIT_2_A => "bit 2, a",
+            Instruction::BIT_2_B => "bit 2, b",
+            Instruction::BIT_2_C => "bit 2, c",
+            Instruction::BIT_2_D => "bit 2, d",
+            Instruction::BIT_2_E => "bit 2, e",
+            Instruction::BIT_2_H => "bit 2, h",
+            Instruction::BIT_2_L => "bit 2, l",
+            Instruction::BIT_2_HL => "bit 2, [hl]",
+            Instruction::BIT_3_A => "bit 3, a",
+            Instruction::BIT_3_B => "bit 3, b",
+            Instruction::BIT_3_C => "bit 3, c",
+            Instruction::BIT_3_D => "bit 3, d",
+            Instruction::BIT_3_E => "bit 3, e",
+            Instruction::BIT_3_H => "bit 3, h",
+            Instruction::BIT_3_L => "bit 3, l",
+            Instruction::BIT_3_HL => "bit 3, [hl]",
+            Instruction::BIT_4_A => "bit 4, a",
+            Instruction::BIT_4_B => "bit 4, b",
+            Instruction::BIT_4_C => "bit 4, c",
+            Instruction::BIT_4_D => "bit 4, d",
+            Instruction::BIT_4_E => "bit 4, e",
+            Instruction::BIT_4_H => "bit 4, h",
+            Instruction::BIT_4_L => "bit 4, l",
+            Instruction::BIT_4_HL => "bit 4, [hl]",
+            Instruction::BIT_5_A => "bit 5, a",
+            Instruction::BIT_5_B => "bit 5, b",
+            Instruction::BIT_5_C => "bit 5, c",
+            Instruction::BIT_5_D => "bit 5, d",
+            Instruction::BIT_5_E => "bit 5, e",
+            Instruction::BIT_5_H => "bit 5, h",
+            Instruction::BIT_5_L => "bit 5, l",
+            Instruction::BIT_5_HL => "bit 5, [hl]",
+            Instruction::BIT_6_A => "bit 6, a",
+            Instruction::BIT_6_B => "bit 6, b",
+            Instruction::BIT_6_C => "bit 6, c",
+            Instruction::BIT_6_D => "bit 6, d",
+            Instruction::BIT_6_E => "bit 6, e",
+            Instruction::BIT_6_H => "bit 6, h",
+            Instruction::BIT_6_L => "bit 6, l",
+            Instruction::BIT_6_HL => "bit 6, [hl]",
+            Instruction::BIT_7_A => "bit 7, a",
+            Instruction::BIT_7_B => "bit 7, b",
+            Instruction::BIT_7_C => "bit 7, c",
+            Instruction::BIT_7_D => "bit 7, d",
+            Instruction::BIT_7_E => "bit 7, e",
+            Instruction::BIT_7_H => "bit 7, h",
+            Instruction::BIT_7_L => "bit 7, l",
+            Instruction::BIT_7_HL => "bit 7, [hl]",
+            Instruction::RES_0_A => "res 0, a",
+            Instruction::RES_0_B => "res 0, b",
+            Instruction::RES_0_C => "res 0, c",
+            Instruction::RES_0_D => "res 0, d",
+            Instruction::RES_0_E => "res 0, e",
+            Instruction::RES_0_H => "res 0, h",
+            Instruction::RES_0_L => "res 0, l",
+            Instruction::RES_0_HL => "res 0, [hl]",
+            Instruction::RES_1_A => "res 1, a",
+            Instruction::RES_1_B => "res 1, b",
+            Instruction::RES_1_C => "res 1, c",
+            Instruction::RES_1_D => "res 1, d",
+            Instruction::RES_1_E => "res 1, e",
+            Instruction::RES_1_H => "res 1, h",
+            Instruction::RES_1_L => "res 1, l",
+            Instruction::RES_1_HL => "res 1, [hl]",
+            Instruction::RES_2_A => "res 2, a",
+            Instruction::RES_2_B => "res 2, b",
+            Instruction::RES_2_C => "res 2, c",
+            Instruction::RES_2_D => "res 2, d",
+            Instruction::RES_2_E => "res 2, e",
+            Instruction::RES_2_H => "res 2, h",
+            Instruction::RES_2_L => "res 2, l",
+            Instruction::RES_2_HL => "res 2, [hl]",
+            Instruction::RES_3_A => "res 3, a",
+            Instruction::RES_3_B => "res 3, b",
+            Instruction::RES_3_C => "res 3, c",
+            Instruction::RES_3_D => "res 3, d",
+            Instruction::RES_3_E => "res 3, e",
+            Instruction::RES_3_H => "res 3, h",
+            Instruction::RES_3_L => "res 3, l",
+            Instruction::RES_3_HL => "res 3, [hl]",
+            Instruction::RES_4_A => "res 4, a",
+            Instruction::RES_4_B => "res 4, b",
+            Instruction::RES_4_C => "res 4, c",
+            Instruction::RES_4_D => "res 4, d",
+            Instruction::RES_4_E => "res 4, e",
+            Instruction::RES_4_H => "res 4, h",
+            Instruction::RES_4_L => "res 4, l",
+            Instruction::RES_4_HL => "res 4, [hl]",
+            Instruction::RES_5_A => "res 5, a",
+            Instruction::RES_5_B => "res 5, b",
+            Instruction::RES_5_C => "res 5, c",
+            Instruction::RES_5_D => "res 5, d",
+            Instruction::RES_5_E => "res 5, e",
+            Instruction::RES_5_H => "res 5, h",
+            Instruction::RES_5_L => "res 5, l",
+            Instruction::RES_5_HL => "res 5, [hl]",
+            Instruction::RES_6_A => "res 6, a",
+            Instruction::RES_6_B => "res 6, b",
+            Instruction::RES_6_C => "res 6, c",
+            Instruction::RES_6_D => "res 6, d",
+            Instruction::RES_6_E => "res 6, e",
+            Instruction::RES_6_H => "res 6, h",
+            Instruction::RES_6_L => "res 6, l",
+            Instruction::RES_6_HL => "res 6, [hl]",
+            Instruction::RES_7_A => "res 7, a",
+            Instruction::RES_7_B => "res 7, b",
+            Instruction::RES_7_C => "res 7, c",
+            Instruction::RES_7_D => "res 7, d",
+            Instruction::RES_7_E => "res 7, e",
+            Instruction::RES_7_H => "res 7, h",
+            Instruction::RES_7_L => "res 7, l",
+            Instruction::RES_7_HL => "res 7, [hl]",
+            Instruction::SET_0_A => "set 0, a",
+            Instruction::SET_0_B => "set 0, b",
+            Instruction::SET_0_C => "set 0, c",
+            Instruction::SET_0_D => "set 0, d",
+            Instruction::SET_0_E => "set 0, e",
+            Instruction::SET_0_H => "set 0, h",
+            Instruction::SET_0_L => "set 0, l",
+            Instruction::SET_0_HL => "set 0, [hl]",
+            Instruction::SET_1_A => "set 1, a",
+            Instruction::SET_1_B => "set 1, b",
+            Instruction::SET_1_C => "set 1, c",
+            Instruction::SET_1_D => "set 1, d",
+            Instruction::SET_1_E => "set 1, e",
+            Instruction::SET_1_H => "set 1, h",
+            Instruction::SET_1_L => "set 1, l",
+            Instruction::SET_1_HL => "set 1, [hl]",
+            Instruction::SET_2_A => "set 2, a",
+            Instruction::SET_2_B => "set 2, b",
+            Instruction::SET_2_C => "set 2, c",
+            Instruction::SET_2_D => "set 2, d",
+            Instruction::SET_2_E => "set 2, e",
+            Instruction::SET_2_H => "set 2, h",
+            Instruction::SET_2_L => "set 2, l",
+            Instruction::SET_2_HL => "set 2, [hl]",
+            Instruction::SET_3_A => "set 3, a",
+            Instruction::SET_3_B => "set 3, b",
+            Instruction::SET_3_C => "set 3, c",
+            Instruction::SET_3_D => "set 3, d",
+            Instruction::SET_3_E => "set 3, e",
+            Instruction::SET_3_H => "set 3, h",
+            Instruction::SET_3_L => "set 3, l",
+            Instruction::SET_3_HL => "set 3, [hl]",
+            Instruction::SET_4_A => "set 4, a",
+            Instruction::SET_4_B => "set 4, b",
+            Instruction::SET_4_C => "set 4, c",
+            Instruction::SET_4_D => "set 4, d",
+            Instruction::SET_4_E => "set 4, e",
+            Instruction::SET_4_H => "set 4, h",
+            Instruction::SET_4_L => "set 4, l",
+            Instruction::SET_4_HL => "set 4, [hl]",
+            Instruction::SET_5_A => "set 5, a",
+            Instruction::SET_5_B => "set 5, b",
+            Instruction::SET_5_C => "set 5, c",
+            Instruction::SET_5_D => "set 5, d",
+            Instruction::SET_5_E => "set 5, e",
+            Instruction::SET_5_H => "set 5, h",
+            Instruction::SET_5_L => "set 5, l",
+            Instruction::SET_5_HL => "set 5, [hl]",
+            Instruction::SET_6_A => "set 6, a",
+            Instruction::SET_6_B => "set 6, b",
+            Instruction::SET_6_C => "set 6, c",
+            Instruction::SET_6_D => "set 6, d",
+            Instruction::SET_6_E => "set 6, e",
+            Instruction::SET_6_H => "set 6, h",
+            Instruction::SET_6_L => "set 6, l",
+            Instruction::SET_6_HL => "set 6, [hl]",
+            Instruction::SET_7_A => "set 7, a",
+            Instruction::SET_7_B => "set 7, b",
+            Instruction::SET_7_C => "set 7, c",
+            Instruction::SET_7_D => "set 7, d",
+            Instruction::SET_7_E => "set 7, e",
+            Instruction::SET_7_H => "set 7, h",
+            Instruction::SET_7_L => "set 7, l",
+            Instruction::SET_7_HL => "set 7, [hl]",
+            Instruction::JR_i8 => "jr {r8}",
+            Instruction::JR_NZ_i8 => "jr nz, {r8}",
+            Instruction::JR_NC_i8 => "jr nc, {r8}",
+            Instruction::JR_C_i8 => "jr c, {r8}",
+            Instruction::JR_Z_i8 => "jr z, {r8}",
+            Instruction::JP_u16 => "jp {u16}",
+            Instruction::JP_NZ_u16 => "jp nz, {u16}",
+            Instruction::JP_NC_u16 => "jp nc, {u16}",
+            Instruction::JP_C_u16 => "jp c, {u16}",
+            Instruction::JP_Z_u16 => "jp z, {u16}",
+            Instruction::JP_HL => "jp hl",
+            Instruction::CALL_u16 => "call {u16}",
+            Instruction::CALL_NZ_u16 => "call nz, {u16}",
+            Instruction::CALL_NC_u16 => "call nc, {u16}",
+            Instruction::CALL_C_u16 => "call c, {u16}",
+            Instruction::CALL_Z_u16 => "call z, {u16}",
+            Instruction::RET => "ret",
+            Instruction::RET_NZ => "ret nz",
+            Instruction::RET_NC => "ret nc",
+            Instruction::RET_C => "ret c",
+            Instruction::RET_Z => "ret z",
+            Instruction::RETI => "reti",
+            Instruction::RST_00h => "rst $00",
+            Instruction::RST_08h => "rst $08",
+            Instruction::RST_10h => "rst $10",
+            Instruction::RST_18h => "rst $18",
+            Instruction::RST_20h => "rst $20",
+            Instruction::RST_28h => "rst $28",
+            Instruction::RST_30h => "rst $30",
+            Instruction::RST_38h => "rst $38",
+            Instruction::STOP => "stop",
+            Instruction::NOP => "nop",
+            Instruction::HALT => "halt",
+            Instruction::DI => "di",
+            Instruction::EI => "ei",
+            Instruction::Invalid => "db",
+            Instruction::CB => "prefix",
         }
     }
 }
+
+/// How an instruction leaves a flag
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FlagEffect {
+    Unaffected,
+    Reset,
+    Set,
+    /// Depends on the result
+    Changed,
+}
+
+/// Immediate operand following the opcode, named as in `Instruction::template`
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OperandKind {
+    Implied,
+    U8,
+    U16,
+    A8,
+    I8,
+    S8,
+    R8,
+}
+
+/// Static facts about an opcode, shared by the CPU and the disassembler
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Metadata {
+    /// Bytes, opcode included
+    pub length: u8,
+    /// M-cycles, when not branching for conditional instructions
+    pub cycles: u8,
+    /// M-cycles of conditional instructions when branching
+    pub cycles_taken: Option<u8>,
+    /// Effect on Z, N, H and C
+    pub flags: [FlagEffect; 4],
+    pub operand: OperandKind,
+}
+
+/// Metadata from the notation of opcode tables, e.g. `b"Z0H-"` for flags
+/// and 0 cycles taken for instructions that do not branch
+const fn op(
+    length: u8,
+    cycles: u8,
+    cycles_taken: u8,
+    flags: &[u8; 4],
+    operand: OperandKind,
+) -> Metadata {
+    let mut effects = [FlagEffect::Unaffected; 4];
+    let mut i = 0;
+    while i < 4 {
+        effects[i] = match flags[i] {
+            b'-' => FlagEffect::Unaffected,
+            b'0' => FlagEffect::Reset,
+            b'1' => FlagEffect::Set,
+            _ => FlagEffect::Changed,
+        };
+        i += 1;
+    }
+    Metadata {
+        length,
+        cycles,
+        cycles_taken: if cycles_taken == 0 {
+            None
+        } else {
+            Some(cycles_taken)
+        },
+        flags: effects,
+        operand,
+    }
+}
+
+/// Metadata of every opcode. STOP counts its padding byte, and the CB prefix
+/// only itself, `CB_OPCODES` covering the instructions it introduces.
+#[rustfmt::skip]
+pub static OPCODES: [Metadata; 256] = [
+    op(1, 1, 0, b"----", Implied), // 0x00 nop
+    op(3, 3, 0, b"----", U16), // 0x01 ld bc, n16
+    op(1, 2, 0, b"----", Implied), // 0x02 ld [bc], a
+    op(1, 2, 0, b"----", Implied), // 0x03 inc bc
+    op(1, 1, 0, b"Z0H-", Implied), // 0x04 inc b
+    op(1, 1, 0, b"Z1H-", Implied), // 0x05 dec b
+    op(2, 2, 0, b"----", U8), // 0x06 ld b, n8
+    op(1, 1, 0, b"000C", Implied), // 0x07 rlca
+    op(3, 5, 0, b"----", U16), // 0x08 ld [n16], sp
+    op(1, 2, 0, b"-0HC", Implied), // 0x09 add hl, bc
+    op(1, 2, 0, b"----", Implied), // 0x0A ld a, [bc]
+    op(1, 2, 0, b"----", Implied), // 0x0B dec bc
+    op(1, 1, 0, b"Z0H-", Implied), // 0x0C inc c
+    op(1, 1, 0, b"Z1H-", Implied), // 0x0D dec c
+    op(2, 2, 0, b"----", U8), // 0x0E ld c, n8
+    op(1, 1, 0, b"000C", Implied), // 0x0F rrca
+    op(2, 1, 0, b"----", Implied), // 0x10 stop
+    op(3, 3, 0, b"----", U16), // 0x11 ld de, n16
+    op(1, 2, 0, b"----", Implied), // 0x12 ld [de], a
+    op(1, 2, 0, b"----", Implied), // 0x13 inc de
+    op(1, 1, 0, b"Z0H-", Implied), // 0x14 inc d
+    op(1, 1, 0, b"Z1H-", Implied), // 0x15 dec d
+    op(2, 2, 0, b"----", U8), // 0x16 ld d, n8
+    op(1, 1, 0, b"000C", Implied), // 0x17 rla
+    op(2, 3, 0, b"----", R8), // 0x18 jr e8
+    op(1, 2, 0, b"-0HC", Implied), // 0x19 add hl, de
+    op(1, 2, 0, b"----", Implied), // 0x1A ld a, [de]
+    op(1, 2, 0, b"----", Implied), // 0x1B dec de
+    op(1, 1, 0, b"Z0H-", Implied), // 0x1C inc e
+    op(1, 1, 0, b"Z1H-", Implied), // 0x1D dec e
+    op(2, 2, 0, b"----", U8), // 0x1E ld e, n8
+    op(1, 1, 0, b"000C", Implied), // 0x1F rra
+    op(2, 2, 3, b"----", R8), // 0x20 jr nz, e8
+    op(3, 3, 0, b"----", U16), // 0x21 ld hl, n16
+    op(1, 2, 0, b"----", Implied), // 0x22 ld [hl+], a
+    op(1, 2, 0, b"----", Implied), // 0x23 inc hl
+    op(1, 1, 0, b"Z0H-", Implied), // 0x24 inc h
+    op(1, 1, 0, b"Z1H-", Implied), // 0x25 dec h
+    op(2, 2, 0, b"----", U8), // 0x26 ld h, n8
+    op(1, 1, 0, b"Z-0C", Implied), // 0x27 daa
+    op(2, 2, 3, b"----", R8), // 0x28 jr z, e8
+    op(1, 2, 0, b"-0HC", Implied), // 0x29 add hl, hl
+    op(1, 2, 0, b"----", Implied), // 0x2A ld a, [hl+]
+    op(1, 2, 0, b"----", Implied), // 0x2B dec hl
+    op(1, 1, 0, b"Z0H-", Implied), // 0x2C inc l
+    op(1, 1, 0, b"Z1H-", Implied), // 0x2D dec l
+    op(2, 2, 0, b"----", U8), // 0x2E ld l, n8
+    op(1, 1, 0, b"-11-", Implied), // 0x2F cpl
+    op(2, 2, 3, b"----", R8), // 0x30 jr nc, e8
+    op(3, 3, 0, b"----", U16), // 0x31 ld sp, n16
+    op(1, 2, 0, b"----", Implied), // 0x32 ld [hl-], a
+    op(1, 2, 0, b"----", Implied), // 0x33 inc sp
+    op(1, 3, 0, b"Z0H-", Implied), // 0x34 inc [hl]
+    op(1, 3, 0, b"Z1H-", Implied), // 0x35 dec [hl]
+    op(2, 3, 0, b"----", U8), // 0x36 ld [hl], n8
+    op(1, 1, 0, b"-001", Implied), // 0x37 scf
+    op(2, 2, 3, b"----", R8), // 0x38 jr c, e8
+    op(1, 2, 0, b"-0HC", Implied), // 0x39 add hl, sp
+    op(1, 2, 0, b"----", Implied), // 0x3A ld a, [hl-]
+    op(1, 2, 0, b"----", Implied), // 0x3B dec sp
+    op(1, 1, 0, b"Z0H-", Implied), // 0x3C inc a
+    op(1, 1, 0, b"Z1H-", Implied), // 0x3D dec a
+    op(2, 2, 0, b"----", U8), // 0x3E ld a, n8
+    op(1, 1, 0, b"-00C", Implied), // 0x3F ccf
+    op(1, 1, 0, b"----", Implied), // 0x40 ld b, b
+    op(1, 1, 0, b"----", Implied), // 0x41 ld b, c
+    op(1, 1, 0, b"----", Implied), // 0x42 ld b, d
+    op(1, 1, 0, b"----", Implied), // 0x43 ld b, e
+    op(1, 1, 0, b"----", Implied), // 0x44 ld b, h
+    op(1, 1, 0, b"----", Implied), // 0x45 ld b, l
+    op(1, 2, 0, b"----", Implied), // 0x46 ld b, [hl]
+    op(1, 1, 0, b"----", Implied), // 0x47 ld b, a
+    op(1, 1, 0, b"----", Implied), // 0x48 ld c, b
+    op(1, 1, 0, b"----", Implied), // 0x49 ld c, c
+    op(1, 1, 0, b"----", Implied), // 0x4A ld c, d
+    op(1, 1, 0, b"----", Implied), // 0x4B ld c, e
+    op(1, 1, 0, b"----", Implied), // 0x4C ld c, h
+    op(1, 1, 0, b"----", Implied), // 0x4D ld c, l
+    op(1, 2, 0, b"----", Implied), // 0x4E ld c, [hl]
+    op(1, 1, 0, b"----", Implied), // 0x4F ld c, a
+    op(1, 1, 0, b"----", Implied), // 0x50 ld d, b
+    op(1, 1, 0, b"----", Implied), // 0x51 ld d, c
+    op(1, 1, 0, b"----", Implied), // 0x52 ld d, d
+    op(1, 1, 0, b"----", Implied), // 0x53 ld d, e
+    op(1, 1, 0, b"----", Implied), // 0x54 ld d, h
+    op(1, 1, 0, b"----", Implied), // 0x55 ld d, l
+    op(1, 2, 0, b"----", Implied), // 0x56 ld d, [hl]
+    op(1, 1, 0, b"----", Implied), // 0x57 ld d, a
+    op(1, 1, 0, b"----", Implied), // 0x58 ld e, b
+    op(1, 1, 0, b"----", Implied), // 0x59 ld e, c
+    op(1, 1, 0, b"----", Implied), // 0x5A ld e, d
+    op(1, 1, 0, b"----", Implied), // 0x5B ld e, e
+    op(1, 1, 0, b"----", Implied), // 0x5C ld e, h
+    op(1, 1, 0, b"----", Implied), // 0x5D ld e, l
+    op(1, 2, 0, b"----", Implied), // 0x5E ld e, [hl]
+    op(1, 1, 0, b"----", Implied), // 0x5F ld e, a
+    op(1, 1, 0, b"----", Implied), // 0x60 ld h, b
+    op(1, 1, 0, b"----", Implied), // 0x61 ld h, c
+    op(1, 1, 0, b"----", Implied), // 0x62 ld h, d
+    op(1, 1, 0, b"----", Implied), // 0x63 ld h, e
+    op(1, 1, 0, b"----", Implied), // 0x64 ld h, h
+    op(1, 1, 0, b"----", Implied), // 0x65 ld h, l
+    op(1, 2, 0, b"----", Implied), // 0x66 ld h, [hl]
+    op(1, 1, 0, b"----", Implied), // 0x67 ld h, a
+    op(1, 1, 0, b"----", Implied), // 0x68 ld l, b
+    op(1, 1, 0, b"----", Implied), // 0x69 ld l, c
+    op(1, 1, 0, b"----", Implied), // 0x6A ld l, d
+    op(1, 1, 0, b"----", Implied), // 0x6B ld l, e
+    op(1, 1, 0, b"----", Implied), // 0x6C ld l, h
+    op(1, 1, 0, b"----", Implied), // 0x6D ld l, l
+    op(1, 2, 0, b"----", Implied), // 0x6E ld l, [hl]
+    op(1, 1, 0, b"----", Implied), // 0x6F ld l, a
+    op(1, 2, 0, b"----", Implied), // 0x70 ld [hl], b
+    op(1, 2, 0, b"----", Implied), // 0x71 ld [hl], c
+    op(1, 2, 0, b"----", Implied), // 0x72 ld [hl], d
+    op(1, 2, 0, b"----", Implied), // 0x73 ld [hl], e
+    op(1, 2, 0, b"----", Implied), // 0x74 ld [hl], h
+    op(1, 2, 0, b"----", Implied), // 0x75 ld [hl], l
+    op(1, 1, 0, b"----", Implied), // 0x76 halt
+    op(1, 2, 0, b"----", Implied), // 0x77 ld [hl], a
+    op(1, 1, 0, b"----", Implied), // 0x78 ld a, b
+    op(1, 1, 0, b"----", Implied), // 0x79 ld a, c
+    op(1, 1, 0, b"----", Implied), // 0x7A ld a, d
+    op(1, 1, 0, b"----", Implied), // 0x7B ld a, e
+    op(1, 1, 0, b"----", Implied), // 0x7C ld a, h
+    op(1, 1, 0, b"----", Implied), // 0x7D ld a, l
+    op(1, 2, 0, b"----", Implied), // 0x7E ld a, [hl]
+    op(1, 1, 0, b"----", Implied), // 0x7F ld a, a
+    op(1, 1, 0, b"Z0HC", Implied), // 0x80 add a, b
+    op(1, 1, 0, b"Z0HC", Implied), // 0x81 add a, c
+    op(1, 1, 0, b"Z0HC", Implied), // 0x82 add a, d
+    op(1, 1, 0, b"Z0HC", Implied), // 0x83 add a, e
+    op(1, 1, 0, b"Z0HC", Implied), // 0x84 add a, h
+    op(1, 1, 0, b"Z0HC", Implied), // 0x85 add a, l
+    op(1, 2, 0, b"Z0HC", Implied), // 0x86 add a, [hl]
+    op(1, 1, 0, b"Z0HC", Implied), // 0x87 add a, a
+    op(1, 1, 0, b"Z0HC", Implied), // 0x88 adc a, b
+    op(1, 1, 0, b"Z0HC", Implied), // 0x89 adc a, c
+    op(1, 1, 0, b"Z0HC", Implied), // 0x8A adc a, d
+    op(1, 1, 0, b"Z0HC", Implied), // 0x8B adc a, e
+    op(1, 1, 0, b"Z0HC", Implied), // 0x8C adc a, h
+    op(1, 1, 0, b"Z0HC", Implied), // 0x8D adc a, l
+    op(1, 2, 0, b"Z0HC", Implied), // 0x8E adc a, [hl]
+    op(1, 1, 0, b"Z0HC", Implied), // 0x8F adc a, a
+    op(1, 1, 0, b"Z1HC", Implied), // 0x90 sub a, b
+    op(1, 1, 0, b"Z1HC", Implied), // 0x91 sub a, c
+    op(1, 1, 0, b"Z1HC", Implied), // 0x92 sub a, d
+    op(1, 1, 0, b"Z1HC", Implied), // 0x93 sub a, e
+    op(1, 1, 0, b"Z1HC", Implied), // 0x94 sub a, h
+    op(1, 1, 0, b"Z1HC", Implied), // 0x95 sub a, l
+    op(1, 2, 0, b"Z1HC", Implied), // 0x96 sub a, [hl]
+    op(1, 1, 0, b"Z1HC", Implied), // 0x97 sub a, a
+    op(1, 1, 0, b"Z1HC", Implied), // 0x98 sbc a, b
+    op(1, 1, 0, b"Z1HC", Implied), // 0x99 sbc a, c
+    op(1, 1, 0, b"Z1HC", Implied), // 0x9A sbc a, d
+    op(1, 1, 0, b"Z1HC", Implied), // 0x9B sbc a, e
+    op(1, 1, 0, b"Z1HC", Implied), // 0x9C sbc a, h
+    op(1, 1, 0, b"Z1HC", Implied), // 0x9D sbc a, l
+    op(1, 2, 0, b"Z1HC", Implied), // 0x9E sbc a, [hl]
+    op(1, 1, 0, b"Z1HC", Implied), // 0x9F sbc a, a
+    op(1, 1, 0, b"Z010", Implied), // 0xA0 and a, b
+    op(1, 1, 0, b"Z010", Implied), // 0xA1 and a, c
+    op(1, 1, 0, b"Z010", Implied), // 0xA2 and a, d
+    op(1, 1, 0, b"Z010", Implied), // 0xA3 and a, e
+    op(1, 1, 0, b"Z010", Implied), // 0xA4 and a, h
+    op(1, 1, 0, b"Z010", Implied), // 0xA5 and a, l
+    op(1, 2, 0, b"Z010", Implied), // 0xA6 and a, [hl]
+    op(1, 1, 0, b"Z010", Implied), // 0xA7 and a, a
+    op(1, 1, 0, b"Z000", Implied), // 0xA8 xor a, b
+    op(1, 1, 0, b"Z000", Implied), // 0xA9 xor a, c
+    op(1, 1, 0, b"Z000", Implied), // 0xAA xor a, d
+    op(1, 1, 0, b"Z000", Implied), // 0xAB xor a, e
+    op(1, 1, 0, b"Z000", Implied), // 0xAC xor a, h
+    op(1, 1, 0, b"Z000", Implied), // 0xAD xor a, l
+    op(1, 2, 0, b"Z000", Implied), // 0xAE xor a, [hl]
+    op(1, 1, 0, b"Z000", Implied), // 0xAF xor a, a
+    op(1, 1, 0, b"Z000", Implied), // 0xB0 or a, b
+    op(1, 1, 0, b"Z000", Implied), // 0xB1 or a, c
+    op(1, 1, 0, b"Z000", Implied), // 0xB2 or a, d
+    op(1, 1, 0, b"Z000", Implied), // 0xB3 or a, e
+    op(1, 1, 0, b"Z000", Implied), // 0xB4 or a, h
+    op(1, 1, 0, b"Z000", Implied), // 0xB5 or a, l
+    op(1, 2, 0, b"Z000", Implied), // 0xB6 or a, [hl]
+    op(1, 1, 0, b"Z000", Implied), // 0xB7 or a, a
+    op(1, 1, 0, b"Z1HC", Implied), // 0xB8 cp a, b
+    op(1, 1, 0, b"Z1HC", Implied), // 0xB9 cp a, c
+    op(1, 1, 0, b"Z1HC", Implied), // 0xBA cp a, d
+    op(1, 1, 0, b"Z1HC", Implied), // 0xBB cp a, e
+    op(1, 1, 0, b"Z1HC", Implied), // 0xBC cp a, h
+    op(1, 1, 0, b"Z1HC", Implied), // 0xBD cp a, l
+    op(1, 2, 0, b"Z1HC", Implied), // 0xBE cp a, [hl]
+    op(1, 1, 0, b"Z1HC", Implied), // 0xBF cp a, a
+    op(1, 2, 5, b"----", Implied), // 0xC0 ret nz
+    op(1, 3, 0, b"----", Implied), // 0xC1 pop bc
+    op(3, 3, 4, b"----", U16), // 0xC2 jp nz, n16
+    op(3, 4, 0, b"----", U16), // 0xC3 jp n16
+    op(3, 3, 6, b"----", U16), // 0xC4 call nz, n16
+    op(1, 4, 0, b"----", Implied), // 0xC5 push bc
+    op(2, 2, 0, b"Z0HC", U8), // 0xC6 add a, n8
+    op(1, 4, 0, b"----", Implied), // 0xC7 rst $00
+    op(1, 2, 5, b"----", Implied), // 0xC8 ret z
+    op(1, 4, 0, b"----", Implied), // 0xC9 ret
+    op(3, 3, 4, b"----", U16), // 0xCA jp z, n16
+    op(1, 1, 0, b"----", Implied), // 0xCB prefix
+    op(3, 3, 6, b"----", U16), // 0xCC call z, n16
+    op(3, 6, 0, b"----", U16), // 0xCD call n16
+    op(2, 2, 0, b"Z0HC", U8), // 0xCE adc a, n8
+    op(1, 4, 0, b"----", Implied), // 0xCF rst $08
+    op(1, 2, 5, b"----", Implied), // 0xD0 ret nc
+    op(1, 3, 0, b"----", Implied), // 0xD1 pop de
+    op(3, 3, 4, b"----", U16), // 0xD2 jp nc, n16
+    op(1, 1, 0, b"----", Implied), // 0xD3 invalid
+    op(3, 3, 6, b"----", U16), // 0xD4 call nc, n16
+    op(1, 4, 0, b"----", Implied), // 0xD5 push de
+    op(2, 2, 0, b"Z1HC", U8), // 0xD6 sub a, n8
+    op(1, 4, 0, b"----", Implied), // 0xD7 rst $10
+    op(1, 2, 5, b"----", Implied), // 0xD8 ret c
+    op(1, 4, 0, b"----", Implied), // 0xD9 reti
+    op(3, 3, 4, b"----", U16), // 0xDA jp c, n16
+    op(1, 1, 0, b"----", Implied), // 0xDB invalid
+    op(3, 3, 6, b"----", U16), // 0xDC call c, n16
+    op(1, 1, 0, b"----", Implied), // 0xDD invalid
+    op(2, 2, 0, b"Z1HC", U8), // 0xDE sbc a, n8
+    op(1, 4, 0, b"----", Implied), // 0xDF rst $18
+    op(2, 3, 0, b"----", A8), // 0xE0 ldh [n8], a
+    op(1, 3, 0, b"----", Implied), // 0xE1 pop hl
+    op(1, 2, 0, b"----", Implied), // 0xE2 ldh [c], a
+    op(1, 1, 0, b"----", Implied), // 0xE3 invalid
+    op(1, 1, 0, b"----", Implied), // 0xE4 invalid
+    op(1, 4, 0, b"----", Implied), // 0xE5 push hl
+    op(2, 2, 0, b"Z010", U8), // 0xE6 and a, n8
+    op(1, 4, 0, b"----", Implied), // 0xE7 rst $20
+    op(2, 4, 0, b"00HC", I8), // 0xE8 add sp, e8
+    op(1, 1, 0, b"----", Implied), // 0xE9 jp hl
+    op(3, 4, 0, b"----", U16), // 0xEA ld [n16], a
+    op(1, 1, 0, b"----", Implied), // 0xEB invalid
+    op(1, 1, 0, b"----", Implied), // 0xEC invalid
+    op(1, 1, 0, b"----", Implied), // 0xED invalid
+    op(2, 2, 0, b"Z000", U8), // 0xEE xor a, n8
+    op(1, 4, 0, b"----", Implied), // 0xEF rst $28
+    op(2, 3, 0, b"----", A8), // 0xF0 ldh a, [n8]
+    op(1, 3, 0, b"ZNHC", Implied), // 0xF1 pop af
+    op(1, 2, 0, b"----", Implied), // 0xF2 ldh a, [c]
+    op(1, 1, 0, b"----", Implied), // 0xF3 di
+    op(1, 1, 0, b"----", Implied), // 0xF4 invalid
+    op(1, 4, 0, b"----", Implied), // 0xF5 push af
+    op(2, 2, 0, b"Z000", U8), // 0xF6 or a, n8
+    op(1, 4, 0, b"----", Implied), // 0xF7 rst $30
+    op(2, 3, 0, b"00HC", S8), // 0xF8 ld hl, sp+e8
+    op(1, 2, 0, b"----", Implied), // 0xF9 ld sp, hl
+    op(3, 4, 0, b"----", U16), // 0xFA ld a, [n16]
+    op(1, 1, 0, b"----", Implied), // 0xFB ei
+    op(1, 1, 0, b"----", Implied), // 0xFC invalid
+    op(1, 1, 0, b"----", Implied), // 0xFD invalid
+    op(2, 2, 0, b"Z1HC", U8), // 0xFE cp a, n8
+    op(1, 4, 0, b"----", Implied), // 0xFF rst $38
+];
+
+/// Metadata of CB-prefixed opcodes, the prefix included
+pub static CB_OPCODES: [Metadata; 256] = cb_opcodes();
+
+const fn cb_opcodes() -> [Metadata; 256] {
+    let mut table = [op(2, 2, 0, b"----", Implied); 256];
+    let mut opcode = 0;
+    while opcode < 256 {
+        let flags = match opcode >> 3 {
+            // SWAP
+            6 => b"Z000",
+            // Rotates and shifts
+            0..=7 => b"Z00C",
+            // BIT
+            8..=15 => b"Z01-",
+            // RES and SET
+            _ => b"----",
+        };
+        let cycles = match (opcode & 0x07, opcode >> 6) {
+            (6, 1) => 3,
+            (6, _) => 4,
+            _ => 2,
+        };
+        table[opcode] = op(2, cycles, 0, flags, Implied);
+        opcode += 1;
+    }
+    table
+}
@@ -1,8 +1,24 @@
+#![allow(clippy::upper_case_acronyms)]
+
+pub mod bus;
 pub mod cpu;
+pub mod debugger;
+pub mod disassembler;
+#[cfg(feature = "window")]
+pub mod frontend;
+pub mod gdb;
 pub mod instructions;
 pub mod memory;
+pub mod model;
+pub mod movie;
+pub mod png;
 pub mod ppu;
+pub mod rewind;
+pub mod runner;
 pub mod sound;
+pub mod state;
+pub mod symbols;
+pub mod trace;
 
 pub const _128KB: usize = 128 * _1KB;
 pub const _64KB: usize = 64 * _1KB;
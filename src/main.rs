@@ -1,22 +1,642 @@
 use gbc::cpu::CPU;
+use gbc::debugger::Debugger;
+use gbc::disassembler;
+use gbc::gdb;
 use gbc::memory::Memory;
+use gbc::model::Model;
+use gbc::movie::{Movie, Recorder};
+use gbc::png;
+use gbc::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+#[cfg(feature = "window")]
+use gbc::rewind::Rewind;
+use gbc::runner::{self, Until};
+use gbc::sound::resampler::APU_CLOCK;
+use gbc::sound::sink::AudioSink;
+use gbc::sound::wav::WavWriter;
+use gbc::state;
+use gbc::symbols::Symbols;
+use gbc::trace::{self, Ring, Tracer};
 use gbc::_32KB;
+use std::env;
 use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Mutex;
 
-fn read_program() -> [u8; _32KB] {
-    let program = fs::read("./tests/fixtures/program.bin").expect("Program is unreadable");
+/// Sample rate of the recorded audio
+const SAMPLE_RATE: u32 = 48_000;
+
+/// Exit codes
+const EXIT_EMULATION_ERROR: i32 = 1;
+const EXIT_USAGE_ERROR: i32 = 2;
+const EXIT_LOAD_ERROR: i32 = 3;
+const EXIT_FAILED: i32 = 4;
+
+/// Exit code used when panicking, which depends on how far along we are
+static ERROR_CODE: AtomicI32 = AtomicI32::new(EXIT_LOAD_ERROR);
+
+/// Trace kept by `--trace-last`, written out when panicking
+static CRASH_TRACE: Mutex<Option<Ring>> = Mutex::new(None);
+
+const USAGE: &str = "Usage: gbc ROM [OPTIONS]
+       gbc disasm ROM [--sym FILE]
+       gbc trace-diff TRACE REFERENCE [--context N]
+
+Options:
+  --model MODEL        Hardware to emulate: dmg0, dmg, mgb, sgb, sgb2, cgb or agb
+  --boot-rom FILE      Boot ROM to run before the cartridge
+  --headless           Run without a window
+  --debug              Run under the command line debugger, see its help command
+  --gdb PORT           Wait for gdb, or another GDB remote client, on localhost:PORT
+  --frames N           Stop after N frames
+  --seconds N          Stop after N seconds of emulated time, running headless
+  --cycles N           Stop after N cycles of the 4 MHz clock, running headless
+  --until-pc ADDR      Stop before executing ADDR, in hex, running headless
+  --until-serial TEXT  Stop once TEXT is sent over the serial port, running headless
+  --until-loop         Stop on reaching a JR -2 infinite loop, running headless
+  --scale N            Window size, in multiples of the LCD size (default 3)
+  --save-dir DIR       Directory for screenshots and save states (default .)
+  --screenshot-at N    Save a screenshot after frame N, as DIR/ROM-N.png
+  --load-state FILE    Start from a save state of the same ROM
+  --save-state FILE    Save the state at the end, running headless
+  --rewind-interval N  Frames between the states kept to rewind to (default 1)
+  --rewind-memory MB   Memory the rewind states may take (default 64)
+  --record-movie FILE  Record the input of every frame, from the start state
+  --play-movie FILE    Play a movie back running headless, failing on a desync
+  --sym FILE           Load labels from an rgbds .sym or .map file, for the
+                       debugger and traces
+  --trace FILE         Log the registers before every instruction, - for stdout
+  --trace-range A-B    Only trace instructions from A to B, in hex
+  --trace-bank N       Only trace instructions in ROM bank N
+  --trace-last N       Only write the last N instructions traced, on exit or crash
+  --doctor             Make LY (0xFF44) read 0x90 while tracing, as Gameboy
+                       Doctor logs expect
+  --record FILE.wav    Record the audio, running headless
+  --stems              Also record each channel to FILE.ch1.wav ~ FILE.ch4.wav
+
+The disasm command prints the ROM as rgbds source, decoding everything as code
+and defining the labels of the symbol file if given.
+The trace-diff command compares a --trace log against a reference log in the
+same format and shows the first instruction they diverge at, with N lines of
+context (default 5), failing if there is one.
+
+Headless runs print the serial output to stdout and save the final frame as
+DIR/ROM.png. When waiting for a PC or serial output, the run fails unless one
+of them is reached first.
+
+In the window, F5 saves the state to DIR/ROM.state and F8 loads it back.
+Holding ` rewinds, stepping back through the states kept.
+
+Movies hold the ROM's CRC-32, the model, a save state to start from, the
+buttons of each frame and a hash of the state every second. Playback compares
+the hashes and stops at the first that differs. A movie being recorded starts
+over from the current state on a reset, a state load or a rewind.
+
+Exit codes: 0 on success, 1 on emulation errors, 2 on usage errors, 3 when the
+ROM, boot ROM, save state or movie cannot be loaded and 4 when a headless run
+fails, traces diverge or a movie desyncs.";
+
+/// Command line options, see `USAGE`
+struct Options {
+    rom: PathBuf,
+    model: Option<Model>,
+    boot_rom: Option<PathBuf>,
+    headless: bool,
+    debug: bool,
+    gdb: Option<u16>,
+    until: Until,
+    scale: u32,
+    save_dir: PathBuf,
+    screenshot_at: Option<u64>,
+    load_state: Option<PathBuf>,
+    save_state: Option<PathBuf>,
+    rewind_interval: u32,
+    rewind_memory: usize,
+    record_movie: Option<PathBuf>,
+    play_movie: Option<PathBuf>,
+    sym: Option<PathBuf>,
+    trace: Option<String>,
+    trace_range: Option<(u16, u16)>,
+    trace_bank: Option<u16>,
+    trace_last: Option<usize>,
+    doctor: bool,
+    record: Option<String>,
+    stems: bool,
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("error: {}\n\n{}", message, USAGE);
+    process::exit(EXIT_USAGE_ERROR);
+}
+
+fn value<T: std::str::FromStr>(args: &mut impl Iterator<Item = String>, option: &str) -> T {
+    let value = args
+        .next()
+        .unwrap_or_else(|| usage_error(&format!("Missing value for {}", option)));
+    value
+        .parse()
+        .unwrap_or_else(|_| usage_error(&format!("Invalid value for {}: {}", option, value)))
+}
+
+/// Parses an address in hex, with an optional 0x or $ prefix
+fn address(text: &str, option: &str) -> u16 {
+    let digits = text.trim_start_matches("0x").trim_start_matches('$');
+    u16::from_str_radix(digits, 16)
+        .unwrap_or_else(|_| usage_error(&format!("Invalid value for {}: {}", option, text)))
+}
+
+impl Options {
+    fn parse() -> Options {
+        let mut rom = None;
+        let mut seconds: Option<u64> = None;
+        let mut options = Options {
+            rom: PathBuf::new(),
+            model: None,
+            boot_rom: None,
+            headless: false,
+            debug: false,
+            gdb: None,
+            until: Until::default(),
+            scale: 3,
+            save_dir: PathBuf::from("."),
+            screenshot_at: None,
+            load_state: None,
+            save_state: None,
+            rewind_interval: 1,
+            rewind_memory: 64,
+            record_movie: None,
+            play_movie: None,
+            sym: None,
+            trace: None,
+            trace_range: None,
+            trace_bank: None,
+            trace_last: None,
+            doctor: false,
+            record: None,
+            stems: false,
+        };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                }
+                "--model" => {
+                    let model: String = value(&mut args, &arg);
+                    let model = model
+                        .parse()
+                        .unwrap_or_else(|error: String| usage_error(&error));
+                    options.model = Some(model);
+                }
+                "--boot-rom" => options.boot_rom = Some(value(&mut args, &arg)),
+                "--headless" => options.headless = true,
+                "--debug" => options.debug = true,
+                "--gdb" => options.gdb = Some(value(&mut args, &arg)),
+                "--frames" => options.until.frames = Some(value(&mut args, &arg)),
+                "--seconds" => seconds = Some(value(&mut args, &arg)),
+                "--cycles" => options.until.cycles = Some(value(&mut args, &arg)),
+                "--until-pc" => {
+                    let pc: String = value(&mut args, &arg);
+                    options.until.pc = Some(address(&pc, &arg));
+                    options.headless = true;
+                }
+                "--until-serial" => {
+                    options.until.serial = Some(value(&mut args, &arg));
+                    options.headless = true;
+                }
+                "--until-loop" => {
+                    options.until.infinite_loop = true;
+                    options.headless = true;
+                }
+                "--scale" => options.scale = value(&mut args, &arg),
+                "--save-dir" => options.save_dir = value(&mut args, &arg),
+                "--screenshot-at" => options.screenshot_at = Some(value(&mut args, &arg)),
+                "--load-state" => options.load_state = Some(value(&mut args, &arg)),
+                "--save-state" => {
+                    options.save_state = Some(value(&mut args, &arg));
+                    options.headless = true;
+                }
+                "--rewind-interval" => options.rewind_interval = value(&mut args, &arg),
+                "--rewind-memory" => options.rewind_memory = value(&mut args, &arg),
+                "--record-movie" => options.record_movie = Some(value(&mut args, &arg)),
+                "--play-movie" => {
+                    options.play_movie = Some(value(&mut args, &arg));
+                    options.headless = true;
+                }
+                "--sym" => options.sym = Some(value(&mut args, &arg)),
+                "--trace" => options.trace = Some(value(&mut args, &arg)),
+                "--trace-range" => {
+                    let range: String = value(&mut args, &arg);
+                    let (start, end) = range.split_once('-').unwrap_or_else(|| {
+                        usage_error(&format!("Invalid value for {}: {}", arg, range))
+                    });
+                    options.trace_range = Some((address(start, &arg), address(end, &arg)));
+                }
+                "--trace-bank" => options.trace_bank = Some(value(&mut args, &arg)),
+                "--trace-last" => options.trace_last = Some(value(&mut args, &arg)),
+                "--doctor" => options.doctor = true,
+                "--record" => {
+                    options.record = Some(value(&mut args, &arg));
+                    options.headless = true;
+                }
+                "--stems" => options.stems = true,
+                _ if arg.starts_with('-') => usage_error(&format!("Unknown option {}", arg)),
+                _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+                _ => usage_error(&format!("Unexpected argument {}", arg)),
+            }
+        }
+        if let Some(seconds) = seconds {
+            let cycles = seconds * APU_CLOCK as u64;
+            options.until.cycles = Some(options.until.cycles.unwrap_or(cycles).min(cycles));
+        }
+        if options.until.cycles.is_some() {
+            options.headless = true;
+        }
+        options.rom = rom.unwrap_or_else(|| usage_error("Missing ROM path"));
+        let filtered = options.trace_range.is_some() || options.trace_bank.is_some();
+        if options.trace.is_none() && (filtered || options.trace_last.is_some()) {
+            usage_error("Trace filters need --trace");
+        }
+        if options.trace.is_none() && options.doctor {
+            usage_error("--doctor needs --trace");
+        }
+        if options.trace_last == Some(0) {
+            usage_error("--trace-last must be at least 1");
+        }
+        let movie = options.record_movie.is_some() || options.play_movie.is_some();
+        if options.record_movie.is_some() && options.play_movie.is_some() {
+            usage_error("A movie cannot be recorded while playing one back");
+        }
+        let debugging = options.debug || options.gdb.is_some();
+        if movie && debugging {
+            usage_error("Movies cannot be recorded or played back under a debugger");
+        }
+        if options.until.pc.is_some() && options.until.serial.is_some() {
+            usage_error("--until-pc and --until-serial cannot be combined");
+        }
+        let headless = options.headless || !cfg!(feature = "window");
+        if headless && !debugging && options.play_movie.is_none() && options.until.is_empty() {
+            usage_error("A headless run needs a stop condition, e.g. --frames");
+        }
+        if options.rewind_interval == 0 {
+            usage_error("Rewind interval must be at least 1");
+        }
+        if options.scale == 0 {
+            usage_error("Scale must be at least 1");
+        }
+        // Created early, so that an unwritable path is a load error
+        if let Some(path) = options.trace.as_deref().filter(|path| *path != "-") {
+            if let Err(error) = fs::File::create(path) {
+                panic!("Trace file {} could not be created: {}", path, error);
+            }
+        }
+        options
+    }
+}
+
+fn read_program(path: &Path) -> [u8; _32KB] {
+    let program = fs::read(path)
+        .unwrap_or_else(|error| panic!("ROM {} is unreadable: {}", path.display(), error));
     let size: usize = program.len();
     if size > _32KB {
-        panic!("Program is too large, maximum size is {} bytes", _32KB);
+        panic!("ROM is too large, maximum size is {} bytes", _32KB);
     }
     let mut buffer: [u8; _32KB] = [0; _32KB];
     buffer[..size].copy_from_slice(&program);
     buffer
 }
 
-fn main() {
-    let program: [u8; _32KB] = read_program();
-    let memory = Memory::new(program);
+fn load_symbols(path: &Path) -> Symbols {
+    Symbols::load(path)
+        .unwrap_or_else(|error| panic!("Symbols {} are unreadable: {}", path.display(), error))
+}
+
+fn load_movie(path: &Path) -> Movie {
+    fs::read(path)
+        .map_err(|error| error.to_string())
+        .and_then(|data| Movie::parse(&data))
+        .unwrap_or_else(|error| panic!("Movie {} is unreadable: {}", path.display(), error))
+}
+
+/// `gbc disasm ROM`, writing the ROM as rgbds source to stdout
+fn disasm(mut args: impl Iterator<Item = String>) {
+    let mut path = None;
+    let mut symbols = Symbols::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sym" => symbols = load_symbols(&value::<PathBuf>(&mut args, &arg)),
+            _ if arg.starts_with('-') => usage_error(&format!("Unknown option {}", arg)),
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => usage_error("Expected a single ROM path"),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage_error("Expected a single ROM path"));
+    let rom = fs::read(&path)
+        .unwrap_or_else(|error| panic!("ROM {} is unreadable: {}", path.display(), error));
+    let stdout = io::stdout();
+    let mut output = BufWriter::new(stdout.lock());
+    disassembler::write_source(&rom, &symbols, &mut output)
+        .and_then(|_| output.flush())
+        .expect("Disassembly could not be written");
+}
+
+/// `gbc trace-diff TRACE REFERENCE`, returning whether the traces match
+fn trace_diff(mut args: impl Iterator<Item = String>) -> bool {
+    let mut paths = Vec::new();
+    let mut context = 5;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--context" => context = value(&mut args, &arg),
+            _ if arg.starts_with('-') => usage_error(&format!("Unknown option {}", arg)),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    if paths.len() != 2 {
+        usage_error("Expected a trace and a reference path");
+    }
+    let open = |path: &PathBuf| {
+        let file = fs::File::open(path)
+            .unwrap_or_else(|error| panic!("{} is unreadable: {}", path.display(), error));
+        io::BufReader::new(file)
+    };
+    let stdout = io::stdout();
+    let mut output = BufWriter::new(stdout.lock());
+    trace::diff(open(&paths[0]), open(&paths[1]), context, &mut output)
+        .and_then(|matched| output.flush().map(|_| matched))
+        .expect("Traces could not be compared")
+}
+
+fn record(memory: &mut Memory, path: &str, stems: bool) {
+    memory.set_audio_sink(
+        Box::new(WavWriter::create(path, SAMPLE_RATE, 2)),
+        SAMPLE_RATE,
+    );
+    if stems {
+        let base = path.strip_suffix(".wav").unwrap_or(path);
+        let stem = |channel: u8| -> Box<dyn AudioSink> {
+            let path = format!("{}.ch{}.wav", base, channel);
+            Box::new(WavWriter::create(path, SAMPLE_RATE, 1))
+        };
+        memory.set_audio_stems([stem(1), stem(2), stem(3), stem(4)], SAMPLE_RATE);
+    }
+}
+
+fn trace(path: &str, options: &Options, symbols: &Symbols) -> Tracer {
+    let output: Box<dyn Write + Send> = if path == "-" {
+        Box::new(BufWriter::new(io::stdout()))
+    } else {
+        let file = fs::File::create(path).expect("Trace file could not be created");
+        Box::new(BufWriter::new(file))
+    };
+    let mut tracer = match options.trace_last {
+        Some(lines) => {
+            let ring = Ring::new(lines, output);
+            *CRASH_TRACE.lock().unwrap() = Some(ring.clone());
+            Tracer::ring(ring)
+        }
+        None => Tracer::new(output),
+    };
+    if let Some((start, end)) = options.trace_range {
+        tracer.set_range(start, end);
+    }
+    if let Some(bank) = options.trace_bank {
+        tracer.set_bank(bank);
+    }
+    tracer.set_symbols(symbols.clone());
+    tracer
+}
+
+fn boot(
+    program: [u8; _32KB],
+    options: &Options,
+    boot_rom: &Option<Vec<u8>>,
+    symbols: &Symbols,
+) -> CPU {
+    let mut memory = match options.model {
+        Some(model) => Memory::with_model(program, model),
+        None => Memory::new(program),
+    };
+    if let Some(boot_rom) = boot_rom {
+        memory
+            .load_boot_rom(boot_rom.clone())
+            .expect("Boot ROM is checked once read");
+    }
+    if let Some(path) = &options.record {
+        record(&mut memory, path, options.stems);
+    }
     let mut cpu = CPU::new(memory);
-    cpu.init();
+    if let Some(path) = &options.load_state {
+        let loaded = fs::read(path)
+            .map_err(|error| error.to_string())
+            .and_then(|data| state::load(&mut cpu, &data));
+        if let Err(error) = loaded {
+            ERROR_CODE.store(EXIT_LOAD_ERROR, Ordering::SeqCst);
+            panic!(
+                "Save state {} could not be loaded: {}",
+                path.display(),
+                error
+            );
+        }
+    }
+    if let Some(path) = &options.trace {
+        cpu.set_trace(trace(path, options, symbols));
+        if options.doctor {
+            cpu.memory_mut().stub_ly(Some(0x90));
+        }
+    }
+    cpu
+}
+
+fn save_frame(cpu: &CPU, name: &str, options: &Options) {
+    let path = options.save_dir.join(name);
+    let (width, height) = (SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+    png::write(&path, width, height, cpu.memory().frame())
+        .unwrap_or_else(|error| panic!("{} could not be written: {}", path.display(), error));
+}
+
+fn rom_name(options: &Options) -> String {
+    let stem = options.rom.file_stem().unwrap_or_default();
+    stem.to_string_lossy().into_owned()
+}
+
+/// Saves the screenshot asked for, if taken at this frame
+fn screenshot(cpu: &CPU, frame: u64, options: &Options) {
+    if options.screenshot_at == Some(frame) {
+        save_frame(
+            cpu,
+            &format!("{}-{}.png", rom_name(options), frame),
+            options,
+        );
+    }
+}
+
+/// Flushes the outputs of a headless run, prints the serial output and saves
+/// the final frame, and the state if asked to
+fn finish_headless(cpu: &mut CPU, options: &Options) {
+    cpu.finish();
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    stdout
+        .write_all(cpu.memory().serial_output())
+        .and_then(|_| stdout.flush())
+        .expect("Serial output could not be printed");
+    save_frame(cpu, &format!("{}.png", rom_name(options)), options);
+    if let Some(path) = &options.save_state {
+        fs::write(path, state::save(cpu))
+            .unwrap_or_else(|error| panic!("{} could not be written: {}", path.display(), error));
+    }
+}
+
+fn movie_error(path: &Path, error: io::Error) -> ! {
+    panic!("Movie {} could not be written: {}", path.display(), error)
+}
+
+/// Runs until one of the conditions asked for is met, then prints the serial
+/// output and saves the final frame. Returns whether the run passed.
+fn run_headless(mut cpu: CPU, options: &Options) -> bool {
+    let mut movie = options.record_movie.as_deref().map(|path| {
+        let mut recorder = Recorder::new(path);
+        recorder
+            .start(&cpu)
+            .unwrap_or_else(|error| movie_error(path, error));
+        (path, recorder)
+    });
+    let exit = runner::run(&mut cpu, &options.until, |cpu, frame| {
+        screenshot(cpu, frame, options);
+        if let Some((path, recorder)) = movie.as_mut() {
+            recorder
+                .on_frame(cpu)
+                .unwrap_or_else(|error| movie_error(path, error));
+        }
+    });
+    finish_headless(&mut cpu, options);
+
+    let passed = options.until.passed(exit);
+    if !passed {
+        eprintln!("failed: run ended on {:?}", exit);
+    }
+    passed
+}
+
+/// Plays a movie back from its start state, then prints the serial output
+/// and saves the final frame. Returns whether it stayed in sync.
+fn run_movie(mut cpu: CPU, movie: &Movie, options: &Options) -> bool {
+    let played = movie.play(&mut cpu, |cpu, frame| screenshot(cpu, frame, options));
+    finish_headless(&mut cpu, options);
+    match played {
+        Ok(()) => {
+            eprintln!("Movie played back in sync, {} frames", movie.inputs.len());
+            true
+        }
+        Err(desync) => {
+            eprintln!("failed: {}", desync);
+            false
+        }
+    }
+}
+
+#[cfg(feature = "window")]
+fn run_window(program: [u8; _32KB], options: Options, boot_rom: Option<Vec<u8>>, symbols: Symbols) {
+    let scale = options.scale;
+    let options = std::rc::Rc::new(options);
+    let boot_options = options.clone();
+    let state_path = options
+        .save_dir
+        .join(format!("{}.state", rom_name(&options)));
+    let rewind = Rewind::new(options.rewind_interval, options.rewind_memory << 20);
+    let mut frame = 0;
+    gbc::frontend::run(
+        move || boot(program, &boot_options, &boot_rom, &symbols),
+        scale,
+        state_path,
+        rewind,
+        options.record_movie.as_deref().map(Recorder::new),
+        move |cpu| {
+            frame += 1;
+            screenshot(cpu, frame, &options);
+            options.until.frames.is_none_or(|frames| frame < frames)
+        },
+    );
+}
+
+fn main() {
+    panic::set_hook(Box::new(|info| {
+        let message = match info.payload().downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => match info.payload().downcast_ref::<String>() {
+                Some(message) => message.clone(),
+                None => String::from("unknown error"),
+            },
+        };
+        eprintln!("error: {}", message);
+        if let Some(ring) = CRASH_TRACE.lock().ok().and_then(|ring| ring.clone()) {
+            ring.dump().ok();
+        }
+        process::exit(ERROR_CODE.load(Ordering::SeqCst));
+    }));
+
+    match env::args().nth(1).as_deref() {
+        Some("disasm") => return disasm(env::args().skip(2)),
+        Some("trace-diff") => {
+            if !trace_diff(env::args().skip(2)) {
+                process::exit(EXIT_FAILED);
+            }
+            return;
+        }
+        _ => {}
+    }
+    let mut options = Options::parse();
+    let program: [u8; _32KB] = read_program(&options.rom);
+    let boot_rom = options.boot_rom.as_ref().map(|path| {
+        let boot_rom = fs::read(path)
+            .unwrap_or_else(|error| panic!("Boot ROM {} is unreadable: {}", path.display(), error));
+        if let Err(error) = Memory::check_boot_rom(&boot_rom) {
+            panic!("{}", error);
+        }
+        boot_rom
+    });
+    let symbols = options
+        .sym
+        .as_deref()
+        .map_or_else(Symbols::default, load_symbols);
+    let movie = options.play_movie.as_deref().map(load_movie);
+    if let Some(movie) = &movie {
+        options.model = Some(movie.model);
+    }
+    ERROR_CODE.store(EXIT_EMULATION_ERROR, Ordering::SeqCst);
+
+    if options.debug {
+        let mut debugger = Debugger::new(boot(program, &options, &boot_rom, &symbols));
+        debugger.set_symbols(symbols);
+        let stdin = io::stdin();
+        debugger
+            .run(stdin.lock(), &mut io::stdout())
+            .expect("Debugger input or output failed");
+        debugger.into_cpu().finish();
+    } else if let Some(port) = options.gdb {
+        let mut cpu = boot(program, &options, &boot_rom, &symbols);
+        gdb::listen(&mut cpu, &format!("127.0.0.1:{}", port)).expect("gdb connection failed");
+        cpu.finish();
+    } else if let Some(movie) = &movie {
+        let mut cpu = boot(program, &options, &boot_rom, &symbols);
+        if let Err(error) = movie.start(&mut cpu) {
+            ERROR_CODE.store(EXIT_LOAD_ERROR, Ordering::SeqCst);
+            panic!("Movie could not be started: {}", error);
+        }
+        if !run_movie(cpu, movie, &options) {
+            process::exit(EXIT_FAILED);
+        }
+    } else if options.headless || !cfg!(feature = "window") {
+        if !run_headless(boot(program, &options, &boot_rom, &symbols), &options) {
+            process::exit(EXIT_FAILED);
+        }
+    } else {
+        #[cfg(feature = "window")]
+        run_window(program, options, boot_rom, symbols);
+    }
 }
@@ -0,0 +1,142 @@
+use crate::state::{self, snapshot};
+
+/// Number of bytes copied into OAM by a single transfer
+pub const OAM_DMA_LENGTH: u16 = 0xA0;
+
+/// M-cycles between the write to 0xFF46 and the first byte being copied
+const OAM_DMA_START_DELAY: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct Transfer {
+    source: u16,
+    index: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct Pending {
+    source: u16,
+    delay: u8,
+}
+
+/// OAM DMA controller mapped at 0xFF46.
+///
+/// A write to the register schedules a 160 byte copy from `XX00 ~ XX9F` into
+/// OAM. Each M-cycle copies a single byte, so the CPU is locked out of the
+/// buses for 160 M-cycles (plus the start-up delay). Writing the register
+/// again while a transfer is running restarts it: the previous transfer
+/// keeps going (and keeps OAM locked) until the new one leaves its start-up
+/// delay.
+#[derive(Debug)]
+pub struct OamDma {
+    /// Last value written to 0xFF46
+    register: u8,
+    active: Option<Transfer>,
+    pending: Option<Pending>,
+    /// Byte currently driven on the bus used as the transfer source
+    latch: u8,
+}
+
+impl OamDma {
+    pub fn init() -> OamDma {
+        OamDma {
+            register: 0xFF,
+            active: None,
+            pending: None,
+            latch: 0xFF,
+        }
+    }
+
+    /// Checks the indices of a state just loaded
+    pub fn validate(&self) -> Result<(), String> {
+        match self.active {
+            Some(transfer) => state::check(
+                "OAM DMA index",
+                transfer.index as usize,
+                0..OAM_DMA_LENGTH as usize,
+            ),
+            None => Ok(()),
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        self.register
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.register = data;
+        // Sources above 0xDFFF are served by the WRAM echo
+        let source = match (data as u16) << 8 {
+            address @ 0xE000..=0xFFFF => address - 0x2000,
+            address => address,
+        };
+        self.pending = Some(Pending {
+            source,
+            delay: OAM_DMA_START_DELAY,
+        });
+    }
+
+    /// Whether a transfer currently owns the buses
+    pub fn is_active(&self) -> bool {
+        self.active.is_some()
+    }
+
+    /// Address the transfer is currently reading from, if any
+    pub fn source(&self) -> Option<u16> {
+        self.active.map(|transfer| transfer.source)
+    }
+
+    /// Value a CPU read gets back when it collides with the transfer
+    pub fn latch(&self) -> u8 {
+        self.latch
+    }
+
+    /// Advances the controller by one M-cycle. Returns the `(source, oam offset)`
+    /// pair that must be copied during this cycle, if any.
+    pub fn step(&mut self) -> Option<(u16, usize)> {
+        let copy = match self.active {
+            Some(transfer) => {
+                let next = transfer.index + 1;
+                self.active = if next < OAM_DMA_LENGTH {
+                    Some(Transfer {
+                        source: transfer.source,
+                        index: next,
+                    })
+                } else {
+                    None
+                };
+                Some((transfer.source + transfer.index, transfer.index as usize))
+            }
+            None => None,
+        };
+
+        if let Some(pending) = self.pending {
+            if pending.delay <= 1 {
+                self.pending = None;
+                self.active = Some(Transfer {
+                    source: pending.source,
+                    index: 0,
+                });
+            } else {
+                self.pending = Some(Pending {
+                    source: pending.source,
+                    delay: pending.delay - 1,
+                });
+            }
+        }
+
+        copy
+    }
+
+    pub fn set_latch(&mut self, data: u8) {
+        self.latch = data;
+    }
+}
+
+snapshot!(Transfer { source, index });
+snapshot!(Pending { source, delay });
+snapshot!(OamDma {
+    register,
+    active,
+    pending,
+    latch
+});
@@ -0,0 +1,145 @@
+use crate::state::{snapshot, Reader, Snapshot, Writer};
+
+/// Bytes copied per HDMA block
+pub const HDMA_BLOCK_LENGTH: u16 = 0x10;
+
+/// M-cycles the CPU is halted for each block copied at single speed
+pub const HDMA_BLOCK_CYCLES: u16 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transfer {
+    /// No transfer running, HDMA5 reads back as 0xFF
+    Idle,
+    /// HBlank DMA copying one block per HBlank
+    HBlank,
+    /// HBlank DMA cancelled by the program, HDMA5 keeps the remaining length
+    Cancelled,
+}
+
+/// CGB VRAM DMA controller mapped at 0xFF51 ~ 0xFF55.
+///
+/// A write to HDMA5 either copies everything right away while the CPU is
+/// halted (General Purpose DMA) or arms a transfer of one 16 byte block per
+/// HBlank (HBlank DMA).
+#[derive(Debug)]
+pub struct VramDma {
+    /// 0xFF51 ~ 0xFF52 Source, lower 4 bits are ignored
+    source: u16,
+    /// 0xFF53 ~ 0xFF54 Destination inside VRAM, lower 4 bits are ignored
+    destination: u16,
+    /// Remaining blocks minus one, as exposed by HDMA5
+    remaining: u8,
+    transfer: Transfer,
+}
+
+impl VramDma {
+    pub fn init() -> VramDma {
+        VramDma {
+            source: 0x0000,
+            destination: 0x8000,
+            remaining: 0x7F,
+            transfer: Transfer::Idle,
+        }
+    }
+
+    /// Checks the destination of a state just loaded, blocks have to fit in
+    /// VRAM
+    pub fn validate(&self) -> Result<(), String> {
+        if self.destination & !0x1FF0 != 0x8000 {
+            return Err(format!(
+                "Invalid HDMA destination {:04X} in save state",
+                self.destination
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn read(&self, address: usize) -> u8 {
+        match address {
+            0xFF55 => match self.transfer {
+                Transfer::Idle => 0xFF,
+                Transfer::HBlank => self.remaining & 0x7F,
+                Transfer::Cancelled => 0x80 | self.remaining,
+            },
+            // HDMA1 ~ HDMA4 are write-only
+            _ => 0xFF,
+        }
+    }
+
+    /// Writes a register. Returns the number of blocks that must be copied
+    /// immediately.
+    pub fn write(&mut self, address: usize, data: u8, lcd_enabled: bool) -> u8 {
+        match address {
+            0xFF51 => self.source = (self.source & 0x00F0) | ((data as u16) << 8),
+            0xFF52 => self.source = (self.source & 0xFF00) | (data as u16 & 0xF0),
+            0xFF53 => {
+                self.destination =
+                    0x8000 | (self.destination & 0x00F0) | ((data as u16 & 0x1F) << 8)
+            }
+            0xFF54 => self.destination = (self.destination & 0xFF00) | (data as u16 & 0xF0),
+            0xFF55 => {
+                let hblank = data & 0x80 != 0;
+                if self.transfer == Transfer::HBlank && !hblank {
+                    self.transfer = Transfer::Cancelled;
+                    return 0;
+                }
+                self.remaining = data & 0x7F;
+                if hblank {
+                    self.transfer = Transfer::HBlank;
+                    // With the LCD off there is no HBlank to wait for
+                    if !lcd_enabled {
+                        return 1;
+                    }
+                } else {
+                    self.transfer = Transfer::Idle;
+                    return self.remaining + 1;
+                }
+            }
+            _ => {}
+        }
+        0
+    }
+
+    /// Called when the PPU enters HBlank, returns whether a block is due
+    pub fn hblank(&self) -> bool {
+        self.transfer == Transfer::HBlank
+    }
+
+    /// Returns the `(source, destination)` of the next block and advances the
+    /// addresses and length past it.
+    pub fn next_block(&mut self) -> (u16, u16) {
+        let block = (self.source, self.destination);
+        self.source = self.source.wrapping_add(HDMA_BLOCK_LENGTH);
+        self.destination = 0x8000 | (self.destination.wrapping_add(HDMA_BLOCK_LENGTH) & 0x1FF0);
+        if self.transfer == Transfer::HBlank && self.remaining == 0 {
+            self.transfer = Transfer::Idle;
+        }
+        self.remaining = self.remaining.wrapping_sub(1) & 0x7F;
+        block
+    }
+}
+
+impl Snapshot for Transfer {
+    fn save(&self, writer: &mut Writer) {
+        (*self as u8).save(writer);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> Result<(), String> {
+        let mut transfer = 0u8;
+        transfer.load(reader)?;
+        *self = match transfer {
+            0 => Transfer::Idle,
+            1 => Transfer::HBlank,
+            2 => Transfer::Cancelled,
+            _ => return Err(format!("Invalid VRAM DMA state {} in save state", transfer)),
+        };
+        Ok(())
+    }
+}
+
+snapshot!(VramDma {
+    source,
+    destination,
+    remaining,
+    transfer
+});
@@ -0,0 +1,104 @@
+use crate::state::snapshot;
+
+/// Buttons in the order of their bits, directions first then actions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::Right,
+        Button::Left,
+        Button::Up,
+        Button::Down,
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+    ];
+
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Joypad mapped at 0xFF00. Bits 4 and 5 select the direction and action
+/// buttons respectively, whose lines are read back in bits 0-3, all of them
+/// active low.
+#[derive(Debug)]
+pub struct Joypad {
+    /// Selection bits as written
+    select: u8,
+    /// One bit per button, set while pressed
+    pressed: u8,
+}
+
+impl Joypad {
+    pub fn init() -> Joypad {
+        Joypad {
+            select: 0x00,
+            pressed: 0x00,
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        let mut lines = 0x0F;
+        if self.select & 0x10 == 0 {
+            lines &= !(self.pressed & 0x0F);
+        }
+        if self.select & 0x20 == 0 {
+            lines &= !(self.pressed >> 4);
+        }
+        0xC0 | self.select | lines
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.select = data & 0x30;
+    }
+
+    /// One bit per button held, by `Button::mask`
+    pub fn pressed(&self) -> u8 {
+        self.pressed
+    }
+
+    /// Updates a button. Returns whether one of the selected lines went low,
+    /// which requests the joypad interrupt.
+    pub fn set(&mut self, button: Button, pressed: bool) -> bool {
+        let before = self.read();
+        if pressed {
+            self.pressed |= button.mask();
+        } else {
+            self.pressed &= !button.mask();
+        }
+        before & !self.read() & 0x0F != 0
+    }
+}
+
+snapshot!(Joypad { select, pressed });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_button_selection() {
+        let mut joypad = Joypad::init();
+        joypad.write(0x20);
+        assert!(joypad.set(Button::Down, true));
+        assert!(!joypad.set(Button::Start, true));
+        assert_eq!(joypad.read(), 0xE7);
+        joypad.write(0x10);
+        assert_eq!(joypad.read(), 0xD7);
+        joypad.write(0x30);
+        assert_eq!(joypad.read(), 0xFF);
+        assert!(!joypad.set(Button::A, true));
+    }
+}
@@ -0,0 +1,142 @@
+use crate::state::snapshot;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    RomOnly,
+    MBC1,
+    MBC3,
+    MBC5,
+}
+
+impl Kind {
+    /// Reads the cartridge type at 0x0147. Unknown controllers are treated
+    /// as ROM only, as they were before any was emulated.
+    pub fn detect(rom: &[u8]) -> Kind {
+        match rom[0x0147] {
+            0x01..=0x03 => Kind::MBC1,
+            0x0F..=0x13 => Kind::MBC3,
+            0x19..=0x1E => Kind::MBC5,
+            _ => Kind::RomOnly,
+        }
+    }
+}
+
+/// Memory bank controller of the cartridge, mapped over the ROM at
+/// 0x0000 ~ 0x7FFF.
+///
+/// Writes to the ROM set its registers, which select the banks seen at
+/// 0x4000 ~ 0x7FFF and 0xA000 ~ 0xBFFF. Offsets past the ROM or RAM backing
+/// them wrap around, like unconnected address lines. The MBC3 clock is not
+/// emulated: selecting its registers reads 0xFF.
+#[derive(Debug)]
+pub struct MBC {
+    kind: Kind,
+    /// 0x0000 ~ 0x1FFF, external RAM is only accessible once enabled
+    ram_enabled: bool,
+    /// 0x2000 ~ 0x3FFF ROM bank mapped at 0x4000 ~ 0x7FFF, 9 bits on MBC5
+    rom_bank: u16,
+    /// 0x4000 ~ 0x5FFF RAM bank, or the upper ROM bank bits on MBC1
+    ram_bank: u8,
+    /// 0x6000 ~ 0x7FFF MBC1 mode, applying `ram_bank` to 0x0000 ~ 0x3FFF
+    /// and RAM too
+    mode: bool,
+}
+
+impl MBC {
+    pub fn init(kind: Kind) -> MBC {
+        MBC {
+            kind,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            mode: false,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, data: u8) {
+        match (self.kind, address) {
+            (Kind::RomOnly, _) => {}
+            (_, 0x0000..=0x1FFF) => self.ram_enabled = data & 0x0F == 0x0A,
+            // Bank 0 cannot be selected and maps bank 1 instead, except on MBC5
+            (Kind::MBC1, 0x2000..=0x3FFF) => self.rom_bank = (data & 0x1F).max(1) as u16,
+            (Kind::MBC3, 0x2000..=0x3FFF) => self.rom_bank = (data & 0x7F).max(1) as u16,
+            (Kind::MBC5, 0x2000..=0x2FFF) => self.rom_bank = self.rom_bank & 0x100 | data as u16,
+            (Kind::MBC5, 0x3000..=0x3FFF) => {
+                self.rom_bank = self.rom_bank & 0xFF | (data as u16 & 0x01) << 8
+            }
+            (Kind::MBC1, 0x4000..=0x5FFF) => self.ram_bank = data & 0x03,
+            (_, 0x4000..=0x5FFF) => self.ram_bank = data & 0x0F,
+            (Kind::MBC1, 0x6000..=0x7FFF) => self.mode = data & 0x01 != 0,
+            _ => {}
+        }
+    }
+
+    /// Offset in the ROM read at `address`, before wrapping to its size
+    pub fn rom_offset(&self, address: u16) -> usize {
+        let bank = match (self.kind, address) {
+            (Kind::MBC1, 0x0000..=0x3FFF) if self.mode => (self.ram_bank as usize) << 5,
+            (_, 0x0000..=0x3FFF) => 0,
+            (Kind::RomOnly, _) => 1,
+            (Kind::MBC1, _) => (self.ram_bank as usize) << 5 | self.rom_bank as usize,
+            _ => self.rom_bank as usize,
+        };
+        bank * 0x4000 + (address as usize & 0x3FFF)
+    }
+
+    /// Offset in the external RAM accessed at `address`, before wrapping to
+    /// its size, or `None` while it is disabled
+    pub fn ram_offset(&self, address: u16) -> Option<usize> {
+        let bank = match self.kind {
+            Kind::RomOnly => 0,
+            _ if !self.ram_enabled => return None,
+            Kind::MBC1 if !self.mode => 0,
+            Kind::MBC3 if self.ram_bank > 0x03 => return None,
+            _ => self.ram_bank as usize,
+        };
+        Some(bank * 0x2000 + (address as usize - 0xA000))
+    }
+}
+
+// The kind comes from the ROM, which is not saved
+snapshot!(MBC {
+    ram_enabled,
+    rom_bank,
+    ram_bank,
+    mode
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mbc1_banks() {
+        let mut mbc = MBC::init(Kind::MBC1);
+        assert_eq!(mbc.rom_offset(0x4000), 0x4000);
+        assert_eq!(mbc.ram_offset(0xA000), None);
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x2000, 0x00);
+        assert_eq!(mbc.rom_offset(0x4001), 0x4001);
+        mbc.write(0x2000, 0x05);
+        mbc.write(0x4000, 0x02);
+        assert_eq!(mbc.rom_offset(0x4000), 0x45 * 0x4000);
+        assert_eq!(mbc.rom_offset(0x0000), 0x0000);
+        assert_eq!(mbc.ram_offset(0xA001), Some(0x0001));
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.rom_offset(0x0000), 0x40 * 0x4000);
+        assert_eq!(mbc.ram_offset(0xA001), Some(0x4001));
+        mbc.write(0x0000, 0x00);
+        assert_eq!(mbc.ram_offset(0xA001), None);
+    }
+
+    #[test]
+    fn test_mbc5_banks() {
+        let mut mbc = MBC::init(Kind::MBC5);
+        mbc.write(0x2000, 0x00);
+        mbc.write(0x3000, 0x01);
+        assert_eq!(mbc.rom_offset(0x4000), 0x100 * 0x4000);
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x4000, 0x03);
+        assert_eq!(mbc.ram_offset(0xBFFF), Some(0x7FFF));
+    }
+}
@@ -1,67 +1,846 @@
+pub mod dma;
+pub mod hdma;
 pub mod io;
+pub mod joypad;
+pub mod mbc;
+pub mod serial;
+pub mod timer;
 
+use crate::bus::Bus;
+use crate::memory::dma::OamDma;
+use crate::memory::hdma::{VramDma, HDMA_BLOCK_CYCLES, HDMA_BLOCK_LENGTH};
 use crate::memory::io::IO;
+use crate::memory::joypad::{Button, Joypad};
+use crate::memory::mbc::{Kind, MBC};
+use crate::memory::serial::Serial;
+use crate::memory::timer::Timer;
+use crate::model::Model;
+use crate::ppu::{Mode, PPU};
+use crate::sound::sink::{AudioOutput, AudioSink};
+use crate::sound::APU;
+use crate::state::{self, snapshot};
 use crate::{_32KB, _4KB, _8KB};
 
+/// Bits of IF/IE for each interrupt source
+pub const INTERRUPT_VBLANK: u8 = 0x01;
+pub const INTERRUPT_STAT: u8 = 0x02;
+pub const INTERRUPT_TIMER: u8 = 0x04;
+pub const INTERRUPT_SERIAL: u8 = 0x08;
+pub const INTERRUPT_JOYPAD: u8 = 0x10;
+
+/// Physical buses the CPU and the DMA controllers contend for
+#[derive(Debug, PartialEq)]
+enum PhysicalBus {
+    /// Cartridge ROM/RAM and WRAM
+    External,
+    /// VRAM
+    Video,
+    /// OAM, I/O registers, HRAM and IE, which live inside the SoC
+    Internal,
+}
+
+impl PhysicalBus {
+    fn of(address: u16) -> PhysicalBus {
+        match address {
+            0x8000..=0x9FFF => PhysicalBus::Video,
+            0xFE00..=0xFFFF => PhysicalBus::Internal,
+            _ => PhysicalBus::External,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Memory {
+    model: Model,
+    /// CGB hardware running a CGB cartridge, as opposed to DMG compatibility
+    cgb_mode: bool,
+    /// 0x0000 ~ 0x00FF, plus 0x0200 ~ 0x08FF on CGB, until 0xFF50 is written
+    boot_rom: Option<Vec<u8>>,
     /// 0x0000 ~ 0x7FFF Bank 0~NN
-    rom: [u8; _32KB],
-    /// 0x8000 ~ 0x9FFF
-    vram: [u8; _8KB],
+    rom: Box<[u8; _32KB]>,
+    /// Bank controller, saved in a chunk of its own
+    mbc: MBC,
+    /// 0x8000 ~ 0x9FFF Bank 0~1
+    vram: Box<[[u8; _8KB]; 2]>,
+    // 0xFF4F
+    vram_bank: usize,
     /// 0xA000 ~ 0xBFFF External RAM
-    ram: [u8; _32KB],
-    /// 0xC000 ~ 0xCFFF WRAM
-    wram1: [u8; _4KB],
-    /// 0xD000 ~ 0xDFFF WRAM
-    wram2: [u8; _4KB],
+    ram: Box<[u8; _32KB]>,
+    /// 0xC000 ~ 0xCFFF WRAM Bank 0, 0xD000 ~ 0xDFFF WRAM Bank 1~7
+    wram: Box<[[u8; _4KB]; 8]>,
+    // 0xFF70
+    wram_bank: usize,
+    /// 0xFE00 ~ 0xFE9F Object Attribute Memory
+    oam: [u8; 0xA0],
     // 0xFF00 ~ 0xFF7F I/O Registers
     io: IO,
+    // 0xFF00 Joypad
+    joypad: Joypad,
+    // 0xFF01 ~ 0xFF02 Serial
+    serial: Serial,
+    // 0xFF04 ~ 0xFF07 Timer
+    timer: Timer,
+    // 0xFF10 ~ 0xFF3F Sound
+    apu: APU,
+    /// 0xFF80 ~ 0xFFFE High RAM
+    hram: [u8; 0x7F],
+    /// 0xFFFF Interrupt Enable
+    interrupt_enable: u8,
+    // 0xFF46 OAM DMA
+    dma: OamDma,
+    // 0xFF51 ~ 0xFF55 VRAM DMA
+    hdma: VramDma,
+    // 0xFF40 ~ 0xFF4B LCD
+    ppu: PPU,
+    /// M-cycles the CPU must stay halted for, e.g. during a VRAM DMA
+    stall: u16,
+    /// 0xFF4D bit 0, a speed switch is armed for the next STOP
+    speed_switch: bool,
+    /// 0xFF4D bit 7, CPU running at 8 MHz
+    double_speed: bool,
+    /// Cycles of the 4 MHz clock elapsed, which unlike the CPU clock does not
+    /// depend on the speed
+    elapsed: u64,
+    /// Value read from 0xFF44 in place of LY, as Gameboy Doctor logs expect
+    ly_stub: Option<u8>,
 }
 
 impl Memory {
     pub fn empty() -> Memory {
-        Memory {
-            rom: [0; _32KB],
-            vram: [0; _8KB],
-            ram: [0; _32KB],
-            wram1: [0; _4KB],
-            wram2: [0; _4KB],
-            io: IO::init(),
-        }
+        Memory::new([0; _32KB])
     }
 
+    /// Creates the memory of the model the cartridge targets
     pub fn new(program: [u8; _32KB]) -> Memory {
+        let model = Model::detect(&program);
+        Memory::with_model(program, model)
+    }
+
+    pub fn with_model(program: [u8; _32KB], model: Model) -> Memory {
         Memory {
+            model,
+            cgb_mode: model.is_cgb() && Model::detect(&program).is_cgb(),
+            boot_rom: None,
             // TODO: Should we use Vec<u8> instead of [u8; _32KB]?
-            rom: program,
-            vram: [0; _8KB],
-            ram: [0; _32KB],
-            wram1: [0; _4KB],
-            wram2: [0; _4KB],
+            mbc: MBC::init(Kind::detect(&program)),
+            rom: Box::new(program),
+            vram: Box::new([[0; _8KB]; 2]),
+            vram_bank: 0,
+            ram: Box::new([0; _32KB]),
+            wram: Box::new([[0; _4KB]; 8]),
+            wram_bank: 1,
+            oam: [0; 0xA0],
             io: IO::init(),
+            joypad: Joypad::init(),
+            serial: Serial::init(),
+            timer: Timer::init(),
+            apu: APU::init(model),
+            hram: [0; 0x7F],
+            interrupt_enable: 0,
+            dma: OamDma::init(),
+            hdma: VramDma::init(),
+            ppu: PPU::init(),
+            stall: 0,
+            speed_switch: false,
+            double_speed: false,
+            elapsed: 0,
+            ly_stub: None,
+        }
+    }
+
+    /// Makes 0xFF44 read `value` instead of LY, or LY again with `None`.
+    pub fn stub_ly(&mut self, value: Option<u8>) {
+        self.ly_stub = value;
+    }
+
+    /// Checks the indices of a state just loaded, which could panic later on
+    pub fn validate(&self) -> Result<(), String> {
+        state::check("VRAM bank", self.vram_bank, 0..2)?;
+        state::check("WRAM bank", self.wram_bank, 1..8)?;
+        if let Some(boot_rom) = &self.boot_rom {
+            Memory::check_boot_rom(boot_rom)?;
+        }
+        self.apu.validate()?;
+        self.dma.validate()?;
+        self.hdma.validate()?;
+        self.ppu.validate()
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    pub fn cgb_mode(&self) -> bool {
+        self.cgb_mode
+    }
+
+    /// Header checksum (0x014D), which the DMG boot ROM leaves in the flags
+    pub fn header_checksum(&self) -> u8 {
+        self.rom[0x014D]
+    }
+
+    /// Checks that a boot ROM has the length of a DMG or CGB one
+    pub fn check_boot_rom(boot_rom: &[u8]) -> Result<(), String> {
+        if boot_rom.len() != 0x100 && boot_rom.len() != 0x900 {
+            return Err(format!(
+                "Boot ROM must be 256 (DMG) or 2304 (CGB) bytes long, got {} bytes",
+                boot_rom.len()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Maps a DMG (256 bytes) or CGB (2304 bytes) boot ROM over the cartridge
+    /// until the program writes to 0xFF50
+    pub fn load_boot_rom(&mut self, boot_rom: Vec<u8>) -> Result<(), String> {
+        Memory::check_boot_rom(&boot_rom)?;
+        self.boot_rom = Some(boot_rom);
+        self.io.write(0xFF50, 0x00);
+        // The boot ROM is the one turning the LCD on
+        self.ppu.write(0xFF40, 0x00);
+        Ok(())
+    }
+
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom.is_some() && self.io.read(0xFF50) & 0x01 == 0
+    }
+
+    fn read_boot_rom(&self, address: u16) -> Option<u8> {
+        match &self.boot_rom {
+            Some(boot_rom) if self.boot_rom_mapped() => match address {
+                0x0000..=0x00FF => Some(boot_rom[address as usize]),
+                0x0200..=0x08FF if boot_rom.len() > 0x100 => Some(boot_rom[address as usize]),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Advances the components clocked alongside the CPU by `cycles` M-cycles.
+    /// The timer, serial port and OAM DMA run at the CPU speed while the PPU
+    /// and APU keep their own rate, so they only see half the dots in double
+    /// speed.
+    pub fn tick(&mut self, cycles: u16) {
+        let dots = if self.double_speed { 2 } else { 4 };
+        self.elapsed += dots as u64 * cycles as u64;
+        for _ in 0..cycles {
+            self.step_dma();
+            let counter = self.timer.counter();
+            if self.timer.step() {
+                self.request_interrupt(INTERRUPT_TIMER);
+            }
+            self.clock_sequencer(counter);
+            self.apu.step(dots);
+            if self.serial.step() {
+                self.request_interrupt(INTERRUPT_SERIAL);
+            }
+            if let Some(Mode::HBlank) = self.ppu.step(dots) {
+                self.ppu.render_line(&self.vram, &self.oam, self.cgb_mode);
+                if self.hdma.hblank() {
+                    self.copy_hdma_blocks(1);
+                }
+            }
+            let interrupts = self.ppu.take_interrupts();
+            if interrupts != 0 {
+                self.request_interrupt(interrupts);
+            }
+        }
+    }
+
+    /// Cartridge ROM, as loaded
+    pub fn rom(&self) -> &[u8] {
+        &self.rom[..]
+    }
+
+    pub fn mbc(&self) -> &MBC {
+        &self.mbc
+    }
+
+    pub fn mbc_mut(&mut self) -> &mut MBC {
+        &mut self.mbc
+    }
+
+    /// Frame drawn by the PPU in RGBA, complete once VBlank starts
+    pub fn frame(&self) -> &[u8] {
+        self.ppu.frame()
+    }
+
+    /// Frames completed by the PPU so far
+    pub fn frames(&self) -> u64 {
+        self.ppu.frames()
+    }
+
+    /// Bytes sent over the serial port so far
+    pub fn serial_output(&self) -> &[u8] {
+        self.serial.output()
+    }
+
+    pub fn request_interrupt(&mut self, interrupt: u8) {
+        let flags = self.io.read(0xFF0F);
+        self.io.write(0xFF0F, flags | interrupt);
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if self.joypad.set(button, pressed) {
+            self.request_interrupt(INTERRUPT_JOYPAD);
+        }
+    }
+
+    /// Buttons held, one bit each by `Button::mask`
+    pub fn buttons(&self) -> u8 {
+        self.joypad.pressed()
+    }
+
+    /// Presses and releases buttons to match what `buttons` returned
+    pub fn set_buttons(&mut self, buttons: u8) {
+        for button in Button::ALL {
+            self.set_button(button, buttons & button.mask() != 0);
+        }
+    }
+
+    pub fn elapsed(&self) -> u64 {
+        self.elapsed
+    }
+
+    fn write_timer(&mut self, address: usize, data: u8) {
+        let counter = self.timer.counter();
+        if self.timer.write(address, data) {
+            self.request_interrupt(INTERRUPT_TIMER);
+        }
+        self.clock_sequencer(counter);
+    }
+
+    /// Clocks the APU frame sequencer on a falling edge of DIV bit 4, or bit 5
+    /// in double speed, so resetting DIV can clock it early
+    fn clock_sequencer(&mut self, counter: u16) {
+        let bit = if self.double_speed { 1 << 13 } else { 1 << 12 };
+        if counter & bit != 0 && self.timer.counter() & bit == 0 {
+            self.apu.clock_sequencer();
+        }
+    }
+
+    /// Delivers the audio, resampled to `sample_rate`, to a sink
+    pub fn set_audio_sink(&mut self, sink: Box<dyn AudioSink>, sample_rate: u32) {
+        self.apu
+            .set_output(AudioOutput::new(sink, sample_rate, self.model));
+    }
+
+    /// Delivers the output of each APU channel, resampled to `sample_rate`,
+    /// to its own sink
+    pub fn set_audio_stems(&mut self, sinks: [Box<dyn AudioSink>; 4], sample_rate: u32) {
+        let model = self.model;
+        let [pulse1, pulse2, wave, noise] = sinks;
+        self.apu.set_stems([
+            AudioOutput::new(pulse1, sample_rate, model),
+            AudioOutput::new(pulse2, sample_rate, model),
+            AudioOutput::new(wave, sample_rate, model),
+            AudioOutput::new(noise, sample_rate, model),
+        ]);
+    }
+
+    /// Flushes the audio left to the sinks
+    pub fn finish_audio(&mut self) {
+        self.apu.finish();
+    }
+
+    fn copy_hdma_blocks(&mut self, blocks: u8) {
+        for _ in 0..blocks {
+            let (source, destination) = self.hdma.next_block();
+            for offset in 0..HDMA_BLOCK_LENGTH {
+                let data = self.read_unrestricted(source.wrapping_add(offset));
+                let address = (destination + offset) as usize - 0x8000;
+                self.vram[self.vram_bank][address] = data;
+            }
+            // The transfer takes the same time at both speeds
+            self.stall += HDMA_BLOCK_CYCLES << self.double_speed as u16;
+        }
+    }
+
+    fn step_dma(&mut self) {
+        if let Some((source, offset)) = self.dma.step() {
+            let data = self.read_unrestricted(source);
+            self.dma.set_latch(data);
+            self.oam[offset] = data;
+        }
+    }
+
+    /// Whether a CPU access to `address` collides with a running OAM DMA
+    fn dma_conflict(&self, address: u16) -> bool {
+        match self.dma.source() {
+            Some(source) => match address {
+                0xFE00..=0xFE9F => true,
+                _ => PhysicalBus::of(address) == PhysicalBus::of(source),
+            },
+            None => false,
         }
     }
 
     pub fn read(&self, address: u16) -> u8 {
+        if self.dma_conflict(address) {
+            return match address {
+                0xFE00..=0xFE9F => 0xFF,
+                _ => self.dma.latch(),
+            };
+        }
+        self.read_unrestricted(address)
+    }
+
+    fn read_unrestricted(&self, address: u16) -> u8 {
         // TODO: Should read access to some regions be protected?
+        if let Some(data) = self.read_boot_rom(address) {
+            return data;
+        }
         match address {
-            0x0000..=0x7FFF => self.rom[address as usize],
-            0x8000..=0x9FFF => self.vram[address as usize - 0x8000],
-            0xA000..=0xBFFF => self.ram[address as usize - 0xA000],
-            0xC000..=0xCFFF => self.wram1[address as usize - 0xC000],
-            0xD000..=0xDFFF => self.wram2[address as usize - 0xD000],
-            0xFF00..=0xFF7F => self.io.read(address as usize),
-            _ => panic!("Forbidden read from memory address: 0x{:04X}", address),
+            0x0000..=0x7FFF => self.rom[self.mbc.rom_offset(address) % self.rom.len()],
+            0x8000..=0x9FFF => self.vram[self.vram_bank][address as usize - 0x8000],
+            0xA000..=0xBFFF => match self.mbc.ram_offset(address) {
+                Some(offset) => self.ram[offset % self.ram.len()],
+                None => 0xFF,
+            },
+            0xC000..=0xCFFF => self.wram[0][address as usize - 0xC000],
+            0xD000..=0xDFFF => self.wram[self.wram_bank][address as usize - 0xD000],
+            // Echo RAM
+            0xE000..=0xFDFF => self.read_unrestricted(address - 0x2000),
+            0xFE00..=0xFE9F => self.oam[address as usize - 0xFE00],
+            // Unusable area, CGB repeats the upper nibble of the address
+            0xFEA0..=0xFEFF if self.model.is_cgb() => {
+                let nibble = address as u8 & 0xF0;
+                nibble | nibble >> 4
+            }
+            0xFEA0..=0xFEFF => 0x00,
+            0xFF00 => self.joypad.read(),
+            0xFF01..=0xFF02 => self.serial.read(address as usize),
+            0xFF04..=0xFF07 => self.timer.read(address as usize),
+            0xFF10..=0xFF3F | 0xFF76..=0xFF77 => self.apu.read(address as usize),
+            0xFF44 if self.ly_stub.is_some() => self.ly_stub.unwrap(),
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.ppu.read(address as usize),
+            0xFF46 => self.dma.read(),
+            // CGB registers are absent on DMG hardware and in compatibility mode
+            0xFF4D | 0xFF4F | 0xFF51..=0xFF55 | 0xFF68..=0xFF6B | 0xFF70 if !self.cgb_mode => 0xFF,
+            0xFF4D => 0x7E | (self.double_speed as u8) << 7 | self.speed_switch as u8,
+            0xFF4F => 0xFE | self.vram_bank as u8,
+            0xFF51..=0xFF55 => self.hdma.read(address as usize),
+            0xFF68..=0xFF6B => self.ppu.read(address as usize),
+            0xFF70 => 0xF8 | self.wram_bank as u8,
+            0xFF03..=0xFF7F => self.io.read(address as usize),
+            0xFF80..=0xFFFE => self.hram[address as usize - 0xFF80],
+            0xFFFF => self.interrupt_enable,
         }
     }
 
     pub fn write(&mut self, address: u16, data: u8) {
+        if self.dma_conflict(address) {
+            return;
+        }
         // TODO: Write access to some regions should be protected
         match address {
-            // ROM data
-            0x0000..=0x7FFF => panic!("Forbidden write into ROM memory region!"),
-            _ => self.ram[address as usize] = data,
+            0x0000..=0x7FFF => self.mbc.write(address, data),
+            0x8000..=0x9FFF => self.vram[self.vram_bank][address as usize - 0x8000] = data,
+            0xA000..=0xBFFF => {
+                if let Some(offset) = self.mbc.ram_offset(address) {
+                    let length = self.ram.len();
+                    self.ram[offset % length] = data;
+                }
+            }
+            0xC000..=0xCFFF => self.wram[0][address as usize - 0xC000] = data,
+            0xD000..=0xDFFF => self.wram[self.wram_bank][address as usize - 0xD000] = data,
+            0xE000..=0xFDFF => self.write(address - 0x2000, data),
+            0xFE00..=0xFE9F => self.oam[address as usize - 0xFE00] = data,
+            0xFEA0..=0xFEFF => {}
+            0xFF00 => self.joypad.write(data),
+            0xFF01..=0xFF02 => self.serial.write(address as usize, data),
+            0xFF04..=0xFF07 => self.write_timer(address as usize, data),
+            0xFF10..=0xFF3F => self.apu.write(address as usize, data),
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.ppu.write(address as usize, data),
+            0xFF46 => self.dma.write(data),
+            0xFF4D | 0xFF4F | 0xFF51..=0xFF55 | 0xFF68..=0xFF6B | 0xFF70 if !self.cgb_mode => {}
+            0xFF4D => self.speed_switch = data & 0x01 != 0,
+            // Once unmapped the boot ROM cannot be mapped back
+            0xFF50 => {
+                if data & 0x01 != 0 {
+                    self.io.write(0xFF50, 0x01);
+                }
+            }
+            0xFF4F => self.vram_bank = (data & 0x01) as usize,
+            0xFF51..=0xFF55 => {
+                let blocks = self
+                    .hdma
+                    .write(address as usize, data, self.ppu.lcd_enabled());
+                self.copy_hdma_blocks(blocks);
+            }
+            0xFF68..=0xFF6B => self.ppu.write(address as usize, data),
+            // Bank 0 cannot be mapped at 0xD000
+            0xFF70 => self.wram_bank = ((data & 0x07) as usize).max(1),
+            0xFF03..=0xFF7F => self.io.write(address as usize, data),
+            0xFF80..=0xFFFE => self.hram[address as usize - 0xFF80] = data,
+            0xFFFF => self.interrupt_enable = data,
+        }
+    }
+}
+
+impl Bus for Memory {
+    fn read(&self, address: u16) -> u8 {
+        Memory::read(self, address)
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        Memory::write(self, address, data)
+    }
+
+    fn tick(&mut self, cycles: u16) {
+        Memory::tick(self, cycles)
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.interrupt_enable & self.io.read(0xFF0F) & 0x1F
+    }
+
+    fn acknowledge_interrupt(&mut self, interrupt: u8) {
+        let flags = self.io.read(0xFF0F);
+        self.io.write(0xFF0F, flags & !interrupt);
+    }
+
+    /// Returns, and clears, the M-cycles the CPU has to spend halted
+    fn take_stall(&mut self) -> u16 {
+        std::mem::replace(&mut self.stall, 0)
+    }
+
+    fn reset_divider(&mut self) {
+        self.write_timer(0xFF04, 0x00);
+    }
+
+    fn speed_switch_armed(&self) -> bool {
+        self.speed_switch
+    }
+
+    /// Performs the speed switch armed through KEY1, triggered by STOP
+    fn switch_speed(&mut self) {
+        self.double_speed = !self.double_speed;
+        self.speed_switch = false;
+    }
+
+    fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    fn rom_bank(&self, address: u16) -> u16 {
+        (self.mbc.rom_offset(address) % self.rom.len() / 0x4000) as u16
+    }
+}
+
+// The ROM is not saved, states are only loaded into the same one
+snapshot!(Memory {
+    model,
+    cgb_mode,
+    boot_rom,
+    vram,
+    vram_bank,
+    ram,
+    wram,
+    wram_bank,
+    oam,
+    io,
+    joypad,
+    serial,
+    timer,
+    apu,
+    hram,
+    interrupt_enable,
+    dma,
+    hdma,
+    ppu,
+    stall,
+    speed_switch,
+    double_speed,
+    elapsed
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::dma::OAM_DMA_LENGTH;
+
+    /// M-cycles from the start of a scanline until HBlank
+    const CYCLES_TO_HBLANK: u16 = (80 + 172) / 4;
+    /// M-cycles per scanline
+    const CYCLES_PER_LINE: u16 = 456 / 4;
+
+    fn cgb_memory() -> Memory {
+        let mut program = [0; _32KB];
+        program[0x0143] = 0x80;
+        Memory::new(program)
+    }
+
+    fn memory_with_sprites() -> Memory {
+        let mut memory = Memory::empty();
+        for i in 0..OAM_DMA_LENGTH {
+            memory.write(0xC100 + i, i as u8);
+        }
+        memory
+    }
+
+    #[test]
+    fn test_oam_dma_transfer() {
+        let mut memory = memory_with_sprites();
+        memory.write(0xFF46, 0xC1);
+        assert_eq!(memory.read(0xFF46), 0xC1);
+
+        // Start-up delay: the CPU still owns the buses
+        assert_eq!(memory.read(0xFE00), 0x00);
+        assert_eq!(memory.read(0xC105), 0x05);
+
+        memory.tick(1);
+        assert_eq!(memory.read(0xFE00), 0xFF);
+
+        memory.tick(OAM_DMA_LENGTH - 1);
+        assert_eq!(memory.read(0xFE00), 0xFF);
+        memory.tick(1);
+        for i in 0..OAM_DMA_LENGTH {
+            assert_eq!(memory.read(0xFE00 + i), i as u8);
+        }
+    }
+
+    #[test]
+    fn test_oam_dma_bus_conflicts() {
+        let mut memory = memory_with_sprites();
+        memory.write(0xFF80, 0x42);
+        memory.write(0x8000, 0x24);
+        memory.write(0xFF46, 0xC1);
+        memory.tick(4);
+
+        // Same bus as the source returns the byte being transferred
+        assert_eq!(memory.read(0x0150), 0x02);
+        assert_eq!(memory.read(0xD000), 0x02);
+        // HRAM and other buses are still reachable
+        assert_eq!(memory.read(0xFF80), 0x42);
+        assert_eq!(memory.read(0x8000), 0x24);
+        // Writes colliding with the transfer are dropped
+        memory.write(0xC000, 0x99);
+        memory.write(0xFF81, 0x99);
+        memory.tick(OAM_DMA_LENGTH);
+        assert_eq!(memory.read(0xC000), 0x00);
+        assert_eq!(memory.read(0xFF81), 0x99);
+    }
+
+    #[test]
+    fn test_oam_dma_restart() {
+        let mut memory = memory_with_sprites();
+        memory.write(0xD000, 0xAB);
+        memory.write(0xFF46, 0xC1);
+        memory.tick(11);
+
+        // Restarting keeps OAM locked during the new start-up delay
+        memory.write(0xFF46, 0xD0);
+        memory.tick(1);
+        assert_eq!(memory.read(0xFE00), 0xFF);
+        memory.tick(1);
+        memory.tick(OAM_DMA_LENGTH - 1);
+        assert_eq!(memory.read(0xFE00), 0xAB);
+        assert_eq!(memory.read(0xFE0A), 0x00);
+    }
+
+    fn memory_with_tiles() -> Memory {
+        let mut memory = cgb_memory();
+        for i in 0..0x40 {
+            memory.write(0xC000 + i, i as u8 + 1);
+        }
+        // Source 0xC000, destination 0x8800 in VRAM bank 1
+        memory.write(0xFF4F, 0x01);
+        memory.write(0xFF51, 0xC0);
+        memory.write(0xFF52, 0x0F);
+        memory.write(0xFF53, 0x08);
+        memory.write(0xFF54, 0x00);
+        memory
+    }
+
+    #[test]
+    fn test_general_purpose_dma() {
+        let mut memory = memory_with_tiles();
+        memory.write(0xFF55, 0x01);
+        assert_eq!(memory.read(0xFF55), 0xFF);
+        assert_eq!(memory.take_stall(), 2 * HDMA_BLOCK_CYCLES);
+        for i in 0..0x20 {
+            assert_eq!(memory.read(0x8800 + i), i as u8 + 1);
         }
+        assert_eq!(memory.read(0x8820), 0x00);
+
+        memory.write(0xFF4F, 0x00);
+        assert_eq!(memory.read(0xFF4F), 0xFE);
+        assert_eq!(memory.read(0x8800), 0x00);
+    }
+
+    #[test]
+    fn test_hblank_dma() {
+        let mut memory = memory_with_tiles();
+        memory.write(0xFF55, 0x81);
+        assert_eq!(memory.read(0xFF55), 0x01);
+        assert_eq!(memory.take_stall(), 0);
+
+        memory.tick(CYCLES_TO_HBLANK);
+        assert_eq!(memory.read(0xFF55), 0x00);
+        assert_eq!(memory.take_stall(), HDMA_BLOCK_CYCLES);
+        assert_eq!(memory.read(0x880F), 0x10);
+        assert_eq!(memory.read(0x8810), 0x00);
+
+        memory.tick(CYCLES_PER_LINE);
+        assert_eq!(memory.read(0xFF55), 0xFF);
+        assert_eq!(memory.read(0x881F), 0x20);
+
+        memory.tick(CYCLES_PER_LINE);
+        assert_eq!(memory.read(0x8820), 0x00);
+    }
+
+    #[test]
+    fn test_double_speed_dma_and_ppu() {
+        let mut memory = memory_with_tiles();
+        memory.write(0xFF4D, 0x01);
+        assert_eq!(memory.read(0xFF4D), 0x7F);
+        memory.switch_speed();
+        assert_eq!(memory.read(0xFF4D), 0xFE);
+
+        memory.write(0xFF55, 0x00);
+        assert_eq!(memory.take_stall(), 2 * HDMA_BLOCK_CYCLES);
+
+        // The PPU needs twice as many CPU cycles to reach HBlank
+        memory.write(0xFF55, 0x80);
+        memory.tick(CYCLES_TO_HBLANK);
+        assert_eq!(memory.read(0xFF55), 0x00);
+        memory.tick(CYCLES_TO_HBLANK);
+        assert_eq!(memory.read(0xFF55), 0xFF);
+    }
+
+    #[test]
+    fn test_hblank_dma_cancel() {
+        let mut memory = memory_with_tiles();
+        memory.write(0xFF55, 0x83);
+        memory.tick(CYCLES_TO_HBLANK);
+        memory.write(0xFF55, 0x00);
+        assert_eq!(memory.read(0xFF55), 0x82);
+
+        memory.tick(CYCLES_PER_LINE);
+        assert_eq!(memory.read(0x8810), 0x00);
+    }
+
+    #[test]
+    fn test_rom_writes_ignored() {
+        let mut program = [0; _32KB];
+        program[0x2000] = 0x42;
+        let mut memory = Memory::new(program);
+        memory.write(0x2000, 0x01);
+        memory.write(0x0000, 0x0A);
+        assert_eq!(memory.read(0x2000), 0x42);
+        assert_eq!(memory.read(0x0000), 0x00);
+    }
+
+    #[test]
+    fn test_ly_stub() {
+        let mut memory = Memory::empty();
+        let ly = memory.read(0xFF44);
+        memory.stub_ly(Some(0x90));
+        assert_eq!(memory.read(0xFF44), 0x90);
+        memory.stub_ly(None);
+        assert_eq!(memory.read(0xFF44), ly);
+    }
+
+    #[test]
+    fn test_mbc_banks() {
+        let mut program = [0; _32KB];
+        program[0x0147] = 0x03;
+        program[0x4000] = 0x42;
+        let mut memory = Memory::new(program);
+        memory.write(0xA000, 0x01);
+        assert_eq!(memory.read(0xA000), 0xFF);
+        memory.write(0x0000, 0x0A);
+        memory.write(0xA000, 0x01);
+        memory.write(0x6000, 0x01);
+        memory.write(0x4000, 0x01);
+        memory.write(0xA000, 0x02);
+        assert_eq!(memory.read(0xA000), 0x02);
+        memory.write(0x4000, 0x00);
+        assert_eq!(memory.read(0xA000), 0x01);
+
+        // Banks past the 32 KB of ROM wrap around
+        assert_eq!(memory.read(0x4000), 0x42);
+        memory.write(0x2000, 0x02);
+        assert_eq!(memory.read(0x4000), 0x00);
+        assert_eq!(memory.rom_bank(0x4000), 0);
+    }
+
+    #[test]
+    fn test_wram_banks() {
+        let mut memory = cgb_memory();
+        assert_eq!(memory.model(), Model::CGB);
+        memory.write(0xD000, 0x01);
+        memory.write(0xFF70, 0x07);
+        assert_eq!(memory.read(0xFF70), 0xFF);
+        assert_eq!(memory.read(0xD000), 0x00);
+        memory.write(0xD000, 0x07);
+        memory.write(0xFF70, 0x00);
+        assert_eq!(memory.read(0xFF70), 0xF9);
+        assert_eq!(memory.read(0xD000), 0x01);
+        assert_eq!(memory.read(0xF000), 0x01);
+    }
+
+    #[test]
+    fn test_invalid_state() {
+        let mut memory = cgb_memory();
+        assert_eq!(memory.validate(), Ok(()));
+        memory.vram_bank = 2;
+        assert_eq!(
+            memory.validate(),
+            Err(String::from("Invalid VRAM bank 2 in save state"))
+        );
+        memory.vram_bank = 0;
+        memory.wram_bank = 8;
+        assert_eq!(
+            memory.validate(),
+            Err(String::from("Invalid WRAM bank 8 in save state"))
+        );
+        memory.wram_bank = 1;
+        memory.boot_rom = Some(vec![0; 0x200]);
+        assert!(memory.validate().is_err());
+    }
+
+    #[test]
+    fn test_dmg_compatibility_mode() {
+        let mut memory = Memory::with_model([0; _32KB], Model::CGB);
+        assert!(!memory.cgb_mode());
+        memory.write(0xFF70, 0x02);
+        memory.write(0xFF4F, 0x01);
+        memory.write(0xFF4D, 0x01);
+        assert_eq!(memory.read(0xFF70), 0xFF);
+        assert_eq!(memory.read(0xFF4F), 0xFF);
+        assert_eq!(memory.read(0xFF4D), 0xFF);
+        assert!(!memory.speed_switch_armed());
+
+        memory.write(0xD000, 0x42);
+        memory.write(0x8000, 0x24);
+        assert_eq!(memory.read(0xFEB3), 0xBB);
+        assert_eq!(Memory::empty().model(), Model::DMG);
+        assert_eq!(Memory::empty().read(0xFEB3), 0x00);
+        assert_eq!(memory.read(0xD000), 0x42);
+        assert_eq!(memory.read(0x8000), 0x24);
+    }
+
+    #[test]
+    fn test_div_clocks_frame_sequencer() {
+        let mut memory = Memory::empty();
+        memory.write(0xFF17, 0xF0);
+        memory.write(0xFF16, 0x3F);
+        memory.write(0xFF19, 0xC0);
+        assert_eq!(memory.read(0xFF26), 0xF2);
+
+        // DIV bit 4 falls every 2048 M-cycles, clocking the length counter
+        memory.tick(2047);
+        assert_eq!(memory.read(0xFF26), 0xF2);
+        memory.tick(1);
+        assert_eq!(memory.read(0xFF26), 0xF0);
+
+        // Resetting DIV while bit 4 is set clocks the sequencer too
+        memory.write(0xFF19, 0xC0);
+        memory.tick(1024);
+        memory.tick(1);
+        memory.write(0xFF04, 0x00);
+        assert_eq!(memory.read(0xFF26), 0xF2);
     }
 }
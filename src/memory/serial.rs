@@ -0,0 +1,118 @@
+use crate::state::snapshot;
+
+/// M-cycles per bit shifted with the internal 8192 Hz clock
+const NORMAL_BIT_CYCLES: u16 = 128;
+/// M-cycles per bit shifted with the CGB internal 262144 Hz clock
+const FAST_BIT_CYCLES: u16 = 4;
+
+/// Serial port mapped at 0xFF01 ~ 0xFF02.
+///
+/// There is no link cable, so transfers using the internal clock shift in
+/// 0xFF from the (absent) other side, while external clock transfers never
+/// complete.
+#[derive(Debug)]
+pub struct Serial {
+    /// 0xFF01 Serial transfer data
+    data: u8,
+    /// 0xFF02 Serial transfer control
+    control: u8,
+    /// Bits left to shift in the current transfer
+    bits: u8,
+    /// M-cycles until the next bit is shifted
+    countdown: u16,
+    /// Every byte sent with the internal clock, which test ROMs print to
+    output: Vec<u8>,
+}
+
+impl Serial {
+    pub fn init() -> Serial {
+        Serial {
+            data: 0x00,
+            control: 0x7E,
+            bits: 0,
+            countdown: 0,
+            output: Vec::new(),
+        }
+    }
+
+    pub fn read(&self, address: usize) -> u8 {
+        match address {
+            0xFF01 => self.data,
+            0xFF02 => 0x7C | self.control,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: usize, data: u8) {
+        match address {
+            0xFF01 => self.data = data,
+            0xFF02 => {
+                self.control = data & 0x83;
+                if self.control & 0x81 == 0x81 {
+                    self.output.push(self.data);
+                    self.bits = 8;
+                    self.countdown = self.bit_cycles();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    fn bit_cycles(&self) -> u16 {
+        if self.control & 0x02 != 0 {
+            FAST_BIT_CYCLES
+        } else {
+            NORMAL_BIT_CYCLES
+        }
+    }
+
+    /// Advances the port by one M-cycle. Returns whether a transfer completed.
+    pub fn step(&mut self) -> bool {
+        if self.bits == 0 {
+            return false;
+        }
+        self.countdown -= 1;
+        if self.countdown > 0 {
+            return false;
+        }
+        self.data = (self.data << 1) | 0x01;
+        self.bits -= 1;
+        self.countdown = self.bit_cycles();
+        if self.bits == 0 {
+            self.control &= 0x7F;
+            return true;
+        }
+        false
+    }
+}
+
+snapshot!(Serial {
+    data,
+    control,
+    bits,
+    countdown,
+    output
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_internal_clock_transfer() {
+        let mut serial = Serial::init();
+        serial.write(0xFF01, 0x42);
+        serial.write(0xFF02, 0x81);
+        for _ in 0..(8 * NORMAL_BIT_CYCLES - 1) {
+            assert!(!serial.step());
+        }
+        assert!(serial.step());
+        assert_eq!(serial.read(0xFF01), 0xFF);
+        assert_eq!(serial.read(0xFF02) & 0x80, 0x00);
+        assert_eq!(serial.output(), [0x42]);
+    }
+}
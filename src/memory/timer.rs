@@ -0,0 +1,127 @@
+use crate::state::snapshot;
+
+/// Bit of the internal counter whose falling edge clocks TIMA, by TAC speed
+const TIMA_TAPS: [u16; 4] = [9, 3, 5, 7];
+
+/// DIV/TIMA timer mapped at 0xFF04 ~ 0xFF07.
+///
+/// DIV is the upper byte of a 16-bit counter incremented every T-cycle of
+/// the CPU clock, so the whole timer speeds up in double speed mode.
+#[derive(Debug)]
+pub struct Timer {
+    /// Internal counter, DIV is its upper byte
+    counter: u16,
+    /// 0xFF05 Timer counter
+    tima: u8,
+    /// 0xFF06 Timer modulo
+    tma: u8,
+    /// 0xFF07 Timer control
+    tac: u8,
+}
+
+impl Timer {
+    pub fn init() -> Timer {
+        Timer {
+            counter: 0x0000,
+            tima: 0x00,
+            tma: 0x00,
+            tac: 0x00,
+        }
+    }
+
+    /// Internal counter driving DIV, also tapped by the APU frame sequencer
+    pub fn counter(&self) -> u16 {
+        self.counter
+    }
+
+    pub fn read(&self, address: usize) -> u8 {
+        match address {
+            0xFF04 => (self.counter >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            0xFF07 => 0xF8 | self.tac,
+            _ => 0xFF,
+        }
+    }
+
+    /// Writes a register. Returns whether TIMA overflowed as a side effect.
+    pub fn write(&mut self, address: usize, data: u8) -> bool {
+        match address {
+            0xFF04 => return self.set_counter(0),
+            0xFF05 => self.tima = data,
+            0xFF06 => self.tma = data,
+            0xFF07 => {
+                let before = self.tima_input();
+                self.tac = data & 0x07;
+                // Disabling the timer or changing the tap can produce a falling edge
+                if before && !self.tima_input() {
+                    return self.increment_tima();
+                }
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// Advances the timer by one M-cycle. Returns whether TIMA overflowed.
+    pub fn step(&mut self) -> bool {
+        self.set_counter(self.counter.wrapping_add(4))
+    }
+
+    fn tima_input(&self) -> bool {
+        let tap = TIMA_TAPS[(self.tac & 0x03) as usize];
+        self.tac & 0x04 != 0 && self.counter & (1 << tap) != 0
+    }
+
+    fn set_counter(&mut self, counter: u16) -> bool {
+        let before = self.tima_input();
+        self.counter = counter;
+        if before && !self.tima_input() {
+            return self.increment_tima();
+        }
+        false
+    }
+
+    fn increment_tima(&mut self) -> bool {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = if overflow { self.tma } else { tima };
+        overflow
+    }
+}
+
+snapshot!(Timer {
+    counter,
+    tima,
+    tma,
+    tac
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_div() {
+        let mut timer = Timer::init();
+        for _ in 0..64 {
+            timer.step();
+        }
+        assert_eq!(timer.read(0xFF04), 0x01);
+        timer.write(0xFF04, 0x55);
+        assert_eq!(timer.read(0xFF04), 0x00);
+    }
+
+    #[test]
+    fn test_tima_overflow() {
+        let mut timer = Timer::init();
+        timer.write(0xFF06, 0xAB);
+        timer.write(0xFF05, 0xFF);
+        // Enabled at 262144 Hz, TIMA ticks every 4 M-cycles
+        timer.write(0xFF07, 0x05);
+        assert!(!timer.step());
+        assert!(!timer.step());
+        assert!(!timer.step());
+        assert!(timer.step());
+        assert_eq!(timer.read(0xFF05), 0xAB);
+    }
+}
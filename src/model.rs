@@ -0,0 +1,116 @@
+use crate::sound::resampler::APU_CLOCK;
+use crate::state::{Reader, Snapshot, Writer};
+use std::str::FromStr;
+
+/// Master clock of the SGB in Hz, the 21.477 MHz SNES clock divided by 5
+pub const SGB_CLOCK: u32 = 4_295_454;
+
+/// Hardware revisions, each leaving a different post-boot state behind and
+/// exposing a different set of features
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Model {
+    /// Original Game Boy, early boot ROM revision
+    DMG0,
+    /// Original Game Boy
+    DMG,
+    /// Game Boy Pocket/Light
+    MGB,
+    /// Super Game Boy
+    SGB,
+    /// Super Game Boy 2
+    SGB2,
+    /// Game Boy Color
+    CGB,
+    /// Game Boy Advance running Game Boy software
+    AGB,
+}
+
+impl Model {
+    /// Picks the model a cartridge targets from its CGB flag (0x0143)
+    pub fn detect(program: &[u8]) -> Model {
+        match program.get(0x0143) {
+            Some(flag) if flag & 0x80 != 0 => Model::CGB,
+            _ => Model::DMG,
+        }
+    }
+
+    /// Whether the hardware has the CGB features (double speed, VRAM/WRAM
+    /// banking, color palettes, VRAM DMA)
+    pub fn is_cgb(&self) -> bool {
+        matches!(self, Model::CGB | Model::AGB)
+    }
+
+    /// Rate of the master clock in Hz. The SGB runs everything 2.4% fast,
+    /// which the SGB2 fixed with a crystal of its own.
+    pub fn clock(&self) -> u32 {
+        match self {
+            Model::SGB => SGB_CLOCK,
+            _ => APU_CLOCK,
+        }
+    }
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Model, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "dmg0" => Ok(Model::DMG0),
+            "dmg" => Ok(Model::DMG),
+            "mgb" => Ok(Model::MGB),
+            "sgb" => Ok(Model::SGB),
+            "sgb2" => Ok(Model::SGB2),
+            "cgb" => Ok(Model::CGB),
+            "agb" => Ok(Model::AGB),
+            _ => Err(format!("Unknown model: {}", name)),
+        }
+    }
+}
+
+impl Snapshot for Model {
+    fn save(&self, writer: &mut Writer) {
+        (*self as u8).save(writer);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> Result<(), String> {
+        let mut model = 0u8;
+        model.load(reader)?;
+        *self = match model {
+            0 => Model::DMG0,
+            1 => Model::DMG,
+            2 => Model::MGB,
+            3 => Model::SGB,
+            4 => Model::SGB2,
+            5 => Model::CGB,
+            6 => Model::AGB,
+            _ => return Err(format!("Invalid model {} in save state", model)),
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let mut program = [0; 0x150];
+        assert_eq!(Model::detect(&program), Model::DMG);
+        program[0x0143] = 0x80;
+        assert_eq!(Model::detect(&program), Model::CGB);
+        program[0x0143] = 0xC0;
+        assert_eq!(Model::detect(&program), Model::CGB);
+        assert!(Model::AGB.is_cgb());
+        assert!(!Model::SGB2.is_cgb());
+        assert_eq!(Model::SGB.clock(), SGB_CLOCK);
+        assert_eq!(Model::SGB2.clock(), APU_CLOCK);
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("cgb".parse(), Ok(Model::CGB));
+        assert_eq!("DMG0".parse(), Ok(Model::DMG0));
+        assert!("gba".parse::<Model>().is_err());
+    }
+}
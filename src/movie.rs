@@ -0,0 +1,279 @@
+use crate::cpu::CPU;
+use crate::model::Model;
+use crate::png;
+use crate::state::{self, Reader, Snapshot, Writer};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Identifies movies
+const MAGIC: &[u8; 8] = b"GBCMOVIE";
+
+/// Version of the format written
+pub const VERSION: u16 = 1;
+
+/// Frames between the state hashes written, once a second
+pub const HASH_INTERVAL: u32 = 60;
+
+/// Hash of everything emulated, compared to find where a playback desyncs
+pub fn state_hash(cpu: &CPU) -> u32 {
+    png::crc32(&state::capture(cpu))
+}
+
+/// Records the buttons held during each frame into a movie file, starting
+/// from a save state.
+///
+/// The file is a header, the ROM's CRC-32, the model, the hash interval and
+/// the start state, followed by a byte of buttons per frame and, after every
+/// `HASH_INTERVAL` frames, the hash of the state. It is flushed every frame
+/// so that a crash leaves it complete up to there.
+pub struct Recorder {
+    path: PathBuf,
+    output: Option<BufWriter<File>>,
+    frames: u32,
+}
+
+impl Recorder {
+    /// Records to `path`, once `start` is called
+    pub fn new(path: &Path) -> Recorder {
+        Recorder {
+            path: path.to_path_buf(),
+            output: None,
+            frames: 0,
+        }
+    }
+
+    /// Starts over from the current state, e.g. after a reset or after
+    /// loading a state, which would otherwise break the movie
+    pub fn start(&mut self, cpu: &CPU) -> io::Result<()> {
+        let mut header = Writer::new();
+        MAGIC.save(&mut header);
+        VERSION.save(&mut header);
+        png::crc32(cpu.memory().rom()).save(&mut header);
+        cpu.memory().model().save(&mut header);
+        HASH_INTERVAL.save(&mut header);
+        state::save(cpu).save(&mut header);
+
+        let mut output = BufWriter::new(File::create(&self.path)?);
+        output.write_all(&header.into_inner())?;
+        output.flush()?;
+        self.output = Some(output);
+        self.frames = 0;
+        Ok(())
+    }
+
+    /// To call after every frame emulated, with the buttons held during it
+    pub fn on_frame(&mut self, cpu: &CPU) -> io::Result<()> {
+        let output = match self.output.as_mut() {
+            Some(output) => output,
+            None => return Ok(()),
+        };
+        output.write_all(&[cpu.memory().buttons()])?;
+        self.frames += 1;
+        if self.frames.is_multiple_of(HASH_INTERVAL) {
+            output.write_all(&state_hash(cpu).to_le_bytes())?;
+        }
+        output.flush()
+    }
+}
+
+/// A movie as recorded
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    /// CRC-32 of the ROM
+    pub rom: u32,
+    pub model: Model,
+    /// Frames between state hashes
+    pub interval: u32,
+    /// Save state the movie starts from
+    pub start: Vec<u8>,
+    /// Buttons held during each frame, as `Memory::buttons` returns them
+    pub inputs: Vec<u8>,
+    /// State hash after every `interval` frames
+    pub hashes: Vec<u32>,
+}
+
+/// First state hash that differs from the one recorded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Desync {
+    /// Frames played when the hashes were compared
+    pub frame: u64,
+    pub hash: u32,
+    pub expected: u32,
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "desync after frame {}: state hash {:08X}, recorded {:08X}",
+            self.frame, self.hash, self.expected
+        )
+    }
+}
+
+impl Movie {
+    /// Reads a movie. Frames cut short, e.g. by a crash while recording, are
+    /// left out.
+    pub fn parse(data: &[u8]) -> Result<Movie, String> {
+        let mut reader = Reader::new(data);
+        let mut magic = [0; 8];
+        if magic.load(&mut reader).is_err() || &magic != MAGIC {
+            return Err(String::from("Not a movie"));
+        }
+        let mut version = 0u16;
+        version.load(&mut reader)?;
+        if version != VERSION {
+            return Err(format!("Movie version {} is not supported", version));
+        }
+        let mut movie = Movie {
+            rom: 0,
+            model: Model::DMG,
+            interval: 0,
+            start: Vec::new(),
+            inputs: Vec::new(),
+            hashes: Vec::new(),
+        };
+        movie.rom.load(&mut reader)?;
+        movie.model.load(&mut reader)?;
+        movie.interval.load(&mut reader)?;
+        movie.start.load(&mut reader)?;
+        if movie.interval == 0 {
+            return Err(String::from("Movie has no hash interval"));
+        }
+        loop {
+            let mut buttons = 0u8;
+            if buttons.load(&mut reader).is_err() {
+                break;
+            }
+            if (movie.inputs.len() as u32 + 1).is_multiple_of(movie.interval) {
+                let mut hash = 0u32;
+                if hash.load(&mut reader).is_err() {
+                    break;
+                }
+                movie.hashes.push(hash);
+            }
+            movie.inputs.push(buttons);
+        }
+        Ok(movie)
+    }
+
+    /// Loads the start state, which has to be from the same ROM
+    pub fn start(&self, cpu: &mut CPU) -> Result<(), String> {
+        if png::crc32(cpu.memory().rom()) != self.rom {
+            return Err(String::from("Movie is from another ROM"));
+        }
+        state::load(cpu, &self.start)
+    }
+
+    /// Plays the inputs back from the start state, calling `on_frame` with
+    /// the number of frames played after each of them. Stops at the first
+    /// state hash that differs.
+    pub fn play<F>(&self, cpu: &mut CPU, mut on_frame: F) -> Result<(), Desync>
+    where
+        F: FnMut(&CPU, u64),
+    {
+        let mut hashes = self.hashes.iter();
+        for (frame, buttons) in (1..).zip(&self.inputs) {
+            cpu.memory_mut().set_buttons(*buttons);
+            cpu.run_frame();
+            on_frame(cpu, frame);
+            if frame.is_multiple_of(self.interval as u64) {
+                let expected = *hashes.next().expect("Movie hashes are parsed with inputs");
+                let hash = state_hash(cpu);
+                if hash != expected {
+                    return Err(Desync {
+                        frame,
+                        hash,
+                        expected,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::tests::cpu_with_program;
+    use crate::memory::joypad::Button;
+    use std::fs;
+
+    /// CPU adding the joypad lines read each frame into 0xC000
+    fn cpu() -> CPU {
+        // LD A,$10; LDH [$00],A
+        // loop: LDH A,[$00]; LD HL,$C000; ADD [HL]; LD [HL],A; JR loop
+        cpu_with_program(&[
+            0x3E, 0x10, 0xE0, 0x00, 0xF0, 0x00, 0x21, 0x00, 0xC0, 0x86, 0x77, 0x18, 0xF7,
+        ])
+    }
+
+    /// Movie path unique to the test and the process, as tests run at the
+    /// same time
+    fn movie_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("gbc-test-{}-{}.movie", name, std::process::id()))
+    }
+
+    /// Records a movie pressing A and Start now and then, returning the
+    /// final state
+    fn record(path: &Path, frames: u32) -> Vec<u8> {
+        let mut cpu = cpu();
+        let mut recorder = Recorder::new(path);
+        recorder.start(&cpu).unwrap();
+        for frame in 0..frames {
+            cpu.memory_mut().set_button(Button::A, frame % 7 < 3);
+            cpu.memory_mut().set_button(Button::Start, frame % 11 == 0);
+            cpu.run_frame();
+            recorder.on_frame(&cpu).unwrap();
+        }
+        state::capture(&cpu)
+    }
+
+    #[test]
+    fn test_playback() {
+        let path = movie_path("playback");
+        let end = record(&path, 130);
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).ok();
+
+        let movie = Movie::parse(&data).unwrap();
+        assert_eq!((movie.inputs.len(), movie.hashes.len()), (130, 2));
+        assert_eq!(movie.inputs[0], Button::A.mask() | Button::Start.mask());
+        let mut cpu = cpu();
+        cpu.run_frame();
+        movie.start(&mut cpu).unwrap();
+        let mut played = 0;
+        assert_eq!(movie.play(&mut cpu, |_, frame| played = frame), Ok(()));
+        assert_eq!(played, 130);
+        assert_eq!(state::capture(&cpu), end);
+
+        // A frame cut short is left out
+        let movie = Movie::parse(&data[..data.len() - 1]).unwrap();
+        assert_eq!(movie.inputs.len(), 129);
+
+        // Different inputs desync at the next hash
+        let mut movie = Movie::parse(&data).unwrap();
+        movie.inputs[70] ^= Button::A.mask();
+        movie.start(&mut cpu).unwrap();
+        let desync = movie.play(&mut cpu, |_, _| {}).unwrap_err();
+        assert_eq!((desync.frame, desync.expected), (120, movie.hashes[1]));
+    }
+
+    #[test]
+    fn test_invalid_movies() {
+        assert_eq!(Movie::parse(b"GBCSTATE"), Err(String::from("Not a movie")));
+        let path = movie_path("invalid");
+        record(&path, 1);
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).ok();
+        let movie = Movie::parse(&data).unwrap();
+        let mut other = cpu_with_program(&[0x01]);
+        assert_eq!(
+            movie.start(&mut other),
+            Err(String::from("Movie is from another ROM"))
+        );
+    }
+}
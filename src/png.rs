@@ -0,0 +1,486 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+/// Largest deflate block stored without compression
+const STORED_BLOCK_LENGTH: usize = 0xFFFF;
+
+/// Encodes RGBA pixels as a PNG. The image data is stored without
+/// compression, which keeps the encoder trivial at the cost of file size.
+pub fn encode(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    if rgba.len() != width as usize * height as usize * 4 {
+        panic!(
+            "Image data does not match a {}x{} RGBA image",
+            width, height
+        );
+    }
+    // Every scanline starts with its filter type, 0 for none
+    let mut scanlines = Vec::with_capacity(rgba.len() + height as usize);
+    for row in rgba.chunks(width as usize * 4) {
+        scanlines.push(0x00);
+        scanlines.extend_from_slice(row);
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, RGBA, deflate, no filtering per line, no interlace
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+pub fn write<P: AsRef<Path>>(path: P, width: u32, height: u32, rgba: &[u8]) -> io::Result<()> {
+    fs::write(path, encode(width, height, rgba))
+}
+
+/// Decoded image, as RGBA pixels
+#[derive(Debug, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+/// Decodes a non-interlaced PNG of any color type into RGBA pixels.
+/// 16-bit channels are truncated to their high byte.
+pub fn decode(png: &[u8]) -> Result<Image, String> {
+    if png.len() < 8 || png[0..8] != SIGNATURE {
+        return Err(String::from("Not a PNG"));
+    }
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut transparency: &[u8] = &[];
+    let mut compressed = Vec::new();
+    let mut offset = 8;
+    while offset + 12 <= png.len() {
+        let length = u32::from_be_bytes([
+            png[offset],
+            png[offset + 1],
+            png[offset + 2],
+            png[offset + 3],
+        ]) as usize;
+        let kind = &png[offset + 4..offset + 8];
+        let data = png
+            .get(offset + 8..offset + 8 + length)
+            .ok_or("Truncated chunk")?;
+        match kind {
+            b"IHDR" if length == 13 => header = Some(data),
+            b"PLTE" => palette = data,
+            b"tRNS" => transparency = data,
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+        offset += length + 12;
+    }
+    let header = header.ok_or("Missing IHDR chunk")?;
+    let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+    let (depth, color_type) = (header[8] as usize, header[9]);
+    if header[12] != 0 {
+        return Err(String::from("Interlaced PNGs are not supported"));
+    }
+    let channels = match color_type {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return Err(format!("Invalid color type {}", color_type)),
+    };
+    if ![1, 2, 4, 8, 16].contains(&depth) {
+        return Err(format!("Invalid bit depth {}", depth));
+    }
+
+    let scanlines = inflate(compressed.get(2..).ok_or("Missing image data")?)?;
+    let bits_per_pixel = channels * depth;
+    let stride = (width as usize * bits_per_pixel).div_ceil(8);
+    let bpp = bits_per_pixel.div_ceil(8);
+    if scanlines.len() < (stride + 1) * height as usize {
+        return Err(String::from("Truncated image data"));
+    }
+    let mut pixels = vec![0x00; stride * height as usize];
+    for y in 0..height as usize {
+        let filter = scanlines[y * (stride + 1)];
+        let line = &scanlines[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        let (previous, current) = pixels.split_at_mut(y * stride);
+        let above = if y == 0 {
+            None
+        } else {
+            Some(&previous[(y - 1) * stride..])
+        };
+        let current = &mut current[..stride];
+        for x in 0..stride {
+            let a = if x >= bpp { current[x - bpp] } else { 0 } as i16;
+            let b = above.map_or(0, |above| above[x]) as i16;
+            let c = match above {
+                Some(above) if x >= bpp => above[x - bpp],
+                _ => 0,
+            } as i16;
+            let predictor = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => (a + b) / 2,
+                4 => {
+                    let p = a + b - c;
+                    let (pa, pb, pc) = ((p - a).abs(), (p - b).abs(), (p - c).abs());
+                    if pa <= pb && pa <= pc {
+                        a
+                    } else if pb <= pc {
+                        b
+                    } else {
+                        c
+                    }
+                }
+                _ => return Err(format!("Invalid filter type {}", filter)),
+            };
+            current[x] = line[x].wrapping_add(predictor as u8);
+        }
+    }
+
+    let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+    for y in 0..height as usize {
+        let line = &pixels[y * stride..(y + 1) * stride];
+        for x in 0..width as usize {
+            let sample = |channel: usize| -> u8 {
+                let index = x * channels + channel;
+                match depth {
+                    16 => line[index * 2],
+                    8 => line[index],
+                    _ => {
+                        let bit = index * depth;
+                        let value = (line[bit / 8] >> (8 - depth - bit % 8)) & ((1 << depth) - 1);
+                        if color_type == 3 {
+                            value
+                        } else {
+                            (value as u16 * 0xFF / ((1 << depth) - 1)) as u8
+                        }
+                    }
+                }
+            };
+            let pixel = match color_type {
+                0 => [sample(0), sample(0), sample(0), 0xFF],
+                2 => [sample(0), sample(1), sample(2), 0xFF],
+                3 => {
+                    let index = sample(0) as usize;
+                    let color = palette
+                        .get(index * 3..index * 3 + 3)
+                        .ok_or("Color missing from the palette")?;
+                    let alpha = transparency.get(index).copied().unwrap_or(0xFF);
+                    [color[0], color[1], color[2], alpha]
+                }
+                4 => [sample(0), sample(0), sample(0), sample(1)],
+                _ => [sample(0), sample(1), sample(2), sample(3)],
+            };
+            rgba.extend_from_slice(&pixel);
+        }
+    }
+    Ok(Image {
+        width,
+        height,
+        rgba,
+    })
+}
+
+pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+    let png = fs::read(path)?;
+    decode(&png).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+/// Reads a deflate stream least significant bit first
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, count: usize) -> Result<usize, String> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = self
+                .data
+                .get(self.position / 8)
+                .ok_or("Truncated deflate stream")?;
+            value |= ((byte >> (self.position % 8)) as usize & 0x01) << i;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        self.position = self.position.div_ceil(8) * 8;
+    }
+}
+
+/// Canonical Huffman code, as the number of codes of each length and the
+/// symbols sorted by code
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0; 16];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0; 16];
+        for length in 1..16 {
+            offsets[length] = offsets[length - 1] + counts[length - 1];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[*length as usize] as usize] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for length in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(String::from("Invalid Huffman code"))
+    }
+}
+
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order the code length code lengths of a dynamic block are stored in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Decompresses a raw deflate stream
+fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = BitReader { data, position: 0 };
+    let mut output = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let start = reader.position / 8;
+                let header = data.get(start..start + 4).ok_or("Truncated stored block")?;
+                let length = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let block = data
+                    .get(start + 4..start + 4 + length)
+                    .ok_or("Truncated stored block")?;
+                output.extend_from_slice(block);
+                reader.position = (start + 4 + length) * 8;
+            }
+            1 => {
+                let mut lengths = [0; 288];
+                lengths[0..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..288].fill(8);
+                let literals = Huffman::new(&lengths);
+                let distances = Huffman::new(&[5; 30]);
+                inflate_block(&mut reader, &mut output, &literals, &distances)?;
+            }
+            2 => {
+                let literal_count = reader.bits(5)? + 257;
+                let distance_count = reader.bits(5)? + 1;
+                let code_length_count = reader.bits(4)? + 4;
+                let mut code_lengths = [0; 19];
+                for index in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+                    code_lengths[*index] = reader.bits(3)? as u8;
+                }
+                let code_lengths = Huffman::new(&code_lengths);
+                let mut lengths = Vec::with_capacity(literal_count + distance_count);
+                while lengths.len() < literal_count + distance_count {
+                    let (length, repeat) = match code_lengths.decode(&mut reader)? {
+                        16 => {
+                            let previous = *lengths.last().ok_or("Nothing to repeat")?;
+                            (previous, 3 + reader.bits(2)?)
+                        }
+                        17 => (0, 3 + reader.bits(3)?),
+                        18 => (0, 11 + reader.bits(7)?),
+                        length => (length as u8, 1),
+                    };
+                    lengths.extend(std::iter::repeat_n(length, repeat));
+                }
+                let literals = Huffman::new(&lengths[..literal_count]);
+                let distances = Huffman::new(&lengths[literal_count..]);
+                inflate_block(&mut reader, &mut output, &literals, &distances)?;
+            }
+            _ => return Err(String::from("Invalid deflate block type")),
+        }
+        if last {
+            return Ok(output);
+        }
+    }
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        match symbol {
+            0..=255 => output.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let index = symbol - 257;
+                let base = *LENGTH_BASES.get(index).ok_or("Invalid length")?;
+                let length = base as usize + reader.bits(LENGTH_EXTRA_BITS[index] as usize)?;
+                let index = distances.decode(reader)? as usize;
+                let base = *DISTANCE_BASES.get(index).ok_or("Invalid distance")?;
+                let distance = base as usize + reader.bits(DISTANCE_EXTRA_BITS[index] as usize)?;
+                if distance > output.len() {
+                    return Err(String::from("Distance beyond the output"));
+                }
+                let start = output.len() - distance;
+                for i in 0..length {
+                    output.push(output[start + i]);
+                }
+            }
+        }
+    }
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wraps data in a zlib stream made of stored deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(STORED_BLOCK_LENGTH).peekable();
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none() as u8;
+        let length = block.len() as u16;
+        stream.push(last);
+        stream.extend_from_slice(&length.to_le_bytes());
+        stream.extend_from_slice(&(!length).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 0x01).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+pub(crate) fn adler32(data: &[u8]) -> u32 {
+    let mut a = 1u32;
+    let mut b = 0u32;
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn test_encode() {
+        let png = encode(2, 1, &[0xFF, 0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF]);
+        assert_eq!(png[0..8], SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..24], [0, 0, 0, 2, 0, 0, 0, 1]);
+        // Stored block holding the filter byte and both pixels
+        assert_eq!(png[41..48], [0x78, 0x01, 0x01, 0x09, 0x00, 0xF6, 0xFF]);
+        assert_eq!(png[48], 0x00);
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+    }
+
+    #[test]
+    fn test_decode_roundtrip() {
+        let rgba: Vec<u8> = (0..3 * 2 * 4).map(|i| (i * 11) as u8).collect();
+        let image = decode(&encode(3, 2, &rgba)).unwrap();
+        assert_eq!((image.width, image.height), (3, 2));
+        assert_eq!(image.rgba, rgba);
+    }
+
+    #[test]
+    fn test_decode_compressed() {
+        // 2x2 8-bit grayscale, rows filtered with Sub and Up, compressed with
+        // fixed Huffman codes by zlib
+        let png = [
+            0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48,
+            0x44, 0x52, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x08, 0x00, 0x00, 0x00,
+            0x00, 0x57, 0xDD, 0x52, 0xF8, 0x00, 0x00, 0x00, 0x0E, 0x49, 0x44, 0x41, 0x54, 0x78,
+            0x9C, 0x63, 0x14, 0x10, 0x60, 0x62, 0x60, 0x00, 0x00, 0x00, 0xA2, 0x00, 0x24, 0x1D,
+            0x60, 0x1B, 0x85, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60,
+            0x82,
+        ];
+        let image = decode(&png).unwrap();
+        let gray: Vec<u8> = image.rgba.chunks(4).map(|pixel| pixel[0]).collect();
+        assert_eq!(gray, [0x10, 0x20, 0x10, 0x20]);
+        assert!(image.rgba.chunks(4).all(|pixel| pixel[3] == 0xFF));
+    }
+
+    #[test]
+    fn test_inflate_dynamic() {
+        let deflate = [
+            0x2D, 0x87, 0x8B, 0x09, 0x00, 0x40, 0x14, 0x40, 0x66, 0xC5, 0xDB, 0x7F, 0x86, 0xFB,
+            0x16, 0x05, 0xCA, 0xA1, 0x28, 0x41, 0xDA, 0x72, 0x85, 0x7E, 0xCE, 0xFB, 0x91, 0x05,
+        ];
+        assert_eq!(
+            inflate(&deflate).unwrap(),
+            b"abbabbabccaccbaabacabaaabaaaaacbaaabaadabaaaadba"
+        );
+    }
+}
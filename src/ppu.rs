@@ -1,7 +1,647 @@
+use crate::memory::{INTERRUPT_STAT, INTERRUPT_VBLANK};
+use crate::state::{self, snapshot, Reader, Snapshot, Writer};
+use crate::_8KB;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+/// Dots (T-cycles at single speed) per frame
+pub const FRAME_DOTS: u32 = DOTS_PER_LINE as u32 * TOTAL_LINES as u32;
+
+/// Dots (T-cycles at single speed) per scanline
+const DOTS_PER_LINE: u16 = 456;
+/// Dots spent in OAM scan at the start of each visible line
+const OAM_SCAN_DOTS: u16 = 80;
+/// Dots spent drawing pixels, ignoring the sprite/window penalties
+const DRAWING_DOTS: u16 = 172;
+/// Visible lines followed by 10 lines of VBlank
+const VISIBLE_LINES: u8 = 144;
+const TOTAL_LINES: u8 = 154;
+/// Objects drawn at most on a single scanline
+const OBJECTS_PER_LINE: usize = 10;
+/// DMG shades, from white to black
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+#[derive(Debug)]
 struct Registers {
+    /// 0xFF40 LCD Control
+    lcdc: u8,
+    /// 0xFF41 LCD Status, only the interrupt selection bits are stored
+    stat: u8,
+    /// 0xFF42 ~ 0xFF43 Background viewport
+    scy: u8,
+    scx: u8,
+    /// 0xFF44 Current scanline
+    ly: u8,
+    /// 0xFF45 Scanline compare
+    lyc: u8,
+    /// 0xFF47 ~ 0xFF49 DMG palettes
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    /// 0xFF4A ~ 0xFF4B Window position
+    wy: u8,
+    wx: u8,
+    /// 0xFF68 Background palette index, bit 7 enables auto-increment
+    bcps: u8,
+    /// 0xFF6A Object palette index, bit 7 enables auto-increment
+    ocps: u8,
+}
+
+#[derive(Debug)]
+pub struct PPU {
+    registers: Registers,
+    /// CGB palette RAM, 8 palettes of 4 RGB555 colors each
+    bg_palettes: [u8; 64],
+    obj_palettes: [u8; 64],
+    mode: Mode,
+    /// Dots elapsed in the current scanline
+    dots: u16,
+    /// Lines of the window drawn so far in this frame
+    window_line: u8,
+    /// STAT interrupt line, the interrupt is requested on its rising edge
+    stat_line: bool,
+    /// Interrupts requested since they were last taken
+    interrupts: u8,
+    /// RGBA pixels of the frame being drawn
+    framebuffer: Vec<u8>,
+    frames: u64,
+}
+
+impl PPU {
+    pub fn init() -> PPU {
+        PPU {
+            registers: Registers {
+                lcdc: 0x91,
+                stat: 0x00,
+                scy: 0x00,
+                scx: 0x00,
+                ly: 0x00,
+                lyc: 0x00,
+                bgp: 0xFC,
+                obp0: 0x00,
+                obp1: 0x00,
+                wy: 0x00,
+                wx: 0x00,
+                bcps: 0x00,
+                ocps: 0x00,
+            },
+            bg_palettes: [0xFF; 64],
+            obj_palettes: [0xFF; 64],
+            mode: Mode::OamScan,
+            dots: 0,
+            window_line: 0,
+            stat_line: false,
+            interrupts: 0x00,
+            framebuffer: vec![0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 4],
+            frames: 0,
+        }
+    }
+
+    /// Checks the indices of a state just loaded
+    pub fn validate(&self) -> Result<(), String> {
+        state::check("LY", self.registers.ly as usize, 0..TOTAL_LINES as usize)?;
+        state::check(
+            "scanline dot",
+            self.dots as usize,
+            0..DOTS_PER_LINE as usize,
+        )?;
+        // The window line is past the last visible line once it was drawn on it
+        let lines = VISIBLE_LINES as usize + 1;
+        state::check("window line", self.window_line as usize, 0..lines)?;
+        if self.framebuffer.len() != SCREEN_WIDTH * SCREEN_HEIGHT * 4 {
+            return Err(String::from("Invalid framebuffer size in save state"));
+        }
+        Ok(())
+    }
+
+    pub fn frame(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Returns, and clears, the VBlank and STAT interrupts requested
+    pub fn take_interrupts(&mut self) -> u8 {
+        std::mem::replace(&mut self.interrupts, 0x00)
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        self.registers.lcdc & 0x80 != 0
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn read(&self, address: usize) -> u8 {
+        let registers = &self.registers;
+        match address {
+            0xFF40 => registers.lcdc,
+            0xFF41 => {
+                let coincidence = if registers.ly == registers.lyc {
+                    0x04
+                } else {
+                    0x00
+                };
+                0x80 | (registers.stat & 0x78) | coincidence | self.mode as u8
+            }
+            0xFF42 => registers.scy,
+            0xFF43 => registers.scx,
+            0xFF44 => registers.ly,
+            0xFF45 => registers.lyc,
+            0xFF47 => registers.bgp,
+            0xFF48 => registers.obp0,
+            0xFF49 => registers.obp1,
+            0xFF4A => registers.wy,
+            0xFF4B => registers.wx,
+            0xFF68 => 0x40 | registers.bcps,
+            0xFF69 => self.bg_palettes[(registers.bcps & 0x3F) as usize],
+            0xFF6A => 0x40 | registers.ocps,
+            0xFF6B => self.obj_palettes[(registers.ocps & 0x3F) as usize],
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: usize, data: u8) {
+        let registers = &mut self.registers;
+        match address {
+            0xFF40 => {
+                let was_enabled = self.lcd_enabled();
+                self.registers.lcdc = data;
+                if was_enabled && !self.lcd_enabled() {
+                    // Turning the LCD off resets it to the top of the frame
+                    self.registers.ly = 0;
+                    self.dots = 0;
+                    self.mode = Mode::HBlank;
+                    self.window_line = 0;
+                    self.stat_line = false;
+                    for pixel in self.framebuffer.iter_mut() {
+                        *pixel = 0xFF;
+                    }
+                } else if !was_enabled && self.lcd_enabled() {
+                    self.mode = Mode::OamScan;
+                }
+            }
+            0xFF41 => {
+                registers.stat = data & 0x78;
+                self.update_stat_line();
+            }
+            0xFF42 => registers.scy = data,
+            0xFF43 => registers.scx = data,
+            0xFF44 => {} // Read-only
+            0xFF45 => {
+                registers.lyc = data;
+                self.update_stat_line();
+            }
+            0xFF47 => registers.bgp = data,
+            0xFF48 => registers.obp0 = data,
+            0xFF49 => registers.obp1 = data,
+            0xFF4A => registers.wy = data,
+            0xFF4B => registers.wx = data,
+            0xFF68 => registers.bcps = data & 0xBF,
+            0xFF69 => {
+                self.bg_palettes[(registers.bcps & 0x3F) as usize] = data;
+                registers.bcps = PPU::next_palette_index(registers.bcps);
+            }
+            0xFF6A => registers.ocps = data & 0xBF,
+            0xFF6B => {
+                self.obj_palettes[(registers.ocps & 0x3F) as usize] = data;
+                registers.ocps = PPU::next_palette_index(registers.ocps);
+            }
+            _ => {}
+        }
+    }
+
+    fn next_palette_index(index: u8) -> u8 {
+        if index & 0x80 != 0 {
+            0x80 | (index.wrapping_add(1) & 0x3F)
+        } else {
+            index
+        }
+    }
+
+    /// Advances the PPU by `dots` dots. Returns the mode that was entered, if
+    /// a transition happened.
+    pub fn step(&mut self, dots: u8) -> Option<Mode> {
+        if !self.lcd_enabled() {
+            return None;
+        }
+        let mut entered = None;
+        for _ in 0..dots {
+            self.dots += 1;
+            if self.dots == DOTS_PER_LINE {
+                self.dots = 0;
+                self.registers.ly = (self.registers.ly + 1) % TOTAL_LINES;
+                if self.registers.ly == 0 {
+                    self.window_line = 0;
+                }
+            }
+            let mode = match (self.registers.ly, self.dots) {
+                (ly, _) if ly >= VISIBLE_LINES => Mode::VBlank,
+                (_, dots) if dots < OAM_SCAN_DOTS => Mode::OamScan,
+                (_, dots) if dots < OAM_SCAN_DOTS + DRAWING_DOTS => Mode::Drawing,
+                _ => Mode::HBlank,
+            };
+            if mode != self.mode {
+                self.mode = mode;
+                entered = Some(mode);
+                if mode == Mode::VBlank {
+                    self.interrupts |= INTERRUPT_VBLANK;
+                    self.frames += 1;
+                }
+            }
+            self.update_stat_line();
+        }
+        entered
+    }
+
+    fn update_stat_line(&mut self) {
+        let registers = &self.registers;
+        let source = match self.mode {
+            Mode::HBlank => 0x08,
+            Mode::VBlank => 0x10,
+            Mode::OamScan => 0x20,
+            Mode::Drawing => 0x00,
+        };
+        let line = self.lcd_enabled()
+            && (registers.stat & source != 0
+                || (registers.stat & 0x40 != 0 && registers.ly == registers.lyc));
+        if line && !self.stat_line {
+            self.interrupts |= INTERRUPT_STAT;
+        }
+        self.stat_line = line;
+    }
+
+    /// Draws the current scanline, once its pixels have been sent
+    pub fn render_line(&mut self, vram: &[[u8; _8KB]; 2], oam: &[u8], cgb_mode: bool) {
+        let ly = self.registers.ly;
+        if ly >= VISIBLE_LINES {
+            return;
+        }
+        // Color index and CGB priority attribute of the background pixels
+        let mut background = [(0u8, false); SCREEN_WIDTH];
+        self.render_background(vram, cgb_mode, &mut background);
+        if self.registers.lcdc & 0x02 != 0 {
+            self.render_objects(vram, oam, cgb_mode, &background);
+        }
+    }
 
+    fn render_background(
+        &mut self,
+        vram: &[[u8; _8KB]; 2],
+        cgb_mode: bool,
+        background: &mut [(u8, bool); SCREEN_WIDTH],
+    ) {
+        let Registers {
+            lcdc,
+            ly,
+            scx,
+            scy,
+            wx,
+            wy,
+            bgp,
+            ..
+        } = self.registers;
+        // On the DMG bit 0 disables both background and window
+        let enabled = cgb_mode || lcdc & 0x01 != 0;
+        let window_visible = enabled && lcdc & 0x20 != 0 && ly >= wy && wx <= 166;
+        let mut window_drawn = false;
+        for (x, pixel) in background.iter_mut().enumerate() {
+            if !enabled {
+                self.set_pixel(x, SHADES[0], SHADES[0], SHADES[0]);
+                continue;
+            }
+            let window = window_visible && x as u16 + 7 >= wx as u16;
+            let (map, column, line) = if window {
+                window_drawn = true;
+                let column = (x as u16 + 7 - wx as u16) as u8;
+                (lcdc & 0x40, column, self.window_line)
+            } else {
+                let column = scx.wrapping_add(x as u8);
+                (lcdc & 0x08, column, scy.wrapping_add(ly))
+            };
+            let map_base = if map != 0 { 0x1C00 } else { 0x1800 };
+            let index = map_base + (line as usize / 8) * 32 + column as usize / 8;
+            let tile = vram[0][index];
+            // CGB attributes live in bank 1 of the tile map
+            let attributes = if cgb_mode { vram[1][index] } else { 0x00 };
+            let mut row = line as usize % 8;
+            if attributes & 0x40 != 0 {
+                row = 7 - row;
+            }
+            let mut bit = 7 - column % 8;
+            if attributes & 0x20 != 0 {
+                bit = 7 - bit;
+            }
+            let address = if lcdc & 0x10 != 0 {
+                tile as usize * 16
+            } else {
+                (0x1000 + tile as i8 as i32 * 16) as usize
+            };
+            let bank = &vram[((attributes >> 3) & 0x01) as usize];
+            let color = PPU::tile_color(bank, address + row * 2, bit);
+            *pixel = (color, attributes & 0x80 != 0);
+            let (r, g, b) = if cgb_mode {
+                PPU::cgb_color(&self.bg_palettes, attributes & 0x07, color)
+            } else {
+                PPU::dmg_color(bgp, color)
+            };
+            self.set_pixel(x, r, g, b);
+        }
+        if window_drawn {
+            self.window_line += 1;
+        }
+    }
+
+    fn render_objects(
+        &mut self,
+        vram: &[[u8; _8KB]; 2],
+        oam: &[u8],
+        cgb_mode: bool,
+        background: &[(u8, bool); SCREEN_WIDTH],
+    ) {
+        let lcdc = self.registers.lcdc;
+        let ly = self.registers.ly as i16;
+        let height = if lcdc & 0x04 != 0 { 16 } else { 8 };
+        let mut objects: Vec<&[u8]> = oam
+            .chunks(4)
+            .filter(|object| {
+                let y = object[0] as i16 - 16;
+                ly >= y && ly < y + height
+            })
+            .take(OBJECTS_PER_LINE)
+            .collect();
+        // The DMG favors the leftmost object, the CGB the first one in OAM
+        if !cgb_mode {
+            objects.sort_by_key(|object| object[1]);
+        }
+        let mut drawn = [false; SCREEN_WIDTH];
+        for object in objects {
+            let attributes = object[3];
+            let mut row = (ly - (object[0] as i16 - 16)) as usize;
+            if attributes & 0x40 != 0 {
+                row = height as usize - 1 - row;
+            }
+            let tile = if height == 16 {
+                object[2] & 0xFE
+            } else {
+                object[2]
+            };
+            let address = tile as usize * 16 + row * 2;
+            let bank = if cgb_mode {
+                (attributes >> 3) & 0x01
+            } else {
+                0
+            };
+            for pixel in 0..8u8 {
+                let x = object[1] as i16 - 8 + pixel as i16;
+                if x < 0 || x >= SCREEN_WIDTH as i16 || drawn[x as usize] {
+                    continue;
+                }
+                let x = x as usize;
+                let bit = if attributes & 0x20 != 0 {
+                    pixel
+                } else {
+                    7 - pixel
+                };
+                let color = PPU::tile_color(&vram[bank as usize], address, bit);
+                if color == 0 {
+                    continue;
+                }
+                // Hidden objects still mask the ones with lower priority
+                drawn[x] = true;
+                let (background_color, background_priority) = background[x];
+                let hidden = if cgb_mode {
+                    lcdc & 0x01 != 0
+                        && background_color != 0
+                        && (attributes & 0x80 != 0 || background_priority)
+                } else {
+                    attributes & 0x80 != 0 && background_color != 0
+                };
+                if hidden {
+                    continue;
+                }
+                let (r, g, b) = if cgb_mode {
+                    PPU::cgb_color(&self.obj_palettes, attributes & 0x07, color)
+                } else {
+                    let palette = if attributes & 0x10 != 0 {
+                        self.registers.obp1
+                    } else {
+                        self.registers.obp0
+                    };
+                    PPU::dmg_color(palette, color)
+                };
+                self.set_pixel(x, r, g, b);
+            }
+        }
+    }
+
+    /// Color index, 0 ~ 3, of a pixel in the tile row at `address`
+    fn tile_color(bank: &[u8], address: usize, bit: u8) -> u8 {
+        let low = (bank[address] >> bit) & 0x01;
+        let high = (bank[address + 1] >> bit) & 0x01;
+        high << 1 | low
+    }
+
+    fn dmg_color(palette: u8, color: u8) -> (u8, u8, u8) {
+        let shade = SHADES[((palette >> (color * 2)) & 0x03) as usize];
+        (shade, shade, shade)
+    }
+
+    /// Converts an RGB555 palette entry to RGB888
+    fn cgb_color(palettes: &[u8; 64], palette: u8, color: u8) -> (u8, u8, u8) {
+        let index = (palette * 8 + color * 2) as usize;
+        let rgb = palettes[index] as u16 | (palettes[index + 1] as u16) << 8;
+        let channel = |shift: u16| {
+            let value = ((rgb >> shift) & 0x1F) as u8;
+            value << 3 | value >> 2
+        };
+        (channel(0), channel(5), channel(10))
+    }
+
+    fn set_pixel(&mut self, x: usize, r: u8, g: u8, b: u8) {
+        let offset = (self.registers.ly as usize * SCREEN_WIDTH + x) * 4;
+        self.framebuffer[offset..offset + 4].copy_from_slice(&[r, g, b, 0xFF]);
+    }
 }
 
-struct PPU {
-    registers: Registers
+impl Snapshot for Mode {
+    fn save(&self, writer: &mut Writer) {
+        (*self as u8).save(writer);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> Result<(), String> {
+        let mut mode = 0u8;
+        mode.load(reader)?;
+        *self = match mode {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::OamScan,
+            3 => Mode::Drawing,
+            _ => return Err(format!("Invalid PPU mode {} in save state", mode)),
+        };
+        Ok(())
+    }
+}
+
+snapshot!(Registers {
+    lcdc,
+    stat,
+    scy,
+    scx,
+    ly,
+    lyc,
+    bgp,
+    obp0,
+    obp1,
+    wy,
+    wx,
+    bcps,
+    ocps
+});
+
+snapshot!(PPU {
+    registers,
+    bg_palettes,
+    obj_palettes,
+    mode,
+    dots,
+    window_line,
+    stat_line,
+    interrupts,
+    framebuffer,
+    frames
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scanline_modes() {
+        let mut ppu = PPU::init();
+        assert_eq!(ppu.step(OAM_SCAN_DOTS as u8 - 1), None);
+        assert_eq!(ppu.step(1), Some(Mode::Drawing));
+        assert_eq!(ppu.step(DRAWING_DOTS as u8), Some(Mode::HBlank));
+        assert_eq!(ppu.read(0xFF41) & 0x03, Mode::HBlank as u8);
+        for _ in 0..(DOTS_PER_LINE - OAM_SCAN_DOTS - DRAWING_DOTS) {
+            ppu.step(1);
+        }
+        assert_eq!(ppu.read(0xFF44), 1);
+        assert_eq!(ppu.mode(), Mode::OamScan);
+    }
+
+    #[test]
+    fn test_invalid_state() {
+        let mut ppu = PPU::init();
+        assert_eq!(ppu.validate(), Ok(()));
+        ppu.registers.ly = TOTAL_LINES;
+        assert_eq!(
+            ppu.validate(),
+            Err(String::from("Invalid LY 154 in save state"))
+        );
+    }
+
+    #[test]
+    fn test_vblank_and_lcd_off() {
+        let mut ppu = PPU::init();
+        for _ in 0..(VISIBLE_LINES as u32 * DOTS_PER_LINE as u32) {
+            ppu.step(1);
+        }
+        assert_eq!(ppu.read(0xFF44), VISIBLE_LINES);
+        assert_eq!(ppu.mode(), Mode::VBlank);
+
+        ppu.write(0xFF40, 0x11);
+        assert_eq!(ppu.read(0xFF44), 0);
+        assert_eq!(ppu.step(4), None);
+        assert_eq!(ppu.read(0xFF41) & 0x03, Mode::HBlank as u8);
+    }
+
+    #[test]
+    fn test_cgb_palettes() {
+        let mut ppu = PPU::init();
+        ppu.write(0xFF68, 0xBE);
+        ppu.write(0xFF69, 0x1F);
+        ppu.write(0xFF69, 0x7C);
+        assert_eq!(ppu.read(0xFF68), 0xC0);
+        ppu.write(0xFF68, 0x3E);
+        assert_eq!(ppu.read(0xFF69), 0x1F);
+        ppu.write(0xFF68, 0x3F);
+        assert_eq!(ppu.read(0xFF69), 0x7C);
+        assert_eq!(ppu.read(0xFF6B), 0xFF);
+    }
+
+    fn pixel(ppu: &PPU, x: usize) -> &[u8] {
+        &ppu.frame()[x * 4..x * 4 + 4]
+    }
+
+    #[test]
+    fn test_render_background_and_objects() {
+        let mut ppu = PPU::init();
+        let mut vram = [[0; _8KB]; 2];
+        let mut oam = [0; 0xA0];
+        // Tile 0 is color 1 across, tile 1 starts with a color 3 pixel
+        vram[0][0x0000] = 0xFF;
+        vram[0][0x0010] = 0x80;
+        vram[0][0x0011] = 0x80;
+        oam[0..4].copy_from_slice(&[16, 12, 1, 0x00]);
+        oam[4..8].copy_from_slice(&[16, 13, 1, 0x00]);
+        ppu.write(0xFF47, 0xE4);
+        ppu.write(0xFF48, 0xE4);
+        ppu.render_line(&vram, &oam, false);
+        assert_eq!(pixel(&ppu, 0), [0xAA, 0xAA, 0xAA, 0xFF]);
+        assert_eq!(pixel(&ppu, 4), [0xAA, 0xAA, 0xAA, 0xFF]);
+
+        ppu.write(0xFF40, 0x93);
+        ppu.render_line(&vram, &oam, false);
+        assert_eq!(pixel(&ppu, 4), [0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(pixel(&ppu, 5), [0x00, 0x00, 0x00, 0xFF]);
+
+        // Behind a non-zero background, the object still masks the next one
+        oam[3] = 0x80;
+        ppu.render_line(&vram, &oam, false);
+        assert_eq!(pixel(&ppu, 4), [0xAA, 0xAA, 0xAA, 0xFF]);
+        assert_eq!(pixel(&ppu, 5), [0x00, 0x00, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn test_render_cgb_attributes() {
+        let mut ppu = PPU::init();
+        let mut vram = [[0; _8KB]; 2];
+        // Palette 2, tile data from bank 1, flipped horizontally
+        vram[1][0x1800] = 0x2A;
+        vram[1][0x0000] = 0x01;
+        ppu.write(0xFF68, 0x80 | 0x12);
+        ppu.write(0xFF69, 0x1F);
+        ppu.write(0xFF69, 0x00);
+        ppu.render_line(&vram, &[0; 0xA0], true);
+        assert_eq!(pixel(&ppu, 0), [0xFF, 0x00, 0x00, 0xFF]);
+        assert_eq!(pixel(&ppu, 1)[0..3], [0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn test_interrupts() {
+        let mut ppu = PPU::init();
+        ppu.write(0xFF45, 0x02);
+        ppu.write(0xFF41, 0x40);
+        for _ in 0..(2 * DOTS_PER_LINE) {
+            ppu.step(1);
+        }
+        assert_eq!(ppu.take_interrupts(), INTERRUPT_STAT);
+        for _ in 0..((VISIBLE_LINES as u16 - 2) * DOTS_PER_LINE) {
+            ppu.step(1);
+        }
+        assert_eq!(ppu.take_interrupts(), INTERRUPT_VBLANK);
+        assert_eq!(ppu.frames(), 1);
+    }
 }
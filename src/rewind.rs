@@ -0,0 +1,283 @@
+use crate::cpu::CPU;
+use crate::state;
+use std::collections::VecDeque;
+
+/// Runs of zeros shorter than this are cheaper kept inside a literal run
+const MIN_ZERO_RUN: usize = 3;
+
+/// Tags of the runs a delta is made of, each followed by its length
+const ZEROS: u8 = 0;
+const LITERAL: u8 = 1;
+
+/// States captured every few frames to step back through, bounded in memory.
+///
+/// Only the latest state is kept whole. Each older one is kept as the XOR of
+/// itself against the next, which is mostly zeros between nearby frames,
+/// run-length encoded. Going back undoes the deltas from the newest, and the
+/// oldest are dropped once over the memory budget.
+pub struct Rewind {
+    /// Frames between captures
+    interval: u32,
+    /// Bytes the latest state and the deltas may take, together
+    budget: usize,
+    /// Frames run since the latest state was captured or restored
+    frames: u32,
+    latest: Option<Vec<u8>>,
+    /// Deltas back to older states, the oldest first
+    deltas: VecDeque<Vec<u8>>,
+    size: usize,
+}
+
+impl Rewind {
+    /// Captures a state every `interval` frames, keeping as many as fit in
+    /// `budget` bytes
+    pub fn new(interval: u32, budget: usize) -> Rewind {
+        Rewind {
+            interval: interval.max(1),
+            budget,
+            frames: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+            size: 0,
+        }
+    }
+
+    /// Forgets every state captured, e.g. after a reset
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.latest = None;
+        self.deltas.clear();
+        self.size = 0;
+    }
+
+    /// States that can be stepped back to
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.latest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    /// Bytes taken by the states kept
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// To call after every frame emulated, captures the state when due
+    pub fn on_frame(&mut self, cpu: &CPU) {
+        self.frames += 1;
+        if self.frames >= self.interval {
+            self.capture(cpu);
+        }
+    }
+
+    fn capture(&mut self, cpu: &CPU) {
+        let current = state::capture(cpu);
+        self.size += current.len();
+        if let Some(latest) = self.latest.take() {
+            self.size -= latest.len();
+            let delta = compress(&latest, &current);
+            self.size += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.latest = Some(current);
+        self.frames = 0;
+        // The latest state is always kept, even over the budget
+        while self.size > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.size -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Goes back to the latest state captured, or to the one before if the
+    /// CPU is still there. Returns false once there is none left.
+    pub fn step_back(&mut self, cpu: &mut CPU) -> bool {
+        if self.frames == 0 {
+            let (latest, delta) = match (self.latest.as_ref(), self.deltas.pop_back()) {
+                (Some(latest), Some(delta)) => (latest, delta),
+                _ => return false,
+            };
+            let previous = decompress(latest, &delta);
+            self.size = self.size - latest.len() - delta.len() + previous.len();
+            self.latest = Some(previous);
+        }
+        let latest = match self.latest.as_ref() {
+            Some(latest) => latest,
+            None => return false,
+        };
+        state::restore(cpu, latest).expect("Rewind state could not be restored");
+        self.frames = 0;
+        true
+    }
+}
+
+/// Appends a LEB128 length
+fn write_length(output: &mut Vec<u8>, mut length: usize) {
+    while length >= 0x80 {
+        output.push(length as u8 | 0x80);
+        length >>= 7;
+    }
+    output.push(length as u8);
+}
+
+fn read_length(data: &[u8], position: &mut usize) -> usize {
+    let mut length = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*position];
+        *position += 1;
+        length |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return length;
+        }
+        shift += 7;
+    }
+}
+
+/// Length of `target`, then `target` XOR `base` as runs of zeros and runs of
+/// literal bytes. The states differ in length when e.g. the serial output
+/// grew, the shorter one is padded with zeros.
+fn compress(target: &[u8], base: &[u8]) -> Vec<u8> {
+    let length = target.len().max(base.len());
+    let byte = |index: usize| {
+        target.get(index).copied().unwrap_or(0) ^ base.get(index).copied().unwrap_or(0)
+    };
+    let mut output = Vec::new();
+    write_length(&mut output, target.len());
+    let mut index = 0;
+    let mut literal = 0;
+    while index < length {
+        let zeros = (index..length)
+            .take_while(|index| byte(*index) == 0)
+            .count();
+        if zeros >= MIN_ZERO_RUN || index + zeros == length {
+            if literal < index {
+                output.push(LITERAL);
+                write_length(&mut output, index - literal);
+                output.extend((literal..index).map(byte));
+            }
+            if zeros > 0 {
+                output.push(ZEROS);
+                write_length(&mut output, zeros);
+            }
+            index += zeros;
+            literal = index;
+        } else {
+            index += zeros.max(1);
+        }
+    }
+    if literal < length {
+        output.push(LITERAL);
+        write_length(&mut output, length - literal);
+        output.extend((literal..length).map(byte));
+    }
+    output
+}
+
+/// Rebuilds the `target` that `compress` was given
+fn decompress(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut position = 0;
+    let length = read_length(delta, &mut position);
+    let mut target = Vec::with_capacity(length.max(base.len()));
+    while position < delta.len() {
+        let tag = delta[position];
+        position += 1;
+        let run = read_length(delta, &mut position);
+        let start = target.len();
+        target.extend((start..start + run).map(|index| base.get(index).copied().unwrap_or(0)));
+        if tag == LITERAL {
+            for (byte, xor) in target[start..]
+                .iter_mut()
+                .zip(&delta[position..position + run])
+            {
+                *byte ^= xor;
+            }
+            position += run;
+        }
+    }
+    target.truncate(length);
+    target
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::tests::cpu_with_program;
+
+    #[test]
+    fn test_compress() {
+        let base = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        for target in [
+            vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10],
+            vec![1, 2, 0, 4, 5, 6, 7, 8, 0, 0],
+            vec![9, 2, 3, 9, 5, 6, 7, 8, 9, 10, 11, 12],
+            vec![1, 2, 3],
+            vec![],
+        ] {
+            let delta = compress(&target, &base);
+            assert_eq!(decompress(&base, &delta), target);
+        }
+        let base = vec![0x55; 0x10000];
+        let mut target = base.clone();
+        target[0x8000] = 0xAA;
+        assert!(compress(&target, &base).len() < 16);
+    }
+
+    /// CPU incrementing 0xC000 in a loop
+    fn cpu() -> CPU {
+        // loop: LD HL,$C000; INC [HL]; JR loop
+        cpu_with_program(&[0x21, 0x00, 0xC0, 0x34, 0x18, 0xFA])
+    }
+
+    fn run(cpu: &mut CPU, rewind: &mut Rewind, frames: u32) {
+        for _ in 0..frames {
+            cpu.run_frame();
+            rewind.on_frame(cpu);
+        }
+    }
+
+    #[test]
+    fn test_step_back() {
+        let mut cpu = cpu();
+        let mut rewind = Rewind::new(2, usize::MAX);
+        let mut states = Vec::new();
+        for _ in 0..4 {
+            run(&mut cpu, &mut rewind, 2);
+            states.push(state::capture(&cpu));
+        }
+        assert_eq!(rewind.len(), 4);
+        // Part way to the next capture goes back to the latest one first
+        run(&mut cpu, &mut rewind, 1);
+        for expected in states.iter().rev() {
+            assert!(rewind.step_back(&mut cpu));
+            assert_eq!(&state::capture(&cpu), expected);
+        }
+        assert!(!rewind.step_back(&mut cpu));
+        assert_eq!(&state::capture(&cpu), &states[0]);
+
+        // Running again picks up from there
+        run(&mut cpu, &mut rewind, 2);
+        assert_eq!(rewind.len(), 2);
+    }
+
+    #[test]
+    fn test_budget() {
+        let mut cpu = cpu();
+        let mut rewind = Rewind::new(1, 0);
+        run(&mut cpu, &mut rewind, 5);
+        assert_eq!(rewind.len(), 1);
+        assert_eq!(rewind.size(), state::capture(&cpu).len());
+
+        let mut rewind = Rewind::new(1, 1 << 20);
+        run(&mut cpu, &mut rewind, 100);
+        assert!(rewind.len() == 100 && rewind.size() < 1 << 20);
+        let size = rewind.size();
+        run(&mut cpu, &mut rewind, 1);
+        assert!(rewind.size() > size);
+        rewind.clear();
+        assert!(rewind.is_empty() && rewind.size() == 0);
+    }
+}
@@ -0,0 +1,197 @@
+use crate::cpu::CPU;
+use crate::ppu::FRAME_DOTS;
+
+/// JR -2, jumping back onto itself
+const JR_SELF: [u8; 2] = [0x18, 0xFE];
+/// LD B, B, used as a software breakpoint
+const LD_B_B: u8 = 0x40;
+
+/// Conditions ending a headless run, the first one met stops it
+#[derive(Debug, Default, Clone)]
+pub struct Until {
+    /// Frames emulated, counted as in `CPU::run_frame`
+    pub frames: Option<u64>,
+    /// Cycles of the 4 MHz clock
+    pub cycles: Option<u64>,
+    /// Instruction about to be executed
+    pub pc: Option<u16>,
+    /// Text sent over the serial port
+    pub serial: Option<String>,
+    /// Reaching a `JR -2`, which test ROMs spin on once done
+    pub infinite_loop: bool,
+    /// Reaching a `LD B, B`, which mooneye and acid2 test ROMs execute once
+    /// done
+    pub breakpoint: bool,
+}
+
+/// Why a headless run ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exit {
+    Frames,
+    Cycles,
+    PC,
+    Serial,
+    InfiniteLoop,
+    Breakpoint,
+}
+
+impl Until {
+    /// Whether a run passed. When waiting for a PC or serial output, only
+    /// reaching them passes, otherwise any way of ending does. A run ends on
+    /// the first of them reached, so waiting for both never passes.
+    pub fn passed(&self, exit: Exit) -> bool {
+        match exit {
+            Exit::PC => self.serial.is_none(),
+            Exit::Serial => self.pc.is_none(),
+            _ => self.pc.is_none() && self.serial.is_none(),
+        }
+    }
+
+    /// Whether no condition is set, so that a run would never end
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_none()
+            && self.cycles.is_none()
+            && self.pc.is_none()
+            && self.serial.is_none()
+            && !self.infinite_loop
+            && !self.breakpoint
+    }
+
+    fn check(&self, cpu: &CPU) -> Option<Exit> {
+        let pc = cpu.pc();
+        if self.pc == Some(pc) {
+            return Some(Exit::PC);
+        }
+        let memory = cpu.memory();
+        if self.infinite_loop && [memory.read(pc), memory.read(pc.wrapping_add(1))] == JR_SELF {
+            return Some(Exit::InfiniteLoop);
+        }
+        if self.breakpoint && memory.read(pc) == LD_B_B {
+            return Some(Exit::Breakpoint);
+        }
+        None
+    }
+}
+
+/// Runs until one of the conditions is met, calling `on_frame` with the
+/// number of frames emulated so far after each of them
+pub fn run<F>(cpu: &mut CPU, until: &Until, mut on_frame: F) -> Exit
+where
+    F: FnMut(&CPU, u64),
+{
+    let start = cpu.memory().elapsed();
+    let mut serial_length = 0;
+    let mut frame = 0;
+    loop {
+        let frames = cpu.memory().frames();
+        let frame_end = cpu.memory().elapsed() + FRAME_DOTS as u64;
+        while cpu.memory().frames() == frames && cpu.memory().elapsed() < frame_end {
+            if let Some(exit) = until.check(cpu) {
+                return exit;
+            }
+            cpu.step();
+
+            let memory = cpu.memory();
+            if let Some(cycles) = until.cycles {
+                if memory.elapsed() - start >= cycles {
+                    return Exit::Cycles;
+                }
+            }
+            if let Some(serial) = &until.serial {
+                let output = memory.serial_output();
+                if output.len() != serial_length {
+                    serial_length = output.len();
+                    if String::from_utf8_lossy(output).contains(serial.as_str()) {
+                        return Exit::Serial;
+                    }
+                }
+            }
+        }
+        frame += 1;
+        on_frame(cpu, frame);
+        if until.frames.is_some_and(|frames| frame >= frames) {
+            return Exit::Frames;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::tests::cpu_with_program;
+
+    /// Sends "ok" over the serial port, then spins on JR -2
+    fn cpu() -> CPU {
+        cpu_with_program(&[
+            0x3E, b'o', // LD A, 'o'
+            0xE0, 0x01, // LDH (SB), A
+            0x3E, 0x81, // LD A, 0x81
+            0xE0, 0x02, // LDH (SC), A
+            0x3E, b'k', // LD A, 'k'
+            0xE0, 0x01, // LDH (SB), A
+            0x3E, 0x81, // LD A, 0x81
+            0xE0, 0x02, // LDH (SC), A
+            0x18, 0xFE, // JR -2
+        ])
+    }
+
+    #[test]
+    fn test_until_serial() {
+        let mut cpu = cpu();
+        let until = Until {
+            serial: Some(String::from("ok")),
+            frames: Some(10),
+            ..Until::default()
+        };
+        let exit = run(&mut cpu, &until, |_, _| {});
+        assert_eq!(exit, Exit::Serial);
+        assert!(until.passed(exit));
+        assert_eq!(cpu.memory().serial_output(), b"ok");
+    }
+
+    #[test]
+    fn test_until_loop_and_pc() {
+        let mut cpu = cpu();
+        let until = Until {
+            infinite_loop: true,
+            ..Until::default()
+        };
+        assert_eq!(run(&mut cpu, &until, |_, _| {}), Exit::InfiniteLoop);
+        assert_eq!(cpu.pc(), 0x110);
+
+        let mut cpu = self::cpu();
+        let until = Until {
+            pc: Some(0x108),
+            serial: Some(String::from("ko")),
+            ..Until::default()
+        };
+        assert_eq!(run(&mut cpu, &until, |_, _| {}), Exit::PC);
+        assert!(!until.passed(Exit::PC));
+        assert!(Until::default().is_empty() && !until.is_empty());
+    }
+
+    #[test]
+    fn test_until_frames_and_cycles() {
+        let mut cpu = cpu();
+        let until = Until {
+            frames: Some(3),
+            serial: Some(String::from("ko")),
+            ..Until::default()
+        };
+        let mut frames = Vec::new();
+        let exit = run(&mut cpu, &until, |_, frame| frames.push(frame));
+        assert_eq!(exit, Exit::Frames);
+        assert!(!until.passed(exit));
+        assert_eq!(frames, [1, 2, 3]);
+
+        let mut cpu = self::cpu();
+        let until = Until {
+            cycles: Some(1000),
+            ..Until::default()
+        };
+        let exit = run(&mut cpu, &until, |_, _| {});
+        assert_eq!(exit, Exit::Cycles);
+        assert!(until.passed(exit));
+        assert!(cpu.memory().elapsed() >= 1000);
+    }
+}
@@ -0,0 +1,1175 @@
+pub mod resampler;
+pub mod sink;
+pub mod wav;
+
+use crate::model::Model;
+use crate::sound::sink::{AudioOutput, Frame};
+use crate::state::{self, snapshot};
+
+/// Waveforms of the four duty cycles of the pulse channels
+const DUTY_CYCLES: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 1, 1, 1],
+    [0, 1, 1, 1, 1, 1, 1, 0],
+];
+
+/// Noise channel divisors in T-cycles, selected by NR43 bits 0-2
+const NOISE_DIVISORS: [u16; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+/// Read masks for 0xFF10 ~ 0xFF26, unused and write-only bits read back as 1
+const READ_MASKS: [u8; 23] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10 ~ NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20 ~ NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30 ~ NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40 ~ NR44
+    0x00, 0x00, 0x70, // NR50 ~ NR52
+];
+
+#[derive(Debug)]
+struct LengthCounter {
+    enabled: bool,
+    counter: u16,
+    /// 64 for pulse and noise channels, 256 for the wave channel
+    maximum: u16,
+}
+
+impl LengthCounter {
+    fn init(maximum: u16) -> LengthCounter {
+        LengthCounter {
+            enabled: false,
+            counter: 0,
+            maximum,
+        }
+    }
+
+    fn load(&mut self, length: u16) {
+        self.counter = self.maximum - length;
+    }
+
+    /// Handles the length enable bit of NRx4, returns whether the channel
+    /// must be disabled. Enabling the counter while the next frame sequencer
+    /// step does not clock it gives it an extra clock.
+    fn write(&mut self, data: u8, extra_clock: bool) -> bool {
+        let enabled = self.enabled;
+        self.enabled = data & 0x40 != 0;
+        if extra_clock && !enabled && self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0 && data & 0x80 == 0;
+        }
+        false
+    }
+
+    fn trigger(&mut self, extra_clock: bool) {
+        if self.counter == 0 {
+            self.counter = self.maximum;
+            if self.enabled && extra_clock {
+                self.counter -= 1;
+            }
+        }
+    }
+
+    /// Clocked at 256 Hz, returns whether the channel must be disabled
+    fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        false
+    }
+}
+
+#[derive(Debug)]
+struct Envelope {
+    /// NRx2 as written
+    register: u8,
+    volume: u8,
+    timer: u8,
+    /// Cleared once the volume reaches 0 or 15
+    running: bool,
+}
+
+impl Envelope {
+    fn init() -> Envelope {
+        Envelope {
+            register: 0x00,
+            volume: 0,
+            timer: 0,
+            running: false,
+        }
+    }
+
+    fn period(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    fn increase(&self) -> bool {
+        self.register & 0x08 != 0
+    }
+
+    /// Writes NRx2. Writing it while the channel plays alters the volume
+    /// right away ("zombie" mode).
+    fn write(&mut self, data: u8, playing: bool) {
+        if playing {
+            if self.period() == 0 && self.running {
+                self.volume += 1;
+            } else if !self.increase() {
+                self.volume += 2;
+            }
+            if self.increase() != (data & 0x08 != 0) {
+                self.volume = 16 - self.volume;
+            }
+            self.volume &= 0x0F;
+        }
+        self.register = data;
+    }
+
+    /// Whether the channel DAC is powered, controlled by the upper 5 bits
+    fn dac_enabled(&self) -> bool {
+        self.register & 0xF8 != 0
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.period();
+        self.running = true;
+    }
+
+    /// Clocked at 64 Hz
+    fn clock(&mut self) {
+        if self.period() == 0 || !self.running {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.period();
+            if self.increase() && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase() && self.volume > 0 {
+                self.volume -= 1;
+            } else {
+                self.running = false;
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Sweep {
+    /// NR10 as written
+    register: u8,
+    enabled: bool,
+    timer: u8,
+    shadow: u16,
+}
+
+impl Sweep {
+    fn init() -> Sweep {
+        Sweep {
+            register: 0x00,
+            enabled: false,
+            timer: 0,
+            shadow: 0,
+        }
+    }
+
+    fn period(&self) -> u8 {
+        (self.register >> 4) & 0x07
+    }
+
+    fn shift(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    fn reload_timer(&mut self) {
+        self.timer = if self.period() == 0 { 8 } else { self.period() };
+    }
+
+    /// Next period, `None` when it overflows and the channel shuts down
+    fn next(&self) -> Option<u16> {
+        let delta = self.shadow >> self.shift();
+        let period = if self.register & 0x08 != 0 {
+            self.shadow.wrapping_sub(delta)
+        } else {
+            self.shadow + delta
+        };
+        if period > 0x07FF {
+            None
+        } else {
+            Some(period)
+        }
+    }
+}
+
+/// Channels 1 and 2, square waves with a selectable duty cycle. Only
+/// channel 1 has a frequency sweep.
+#[derive(Debug)]
+struct Pulse {
+    enabled: bool,
+    sweep: Option<Sweep>,
+    /// NRx1 bits 6-7
+    duty: u8,
+    duty_step: usize,
+    /// NRx3 and NRx4 bits 0-2
+    period: u16,
+    timer: u16,
+    length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl Pulse {
+    fn init(sweep: bool) -> Pulse {
+        Pulse {
+            enabled: false,
+            sweep: if sweep { Some(Sweep::init()) } else { None },
+            duty: 0,
+            duty_step: 0,
+            period: 0,
+            timer: 0,
+            length: LengthCounter::init(64),
+            envelope: Envelope::init(),
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        state::check("pulse duty", self.duty as usize, 0..DUTY_CYCLES.len())?;
+        state::check("pulse duty step", self.duty_step, 0..8)?;
+        state::check("pulse period", self.period as usize, 0..2048)?;
+        match &self.sweep {
+            Some(sweep) => state::check("sweep period", sweep.shadow as usize, 0..2048),
+            None => Ok(()),
+        }
+    }
+
+    fn read(&self, register: usize) -> u8 {
+        match register {
+            0 => self.sweep.as_ref().map_or(0x00, |sweep| sweep.register),
+            1 => self.duty << 6,
+            2 => self.envelope.register,
+            4 => (self.length.enabled as u8) << 6,
+            _ => 0x00,
+        }
+    }
+
+    fn write(&mut self, register: usize, data: u8, extra_clock: bool) {
+        match register {
+            0 => {
+                if let Some(sweep) = self.sweep.as_mut() {
+                    sweep.register = data;
+                }
+            }
+            1 => {
+                self.duty = data >> 6;
+                self.length.load((data & 0x3F) as u16);
+            }
+            2 => {
+                self.envelope.write(data, self.enabled);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.period = (self.period & 0x0700) | data as u16,
+            4 => {
+                self.period = (self.period & 0x00FF) | ((data as u16 & 0x07) << 8);
+                if self.length.write(data, extra_clock) {
+                    self.enabled = false;
+                }
+                if data & 0x80 != 0 {
+                    self.trigger(extra_clock);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn trigger(&mut self, extra_clock: bool) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger(extra_clock);
+        self.timer = (2048 - self.period) * 4;
+        self.envelope.trigger();
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.shadow = self.period;
+            sweep.reload_timer();
+            sweep.enabled = sweep.period() != 0 || sweep.shift() != 0;
+            if sweep.shift() != 0 && sweep.next().is_none() {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn step(&mut self, cycles: u16) {
+        for _ in 0..cycles {
+            self.timer = self.timer.saturating_sub(1);
+            if self.timer == 0 {
+                self.timer = (2048 - self.period) * 4;
+                self.duty_step = (self.duty_step + 1) % 8;
+            }
+        }
+    }
+
+    /// Clocked at 128 Hz
+    fn clock_sweep(&mut self) {
+        let sweep = match self.sweep.as_mut() {
+            Some(sweep) => sweep,
+            None => return,
+        };
+        sweep.timer = sweep.timer.saturating_sub(1);
+        if sweep.timer > 0 {
+            return;
+        }
+        sweep.reload_timer();
+        if !sweep.enabled || sweep.period() == 0 {
+            return;
+        }
+        match sweep.next() {
+            Some(period) if sweep.shift() != 0 => {
+                sweep.shadow = period;
+                self.period = period;
+                // The new period is checked again for overflow right away
+                if sweep.next().is_none() {
+                    self.enabled = false;
+                }
+            }
+            Some(_) => {}
+            None => self.enabled = false,
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled {
+            DUTY_CYCLES[self.duty as usize][self.duty_step] * self.envelope.volume
+        } else {
+            0
+        }
+    }
+}
+
+/// Channel 3, plays the 32 4-bit samples stored in wave RAM
+#[derive(Debug)]
+struct Wave {
+    enabled: bool,
+    /// NR30 bit 7
+    dac_enabled: bool,
+    /// NR32 bits 5-6
+    volume: u8,
+    period: u16,
+    timer: u16,
+    /// Index of the sample being played, 0 ~ 31
+    position: usize,
+    /// Whether a sample was fetched from wave RAM during the last step
+    fetched: bool,
+    length: LengthCounter,
+    /// 0xFF30 ~ 0xFF3F, high nibble played first
+    ram: [u8; 16],
+    /// The CGB gives the CPU access to wave RAM at any time while playing
+    cgb: bool,
+}
+
+impl Wave {
+    fn init(cgb: bool) -> Wave {
+        Wave {
+            enabled: false,
+            dac_enabled: false,
+            volume: 0,
+            period: 0,
+            timer: 0,
+            position: 0,
+            fetched: false,
+            length: LengthCounter::init(256),
+            ram: [0; 16],
+            cgb,
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        state::check("wave volume", self.volume as usize, 0..4)?;
+        state::check("wave period", self.period as usize, 0..2048)?;
+        state::check("wave position", self.position, 0..32)
+    }
+
+    /// While the channel plays, wave RAM accesses land on the byte being
+    /// played instead. The DMG only allows them at the moment the channel
+    /// fetches that byte, reading 0xFF and ignoring writes otherwise.
+    fn ram_offset(&self, offset: usize) -> Option<usize> {
+        if !self.enabled {
+            Some(offset)
+        } else if self.cgb || self.fetched {
+            Some(self.position / 2)
+        } else {
+            None
+        }
+    }
+
+    fn read_ram(&self, offset: usize) -> u8 {
+        self.ram_offset(offset)
+            .map_or(0xFF, |offset| self.ram[offset])
+    }
+
+    fn write_ram(&mut self, offset: usize, data: u8) {
+        if let Some(offset) = self.ram_offset(offset) {
+            self.ram[offset] = data;
+        }
+    }
+
+    fn read(&self, register: usize) -> u8 {
+        match register {
+            0 => (self.dac_enabled as u8) << 7,
+            2 => self.volume << 5,
+            4 => (self.length.enabled as u8) << 6,
+            _ => 0x00,
+        }
+    }
+
+    fn write(&mut self, register: usize, data: u8, extra_clock: bool) {
+        match register {
+            0 => {
+                self.dac_enabled = data & 0x80 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(data as u16),
+            2 => self.volume = (data >> 5) & 0x03,
+            3 => self.period = (self.period & 0x0700) | data as u16,
+            4 => {
+                self.period = (self.period & 0x00FF) | ((data as u16 & 0x07) << 8);
+                if self.length.write(data, extra_clock) {
+                    self.enabled = false;
+                }
+                if data & 0x80 != 0 {
+                    self.trigger(extra_clock);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn trigger(&mut self, extra_clock: bool) {
+        // Retriggering the DMG right as it fetches a byte corrupts the first
+        // bytes of wave RAM
+        if !self.cgb && self.enabled && self.timer <= 2 {
+            let offset = ((self.position + 1) % 32) / 2;
+            if offset < 4 {
+                self.ram[0] = self.ram[offset];
+            } else {
+                let block = offset & !0x03;
+                self.ram.copy_within(block..block + 4, 0);
+            }
+        }
+        self.enabled = self.dac_enabled;
+        self.length.trigger(extra_clock);
+        self.timer = (2048 - self.period) * 2;
+        self.position = 0;
+    }
+
+    fn step(&mut self, cycles: u16) {
+        self.fetched = false;
+        for _ in 0..cycles {
+            self.timer = self.timer.saturating_sub(1);
+            if self.timer == 0 {
+                self.timer = (2048 - self.period) * 2;
+                self.position = (self.position + 1) % 32;
+                self.fetched = true;
+            }
+        }
+    }
+
+    fn sample(&self) -> u8 {
+        let byte = self.ram[self.position / 2];
+        if self.position & 0x01 == 0 {
+            byte >> 4
+        } else {
+            byte & 0x0F
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled || self.volume == 0 {
+            return 0;
+        }
+        self.sample() >> (self.volume - 1)
+    }
+}
+
+/// Channel 4, pseudo-random noise from a linear feedback shift register
+#[derive(Debug)]
+struct Noise {
+    enabled: bool,
+    /// NR43 as written
+    register: u8,
+    lfsr: u16,
+    timer: u32,
+    length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl Noise {
+    fn init() -> Noise {
+        Noise {
+            enabled: false,
+            register: 0x00,
+            lfsr: 0x7FFF,
+            timer: 0,
+            length: LengthCounter::init(64),
+            envelope: Envelope::init(),
+        }
+    }
+
+    fn read(&self, register: usize) -> u8 {
+        match register {
+            2 => self.envelope.register,
+            3 => self.register,
+            4 => (self.length.enabled as u8) << 6,
+            _ => 0x00,
+        }
+    }
+
+    fn write(&mut self, register: usize, data: u8, extra_clock: bool) {
+        match register {
+            1 => self.length.load((data & 0x3F) as u16),
+            2 => {
+                self.envelope.write(data, self.enabled);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.register = data,
+            4 => {
+                if self.length.write(data, extra_clock) {
+                    self.enabled = false;
+                }
+                if data & 0x80 != 0 {
+                    self.trigger(extra_clock);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (NOISE_DIVISORS[(self.register & 0x07) as usize] as u32) << (self.register >> 4)
+    }
+
+    fn trigger(&mut self, extra_clock: bool) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger(extra_clock);
+        self.timer = self.period();
+        self.envelope.trigger();
+        self.lfsr = 0x7FFF;
+    }
+
+    fn step(&mut self, cycles: u16) {
+        for _ in 0..cycles {
+            self.timer = self.timer.saturating_sub(1);
+            if self.timer == 0 {
+                self.timer = self.period();
+                let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 0x01;
+                self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+                // 7-bit mode also feeds bit 6
+                if self.register & 0x08 != 0 {
+                    self.lfsr = (self.lfsr & !0x0040) | (feedback << 6);
+                }
+            }
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && self.lfsr & 0x01 == 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+}
+
+/// Audio Processing Unit mapped at 0xFF10 ~ 0xFF3F, plus the CGB PCM12 and
+/// PCM34 registers at 0xFF76 ~ 0xFF77.
+///
+/// The channels are clocked at 4 MHz regardless of the CPU speed, while the
+/// frame sequencer ticking lengths, sweep and envelopes is driven by DIV.
+#[derive(Debug)]
+pub struct APU {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    wave: Wave,
+    noise: Noise,
+    /// 0xFF24 Master volume
+    nr50: u8,
+    /// 0xFF25 Panning
+    nr51: u8,
+    /// 0xFF26 bit 7
+    powered: bool,
+    /// Frame sequencer step, 0 ~ 7
+    sequencer: u8,
+    cgb: bool,
+    output: Option<AudioOutput>,
+    /// Outputs of each channel on its own, before panning and volume
+    stems: Vec<AudioOutput>,
+}
+
+impl APU {
+    pub fn init(model: Model) -> APU {
+        APU {
+            pulse1: Pulse::init(true),
+            pulse2: Pulse::init(false),
+            wave: Wave::init(model.is_cgb()),
+            noise: Noise::init(),
+            nr50: 0x77,
+            nr51: 0xF3,
+            powered: true,
+            sequencer: 0,
+            cgb: model.is_cgb(),
+            output: None,
+            stems: vec![],
+        }
+    }
+
+    /// Checks the indices of a state just loaded
+    pub fn validate(&self) -> Result<(), String> {
+        self.pulse1.validate()?;
+        self.pulse2.validate()?;
+        self.wave.validate()?;
+        state::check("frame sequencer step", self.sequencer as usize, 0..8)
+    }
+
+    /// Starts delivering the resampled output to a sink
+    pub fn set_output(&mut self, output: AudioOutput) {
+        self.output = Some(output);
+    }
+
+    /// Starts delivering each channel output to its own sink, in mono
+    pub fn set_stems(&mut self, stems: [AudioOutput; 4]) {
+        self.stems = Vec::from(stems);
+    }
+
+    /// Flushes the samples left and lets the sinks know the output ended
+    pub fn finish(&mut self) {
+        if let Some(output) = self.output.as_mut() {
+            output.finish();
+        }
+        for stem in self.stems.iter_mut() {
+            stem.finish();
+        }
+    }
+
+    pub fn read(&self, address: usize) -> u8 {
+        let data = match address {
+            0xFF10..=0xFF14 => self.pulse1.read(address - 0xFF10),
+            0xFF15..=0xFF19 => self.pulse2.read(address - 0xFF15),
+            0xFF1A..=0xFF1E => self.wave.read(address - 0xFF1A),
+            0xFF1F..=0xFF23 => self.noise.read(address - 0xFF1F),
+            0xFF24 => self.nr50,
+            0xFF25 => self.nr51,
+            0xFF26 => {
+                (self.powered as u8) << 7
+                    | (self.noise.enabled as u8) << 3
+                    | (self.wave.enabled as u8) << 2
+                    | (self.pulse2.enabled as u8) << 1
+                    | self.pulse1.enabled as u8
+            }
+            0xFF30..=0xFF3F => return self.wave.read_ram(address - 0xFF30),
+            // Digital outputs of the channels, 0xFF76 PCM12 and 0xFF77 PCM34
+            0xFF76 | 0xFF77 if self.cgb => {
+                let channels = self.channels();
+                let pair = (address - 0xFF76) * 2;
+                return channels[pair] | channels[pair + 1] << 4;
+            }
+            _ => return 0xFF,
+        };
+        data | READ_MASKS[address - 0xFF10]
+    }
+
+    pub fn write(&mut self, address: usize, data: u8) {
+        if !self.powered {
+            return self.write_powered_off(address, data);
+        }
+        // Lengths enabled now get an extra clock when the next frame
+        // sequencer step does not clock them
+        let extra_clock = self.sequencer & 0x01 != 0;
+        match address {
+            0xFF10..=0xFF14 => self.pulse1.write(address - 0xFF10, data, extra_clock),
+            0xFF15..=0xFF19 => self.pulse2.write(address - 0xFF15, data, extra_clock),
+            0xFF1A..=0xFF1E => self.wave.write(address - 0xFF1A, data, extra_clock),
+            0xFF1F..=0xFF23 => self.noise.write(address - 0xFF1F, data, extra_clock),
+            0xFF24 => self.nr50 = data,
+            0xFF25 => self.nr51 = data,
+            0xFF26 if data & 0x80 == 0 => self.power_off(),
+            0xFF30..=0xFF3F => self.wave.write_ram(address - 0xFF30, data),
+            _ => {}
+        }
+    }
+
+    /// Only NR52 and wave RAM are writable while powered off, plus the
+    /// length counters on the DMG
+    fn write_powered_off(&mut self, address: usize, data: u8) {
+        match address {
+            0xFF11 if !self.cgb => self.pulse1.length.load((data & 0x3F) as u16),
+            0xFF16 if !self.cgb => self.pulse2.length.load((data & 0x3F) as u16),
+            0xFF1B if !self.cgb => self.wave.length.load(data as u16),
+            0xFF20 if !self.cgb => self.noise.length.load((data & 0x3F) as u16),
+            0xFF26 if data & 0x80 != 0 => {
+                self.powered = true;
+                self.sequencer = 0;
+            }
+            0xFF30..=0xFF3F => self.wave.write_ram(address - 0xFF30, data),
+            _ => {}
+        }
+    }
+
+    /// Clears every register but wave RAM. The DMG keeps its length
+    /// counters running.
+    fn power_off(&mut self) {
+        let lengths = [
+            self.pulse1.length.counter,
+            self.pulse2.length.counter,
+            self.wave.length.counter,
+            self.noise.length.counter,
+        ];
+        let ram = self.wave.ram;
+        self.pulse1 = Pulse::init(true);
+        self.pulse2 = Pulse::init(false);
+        self.wave = Wave::init(self.cgb);
+        self.noise = Noise::init();
+        self.wave.ram = ram;
+        if !self.cgb {
+            self.pulse1.length.counter = lengths[0];
+            self.pulse2.length.counter = lengths[1];
+            self.wave.length.counter = lengths[2];
+            self.noise.length.counter = lengths[3];
+        }
+        self.nr50 = 0x00;
+        self.nr51 = 0x00;
+        self.powered = false;
+    }
+
+    /// Advances the channels by `cycles` T-cycles of the 4 MHz clock
+    pub fn step(&mut self, cycles: u8) {
+        if self.powered {
+            self.pulse1.step(cycles as u16);
+            self.pulse2.step(cycles as u16);
+            self.wave.step(cycles as u16);
+            self.noise.step(cycles as u16);
+        }
+        if self.output.is_some() {
+            let frame = self.sample();
+            if let Some(output) = self.output.as_mut() {
+                output.push(cycles as u32, frame);
+            }
+        }
+        if !self.stems.is_empty() {
+            let analog = self.analog();
+            for (stem, level) in self.stems.iter_mut().zip(analog.iter()) {
+                let frame = Frame {
+                    left: *level,
+                    right: *level,
+                };
+                stem.push(cycles as u32, frame);
+            }
+        }
+    }
+
+    /// Clocks the frame sequencer, on each falling edge of DIV bit 4 (bit 5
+    /// in double speed), that is at 512 Hz
+    pub fn clock_sequencer(&mut self) {
+        if !self.powered {
+            return;
+        }
+        if self.sequencer & 0x01 == 0 {
+            self.clock_lengths();
+        }
+        if self.sequencer == 2 || self.sequencer == 6 {
+            self.pulse1.clock_sweep();
+        }
+        if self.sequencer == 7 {
+            self.pulse1.envelope.clock();
+            self.pulse2.envelope.clock();
+            self.noise.envelope.clock();
+        }
+        self.sequencer = (self.sequencer + 1) % 8;
+    }
+
+    fn clock_lengths(&mut self) {
+        if self.pulse1.length.clock() {
+            self.pulse1.enabled = false;
+        }
+        if self.pulse2.length.clock() {
+            self.pulse2.enabled = false;
+        }
+        if self.wave.length.clock() {
+            self.wave.enabled = false;
+        }
+        if self.noise.length.clock() {
+            self.noise.enabled = false;
+        }
+    }
+
+    /// Digital output, 0 ~ 15, of each channel
+    pub fn channels(&self) -> [u8; 4] {
+        [
+            self.pulse1.output(),
+            self.pulse2.output(),
+            self.wave.output(),
+            self.noise.output(),
+        ]
+    }
+
+    /// Whether each channel DAC is powered, a channel with its DAC off is silent
+    fn dacs(&self) -> [bool; 4] {
+        [
+            self.pulse1.envelope.dac_enabled(),
+            self.pulse2.envelope.dac_enabled(),
+            self.wave.dac_enabled,
+            self.noise.envelope.dac_enabled(),
+        ]
+    }
+
+    /// Output of each channel DAC, which maps 0 ~ 15 to 1.0 ~ -1.0
+    fn analog(&self) -> [f32; 4] {
+        let mut analog = [0.0; 4];
+        if !self.powered {
+            return analog;
+        }
+        let dacs = self.dacs();
+        for (channel, digital) in self.channels().iter().enumerate() {
+            if dacs[channel] {
+                analog[channel] = 1.0 - *digital as f32 / 7.5;
+            }
+        }
+        analog
+    }
+
+    /// Current stereo output, before resampling
+    pub fn sample(&self) -> Frame {
+        let mut frame = Frame {
+            left: 0.0,
+            right: 0.0,
+        };
+        if !self.powered {
+            return frame;
+        }
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (channel, analog) in self.analog().iter().enumerate() {
+            if self.nr51 & (0x10 << channel) != 0 {
+                left += analog;
+            }
+            if self.nr51 & (0x01 << channel) != 0 {
+                right += analog;
+            }
+        }
+        let left_volume = ((self.nr50 >> 4) & 0x07) as f32 + 1.0;
+        let right_volume = (self.nr50 & 0x07) as f32 + 1.0;
+        frame.left = left * left_volume / 32.0;
+        frame.right = right * right_volume / 32.0;
+        frame
+    }
+}
+
+impl Default for Sweep {
+    fn default() -> Sweep {
+        Sweep::init()
+    }
+}
+
+snapshot!(LengthCounter {
+    enabled,
+    counter,
+    maximum
+});
+snapshot!(Envelope {
+    register,
+    volume,
+    timer,
+    running
+});
+snapshot!(Sweep {
+    register,
+    enabled,
+    timer,
+    shadow
+});
+snapshot!(Pulse {
+    enabled,
+    sweep,
+    duty,
+    duty_step,
+    period,
+    timer,
+    length,
+    envelope
+});
+snapshot!(Wave {
+    enabled,
+    dac_enabled,
+    volume,
+    period,
+    timer,
+    position,
+    fetched,
+    length,
+    ram,
+    cgb
+});
+snapshot!(Noise {
+    enabled,
+    register,
+    lfsr,
+    timer,
+    length,
+    envelope
+});
+// The audio outputs belong to the host, they are kept as they are
+snapshot!(APU {
+    pulse1,
+    pulse2,
+    wave,
+    noise,
+    nr50,
+    nr51,
+    powered,
+    sequencer,
+    cgb
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_reads() {
+        let mut apu = APU::init(Model::DMG);
+        apu.write(0xFF11, 0x80);
+        assert_eq!(apu.read(0xFF11), 0xBF);
+        assert_eq!(apu.read(0xFF13), 0xFF);
+        assert_eq!(apu.read(0xFF15), 0xFF);
+        assert_eq!(apu.read(0xFF27), 0xFF);
+        assert_eq!(apu.read(0xFF26), 0xF0);
+    }
+
+    #[test]
+    fn test_invalid_state() {
+        let mut apu = APU::init(Model::DMG);
+        assert_eq!(apu.validate(), Ok(()));
+        apu.pulse2.duty_step = 8;
+        assert_eq!(
+            apu.validate(),
+            Err(String::from("Invalid pulse duty step 8 in save state"))
+        );
+        apu.pulse2.duty_step = 0;
+        apu.wave.position = 32;
+        assert_eq!(
+            apu.validate(),
+            Err(String::from("Invalid wave position 32 in save state"))
+        );
+    }
+
+    #[test]
+    fn test_pulse_length_counter() {
+        let mut apu = APU::init(Model::DMG);
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF16, 0x3E);
+        apu.write(0xFF19, 0xC7);
+        assert_eq!(apu.read(0xFF26) & 0x0F, 0x02);
+
+        // Two 256 Hz length clocks silence the channel
+        apu.clock_sequencer();
+        assert_eq!(apu.read(0xFF26) & 0x0F, 0x02);
+        apu.clock_sequencer();
+        apu.clock_sequencer();
+        assert_eq!(apu.read(0xFF26) & 0x0F, 0x00);
+    }
+
+    #[test]
+    fn test_pulse_duty_and_envelope() {
+        let mut apu = APU::init(Model::DMG);
+        apu.write(0xFF16, 0x80);
+        apu.write(0xFF17, 0xA1);
+        apu.write(0xFF18, 0xFF);
+        apu.write(0xFF19, 0x87);
+        // Period 0x7FF steps the duty cycle every 4 T-cycles
+        let mut waveform = vec![];
+        for _ in 0..8 {
+            apu.step(4);
+            waveform.push(apu.channels()[1]);
+        }
+        assert_eq!(waveform, vec![0, 0, 0, 0, 10, 10, 10, 10]);
+
+        for _ in 0..8 {
+            apu.clock_sequencer();
+        }
+        assert_eq!(apu.pulse2.envelope.volume, 9);
+    }
+
+    #[test]
+    fn test_sweep_overflow() {
+        let mut apu = APU::init(Model::DMG);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF10, 0x11);
+        apu.write(0xFF13, 0xFF);
+        apu.write(0xFF14, 0x87);
+        assert_eq!(apu.read(0xFF26) & 0x01, 0x00);
+
+        apu.write(0xFF13, 0x00);
+        apu.write(0xFF14, 0x84);
+        assert_eq!(apu.read(0xFF26) & 0x01, 0x01);
+        apu.clock_sequencer();
+        apu.clock_sequencer();
+        apu.clock_sequencer();
+        assert_eq!(apu.pulse1.period, 0x0600);
+    }
+
+    #[test]
+    fn test_wave_channel() {
+        let mut apu = APU::init(Model::DMG);
+        apu.write(0xFF30, 0xF7);
+        apu.write(0xFF1A, 0x80);
+        apu.write(0xFF1C, 0x20);
+        apu.write(0xFF1D, 0xFF);
+        apu.write(0xFF1E, 0x87);
+        assert_eq!(apu.channels()[2], 0x0F);
+        apu.step(2);
+        assert_eq!(apu.channels()[2], 0x07);
+
+        apu.write(0xFF1C, 0x60);
+        assert_eq!(apu.channels()[2], 0x01);
+    }
+
+    #[test]
+    fn test_noise_lfsr() {
+        let mut apu = APU::init(Model::DMG);
+        apu.write(0xFF21, 0xF0);
+        apu.write(0xFF22, 0x08);
+        apu.write(0xFF23, 0x80);
+        assert_eq!(apu.noise.lfsr, 0x7FFF);
+        // 7-bit mode feeds bit 6 along with bit 14
+        apu.step(8);
+        assert_eq!(apu.noise.lfsr, 0x3FBF);
+        assert_eq!(apu.channels()[3], 0);
+        apu.step(8);
+        assert_eq!(apu.noise.lfsr & 0x4040, 0x0000);
+    }
+
+    #[test]
+    fn test_mixer_panning() {
+        let mut apu = APU::init(Model::DMG);
+        apu.write(0xFF24, 0x07);
+        apu.write(0xFF25, 0x02);
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF19, 0x80);
+        let frame = apu.sample();
+        assert_eq!(frame.left, 0.0);
+        assert_eq!(frame.right, 0.25);
+
+        apu.write(0xFF26, 0x00);
+        assert_eq!(apu.sample().right, 0.0);
+        assert_eq!(apu.read(0xFF26), 0x70);
+    }
+
+    #[test]
+    fn test_pcm_registers() {
+        let mut apu = APU::init(Model::CGB);
+        apu.write(0xFF16, 0x80);
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF19, 0x80);
+        assert_eq!(apu.read(0xFF76), 0xF0);
+        assert_eq!(apu.read(0xFF77), 0x00);
+        assert_eq!(APU::init(Model::DMG).read(0xFF76), 0xFF);
+    }
+
+    #[test]
+    fn test_zombie_mode() {
+        let mut apu = APU::init(Model::DMG);
+        apu.write(0xFF12, 0xA0);
+        apu.write(0xFF14, 0x80);
+        assert_eq!(apu.pulse1.envelope.volume, 10);
+        apu.write(0xFF12, 0xA0);
+        assert_eq!(apu.pulse1.envelope.volume, 11);
+        // Flipping the direction mirrors the volume
+        apu.write(0xFF12, 0xA8);
+        assert_eq!(apu.pulse1.envelope.volume, 4);
+    }
+
+    #[test]
+    fn test_length_extra_clock() {
+        let mut apu = APU::init(Model::DMG);
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF16, 0x3F);
+        apu.write(0xFF19, 0x80);
+        // Step 0 clocks lengths, so step 1 is next
+        apu.clock_sequencer();
+        apu.write(0xFF19, 0x40);
+        assert_eq!(apu.read(0xFF26) & 0x02, 0x00);
+
+        // Triggering with an expired length reloads it minus the extra clock
+        apu.write(0xFF19, 0xC0);
+        assert_eq!(apu.pulse2.length.counter, 63);
+        apu.clock_sequencer();
+        apu.write(0xFF19, 0xC0);
+        assert_eq!(apu.pulse2.length.counter, 63);
+        assert_eq!(apu.read(0xFF26) & 0x02, 0x02);
+    }
+
+    #[test]
+    fn test_power_off() {
+        for &model in [Model::DMG, Model::CGB].iter() {
+            let mut apu = APU::init(model);
+            apu.write(0xFF30, 0x12);
+            apu.write(0xFF11, 0xBF);
+            apu.write(0xFF26, 0x00);
+            assert_eq!(apu.read(0xFF24), 0x00);
+            assert_eq!(apu.read(0xFF11), 0x3F);
+            assert_eq!(apu.read(0xFF30), 0x12);
+
+            apu.write(0xFF24, 0x77);
+            apu.write(0xFF30, 0x34);
+            apu.write(0xFF11, 0x3E);
+            assert_eq!(apu.read(0xFF24), 0x00);
+            assert_eq!(apu.read(0xFF30), 0x34);
+            let length = if model.is_cgb() { 0 } else { 2 };
+            assert_eq!(apu.pulse1.length.counter, length);
+
+            apu.write(0xFF26, 0x80);
+            apu.write(0xFF24, 0x77);
+            assert_eq!(apu.read(0xFF24), 0x77);
+        }
+    }
+
+    #[test]
+    fn test_wave_ram_while_playing() {
+        for &model in [Model::DMG, Model::CGB].iter() {
+            let mut apu = APU::init(model);
+            for offset in 0..16 {
+                apu.write(0xFF30 + offset, offset as u8);
+            }
+            apu.write(0xFF1A, 0x80);
+            apu.write(0xFF1D, 0xFF);
+            apu.write(0xFF1E, 0x87);
+            apu.step(4);
+            // The channel fetched sample 2, in byte 1, during this step
+            assert_eq!(apu.read(0xFF3F), 0x01);
+            apu.step(1);
+            let data = if model.is_cgb() { 0x01 } else { 0xFF };
+            assert_eq!(apu.read(0xFF3F), data);
+            apu.write(0xFF30, 0xAB);
+            assert_eq!(apu.wave.ram[1] == 0xAB, model.is_cgb());
+        }
+    }
+
+    #[test]
+    fn test_wave_trigger_corruption() {
+        let mut apu = APU::init(Model::DMG);
+        for offset in 0..16 {
+            apu.write(0xFF30 + offset, offset as u8);
+        }
+        apu.write(0xFF1A, 0x80);
+        apu.write(0xFF1D, 0x00);
+        apu.write(0xFF1E, 0x87);
+        // Right before fetching sample 10, in byte 5 of the second block
+        for _ in 0..(((2048 - 0x0700) * 2 * 10 - 2) / 2) {
+            apu.step(2);
+        }
+        apu.write(0xFF1E, 0x87);
+        assert_eq!(apu.wave.ram[0..4], [4, 5, 6, 7]);
+        assert_eq!(apu.wave.ram[4..8], [4, 5, 6, 7]);
+    }
+}
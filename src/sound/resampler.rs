@@ -0,0 +1,231 @@
+use crate::model::Model;
+use crate::sound::sink::Frame;
+use std::f64::consts::PI;
+
+/// Rate of the APU clock in Hz, which the channels are stepped at
+pub const APU_CLOCK: u32 = 4_194_304;
+
+/// Taps of the band-limited step, in output samples
+const KERNEL_WIDTH: usize = 16;
+/// Sub-sample positions a step can start at
+const KERNEL_PHASES: usize = 32;
+/// Cutoff of the kernel relative to the output sample rate, just below Nyquist
+const CUTOFF: f64 = 0.45;
+
+/// Converts the APU output, a level changing at up to 4 MHz, to a fixed
+/// sample rate by band-limited synthesis: every change of level is added to
+/// the output as a windowed-sinc step, so no harmonic above the Nyquist
+/// frequency aliases back into the audible range.
+///
+/// The output is then run through the high-pass filter of the console, which
+/// removes the DC offset the channel DACs leave behind.
+#[derive(Debug)]
+pub struct Resampler {
+    /// Output samples per APU cycle, at the clock rate of the model
+    ratio: f64,
+    /// Impulse responses, one row per phase, each summing up to 1
+    kernel: Vec<[f32; KERNEL_WIDTH]>,
+    /// Pending level differences of each side, starting at sample `read`
+    deltas: [Vec<f32>; 2],
+    /// APU cycles elapsed
+    clock: u64,
+    /// Samples handed out so far
+    read: u64,
+    /// Current input level of each side
+    level: [f32; 2],
+    /// Running sum of the deltas, i.e. the band-limited signal
+    sum: [f32; 2],
+    high_pass: [HighPass; 2],
+}
+
+impl Resampler {
+    pub fn new(sample_rate: u32, model: Model) -> Resampler {
+        if sample_rate == 0 || sample_rate > APU_CLOCK {
+            panic!("Unsupported sample rate: {} Hz", sample_rate);
+        }
+        let charge = HighPass::charge(model, sample_rate);
+        Resampler {
+            ratio: sample_rate as f64 / model.clock() as f64,
+            kernel: kernel(),
+            deltas: [vec![], vec![]],
+            clock: 0,
+            read: 0,
+            level: [0.0; 2],
+            sum: [0.0; 2],
+            high_pass: [HighPass::init(charge), HighPass::init(charge)],
+        }
+    }
+
+    /// Advances the input by `cycles` APU cycles, after which its level
+    /// becomes `frame`
+    pub fn push(&mut self, cycles: u32, frame: Frame) {
+        self.clock += cycles as u64;
+        let position = self.clock as f64 * self.ratio - self.read as f64;
+        let index = position.floor();
+        let phase = ((position - index) * KERNEL_PHASES as f64) as usize;
+        let index = index as usize;
+        for (side, level) in [frame.left, frame.right].iter().enumerate() {
+            let delta = level - self.level[side];
+            if delta == 0.0 {
+                continue;
+            }
+            self.level[side] = *level;
+            let deltas = &mut self.deltas[side];
+            if deltas.len() < index + KERNEL_WIDTH {
+                deltas.resize(index + KERNEL_WIDTH, 0.0);
+            }
+            for (tap, weight) in self.kernel[phase].iter().enumerate() {
+                deltas[index + tap] += delta * weight;
+            }
+        }
+    }
+
+    /// Samples no later level change can affect anymore
+    pub fn available(&self) -> usize {
+        ((self.clock as f64 * self.ratio).floor() as u64 - self.read) as usize
+    }
+
+    /// Moves the available samples into `frames`
+    pub fn read(&mut self, frames: &mut Vec<Frame>) {
+        let count = self.available();
+        for side in 0..2 {
+            let deltas = &mut self.deltas[side];
+            if deltas.len() < count {
+                deltas.resize(count, 0.0);
+            }
+        }
+        for index in 0..count {
+            let mut sides = [0.0; 2];
+            for (side, output) in sides.iter_mut().enumerate() {
+                self.sum[side] += self.deltas[side][index];
+                *output = self.high_pass[side].filter(self.sum[side]);
+            }
+            frames.push(Frame {
+                left: sides[0],
+                right: sides[1],
+            });
+        }
+        for deltas in self.deltas.iter_mut() {
+            deltas.drain(..count);
+        }
+        self.read += count as u64;
+    }
+}
+
+/// Builds the band-limited step kernel, a Blackman-windowed sinc
+fn kernel() -> Vec<[f32; KERNEL_WIDTH]> {
+    let half = (KERNEL_WIDTH / 2) as f64;
+    (0..KERNEL_PHASES)
+        .map(|phase| {
+            let offset = phase as f64 / KERNEL_PHASES as f64;
+            let mut taps = [0.0; KERNEL_WIDTH];
+            for (tap, weight) in taps.iter_mut().enumerate() {
+                let x = tap as f64 - offset - half + 1.0;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (2.0 * PI * CUTOFF * x).sin() / (2.0 * PI * CUTOFF * x)
+                };
+                let u = (x + half) / (KERNEL_WIDTH as f64 + 1.0);
+                let window = 0.42 - 0.5 * (2.0 * PI * u).cos() + 0.08 * (4.0 * PI * u).cos();
+                *weight = sinc * window;
+            }
+            let total: f64 = taps.iter().sum();
+            let mut row = [0.0; KERNEL_WIDTH];
+            for (tap, weight) in taps.iter().enumerate() {
+                row[tap] = (weight / total) as f32;
+            }
+            row
+        })
+        .collect()
+}
+
+/// DC-blocking filter formed by the output capacitor of the console
+#[derive(Debug)]
+struct HighPass {
+    /// Fraction of the charge kept across one output sample
+    charge: f32,
+    capacitor: f32,
+}
+
+impl HighPass {
+    fn init(charge: f32) -> HighPass {
+        HighPass {
+            charge,
+            capacitor: 0.0,
+        }
+    }
+
+    /// Charge kept after one output sample, the capacitor of the CGB and
+    /// of the Game Boy Pocket discharges faster than the original one's
+    fn charge(model: Model, sample_rate: u32) -> f32 {
+        let charge: f64 = match model {
+            Model::DMG0 | Model::DMG | Model::SGB | Model::SGB2 => 0.999958,
+            Model::MGB | Model::CGB | Model::AGB => 0.998943,
+        };
+        charge.powf(APU_CLOCK as f64 / sample_rate as f64) as f32
+    }
+
+    fn filter(&mut self, input: f32) -> f32 {
+        let output = input - self.capacitor;
+        self.capacitor = input - output * self.charge;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resample(resampler: &mut Resampler, cycles: u32, level: f32) -> Vec<Frame> {
+        let mut frames = vec![];
+        let frame = Frame {
+            left: level,
+            right: -level,
+        };
+        for _ in 0..cycles / 4 {
+            resampler.push(4, frame);
+        }
+        resampler.read(&mut frames);
+        frames
+    }
+
+    #[test]
+    fn test_kernel_is_normalized() {
+        for row in kernel() {
+            let total: f32 = row.iter().sum();
+            assert!((total - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_sample_count() {
+        let mut resampler = Resampler::new(48_000, Model::CGB);
+        let frames = resample(&mut resampler, APU_CLOCK, 0.0);
+        assert_eq!(frames.len(), 48_000);
+        let frames = resample(&mut resampler, APU_CLOCK / 2, 0.0);
+        assert_eq!(frames.len(), 24_000);
+
+        // The faster SGB clock plays everything slightly sharp
+        let mut resampler = Resampler::new(48_000, Model::SGB);
+        let frames = resample(&mut resampler, APU_CLOCK, 0.0);
+        assert_eq!(frames.len(), 46_869);
+    }
+
+    #[test]
+    fn test_step_settles_and_decays() {
+        let mut resampler = Resampler::new(44_100, Model::DMG);
+        let frames = resample(&mut resampler, 4096, 0.5);
+        // The step is band-limited, ringing slightly past its level
+        let peak = frames.iter().map(|frame| frame.left).fold(0.0, f32::max);
+        assert!(peak > 0.5 && peak < 0.55);
+        assert!(frames[0].left.abs() < 0.01);
+        assert!(frames
+            .iter()
+            .all(|frame| (frame.left + frame.right).abs() < 1e-6));
+
+        // A constant level is drained by the high-pass filter
+        let frames = resample(&mut resampler, APU_CLOCK * 2, 0.5);
+        assert!(frames.last().unwrap().left.abs() < 0.01);
+    }
+}
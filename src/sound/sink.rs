@@ -0,0 +1,119 @@
+use crate::model::Model;
+use crate::sound::resampler::Resampler;
+use std::fmt;
+
+/// Frames collected before being handed to the sink
+const BUFFER_FRAMES: usize = 512;
+
+/// Stereo sample, each side within -1.0 ~ 1.0
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    pub left: f32,
+    pub right: f32,
+}
+
+impl Frame {
+    pub fn to_i16(&self) -> [i16; 2] {
+        let convert = |sample: f32| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        [convert(self.left), convert(self.right)]
+    }
+}
+
+/// Destination of the resampled audio, e.g. the frontend audio device or a
+/// WAV file
+pub trait AudioSink {
+    /// Receives the next frames, at the sample rate the output was set up with
+    fn write(&mut self, frames: &[Frame]);
+
+    /// Called once the emulation stops, nothing gets written afterwards
+    fn finish(&mut self) {}
+}
+
+/// Resamples the APU output and feeds it to a sink in batches
+pub struct AudioOutput {
+    resampler: Resampler,
+    frames: Vec<Frame>,
+    sink: Box<dyn AudioSink>,
+}
+
+impl AudioOutput {
+    pub fn new(sink: Box<dyn AudioSink>, sample_rate: u32, model: Model) -> AudioOutput {
+        AudioOutput {
+            resampler: Resampler::new(sample_rate, model),
+            frames: Vec::with_capacity(BUFFER_FRAMES),
+            sink,
+        }
+    }
+
+    pub fn push(&mut self, cycles: u32, frame: Frame) {
+        self.resampler.push(cycles, frame);
+        if self.resampler.available() >= BUFFER_FRAMES {
+            self.flush();
+        }
+    }
+
+    /// Hands out every frame resampled so far
+    pub fn flush(&mut self) {
+        self.resampler.read(&mut self.frames);
+        if !self.frames.is_empty() {
+            self.sink.write(&self.frames);
+            self.frames.clear();
+        }
+    }
+
+    pub fn finish(&mut self) {
+        self.flush();
+        self.sink.finish();
+    }
+}
+
+impl fmt::Debug for AudioOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AudioOutput")
+            .field("resampler", &self.resampler)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sound::resampler::APU_CLOCK;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Collector(Rc<RefCell<Vec<Frame>>>);
+
+    impl AudioSink for Collector {
+        fn write(&mut self, frames: &[Frame]) {
+            self.0.borrow_mut().extend_from_slice(frames);
+        }
+    }
+
+    #[test]
+    fn test_output_batches() {
+        let frames = Rc::new(RefCell::new(vec![]));
+        let sink = Box::new(Collector(frames.clone()));
+        let mut output = AudioOutput::new(sink, 32_768, Model::DMG);
+        let silence = Frame {
+            left: 0.0,
+            right: 0.0,
+        };
+        for _ in 0..(APU_CLOCK / 64) {
+            output.push(4, silence);
+        }
+        assert_eq!(frames.borrow().len(), 2048);
+        output.push(APU_CLOCK / 32_768 * 100, silence);
+        output.finish();
+        assert_eq!(frames.borrow().len(), 2148);
+    }
+
+    #[test]
+    fn test_frame_to_i16() {
+        let frame = Frame {
+            left: 2.0,
+            right: -0.5,
+        };
+        assert_eq!(frame.to_i16(), [32767, -16383]);
+    }
+}
@@ -0,0 +1,126 @@
+use crate::sound::sink::{AudioSink, Frame};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Size of the RIFF header up to the sample data
+const HEADER_LENGTH: u32 = 44;
+
+/// Writes 16-bit PCM WAV files, either stereo or mono (left side only, as
+/// used by the per-channel stems)
+#[derive(Debug)]
+pub struct WavWriter {
+    file: BufWriter<File>,
+    channels: u16,
+    /// Bytes of sample data written so far
+    length: u32,
+}
+
+impl WavWriter {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32, channels: u16) -> WavWriter {
+        if channels != 1 && channels != 2 {
+            panic!("WAV files are written either mono or stereo");
+        }
+        let file = File::create(path).expect("WAV file is not writable");
+        let mut writer = WavWriter {
+            file: BufWriter::new(file),
+            channels,
+            length: 0,
+        };
+        writer.write_header(sample_rate);
+        writer
+    }
+
+    fn write_header(&mut self, sample_rate: u32) {
+        let block_align = self.channels * 2;
+        let mut header = Vec::with_capacity(HEADER_LENGTH as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(HEADER_LENGTH - 8).to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        // PCM
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&self.channels.to_le_bytes());
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        self.file
+            .write_all(&header)
+            .expect("WAV file is not writable");
+    }
+}
+
+impl AudioSink for WavWriter {
+    fn write(&mut self, frames: &[Frame]) {
+        let channels = self.channels as usize;
+        let mut data = Vec::with_capacity(frames.len() * channels * 2);
+        for frame in frames {
+            for sample in frame.to_i16().iter().take(channels) {
+                data.extend_from_slice(&sample.to_le_bytes());
+            }
+        }
+        self.file
+            .write_all(&data)
+            .expect("WAV file is not writable");
+        self.length += data.len() as u32;
+    }
+
+    /// Fills in the chunk sizes, unknown until now
+    fn finish(&mut self) {
+        let file = &mut self.file;
+        let sizes = [(4, HEADER_LENGTH - 8 + self.length), (40, self.length)];
+        for (offset, size) in sizes.iter() {
+            file.seek(SeekFrom::Start(*offset))
+                .and_then(|_| file.write_all(&size.to_le_bytes()))
+                .expect("WAV file is not writable");
+        }
+        file.flush().expect("WAV file is not writable");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_wav_file() {
+        let path = std::env::temp_dir().join("gbc_test_wav_file.wav");
+        let mut writer = WavWriter::create(&path, 48_000, 2);
+        let frame = Frame {
+            left: 0.5,
+            right: -1.0,
+        };
+        writer.write(&[frame, frame, frame]);
+        writer.finish();
+
+        let wav = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(wav.len(), 44 + 12);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(wav[4..8], 48u32.to_le_bytes());
+        assert_eq!(wav[22..24], 2u16.to_le_bytes());
+        assert_eq!(wav[24..28], 48_000u32.to_le_bytes());
+        assert_eq!(wav[40..44], 12u32.to_le_bytes());
+        assert_eq!(wav[44..48], [0xFF, 0x3F, 0x01, 0x80]);
+    }
+
+    #[test]
+    fn test_mono_stem() {
+        let path = std::env::temp_dir().join("gbc_test_mono_stem.wav");
+        let mut writer = WavWriter::create(&path, 44_100, 1);
+        writer.write(&[Frame {
+            left: -0.5,
+            right: 1.0,
+        }]);
+        writer.finish();
+
+        let wav = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(wav[32..34], 2u16.to_le_bytes());
+        assert_eq!(wav[44..], [0x01, 0xC0]);
+    }
+}
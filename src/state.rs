@@ -0,0 +1,550 @@
+use crate::cpu::CPU;
+use crate::png;
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::ops::Range;
+
+/// Identifies save states
+const MAGIC: &[u8; 8] = b"GBCSTATE";
+/// Magic, version and checksum
+const HEADER_LENGTH: usize = 14;
+
+/// Version of the format written, to bump whenever the contents of a chunk
+/// change, with a migration from the previous version
+pub const VERSION: u16 = 1;
+
+/// Upgrades the chunks of a state from a version to the next
+pub type Migration = fn(&mut Vec<Chunk>) -> Result<(), String>;
+
+/// Migrations from each version to the next, starting from version 1
+const MIGRATIONS: [Migration; VERSION as usize - 1] = [];
+
+/// Section of a state, e.g. the CPU or the memory
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub tag: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+/// Writes the header, the CRC-32 of the chunks and the chunks, each as its
+/// tag, its length and its data
+pub fn encode(chunks: &[Chunk]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for chunk in chunks {
+        body.extend_from_slice(&chunk.tag);
+        body.extend_from_slice(&(chunk.data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&chunk.data);
+    }
+    let mut state = Vec::with_capacity(HEADER_LENGTH + body.len());
+    state.extend_from_slice(MAGIC);
+    state.extend_from_slice(&VERSION.to_le_bytes());
+    state.extend_from_slice(&png::crc32(&body).to_le_bytes());
+    state.extend_from_slice(&body);
+    state
+}
+
+/// Checks a state and splits it into chunks, migrated to the current version
+pub fn decode(state: &[u8]) -> Result<Vec<Chunk>, String> {
+    let chunks = decode_chunks(state)?;
+    let version = u16::from_le_bytes([state[8], state[9]]);
+    migrate(chunks, version, &MIGRATIONS)
+}
+
+fn decode_chunks(state: &[u8]) -> Result<Vec<Chunk>, String> {
+    if state.len() < HEADER_LENGTH || &state[..8] != MAGIC {
+        return Err(String::from("Not a save state"));
+    }
+    let body = &state[HEADER_LENGTH..];
+    let checksum = u32::from_le_bytes([state[10], state[11], state[12], state[13]]);
+    if png::crc32(body) != checksum {
+        return Err(String::from(
+            "Save state is corrupted, its checksum does not match",
+        ));
+    }
+    let mut reader = Reader::new(body);
+    let mut chunks = Vec::new();
+    while !reader.data.is_empty() {
+        let mut tag = [0; 4];
+        tag.load(&mut reader)?;
+        let length = u32::read(&mut reader)? as usize;
+        let data = reader.take(length)?.to_vec();
+        chunks.push(Chunk { tag, data });
+    }
+    Ok(chunks)
+}
+
+fn migrate(
+    mut chunks: Vec<Chunk>,
+    version: u16,
+    migrations: &[Migration],
+) -> Result<Vec<Chunk>, String> {
+    let current = migrations.len() as u16 + 1;
+    if version == 0 || version > current {
+        return Err(format!(
+            "Save state version {} is not supported, {} at most",
+            version, current
+        ));
+    }
+    for migration in &migrations[version as usize - 1..] {
+        migration(&mut chunks)?;
+    }
+    Ok(chunks)
+}
+
+/// Data of the chunk tagged `tag`
+pub fn chunk<'a>(chunks: &'a [Chunk], tag: &[u8; 4]) -> Result<&'a [u8], String> {
+    chunks
+        .iter()
+        .find(|chunk| &chunk.tag == tag)
+        .map(|chunk| chunk.data.as_slice())
+        .ok_or_else(|| format!("Save state has no {} chunk", String::from_utf8_lossy(tag)))
+}
+
+/// Serializes a value with `save`
+fn save_chunk<T: Snapshot + ?Sized>(tag: &[u8; 4], value: &T) -> Chunk {
+    let mut writer = Writer::new();
+    value.save(&mut writer);
+    Chunk {
+        tag: *tag,
+        data: writer.data,
+    }
+}
+
+/// Restores a value with `load`, which has to use up the whole chunk
+fn load_chunk<T: Snapshot + ?Sized>(
+    chunks: &[Chunk],
+    tag: &[u8; 4],
+    value: &mut T,
+) -> Result<(), String> {
+    let mut reader = Reader::new(chunk(chunks, tag)?);
+    value.load(&mut reader)?;
+    if !reader.data.is_empty() {
+        return Err(format!(
+            "Save state {} chunk is too long",
+            String::from_utf8_lossy(tag)
+        ));
+    }
+    Ok(())
+}
+
+/// Saves the CPU and the memory, along with the CRC-32 of the ROM they run,
+/// to check it is the same one when loading, and a thumbnail of the screen.
+/// The registers of the bank controller are saved apart from the memory,
+/// which holds the cartridge RAM.
+pub fn save(cpu: &CPU) -> Vec<u8> {
+    let memory = cpu.memory();
+    let rom = png::crc32(memory.rom());
+    encode(&[
+        save_chunk(b"ROM ", &rom),
+        save_chunk(b"THMB", &thumbnail_png(memory.frame())),
+        save_chunk(b"CPU ", cpu),
+        save_chunk(b"MEM ", memory),
+        save_chunk(b"MBC ", memory.mbc()),
+    ])
+}
+
+/// Restores a state saved with `save`. The CPU is left as it was if the
+/// state is invalid or was saved from another ROM.
+pub fn load(cpu: &mut CPU, state: &[u8]) -> Result<(), String> {
+    let chunks = decode(state)?;
+    let mut rom = 0u32;
+    load_chunk(&chunks, b"ROM ", &mut rom)?;
+    if rom != png::crc32(cpu.memory().rom()) {
+        return Err(String::from("Save state is from another ROM"));
+    }
+    // Saved first, so that a failure does not leave it half loaded
+    let backup = capture(cpu);
+    let result = load_chunk(&chunks, b"CPU ", cpu)
+        .and_then(|_| load_chunk(&chunks, b"MEM ", cpu.memory_mut()))
+        .and_then(|_| load_chunk(&chunks, b"MBC ", cpu.memory_mut().mbc_mut()))
+        .and_then(|_| cpu.memory().validate());
+    if result.is_err() {
+        restore(cpu, &backup).expect("State saved could not be restored");
+    }
+    result
+}
+
+/// The CPU and memory alone, without the header, ROM check or thumbnail, for
+/// states kept in memory by the same emulator
+pub fn capture(cpu: &CPU) -> Vec<u8> {
+    let mut writer = Writer::new();
+    cpu.save(&mut writer);
+    cpu.memory().save(&mut writer);
+    cpu.memory().mbc().save(&mut writer);
+    writer.data
+}
+
+/// Restores what `capture` took
+pub fn restore(cpu: &mut CPU, snapshot: &[u8]) -> Result<(), String> {
+    let mut reader = Reader::new(snapshot);
+    cpu.load(&mut reader)?;
+    cpu.memory_mut().load(&mut reader)?;
+    cpu.memory_mut().mbc_mut().load(&mut reader)?;
+    if !reader.data.is_empty() {
+        return Err(String::from("Snapshot is too long"));
+    }
+    cpu.memory().validate()
+}
+
+/// Thumbnail of the screen saved in a state, as a PNG
+pub fn thumbnail(state: &[u8]) -> Result<Vec<u8>, String> {
+    let chunks = decode(state)?;
+    let mut png = Vec::new();
+    load_chunk(&chunks, b"THMB", &mut png)?;
+    Ok(png)
+}
+
+/// Screen at half size as a PNG, averaging blocks of 2x2 pixels
+fn thumbnail_png(frame: &[u8]) -> Vec<u8> {
+    let (width, height) = (SCREEN_WIDTH / 2, SCREEN_HEIGHT / 2);
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            for channel in 0..4 {
+                let pixel = |dx: usize, dy: usize| {
+                    frame[((y * 2 + dy) * SCREEN_WIDTH + x * 2 + dx) * 4 + channel] as u16
+                };
+                let sum = pixel(0, 0) + pixel(1, 0) + pixel(0, 1) + pixel(1, 1);
+                rgba.push((sum / 4) as u8);
+            }
+        }
+    }
+    png::encode(width as u32, height as u32, &rgba)
+}
+
+/// Little-endian serialization of the values saved
+pub struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer { data: Vec::new() }
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl Default for Writer {
+    fn default() -> Writer {
+        Writer::new()
+    }
+}
+
+/// Reads back what a `Writer` wrote
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        if length > self.data.len() {
+            return Err(String::from("Save state is truncated"));
+        }
+        let (taken, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(taken)
+    }
+}
+
+/// State that can be saved and loaded back in place. Loading reads the
+/// fields in the order they were saved.
+pub trait Snapshot {
+    fn save(&self, writer: &mut Writer);
+
+    fn load(&mut self, reader: &mut Reader) -> Result<(), String>;
+}
+
+/// Rejects a value loaded out of `valid`, e.g. an index which would panic on
+/// the next access
+pub fn check(name: &str, value: usize, valid: Range<usize>) -> Result<(), String> {
+    if valid.contains(&value) {
+        Ok(())
+    } else {
+        Err(format!("Invalid {} {} in save state", name, value))
+    }
+}
+
+/// Implements `Snapshot` for a struct by saving and loading the fields
+/// listed, in order. Fields left out, e.g. host outputs, are kept on load.
+macro_rules! snapshot {
+    ($type:ty { $($field:ident),* $(,)? }) => {
+        impl $crate::state::Snapshot for $type {
+            fn save(&self, writer: &mut $crate::state::Writer) {
+                $($crate::state::Snapshot::save(&self.$field, writer);)*
+            }
+
+            fn load(&mut self, reader: &mut $crate::state::Reader) -> Result<(), String> {
+                $($crate::state::Snapshot::load(&mut self.$field, reader)?;)*
+                Ok(())
+            }
+        }
+    };
+}
+
+pub(crate) use snapshot;
+
+/// Snapshots of values that can be read on their own
+trait Value: Sized {
+    fn read(reader: &mut Reader) -> Result<Self, String>;
+}
+
+macro_rules! integer {
+    ($type:ty) => {
+        impl Value for $type {
+            fn read(reader: &mut Reader) -> Result<$type, String> {
+                const LENGTH: usize = std::mem::size_of::<$type>();
+                let mut bytes = [0; LENGTH];
+                bytes.copy_from_slice(reader.take(LENGTH)?);
+                Ok(<$type>::from_le_bytes(bytes))
+            }
+        }
+
+        impl Snapshot for $type {
+            fn save(&self, writer: &mut Writer) {
+                writer.data.extend_from_slice(&self.to_le_bytes());
+            }
+
+            fn load(&mut self, reader: &mut Reader) -> Result<(), String> {
+                *self = <$type>::read(reader)?;
+                Ok(())
+            }
+        }
+    };
+}
+
+integer!(u8);
+integer!(u16);
+integer!(u32);
+integer!(u64);
+
+impl Snapshot for bool {
+    fn save(&self, writer: &mut Writer) {
+        (*self as u8).save(writer);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> Result<(), String> {
+        *self = match u8::read(reader)? {
+            0 => false,
+            1 => true,
+            value => return Err(format!("Invalid boolean {} in save state", value)),
+        };
+        Ok(())
+    }
+}
+
+/// Saved as 64 bits wide, whatever the platform
+impl Snapshot for usize {
+    fn save(&self, writer: &mut Writer) {
+        (*self as u64).save(writer);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> Result<(), String> {
+        *self = u64::read(reader)? as usize;
+        Ok(())
+    }
+}
+
+impl<T: Snapshot, const N: usize> Snapshot for [T; N] {
+    fn save(&self, writer: &mut Writer) {
+        for value in self {
+            value.save(writer);
+        }
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> Result<(), String> {
+        for value in self {
+            value.load(reader)?;
+        }
+        Ok(())
+    }
+}
+
+/// Saved with its length, as it may change
+impl<T: Snapshot + Default> Snapshot for Vec<T> {
+    fn save(&self, writer: &mut Writer) {
+        (self.len() as u32).save(writer);
+        for value in self {
+            value.save(writer);
+        }
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> Result<(), String> {
+        let length = u32::read(reader)? as usize;
+        // Every value takes at least a byte
+        if length > reader.data.len() {
+            return Err(String::from("Save state is truncated"));
+        }
+        self.clear();
+        self.resize_with(length, T::default);
+        for value in self.iter_mut() {
+            value.load(reader)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Snapshot + Default> Snapshot for Option<T> {
+    fn save(&self, writer: &mut Writer) {
+        self.is_some().save(writer);
+        if let Some(value) = self {
+            value.save(writer);
+        }
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> Result<(), String> {
+        let mut some = false;
+        some.load(reader)?;
+        *self = if some {
+            let mut value = self.take().unwrap_or_default();
+            value.load(reader)?;
+            Some(value)
+        } else {
+            None
+        };
+        Ok(())
+    }
+}
+
+impl<T: Snapshot + ?Sized> Snapshot for Box<T> {
+    fn save(&self, writer: &mut Writer) {
+        (**self).save(writer);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> Result<(), String> {
+        (**self).load(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::tests::cpu_with_program;
+
+    /// CPU running a loop that counts into 0xC000 and plays a note, followed
+    /// by `rom_byte` to tell ROMs apart
+    fn cpu(rom_byte: u8) -> CPU {
+        // LD A,$80; LDH [$26],A; LDH [$12],A; LDH [$14],A
+        // loop: LD HL,$C000; INC [HL]; JR loop
+        cpu_with_program(&[
+            0x3E, 0x80, 0xE0, 0x26, 0xE0, 0x12, 0xE0, 0x14, 0x21, 0x00, 0xC0, 0x34, 0x18, 0xFA,
+            rom_byte,
+        ])
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut cpu = cpu(0);
+        cpu.run_frame();
+        let state = save(&cpu);
+        cpu.run_frame();
+        let expected = save(&cpu);
+        let pc = cpu.pc();
+
+        let mut other = self::cpu(0);
+        load(&mut other, &state).unwrap();
+        assert_eq!(save(&other), state);
+        other.run_frame();
+        assert_eq!(other.pc(), pc);
+        assert_eq!(save(&other), expected);
+    }
+
+    #[test]
+    fn test_invalid_states() {
+        let mut cpu = cpu(0);
+        cpu.run_frame();
+        let state = save(&cpu);
+        let before = save(&cpu);
+        assert_eq!(
+            load(&mut cpu, b"GBCSTATE"),
+            Err(String::from("Not a save state"))
+        );
+        let mut corrupted = state.clone();
+        *corrupted.last_mut().unwrap() ^= 0x01;
+        assert!(load(&mut cpu, &corrupted).unwrap_err().contains("checksum"));
+        let mut newer = state.clone();
+        newer[8] = VERSION as u8 + 1;
+        assert!(load(&mut cpu, &newer)
+            .unwrap_err()
+            .contains("not supported"));
+        assert_eq!(
+            load(&mut self::cpu(1), &state),
+            Err(String::from("Save state is from another ROM"))
+        );
+
+        // A chunk cut short is caught after part of the CPU was loaded
+        let mut chunks = decode(&state).unwrap();
+        let memory = chunks
+            .iter_mut()
+            .find(|chunk| &chunk.tag == b"MEM ")
+            .unwrap();
+        memory.data.truncate(100);
+        let truncated = encode(&chunks);
+        let mut other = self::cpu(0);
+        let fresh = save(&other);
+        assert_eq!(
+            load(&mut other, &truncated),
+            Err(String::from("Save state is truncated"))
+        );
+        assert_eq!(save(&other), fresh);
+        assert_eq!(save(&cpu), before);
+    }
+
+    #[test]
+    fn test_mbc_registers() {
+        // Cartridge type at 0x0147: MBC1 with RAM and battery
+        let mut header = [0x00; 0x48];
+        header[0x47] = 0x03;
+        let mut cpu = cpu_with_program(&header);
+        let memory = cpu.memory_mut();
+        memory.write(0x0000, 0x0A);
+        memory.write(0x6000, 0x01);
+        memory.write(0x4000, 0x01);
+        memory.write(0xA000, 0x42);
+        let state = save(&cpu);
+
+        let mut other = cpu_with_program(&header);
+        load(&mut other, &state).unwrap();
+        assert_eq!(other.memory().read(0xA000), 0x42);
+        other.memory_mut().write(0x4000, 0x00);
+        assert_eq!(other.memory().read(0xA000), 0x00);
+    }
+
+    #[test]
+    fn test_migrations() {
+        let chunks = vec![Chunk {
+            tag: *b"CPU ",
+            data: vec![1],
+        }];
+        let migrations: [Migration; 2] = [
+            |chunks| {
+                chunks[0].data.push(2);
+                Ok(())
+            },
+            |chunks| {
+                chunks[0].tag = *b"CPU2";
+                Ok(())
+            },
+        ];
+        let migrated = migrate(chunks.clone(), 1, &migrations).unwrap();
+        assert_eq!(migrated[0].data, [1, 2]);
+        assert_eq!(&migrated[0].tag, b"CPU2");
+        let migrated = migrate(chunks.clone(), 2, &migrations).unwrap();
+        assert_eq!(migrated[0].data, [1]);
+        assert_eq!(migrate(chunks.clone(), 3, &migrations).unwrap(), chunks);
+        assert!(migrate(chunks, 4, &migrations).is_err());
+    }
+
+    #[test]
+    fn test_thumbnail() {
+        let mut cpu = cpu(0);
+        cpu.run_frame();
+        let image = png::decode(&thumbnail(&save(&cpu)).unwrap()).unwrap();
+        assert_eq!((image.width, image.height), (80, 72));
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Start of each memory region, labels only extend to the end of theirs
+const REGIONS: [u16; 12] = [
+    0x0000, 0x4000, 0x8000, 0xA000, 0xC000, 0xD000, 0xE000, 0xFE00, 0xFEA0, 0xFF00, 0xFF80, 0xFFFF,
+];
+
+fn region(address: u16) -> u16 {
+    REGIONS
+        .iter()
+        .copied()
+        .take_while(|start| *start <= address)
+        .last()
+        .unwrap_or(0)
+}
+
+/// Labels from the symbol files rgblink writes, `.sym` with `-n` or `.map`
+/// with `-m`. Banks only tell ROM labels apart, RAM labels are looked up
+/// whatever the bank mapped.
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    /// By address then bank, the first label defined there
+    labels: BTreeMap<(u16, u16), String>,
+    /// Bank and address of each label
+    addresses: HashMap<String, (u16, u16)>,
+}
+
+impl Symbols {
+    /// Reads a `.map` file, or else a `.sym` file
+    pub fn load(path: &Path) -> io::Result<Symbols> {
+        let text = fs::read_to_string(path)?;
+        if path.extension().is_some_and(|extension| extension == "map") {
+            Ok(Symbols::parse_map(&text))
+        } else {
+            Ok(Symbols::parse_sym(&text))
+        }
+    }
+
+    /// Parses `BB:AAAA Label` lines, skipping comments and anything else
+    pub fn parse_sym(text: &str) -> Symbols {
+        let mut symbols = Symbols::default();
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap_or("");
+            let (location, name) = match line.trim().split_once(char::is_whitespace) {
+                Some(symbol) => symbol,
+                None => continue,
+            };
+            let (bank, address) = match location.split_once(':') {
+                Some(location) => location,
+                None => continue,
+            };
+            if let (Ok(bank), Ok(address)) = (
+                u16::from_str_radix(bank, 16),
+                u16::from_str_radix(address, 16),
+            ) {
+                symbols.insert(bank, address, name.trim());
+            }
+        }
+        symbols
+    }
+
+    /// Parses the `$AAAA = Label` lines under each `... bank #N:` heading
+    pub fn parse_map(text: &str) -> Symbols {
+        let mut symbols = Symbols::default();
+        let mut bank = 0;
+        for line in text.lines() {
+            let line = line.trim();
+            let heading = line.to_ascii_lowercase();
+            if let Some((_, number)) = heading.split_once("bank #") {
+                let digits: String = number.chars().take_while(char::is_ascii_digit).collect();
+                bank = digits.parse().unwrap_or(0);
+                continue;
+            }
+            let symbol = line
+                .strip_prefix('$')
+                .and_then(|symbol| symbol.split_once(" = "));
+            if let Some((address, name)) = symbol {
+                if let Ok(address) = u16::from_str_radix(address.trim(), 16) {
+                    symbols.insert(bank, address, name.trim());
+                }
+            }
+        }
+        symbols
+    }
+
+    fn insert(&mut self, bank: u16, address: u16, name: &str) {
+        if name.is_empty() {
+            return;
+        }
+        self.labels
+            .entry((address, bank))
+            .or_insert_with(|| name.to_string());
+        self.addresses
+            .entry(name.to_string())
+            .or_insert((bank, address));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// Address of a label, or of `label+offset`
+    pub fn address(&self, text: &str) -> Option<u16> {
+        let (name, offset) = match text.split_once('+') {
+            Some((name, offset)) => (name.trim(), parse_offset(offset.trim())?),
+            None => (text.trim(), 0),
+        };
+        let (_, address) = self.addresses.get(name)?;
+        Some(address.wrapping_add(offset))
+    }
+
+    /// Label defined exactly at an address, in ROM bank `bank` if it is ROM
+    pub fn label_at(&self, bank: u16, address: u16) -> Option<&str> {
+        self.nearest(bank, address)
+            .filter(|(_, start, _)| *start == address)
+            .map(|(_, _, name)| name)
+    }
+
+    /// `bank:label+offset` for the closest label at or before an address,
+    /// in ROM bank `bank` if it is ROM
+    pub fn label(&self, bank: u16, address: u16) -> Option<String> {
+        let (bank, start, name) = self.nearest(bank, address)?;
+        let offset = address - start;
+        if offset == 0 {
+            Some(format!("{:02X}:{}", bank, name))
+        } else {
+            Some(format!("{:02X}:{}+{}", bank, name, offset))
+        }
+    }
+
+    /// Bank, address and name of the closest label at or before an address
+    fn nearest(&self, bank: u16, address: u16) -> Option<(u16, u16, &str)> {
+        let start = region(address);
+        self.labels
+            .range((start, 0)..=(address, u16::MAX))
+            .rev()
+            .find(|((_, label_bank), _)| address >= 0x8000 || *label_bank == bank)
+            .map(|((address, bank), name)| (*bank, *address, name.as_str()))
+    }
+}
+
+/// Offsets are decimal, or hex with a 0x or $ prefix
+fn parse_offset(text: &str) -> Option<u16> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix('$')) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYM: &str = "; File generated by rgblink
+00:0150 Main
+00:0153 Main.loop
+01:4000 Data
+00:C000 wCounter
+01:D000 wBuffer
+";
+
+    #[test]
+    fn test_sym() {
+        let symbols = Symbols::parse_sym(SYM);
+        assert_eq!(symbols.address("Main.loop"), Some(0x0153));
+        assert_eq!(symbols.address("Main + $2"), Some(0x0152));
+        assert_eq!(symbols.address("Missing"), None);
+        assert_eq!(symbols.label(0, 0x0150).as_deref(), Some("00:Main"));
+        assert_eq!(symbols.label(0, 0x0155).as_deref(), Some("00:Main.loop+2"));
+        assert_eq!(symbols.label(1, 0x4010).as_deref(), Some("01:Data+16"));
+        assert_eq!(symbols.label(2, 0x4010), None);
+        assert_eq!(symbols.label(0, 0x0100), None);
+        assert_eq!(symbols.label(0, 0x8000), None);
+        assert_eq!(symbols.label(0, 0xD004).as_deref(), Some("01:wBuffer+4"));
+        assert_eq!(symbols.label_at(0, 0x0153), Some("Main.loop"));
+        assert_eq!(symbols.label_at(0, 0x0154), None);
+    }
+
+    #[test]
+    fn test_map() {
+        let map = "SUMMARY:
+	ROM0: 340 bytes used / 16044 free
+
+ROM0 bank #0:
+	SECTION: $0150-$0163 ($0014 bytes) [\"Main\"]
+	         $0150 = Main
+	         $0153 = Main.loop
+	EMPTY: $0164-$3fff ($3e9c bytes)
+
+ROMX bank #1:
+	SECTION: $4000-$40ff ($0100 bytes) [\"Data\"]
+	         $4000 = Data
+";
+        let symbols = Symbols::parse_map(map);
+        assert_eq!(symbols.address("Main.loop"), Some(0x0153));
+        assert_eq!(symbols.label(1, 0x4001).as_deref(), Some("01:Data+1"));
+        assert_eq!(symbols.label(0, 0x4001), None);
+    }
+}
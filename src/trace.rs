@@ -0,0 +1,483 @@
+use crate::bus::Bus;
+use crate::cpu::Registers;
+use crate::symbols::Symbols;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+
+/// Last lines traced, shared so that they can be dumped on a crash
+#[derive(Clone)]
+pub struct Ring(Arc<Mutex<RingState>>);
+
+struct RingState {
+    lines: VecDeque<String>,
+    capacity: usize,
+    output: Box<dyn Write + Send>,
+}
+
+impl Ring {
+    pub fn new(capacity: usize, output: Box<dyn Write + Send>) -> Ring {
+        Ring(Arc::new(Mutex::new(RingState {
+            lines: VecDeque::with_capacity(capacity),
+            capacity,
+            output,
+        })))
+    }
+
+    fn push(&self, line: String) {
+        let mut ring = self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if ring.lines.len() == ring.capacity {
+            ring.lines.pop_front();
+        }
+        ring.lines.push_back(line);
+    }
+
+    /// Writes out the lines kept, oldest first
+    pub fn dump(&self) -> io::Result<()> {
+        let mut ring = self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let RingState { lines, output, .. } = &mut *ring;
+        for line in lines.drain(..) {
+            writeln!(output, "{}", line)?;
+        }
+        output.flush()
+    }
+}
+
+enum Output {
+    Stream(Box<dyn Write>),
+    Ring(Ring),
+}
+
+/// Logs the CPU state before each instruction in the Gameboy Doctor format,
+/// `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`,
+/// optionally only within an address range or ROM bank. With symbols, the
+/// label of PC follows as a comment.
+pub struct Tracer {
+    output: Output,
+    range: Option<(u16, u16)>,
+    bank: Option<u16>,
+    symbols: Symbols,
+}
+
+impl Tracer {
+    pub fn new(output: Box<dyn Write>) -> Tracer {
+        Tracer {
+            output: Output::Stream(output),
+            range: None,
+            bank: None,
+            symbols: Symbols::default(),
+        }
+    }
+
+    /// Keeps only the last lines, written out by `finish` or `Ring::dump`
+    pub fn ring(ring: Ring) -> Tracer {
+        Tracer {
+            output: Output::Ring(ring),
+            range: None,
+            bank: None,
+            symbols: Symbols::default(),
+        }
+    }
+
+    /// Traces only instructions from `start` to `end`, inclusive
+    pub fn set_range(&mut self, start: u16, end: u16) {
+        self.range = Some((start, end));
+    }
+
+    /// Traces only instructions in the ROM, from `bank`
+    pub fn set_bank(&mut self, bank: u16) {
+        self.bank = Some(bank);
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    pub fn trace<B: Bus>(&mut self, registers: &Registers, bus: &B) {
+        let pc = registers.pc;
+        if self
+            .range
+            .is_some_and(|(start, end)| pc < start || pc > end)
+        {
+            return;
+        }
+        if self
+            .bank
+            .is_some_and(|bank| pc >= 0x8000 || bus.rom_bank(pc) != bank)
+        {
+            return;
+        }
+        let [a, f] = registers.af.to_be_bytes();
+        let [b, c] = registers.bc.to_be_bytes();
+        let [d, e] = registers.de.to_be_bytes();
+        let [h, l] = registers.hl.to_be_bytes();
+        let memory: Vec<String> = (0..4)
+            .map(|offset| format!("{:02X}", bus.peek(pc.wrapping_add(offset))))
+            .collect();
+        let mut line = format!(
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{}",
+            a,
+            f,
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
+            registers.sp,
+            pc,
+            memory.join(",")
+        );
+        if let Some(label) = self.symbols.label(bus.rom_bank(pc), pc) {
+            line += &format!(" ; {}", label);
+        }
+        match &mut self.output {
+            Output::Stream(output) => writeln!(output, "{}", line).expect("Trace is not writable"),
+            Output::Ring(ring) => ring.push(line),
+        }
+    }
+
+    /// Flushes the trace, or writes out the lines kept in ring mode
+    pub fn finish(&mut self) {
+        match &mut self.output {
+            Output::Stream(output) => output.flush(),
+            Output::Ring(ring) => ring.dump(),
+        }
+        .expect("Trace is not writable")
+    }
+}
+
+/// Flags of F, by bit
+const FLAGS: [(u8, char); 4] = [(0x80, 'Z'), (0x40, 'N'), (0x20, 'H'), (0x10, 'C')];
+
+/// `name:value` fields of a trace line, up to a comment
+fn fields(line: &str) -> Vec<(&str, &str)> {
+    let line = line.split(';').next().unwrap_or("");
+    line.split_whitespace()
+        .map(|field| field.split_once(':').unwrap_or((field, "")))
+        .collect()
+}
+
+/// Names of the fields that differ between a trace line and the reference
+fn differences(line: &str, reference: &str) -> Vec<String> {
+    let (fields, reference) = (fields(line), fields(reference));
+    let value = |fields: &[(&str, &str)], name: &str| {
+        fields
+            .iter()
+            .find(|(other, _)| *other == name)
+            .map(|(_, value)| value.to_ascii_uppercase())
+    };
+    let mut names: Vec<String> = Vec::new();
+    for (name, _) in reference.iter().chain(&fields) {
+        let differs = value(&fields, name) != value(&reference, name);
+        if differs && !names.iter().any(|other| other == name) {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+/// Flags that differ between the F fields of a trace line and the reference
+fn flag_differences(line: &str, reference: &str) -> Vec<char> {
+    let flags = |line: &str| {
+        fields(line)
+            .iter()
+            .find(|(name, _)| *name == "F")
+            .and_then(|(_, value)| u8::from_str_radix(value, 16).ok())
+    };
+    match (flags(line), flags(reference)) {
+        (Some(flags), Some(expected)) => FLAGS
+            .iter()
+            .filter(|(bit, _)| (flags ^ expected) & bit != 0)
+            .map(|(_, flag)| *flag)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Carets under the fields named
+fn markers(line: &str, names: &[String]) -> String {
+    let mut markers = String::new();
+    for field in line.split(' ') {
+        let name = field.split(':').next().unwrap_or(field);
+        let marker = if names.iter().any(|other| other == name) {
+            '^'
+        } else {
+            ' '
+        };
+        markers.extend(std::iter::repeat_n(marker, field.len()));
+        markers.push(' ');
+    }
+    markers.trim_end().to_string()
+}
+
+/// Writes the divergent line of one side, marking the fields named, then the
+/// lines following it
+fn write_side<W: Write>(
+    output: &mut W,
+    title: &str,
+    first: &Option<String>,
+    lines: &mut impl Iterator<Item = io::Result<String>>,
+    number: usize,
+    context: usize,
+    names: &[String],
+) -> io::Result<()> {
+    writeln!(output, "{}:", title)?;
+    let first = match first {
+        Some(line) => line,
+        None => return writeln!(output, "  > {:>7}  (end of {})", number, title),
+    };
+    writeln!(output, "  > {:>7}  {}", number, first)?;
+    if !names.is_empty() {
+        writeln!(output, "             {}", markers(first, names))?;
+    }
+    for (offset, line) in lines.take(context).enumerate() {
+        writeln!(
+            output,
+            "    {:>7}  {}",
+            number + 1 + offset,
+            line?.trim_end()
+        )?;
+    }
+    Ok(())
+}
+
+/// Compares a trace against a reference log, both in the Gameboy Doctor
+/// format, and writes out the first instruction at which they diverge, with
+/// `context` lines around it and the registers or flags that differ. Returns
+/// whether they match.
+pub fn diff<T: BufRead, R: BufRead, W: Write>(
+    trace: T,
+    reference: R,
+    context: usize,
+    output: &mut W,
+) -> io::Result<bool> {
+    let (mut trace, mut reference) = (trace.lines(), reference.lines());
+    let mut before: VecDeque<String> = VecDeque::with_capacity(context + 1);
+    let mut number = 0;
+    loop {
+        number += 1;
+        let line = trace
+            .next()
+            .transpose()?
+            .map(|line| line.trim_end().to_string());
+        let expected = reference
+            .next()
+            .transpose()?
+            .map(|line| line.trim_end().to_string());
+        let names = match (&line, &expected) {
+            (None, None) => {
+                writeln!(output, "Traces match, {} instructions", number - 1)?;
+                return Ok(true);
+            }
+            (Some(line), Some(expected)) => {
+                let names = differences(line, expected);
+                if names.is_empty() {
+                    before.push_back(line.clone());
+                    if before.len() > context {
+                        before.pop_front();
+                    }
+                    continue;
+                }
+                names
+            }
+            _ => Vec::new(),
+        };
+
+        writeln!(output, "First divergence at line {}", number)?;
+        writeln!(output)?;
+        for (offset, line) in before.iter().enumerate() {
+            writeln!(
+                output,
+                "    {:>7}  {}",
+                number - before.len() + offset,
+                line
+            )?;
+        }
+        write_side(output, "trace", &line, &mut trace, number, context, &names)?;
+        write_side(
+            output,
+            "reference",
+            &expected,
+            &mut reference,
+            number,
+            context,
+            &names,
+        )?;
+        writeln!(output)?;
+        let differs = match (&line, &expected) {
+            (Some(line), Some(expected)) => {
+                let flags = flag_differences(line, expected);
+                let names: Vec<String> = names
+                    .iter()
+                    .map(|name| match name.as_str() {
+                        "F" if !flags.is_empty() => {
+                            let flags: Vec<String> = flags.iter().map(char::to_string).collect();
+                            format!("F ({})", flags.join(" "))
+                        }
+                        _ => name.clone(),
+                    })
+                    .collect();
+                format!("Differs: {}", names.join(", "))
+            }
+            (None, _) => String::from("The trace ends first"),
+            (_, None) => String::from("The reference ends first"),
+        };
+        writeln!(output, "{}", differs)?;
+        return Ok(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::FlatBus;
+
+    /// Output that can still be read once handed to a tracer
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(data)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        fn lines(&self) -> Vec<String> {
+            let text = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+            text.lines().map(String::from).collect()
+        }
+    }
+
+    fn registers(pc: u16) -> Registers {
+        Registers {
+            af: 0x01B0,
+            bc: 0x0013,
+            de: 0x00D8,
+            hl: 0x014D,
+            sp: 0xFFFE,
+            pc,
+        }
+    }
+
+    #[test]
+    fn test_doctor_format() {
+        let bus = FlatBus::with_code(0x0100, &[0x00, 0xC3, 0x13, 0x02]);
+        let output = Shared::default();
+        let mut tracer = Tracer::new(Box::new(output.clone()));
+        tracer.trace(&registers(0x0100), &bus);
+        tracer.finish();
+        assert_eq!(
+            output.lines(),
+            ["A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02"]
+        );
+    }
+
+    #[test]
+    fn test_filters() {
+        let bus = FlatBus::init();
+        let output = Shared::default();
+        let mut tracer = Tracer::new(Box::new(output.clone()));
+        tracer.set_range(0x0100, 0x4FFF);
+        tracer.set_bank(1);
+        for &pc in &[0x0100, 0x4000, 0x4FFF, 0x5000, 0xC000] {
+            tracer.trace(&registers(pc), &bus);
+        }
+        let pcs: Vec<String> = output
+            .lines()
+            .iter()
+            .map(|line| line.split(' ').nth(9).unwrap().to_string())
+            .collect();
+        assert_eq!(pcs, ["PC:4000", "PC:4FFF"]);
+    }
+
+    #[test]
+    fn test_symbols() {
+        let bus = FlatBus::init();
+        let output = Shared::default();
+        let mut tracer = Tracer::new(Box::new(output.clone()));
+        tracer.set_symbols(Symbols::parse_sym("00:0100 Start"));
+        tracer.trace(&registers(0x0102), &bus);
+        tracer.trace(&registers(0x00FF), &bus);
+        let lines = output.lines();
+        assert!(lines[0].ends_with("PCMEM:00,00,00,00 ; 00:Start+2"));
+        assert!(lines[1].ends_with("PCMEM:00,00,00,00"));
+        let (matched, _) = diff_lines(&[&lines[0]], &[&lines[0][..lines[0].len() - 13]]);
+        assert!(matched);
+    }
+
+    #[test]
+    fn test_ring() {
+        let bus = FlatBus::init();
+        let output = Shared::default();
+        let ring = Ring::new(2, Box::new(output.clone()));
+        let mut tracer = Tracer::ring(ring.clone());
+        for pc in 0..5 {
+            tracer.trace(&registers(pc), &bus);
+        }
+        assert!(output.lines().is_empty());
+        ring.dump().unwrap();
+        let pcs: Vec<String> = output
+            .lines()
+            .iter()
+            .map(|line| line.split(' ').nth(9).unwrap().to_string())
+            .collect();
+        assert_eq!(pcs, ["PC:0003", "PC:0004"]);
+    }
+
+    const LINE: &str = "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02";
+
+    fn diff_lines(trace: &[&str], reference: &[&str]) -> (bool, Vec<String>) {
+        let (trace, reference) = (trace.join("\n"), reference.join("\r\n"));
+        let mut output = Vec::new();
+        let matched = diff(trace.as_bytes(), reference.as_bytes(), 1, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        (matched, output.lines().map(String::from).collect())
+    }
+
+    #[test]
+    fn test_diff_match() {
+        let lowercase = LINE.replace("D8", "d8");
+        let (matched, output) = diff_lines(&[LINE, LINE], &[LINE, &lowercase]);
+        assert!(matched);
+        assert_eq!(output, ["Traces match, 2 instructions"]);
+    }
+
+    #[test]
+    fn test_diff_registers_and_flags() {
+        let divergent = LINE.replace("F:B0", "F:20").replace("H:01", "H:02");
+        let (matched, output) =
+            diff_lines(&[LINE, LINE, &divergent, LINE], &[LINE, LINE, LINE, LINE]);
+        assert!(!matched);
+        let markers = format!("{}^^^^ {}^^^^", " ".repeat(18), " ".repeat(20));
+        assert_eq!(output[0], "First divergence at line 3");
+        assert_eq!(output[2], format!("          2  {}", LINE));
+        assert_eq!(output[3], "trace:");
+        assert_eq!(output[4], format!("  >       3  {}", divergent));
+        assert_eq!(output[5], markers);
+        assert_eq!(output[6], format!("          4  {}", LINE));
+        assert_eq!(output[7], "reference:");
+        assert_eq!(output[8], format!("  >       3  {}", LINE));
+        assert_eq!(output[9], markers);
+        assert_eq!(output.last().unwrap(), "Differs: F (Z C), H");
+    }
+
+    #[test]
+    fn test_diff_end() {
+        let (matched, output) = diff_lines(&[LINE], &[LINE, LINE]);
+        assert!(!matched);
+        assert_eq!(output[4], "  >       2  (end of trace)");
+        assert_eq!(output.last().unwrap(), "The trace ends first");
+    }
+}
@@ -0,0 +1,298 @@
+//! Conformance harness running test ROM suites headlessly.
+//!
+//! ROMs are discovered under `$GBC_TEST_ROMS`, or `test/roms` by default,
+//! and judged by the protocol of the suite they belong to:
+//!
+//! - `blargg/**.gb`: passes once "Passed" is sent over the serial port
+//! - `mooneye/**.gb`: passes when reaching `LD B, B` with the Fibonacci
+//!   numbers 3, 5, 8, 13, 21, 34 in B, C, D, E, H, L
+//! - `**/*acid2*.gb`: passes when the frame shown on reaching `LD B, B`
+//!   matches the reference image next to the ROM, `<rom>.png`
+//!
+//! Unsupported cartridges, over 32 KB or with a mapper other than MBC1, MBC3
+//! and MBC5, are skipped since only 32 KB of ROM is loaded. This leaves out
+//! e.g. the combined `cpu_instrs.gb` and the mooneye MBC tests, whose
+//! individual ROMs run instead.
+//!
+//! The results are written to `test/conformance.md`, and a ROM that passed
+//! there before but fails now is reported as a regression. The harness does
+//! nothing when there are no ROMs, run it with
+//! `cargo test --release --test conformance -- --nocapture`.
+
+use gbc::cpu::CPU;
+use gbc::memory::Memory;
+use gbc::model::Model;
+use gbc::png;
+use gbc::runner::{self, Exit, Until};
+use gbc::sound::resampler::APU_CLOCK;
+use gbc::_32KB;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+
+const REPORT: &str = "test/conformance.md";
+/// Emulated time each suite gets before a ROM times out
+const BLARGG_SECONDS: u64 = 60;
+const MOONEYE_SECONDS: u64 = 20;
+const ACID2_SECONDS: u64 = 10;
+/// Registers mooneye test ROMs pass with, in BC, DE and HL
+const FIBONACCI: [u16; 3] = [0x0305, 0x080D, 0x1522];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Suite {
+    Blargg,
+    Mooneye,
+    Acid2,
+}
+
+struct Outcome {
+    rom: String,
+    passed: bool,
+    detail: String,
+}
+
+fn find_roms(directory: &Path, roms: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            find_roms(&path, roms);
+        } else if matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("gb" | "gbc")
+        ) {
+            roms.push(path);
+        }
+    }
+}
+
+/// Whether a ROM is over 32 KB or has a mapper that is not emulated. The
+/// cartridge types emulated are ROM only, ROM with RAM, MBC1, MBC3 and MBC5.
+fn unsupported(rom: &Path) -> bool {
+    match fs::read(rom) {
+        Ok(data) => {
+            data.len() > _32KB
+                || !matches!(
+                    data.get(0x0147),
+                    Some(0x00..=0x03 | 0x08..=0x09 | 0x0F..=0x13 | 0x19..=0x1E)
+                )
+        }
+        Err(_) => false,
+    }
+}
+
+fn suite(root: &Path, rom: &Path) -> Option<Suite> {
+    let relative = rom
+        .strip_prefix(root)
+        .ok()?
+        .to_string_lossy()
+        .to_lowercase();
+    if rom.file_stem()?.to_string_lossy().contains("acid2") {
+        Some(Suite::Acid2)
+    } else if relative.starts_with("blargg") {
+        Some(Suite::Blargg)
+    } else if relative.starts_with("mooneye") {
+        Some(Suite::Mooneye)
+    } else {
+        None
+    }
+}
+
+/// Model a mooneye ROM targets, from the suffix of its name, e.g.
+/// `boot_regs-cgb` or `di_timing-GS`
+fn mooneye_model(rom: &Path) -> Option<Model> {
+    let stem = rom.file_stem()?.to_string_lossy().into_owned();
+    let suffix = stem.rsplit('-').next()?;
+    match suffix {
+        "C" | "cgb" | "cgbABCDE" | "cgb0" => Some(Model::CGB),
+        "A" | "agb" => Some(Model::AGB),
+        "S" | "sgb" => Some(Model::SGB),
+        "sgb2" => Some(Model::SGB2),
+        "mgb" => Some(Model::MGB),
+        "dmg0" => Some(Model::DMG0),
+        _ => None,
+    }
+}
+
+fn run_rom(suite: Suite, rom: &Path) -> Result<String, String> {
+    let data = fs::read(rom).map_err(|error| error.to_string())?;
+    let mut program = [0; _32KB];
+    program[..data.len()].copy_from_slice(&data);
+    let memory = match suite {
+        Suite::Mooneye => match mooneye_model(rom) {
+            Some(model) => Memory::with_model(program, model),
+            None => Memory::new(program),
+        },
+        _ => Memory::new(program),
+    };
+    let mut cpu = CPU::new(memory);
+
+    let seconds = match suite {
+        Suite::Blargg => BLARGG_SECONDS,
+        Suite::Mooneye => MOONEYE_SECONDS,
+        Suite::Acid2 => ACID2_SECONDS,
+    };
+    let until = Until {
+        cycles: Some(seconds * APU_CLOCK as u64),
+        serial: Some(String::from("Passed")).filter(|_| suite == Suite::Blargg),
+        infinite_loop: suite == Suite::Blargg,
+        breakpoint: suite != Suite::Blargg,
+        ..Until::default()
+    };
+    let exit = runner::run(&mut cpu, &until, |_, _| {});
+
+    match suite {
+        Suite::Blargg if exit == Exit::Serial => Ok(String::new()),
+        Suite::Blargg => {
+            let output = String::from_utf8_lossy(cpu.memory().serial_output());
+            let last_line = output.trim().lines().last().unwrap_or("").to_string();
+            Err(format!("{:?}: {}", exit, last_line))
+        }
+        _ if exit != Exit::Breakpoint => Err(format!("{:?}", exit)),
+        Suite::Mooneye => {
+            let registers = cpu.registers();
+            let actual = [registers.bc, registers.de, registers.hl];
+            if actual == FIBONACCI {
+                Ok(String::new())
+            } else {
+                Err(format!(
+                    "BC:{:04X} DE:{:04X} HL:{:04X}",
+                    actual[0], actual[1], actual[2]
+                ))
+            }
+        }
+        Suite::Acid2 => {
+            let reference = png::read(rom.with_extension("png"))
+                .map_err(|error| format!("no reference image: {}", error))?;
+            let frame = cpu.memory().frame();
+            if reference.rgba.len() != frame.len() {
+                return Err(String::from("reference image size differs"));
+            }
+            let different = reference
+                .rgba
+                .chunks(4)
+                .zip(frame.chunks(4))
+                .filter(|(expected, actual)| expected[0..3] != actual[0..3])
+                .count();
+            if different == 0 {
+                Ok(String::new())
+            } else {
+                Err(format!("{} pixels differ", different))
+            }
+        }
+    }
+}
+
+/// Results of the previous run, by ROM
+fn previous_results() -> HashMap<String, bool> {
+    let report = fs::read_to_string(REPORT).unwrap_or_default();
+    report
+        .lines()
+        .filter_map(|line| {
+            let cells: Vec<&str> = line.split('|').map(str::trim).collect();
+            match cells.as_slice() {
+                ["", rom, result, ..] if *result == "pass" || *result == "FAIL" => {
+                    Some((rom.trim_matches('`').to_string(), *result == "pass"))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn write_report(outcomes: &[Outcome], skipped: usize) {
+    let passed = outcomes.iter().filter(|outcome| outcome.passed).count();
+    let mut report = format!(
+        "# Conformance\n\n{} / {} test ROMs pass, {} unsupported are skipped.\n\n| ROM | Result | Detail |\n| --- | --- | --- |\n",
+        passed,
+        outcomes.len(),
+        skipped
+    );
+    for outcome in outcomes {
+        let result = if outcome.passed { "pass" } else { "FAIL" };
+        report += &format!("| `{}` | {} | {} |\n", outcome.rom, result, outcome.detail);
+    }
+    fs::write(REPORT, report).expect("Conformance report could not be written");
+}
+
+#[test]
+fn conformance() {
+    let root = PathBuf::from(env::var("GBC_TEST_ROMS").unwrap_or_else(|_| "test/roms".into()));
+    let mut roms = Vec::new();
+    find_roms(&root, &mut roms);
+    let mut roms: Vec<(Suite, PathBuf)> = roms
+        .into_iter()
+        .filter_map(|rom| suite(&root, &rom).map(|suite| (suite, rom)))
+        .collect();
+    let found = roms.len();
+    roms.retain(|(_, rom)| !unsupported(rom));
+    let skipped = found - roms.len();
+    if roms.is_empty() {
+        println!(
+            "No unbanked test ROMs found under {}, skipping",
+            root.display()
+        );
+        return;
+    }
+    roms.sort_by(|a, b| a.1.cmp(&b.1));
+
+    // ROMs are expected to panic, e.g. on unsupported hardware, which only
+    // fails them
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let queue = Mutex::new(roms.iter());
+    let outcomes = Mutex::new(Vec::new());
+    let workers = thread::available_parallelism().map_or(1, |n| n.get());
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let next = queue.lock().unwrap().next();
+                let (suite, rom) = match next {
+                    Some(next) => next,
+                    None => return,
+                };
+                let result = panic::catch_unwind(AssertUnwindSafe(|| run_rom(*suite, rom)))
+                    .unwrap_or_else(|payload| {
+                        let message = payload
+                            .downcast_ref::<&str>()
+                            .map(|message| message.to_string())
+                            .or_else(|| payload.downcast_ref::<String>().cloned())
+                            .unwrap_or_default();
+                        Err(format!("panicked: {}", message))
+                    });
+                let outcome = Outcome {
+                    rom: rom.strip_prefix(&root).unwrap_or(rom).display().to_string(),
+                    passed: result.is_ok(),
+                    detail: result.unwrap_or_else(|error| error),
+                };
+                outcomes.lock().unwrap().push(outcome);
+            });
+        }
+    });
+    panic::set_hook(hook);
+
+    let mut outcomes = outcomes.into_inner().unwrap();
+    outcomes.sort_by(|a, b| a.rom.cmp(&b.rom));
+    println!("{:<60} Result", "ROM");
+    for outcome in &outcomes {
+        let result = if outcome.passed { "pass" } else { "FAIL" };
+        println!("{:<60} {} {}", outcome.rom, result, outcome.detail);
+    }
+
+    let previous = previous_results();
+    let regressions: Vec<&str> = outcomes
+        .iter()
+        .filter(|outcome| !outcome.passed && previous.get(&outcome.rom) == Some(&true))
+        .map(|outcome| outcome.rom.as_str())
+        .collect();
+    println!("{} unsupported ROMs skipped", skipped);
+    write_report(&outcomes, skipped);
+    assert!(regressions.is_empty(), "Regressions: {:?}", regressions);
+}
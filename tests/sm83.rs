@@ -0,0 +1,186 @@
+//! Single-step CPU tests, in the community SM83 JSON format: one file per
+//! opcode (`00.json` ~ `ff.json`, `cb 00.json` ~ `cb ff.json`), each holding
+//! test cases made of the initial state, the final state and the bus activity
+//! of every M-cycle.
+//!
+//! Files are discovered under `$GBC_SM83_TESTS`, or `test/sm83` by default,
+//! and run against the CPU on a `FlatBus`. Registers, IME, RAM and the bus
+//! activity of every M-cycle are checked: the read or write each one does,
+//! if any, which also checks the number of M-cycles taken. The test does
+//! nothing when there are no files, run it with
+//! `cargo test --release --test sm83 -- --nocapture`.
+
+use gbc::bus::{Access, FlatBus};
+use gbc::cpu::CPU;
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn number(state: &Value, key: &str) -> Option<u16> {
+    state.get(key)?.as_u64().map(|value| value as u16)
+}
+
+fn register(state: &Value, key: &str) -> u16 {
+    number(state, key).unwrap_or_else(|| panic!("Missing register {}", key))
+}
+
+fn pair(state: &Value, msb: &str, lsb: &str) -> u16 {
+    register(state, msb) << 8 | register(state, lsb)
+}
+
+fn ram(state: &Value) -> Vec<(u16, u8)> {
+    let entries = state.get("ram").and_then(Value::as_array);
+    entries
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let address = entry.get(0)?.as_u64()?;
+            let data = entry.get(1)?.as_u64()?;
+            Some((address as u16, data as u8))
+        })
+        .collect()
+}
+
+/// Read or write expected on each M-cycle, none when the bus is idle
+fn activity(cycles: &[Value]) -> Vec<Option<Access>> {
+    cycles
+        .iter()
+        .map(|cycle| {
+            let address = cycle.get(0)?.as_u64()? as u16;
+            let data = cycle.get(1)?.as_u64()? as u8;
+            let pins = cycle.get(2)?.as_str()?;
+            if pins.contains('r') {
+                Some(Access::Read(address, data))
+            } else if pins.contains('w') {
+                Some(Access::Write(address, data))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+fn load(cpu: &mut CPU<FlatBus>, state: &Value) {
+    let registers = cpu.registers_mut();
+    registers.af = pair(state, "a", "f");
+    registers.bc = pair(state, "b", "c");
+    registers.de = pair(state, "d", "e");
+    registers.hl = pair(state, "h", "l");
+    registers.sp = register(state, "sp");
+    registers.pc = register(state, "pc");
+    cpu.set_ime(number(state, "ime") == Some(1));
+    let bus = cpu.bus_mut();
+    if let Some(ie) = number(state, "ie") {
+        bus.poke(0xFFFF, ie as u8);
+    }
+    for (address, data) in ram(state) {
+        bus.poke(address, data);
+    }
+}
+
+/// Runs a test case, returning what differs from the expected final state
+fn run(test: &Value) -> Result<(), String> {
+    let mut cpu = CPU::with_bus(FlatBus::init());
+    load(&mut cpu, &test["initial"]);
+    cpu.step();
+
+    let expected = &test["final"];
+    let mut errors = Vec::new();
+    let registers = cpu.registers();
+    let actual = [
+        ("AF", registers.af, pair(expected, "a", "f")),
+        ("BC", registers.bc, pair(expected, "b", "c")),
+        ("DE", registers.de, pair(expected, "d", "e")),
+        ("HL", registers.hl, pair(expected, "h", "l")),
+        ("SP", registers.sp, register(expected, "sp")),
+        ("PC", registers.pc, register(expected, "pc")),
+    ];
+    for (name, actual, expected) in actual.iter() {
+        if actual != expected {
+            errors.push(format!("{} {:04X} != {:04X}", name, actual, expected));
+        }
+    }
+    if let Some(ime) = number(expected, "ime") {
+        if cpu.ime() != (ime == 1) {
+            errors.push(format!("IME {} != {}", cpu.ime() as u8, ime));
+        }
+    }
+
+    let bus = cpu.bus_mut();
+    for (address, data) in ram(expected) {
+        if bus.peek(address) != data {
+            let actual = bus.peek(address);
+            errors.push(format!("({:04X}) {:02X} != {:02X}", address, actual, data));
+        }
+    }
+    // The bus counts the M-cycles ticked, independently of the CPU
+    let mut actual = vec![None; bus.cycles() as usize];
+    for (cycle, access) in bus.take_accesses() {
+        match actual.get_mut(cycle as usize) {
+            Some(slot @ None) => *slot = Some(access),
+            _ => errors.push(format!("{:?} on M-cycle {} overlaps", access, cycle)),
+        }
+    }
+    let cycles = test["cycles"].as_array().map_or(&[][..], Vec::as_slice);
+    let expected = activity(cycles);
+    if actual.len() != expected.len() {
+        errors.push(format!("{} M-cycles != {}", actual.len(), expected.len()));
+    }
+    let differs = actual.iter().zip(&expected).position(|(a, e)| a != e);
+    if let Some(cycle) = differs {
+        errors.push(format!(
+            "M-cycle {}: {:?} != {:?}",
+            cycle, actual[cycle], expected[cycle]
+        ));
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join(", "))
+    }
+}
+
+#[test]
+fn sm83() {
+    let root = PathBuf::from(env::var("GBC_SM83_TESTS").unwrap_or_else(|_| "test/sm83".into()));
+    let mut files: Vec<PathBuf> = fs::read_dir(&root)
+        .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+        .unwrap_or_default();
+    files.retain(|path| {
+        path.extension()
+            .is_some_and(|extension| extension == "json")
+    });
+    if files.is_empty() {
+        println!("No SM83 tests found under {}, skipping", root.display());
+        return;
+    }
+    files.sort();
+
+    let mut failed = Vec::new();
+    for file in &files {
+        let name = file.file_stem().unwrap_or_default().to_string_lossy();
+        let json = fs::read_to_string(file).expect("Test file is unreadable");
+        let tests: Value = serde_json::from_str(&json).expect("Test file is not valid JSON");
+        let tests = tests
+            .as_array()
+            .expect("Test file is not an array of tests");
+        let mut first_failure = None;
+        let mut passed = 0;
+        for test in tests {
+            match run(test) {
+                Ok(()) => passed += 1,
+                Err(error) => {
+                    first_failure.get_or_insert_with(|| format!("{}: {}", test["name"], error));
+                }
+            }
+        }
+        println!("{:<8} {:>5} / {:<5}", name, passed, tests.len());
+        if let Some(failure) = first_failure {
+            println!("         {}", failure);
+            failed.push(name.into_owned());
+        }
+    }
+    assert!(failed.is_empty(), "Opcodes failing: {:?}", failed);
+}